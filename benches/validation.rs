@@ -0,0 +1,135 @@
+// Benchmarks of BitNamesState::validate_body and connect_body on synthetic blocks full of
+// registrations.
+//
+// cargo bench --bench validation
+//
+// With --profile-time every benchmark instead runs for that many seconds under pprof and writes a
+// flamegraph to target/criterion/<benchmark>/profile/flamegraph.svg:
+//
+// cargo bench --bench validation -- --profile-time 10
+
+use bitnames::bitnames::{BitName, BitNamesState};
+use bitnames::chain_params::{self, ChainParams};
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use ddk::authorization::Authorization;
+use ddk::heed;
+use ddk::node::State;
+use ddk::types::{Address, AuthorizedTransaction, Body, Content, OutPoint, Output, Transaction};
+use pprof::criterion::{Output as ProfileOutput, PProfProfiler};
+
+// Numbers of registrations in the benchmarked blocks.
+const SIZES: &[usize] = &[1_000, 10_000, 100_000];
+// Fee each registration pays on top of the registration price.
+const FEE: u64 = 1000;
+
+// Regtest, without the per-block registration limit, so blocks bigger than any valid block today
+// can be measured too.
+static PARAMS: ChainParams = ChainParams {
+    max_registrations_per_block: usize::MAX,
+    ..chain_params::REGTEST
+};
+
+struct Fixture {
+    env: heed::Env,
+    ddk_state: ddk::state::State<Authorization, BitName>,
+    state: BitNamesState,
+    body: Body<Authorization, BitName>,
+}
+
+// A fresh state with one utxo per registration, and a body at height 1 registering n keys, each
+// in its own transaction spending one of the utxos.
+fn fixture(n: usize) -> Fixture {
+    let path = std::env::temp_dir().join(format!("bitnames-bench-{n}"));
+    let _ = std::fs::remove_dir_all(&path);
+    std::fs::create_dir_all(&path).unwrap();
+    let env = heed::EnvOpenOptions::new()
+        .map_size(16 * 1024 * 1024 * 1024)
+        .max_dbs(ddk::state::State::<Authorization, BitName>::NUM_DBS + BitNamesState::NUM_DBS)
+        .open(&path)
+        .unwrap();
+    let ddk_state = ddk::state::State::new(&env).unwrap();
+    let state = BitNamesState::new(&env).unwrap();
+    let price = PARAMS.registration_price(1);
+    let mut txn = env.write_txn().unwrap();
+    let mut transactions = Vec::with_capacity(n);
+    for i in 0..n as u64 {
+        let address = Address::from(ddk::types::hash(&("address", i)));
+        let input = OutPoint::Regular {
+            txid: ddk::types::hash(&("funding", i)).into(),
+            vout: 0,
+        };
+        let funding = Output {
+            address,
+            content: Content::Value(price + FEE),
+        };
+        ddk_state.utxos.put(&mut txn, &input, &funding).unwrap();
+        let registration = Output {
+            address,
+            content: Content::Custom(BitName::KeyValue {
+                key: ddk::types::hash(&("key", i)),
+                value: ddk::types::hash(&("value", i)),
+            }),
+        };
+        // BitNamesState doesn't check authorizations, ddk does that before calling it.
+        transactions.push(AuthorizedTransaction {
+            transaction: Transaction {
+                inputs: vec![input],
+                outputs: vec![registration],
+            },
+            authorizations: vec![],
+        });
+    }
+    txn.commit().unwrap();
+    Fixture {
+        env,
+        ddk_state,
+        state,
+        body: Body::new(transactions, vec![]),
+    }
+}
+
+fn bench_validation(c: &mut Criterion) {
+    chain_params::select(&PARAMS);
+    let mut validate = c.benchmark_group("validate_body");
+    validate.sample_size(10);
+    let mut connect_fixtures = vec![];
+    for &n in SIZES {
+        let fixture = fixture(n);
+        validate.throughput(Throughput::Elements(n as u64));
+        validate.bench_with_input(BenchmarkId::from_parameter(n), &fixture, |b, fixture| {
+            b.iter(|| {
+                let txn = fixture.env.read_txn().unwrap();
+                fixture
+                    .state
+                    .validate_body(&txn, 1, &fixture.ddk_state, &fixture.body)
+                    .unwrap();
+            })
+        });
+        connect_fixtures.push((n, fixture));
+    }
+    validate.finish();
+
+    let mut connect = c.benchmark_group("connect_body");
+    connect.sample_size(10);
+    for (n, fixture) in &connect_fixtures {
+        connect.throughput(Throughput::Elements(*n as u64));
+        connect.bench_with_input(BenchmarkId::from_parameter(n), fixture, |b, fixture| {
+            b.iter(|| {
+                // Never committed, so every iteration connects the block on the same state.
+                let mut txn = fixture.env.write_txn().unwrap();
+                fixture
+                    .state
+                    .connect_body(&mut txn, 1, &fixture.ddk_state, &fixture.body)
+                    .unwrap();
+            })
+        });
+    }
+    connect.finish();
+}
+
+criterion_group! {
+    name = benches;
+    config = Criterion::default().with_profiler(PProfProfiler::new(100, ProfileOutput::Flamegraph(None)));
+    targets = bench_validation
+}
+criterion_main!(benches);
@@ -0,0 +1,21 @@
+/* C interface to BitNames resolution, implemented in src/ffi.rs. Link with -lbitnames. */
+#ifndef BITNAMES_H
+#define BITNAMES_H
+
+#include <stdint.h>
+
+#define BITNAMES_NOT_FOUND 0
+#define BITNAMES_FOUND 1
+#define BITNAMES_ERROR (-1)
+
+/* Resolve the 32 byte key to its 32 byte value, written to out_buf, following its redirects,
+ * by asking the node at BITNAMES_RPC_ADDR (default 127.0.0.1:36000). */
+int bitnames_resolve(const uint8_t *key, uint8_t *out_buf);
+
+/* Check that public_key signed key resolving to value (NULL if unregistered) at height, as
+ * returned by the resolve_signed RPC method. Returns 1 if valid, 0 if not. signature is 64
+ * bytes, the other arguments 32. */
+int bitnames_verify_resolution(const uint8_t *key, const uint8_t *value, uint32_t height,
+                               const uint8_t *public_key, const uint8_t *signature);
+
+#endif
@@ -0,0 +1,120 @@
+use crate::bitnames::StateReader;
+use ddk::types::{Address, Hash};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+// Names whose value changed less than this many days ago are flagged when resolved, since a record
+// that just changed is what a hijacked or mistyped name looks like.
+const RECENT_CHANGE_DAYS: u32 = 7;
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Contact {
+    Address(Address),
+    // Resolved to the address stored as the name's value every time it is paid.
+    Name(Hash),
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ResolvedContact {
+    pub address: Address,
+    // Set for contacts stored as names.
+    pub name: Option<Hash>,
+    // Height at which the name's value last changed.
+    pub changed_height: Option<u32>,
+    pub recently_changed: bool,
+}
+
+// Labelled contacts, persisted as a bincode encoded map in address_book.dat.
+pub struct AddressBook {
+    path: PathBuf,
+    contacts: Mutex<BTreeMap<String, Contact>>,
+}
+
+impl AddressBook {
+    pub fn open(datadir: &Path) -> anyhow::Result<Self> {
+        let path = datadir.join("address_book.dat");
+        let contacts = if path.exists() {
+            bincode::deserialize(&std::fs::read(&path)?)?
+        } else {
+            BTreeMap::new()
+        };
+        Ok(Self {
+            path,
+            contacts: Mutex::new(contacts),
+        })
+    }
+
+    pub fn add(&self, label: String, contact: Contact) -> anyhow::Result<()> {
+        let mut contacts = self.contacts.lock().unwrap();
+        contacts.insert(label, contact);
+        self.save(&contacts)
+    }
+
+    pub fn remove(&self, label: &str) -> anyhow::Result<()> {
+        let mut contacts = self.contacts.lock().unwrap();
+        if contacts.remove(label).is_none() {
+            anyhow::bail!("no contact labelled {label}");
+        }
+        self.save(&contacts)
+    }
+
+    pub fn list(&self) -> Vec<(String, Contact)> {
+        let contacts = self.contacts.lock().unwrap();
+        contacts
+            .iter()
+            .map(|(label, contact)| (label.clone(), contact.clone()))
+            .collect()
+    }
+
+    // The address to pay label at right now.
+    pub fn resolve(&self, state: &StateReader, label: &str) -> anyhow::Result<ResolvedContact> {
+        let contact = self.contacts.lock().unwrap().get(label).cloned();
+        let key = match contact {
+            Some(Contact::Address(address)) => {
+                return Ok(ResolvedContact {
+                    address,
+                    name: None,
+                    changed_height: None,
+                    recently_changed: false,
+                })
+            }
+            Some(Contact::Name(key)) => key,
+            None => anyhow::bail!("no contact labelled {label}"),
+        };
+        let (value, changed_height, tip) = state.read(|txn, state| {
+            // Redirects are followed, and a change to any name on the way counts as a change.
+            let chain = state.follow_redirects(txn, &key)?;
+            let mut changed_height = None;
+            for key in &chain {
+                changed_height = changed_height.max(state.get_registration_height(txn, key)?);
+            }
+            Ok((
+                state.get_value(txn, chain.last().unwrap())?,
+                changed_height,
+                state.get_tip_height(txn)?,
+            ))
+        })?;
+        let value = match value {
+            Some(value) => value,
+            None => anyhow::bail!("the name of contact {label} isn't registered"),
+        };
+        let recent = RECENT_CHANGE_DAYS * state.params().blocks_per_day;
+        let recently_changed =
+            changed_height.map_or(false, |height| tip.saturating_sub(height) < recent);
+        Ok(ResolvedContact {
+            address: Address::from(value),
+            name: Some(key),
+            changed_height,
+            recently_changed,
+        })
+    }
+
+    fn save(&self, contacts: &BTreeMap<String, Contact>) -> anyhow::Result<()> {
+        let tmp_path = self.path.with_extension("dat.tmp");
+        std::fs::write(&tmp_path, bincode::serialize(contacts)?)?;
+        std::fs::rename(&tmp_path, &self.path)?;
+        Ok(())
+    }
+}
@@ -0,0 +1,55 @@
+use ddk::types::{Address, Hash, Transaction};
+use serde::{Deserialize, Serialize};
+
+// BitName::Versioned content version of payment aliases.
+//
+// An alias maps user to an address under a name, so that user@name can be paid like an email
+// address. Like TLSA records, aliases are only indexed from the transaction registering their
+// key, since names never change and nobody else may speak for one, and there are no consensus
+// rules for them.
+pub const ALIAS_VERSION: u8 = 4;
+
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Eq)]
+pub struct AliasRecord {
+    pub key: Hash,
+    pub user: String,
+    pub address: Address,
+}
+
+impl AliasRecord {
+    // Decode a Versioned payload, if it is a record transaction is allowed to carry.
+    pub fn decode(
+        payload: &[u8],
+        transaction: &Transaction<crate::bitnames::BitName>,
+    ) -> Option<Self> {
+        let record: Self = bincode::deserialize(payload).ok()?;
+        if !crate::bitnames::registered_keys(transaction).contains(&record.key) {
+            return None;
+        }
+        Some(record)
+    }
+
+    pub fn encode(&self) -> Vec<u8> {
+        bincode::serialize(self).expect("alias records always serialize")
+    }
+}
+
+// Key of a human readable name, the blake3 hash of its lowercase UTF-8 bytes, with punycode
+// labels decoded first so both forms of an internationalized name have the same key, see
+// idn::canonical.
+//
+// The chain only ever sees keys, so this is a convention rather than a rule: a name registered
+// under any other key can't be reached through an identifier.
+pub fn name_key(name: &str) -> Hash {
+    blake3::hash(crate::idn::canonical(name).as_bytes()).into()
+}
+
+// Split user@name into the user and the key of name.
+pub fn parse_identifier(identifier: &str) -> anyhow::Result<(String, Hash)> {
+    match identifier.rsplit_once('@') {
+        Some((user, name)) if !user.is_empty() && !name.is_empty() => {
+            Ok((user.to_lowercase(), name_key(name)))
+        }
+        _ => anyhow::bail!("{identifier} is not of the form user@name"),
+    }
+}
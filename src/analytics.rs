@@ -0,0 +1,176 @@
+use crate::bitnames::{BitName, BitNamesState, Error, StateReader};
+use crate::notify::{self, Event};
+use ddk::heed;
+use ddk::types::{Address, Content};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::Mutex;
+use tokio::sync::broadcast;
+
+// Blocks between rebuilds of the cached report, a day on mainnet. Building one reads every stored
+// body, too much to do per block or per request.
+pub const REPORT_INTERVAL: u32 = 144;
+// Owners listed in a report, by decreasing number of names.
+pub const TOP_OWNERS: usize = 100;
+// A day is a burst if it saw at least this many times the registrations of the median day with
+// any, and at least MIN_BURST registrations.
+const BURST_FACTOR: u64 = 4;
+const MIN_BURST: u64 = 10;
+
+// Aggregate statistics over the namespace, for researchers looking for squatting and for tuning
+// the registration price. Names are never renewed, transferred or expire, so owners are fixed at
+// registration and there are no renewal rates or expiry cliffs to report.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct NameReport {
+    // Tip the report was built at.
+    pub height: u32,
+    // Names registered in blocks, genesis names aren't counted.
+    pub names: u64,
+    // Distinct addresses that own at least one name.
+    pub owners: u64,
+    // Names whose registering block was pruned or came from a snapshot, their owners aren't
+    // known anymore.
+    pub unknown_owner: u64,
+    pub top_owners: Vec<OwnerCount>,
+    pub bursts: Vec<Burst>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct OwnerCount {
+    pub address: Address,
+    pub names: u64,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Burst {
+    // Day index, height / blocks_per_day.
+    pub day: u32,
+    pub registrations: u64,
+}
+
+// Build a report of the state as of txn. The owner of a name is the address its registration
+// output paid to.
+pub fn build(txn: &heed::RoTxn, state: &BitNamesState) -> Result<NameReport, Error> {
+    let height = state.get_tip_height(txn)?;
+    let mut owners: HashMap<Address, u64> = HashMap::new();
+    for height in 0..=height {
+        let keys = state.get_registered_keys(txn, height)?;
+        if keys.is_empty() {
+            continue;
+        }
+        let body = match state.get_block(txn, height)? {
+            Some(body) => body,
+            None => continue,
+        };
+        for transaction in &body.transactions {
+            for output in &transaction.outputs {
+                if let Content::Custom(BitName::KeyValue { .. }) = output.content {
+                    *owners.entry(output.address.clone()).or_default() += 1;
+                }
+            }
+        }
+    }
+    let names = state.get_chain_stats(txn)?.registered_names;
+    let unknown_owner = names.saturating_sub(owners.values().sum());
+    let mut top_owners: Vec<OwnerCount> = owners
+        .iter()
+        .map(|(address, &names)| OwnerCount {
+            address: address.clone(),
+            names,
+        })
+        .collect();
+    top_owners.sort_by(|a, b| b.names.cmp(&a.names));
+    top_owners.truncate(TOP_OWNERS);
+    Ok(NameReport {
+        height,
+        names,
+        owners: owners.len() as u64,
+        unknown_owner,
+        top_owners,
+        bursts: bursts(state.get_registrations_per_day(txn, usize::MAX)?),
+    })
+}
+
+fn bursts(mut days: Vec<(u32, u64)>) -> Vec<Burst> {
+    let mut counts: Vec<u64> = days.iter().map(|&(_, count)| count).collect();
+    counts.sort_unstable();
+    let median = match counts.get(counts.len() / 2) {
+        Some(&median) => median,
+        None => return vec![],
+    };
+    days.sort_by_key(|&(day, _)| day);
+    days.into_iter()
+        .filter(|&(_, count)| count >= MIN_BURST && count >= median * BURST_FACTOR)
+        .map(|(day, registrations)| Burst { day, registrations })
+        .collect()
+}
+
+impl NameReport {
+    // The report as CSV, one row per figure: the totals, then every listed owner, then every
+    // burst.
+    pub fn to_csv(&self) -> String {
+        let mut csv = String::from("kind,subject,count\n");
+        csv.push_str(&format!("height,,{}\n", self.height));
+        csv.push_str(&format!("names,,{}\n", self.names));
+        csv.push_str(&format!("owners,,{}\n", self.owners));
+        csv.push_str(&format!("unknown_owner,,{}\n", self.unknown_owner));
+        for owner in &self.top_owners {
+            let address = serde_json::to_string(&owner.address).unwrap_or_default();
+            csv.push_str(&format!(
+                "owner,\"{}\",{}\n",
+                address.replace('"', "\"\""),
+                owner.names
+            ));
+        }
+        for burst in &self.bursts {
+            csv.push_str(&format!("burst,{},{}\n", burst.day, burst.registrations));
+        }
+        csv
+    }
+}
+
+// The most recent report, rebuilt by run every REPORT_INTERVAL blocks.
+#[derive(Default)]
+pub struct ReportCache {
+    report: Mutex<Option<NameReport>>,
+}
+
+impl ReportCache {
+    // None until the first report is built, shortly after the node starts.
+    pub fn get(&self) -> Option<NameReport> {
+        self.report.lock().unwrap().clone()
+    }
+}
+
+pub async fn run(cache: std::sync::Arc<ReportCache>, state: StateReader) {
+    let mut events = notify::subscribe();
+    let mut built_at = None;
+    loop {
+        let height = match state.read(|txn, state| state.get_tip_height(txn)) {
+            Ok(height) => height,
+            Err(err) => {
+                log::error!("failed to read the tip height: {err}");
+                return;
+            }
+        };
+        if built_at.map_or(true, |built_at| height >= built_at + REPORT_INTERVAL) {
+            // Reads every body, so it runs off the async workers.
+            let reader = state.clone();
+            match tokio::task::spawn_blocking(move || reader.read(build)).await {
+                Ok(Ok(report)) => {
+                    built_at = Some(report.height);
+                    *cache.report.lock().unwrap() = Some(report);
+                }
+                Ok(Err(err)) => log::error!("failed to build the name report: {err}"),
+                Err(err) => log::error!("name report task failed: {err}"),
+            }
+        }
+        loop {
+            match events.recv().await {
+                Ok(Event::Block { .. }) | Err(broadcast::error::RecvError::Lagged(_)) => break,
+                Ok(_) => continue,
+                Err(broadcast::error::RecvError::Closed) => return,
+            }
+        }
+    }
+}
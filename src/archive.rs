@@ -0,0 +1,170 @@
+use crate::bitnames::{BitName, StateReader};
+use crate::notify::{self, Event};
+use ddk::authorization::Authorization;
+use ddk::types::Hash;
+use ed25519_dalek::Keypair;
+use std::path::{Path, PathBuf};
+use tokio::sync::broadcast;
+
+// Copies of every connected block body and of periodic signed snapshots, outside the node's
+// databases, so the chain can be audited or the node rebuilt with --fast-sync if the datadir is
+// lost. Files are written under the archive directory as
+//
+// * blocks/<height>-<merkle root>.bin, the bincode encoded body
+// * snapshots/<height>.snapshot, a snapshot signed with the node key, like `bitnames snapshot
+//   create` writes
+//
+// with heights zero padded so they sort. Object storage is reached through the upload command,
+// run with the path of every new file and its path relative to the archive directory, for
+// example a script running `aws s3 cp "$1" "s3://bucket/$2"`. A failed upload is logged and not
+// retried, the file stays in the archive directory either way.
+pub struct Archive {
+    dir: PathBuf,
+    upload_command: Option<String>,
+    snapshot_interval: u32,
+    node_key: Keypair,
+    state: StateReader,
+}
+
+// Height of the last archived block, kept in the archive directory so that a restarted node
+// catches up on blocks it connected while archiving was off or behind.
+const LAST_ARCHIVED_FILE: &str = "last_archived";
+
+impl Archive {
+    pub fn new(
+        dir: PathBuf,
+        upload_command: Option<String>,
+        snapshot_interval: u32,
+        node_key: Keypair,
+        state: StateReader,
+    ) -> anyhow::Result<Self> {
+        std::fs::create_dir_all(dir.join("blocks"))?;
+        std::fs::create_dir_all(dir.join("snapshots"))?;
+        Ok(Self {
+            dir,
+            upload_command,
+            snapshot_interval,
+            node_key,
+            state,
+        })
+    }
+
+    fn last_archived(&self) -> anyhow::Result<u32> {
+        let path = self.dir.join(LAST_ARCHIVED_FILE);
+        if !path.exists() {
+            return Ok(0);
+        }
+        Ok(std::fs::read_to_string(path)?.trim().parse()?)
+    }
+
+    // Archive the blocks replacing disconnected ones too, their bodies differ. Files of the
+    // disconnected blocks stay, they are told apart by merkle root.
+    fn rewind(&self, height: u32) -> anyhow::Result<()> {
+        if self.last_archived()? > height {
+            write_atomically(
+                &self.dir.join(LAST_ARCHIVED_FILE),
+                height.to_string().as_bytes(),
+            )?;
+        }
+        Ok(())
+    }
+
+    // Archive every stored block above the last archived one up to the tip, and a snapshot if the
+    // tip is at a multiple of the snapshot interval. Blocks pruned before they were archived are
+    // skipped with a warning.
+    async fn catch_up(&self) -> anyhow::Result<()> {
+        let last_archived = self.last_archived()?;
+        let tip = self.state.read(|txn, state| state.get_tip_height(txn))?;
+        for height in last_archived + 1..=tip {
+            let body = self.state.read(|txn, state| state.get_block(txn, height))?;
+            match body {
+                Some(body) => self.archive_block(height, &body).await?,
+                None => log::warn!("block {height} was pruned before it was archived"),
+            }
+            if height == tip && self.snapshot_interval > 0 && height % self.snapshot_interval == 0 {
+                self.archive_snapshot().await?;
+            }
+            write_atomically(
+                &self.dir.join(LAST_ARCHIVED_FILE),
+                height.to_string().as_bytes(),
+            )?;
+        }
+        Ok(())
+    }
+
+    async fn archive_block(
+        &self,
+        height: u32,
+        body: &ddk::types::Body<Authorization, BitName>,
+    ) -> anyhow::Result<()> {
+        let merkle_root: Hash = body.compute_merkle_root().into();
+        let name = format!("blocks/{height:010}-{}.bin", hex::encode(merkle_root));
+        write_atomically(&self.dir.join(&name), &bincode::serialize(body)?)?;
+        self.upload(&name).await;
+        Ok(())
+    }
+
+    // Snapshots can only be taken of the current tip, so none are taken for blocks archived while
+    // catching up.
+    async fn archive_snapshot(&self) -> anyhow::Result<()> {
+        let snapshot = self.state.read(|txn, state| state.create_snapshot(txn))?;
+        let name = format!("snapshots/{:010}.snapshot", snapshot.height);
+        let signed = snapshot.sign(&self.node_key);
+        write_atomically(&self.dir.join(&name), &bincode::serialize(&signed)?)?;
+        log::info!(
+            "archived snapshot at height {}, state root {}",
+            signed.snapshot.height,
+            hex::encode(signed.snapshot.state_root())
+        );
+        self.upload(&name).await;
+        Ok(())
+    }
+
+    async fn upload(&self, name: &str) {
+        let command = match &self.upload_command {
+            Some(command) => command,
+            None => return,
+        };
+        let status = tokio::process::Command::new(command)
+            .arg(self.dir.join(name))
+            .arg(name)
+            .status()
+            .await;
+        match status {
+            Ok(status) if status.success() => {}
+            Ok(status) => log::warn!("archive upload {command} {name} exited with {status}"),
+            Err(err) => log::error!("failed to run archive upload {command}: {err}"),
+        }
+    }
+}
+
+fn write_atomically(path: &Path, bytes: &[u8]) -> anyhow::Result<()> {
+    let tmp = path.with_extension("tmp");
+    std::fs::write(&tmp, bytes)?;
+    std::fs::rename(tmp, path)?;
+    Ok(())
+}
+
+// Archive blocks as they are connected, after catching up on the ones connected since the last
+// run. Like hooks this runs off the notification channel, so a slow target never holds up block
+// connection, and missed notifications are made up for from the stored bodies.
+pub async fn run(archive: Archive) {
+    let mut events = notify::subscribe();
+    loop {
+        if let Err(err) = archive.catch_up().await {
+            log::error!("archiving failed: {err}");
+        }
+        loop {
+            match events.recv().await {
+                Ok(Event::Block { .. }) | Err(broadcast::error::RecvError::Lagged(_)) => break,
+                Ok(Event::Disconnected { height }) => {
+                    if let Err(err) = archive.rewind(height.saturating_sub(1)) {
+                        log::error!("archiving failed: {err}");
+                    }
+                }
+                Ok(_) => continue,
+                Err(broadcast::error::RecvError::Closed) => return,
+            }
+        }
+    }
+}
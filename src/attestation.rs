@@ -0,0 +1,72 @@
+use ddk::types::{Address, Hash, Transaction};
+use ed25519_dalek::{Keypair, PublicKey, Signature, Signer, Verifier};
+use serde::{Deserialize, Serialize};
+
+// BitName::Versioned content version of cross-chain attestations.
+//
+// An attestation states that whoever registered a name also controls an address on another
+// sidechain, proven by a signature of the address's key over the name's key. Sidechains built on
+// ddk derive addresses from ed25519 keys the same way this one does, so the proof is checked
+// right here, when the registration is connected. Mainchain addresses use other signature schemes
+// and can't be attested this way. Like TLSA records, attestations are only indexed from the
+// transaction registering their key, and records that don't verify are ignored.
+pub const ATTESTATION_VERSION: u8 = 7;
+
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Eq)]
+pub struct AttestationRecord {
+    pub key: Hash,
+    // Slot of the sidechain the address is on.
+    pub sidechain_number: u32,
+    pub address: Address,
+    pub public_key: PublicKey,
+    pub signature: Signature,
+}
+
+impl AttestationRecord {
+    // Attest that the holder of keypair, whose address on sidechain_number it derives, is behind
+    // key.
+    pub fn new(keypair: &Keypair, key: Hash, sidechain_number: u32) -> Self {
+        let address = ddk::authorization::get_address(&keypair.public);
+        let signature = keypair.sign(&message(&key, sidechain_number, &address));
+        Self {
+            key,
+            sidechain_number,
+            address,
+            public_key: keypair.public,
+            signature,
+        }
+    }
+
+    // Whether public_key derives address and signed the attestation.
+    pub fn verify(&self) -> bool {
+        ddk::authorization::get_address(&self.public_key) == self.address
+            && self
+                .public_key
+                .verify(
+                    &message(&self.key, self.sidechain_number, &self.address),
+                    &self.signature,
+                )
+                .is_ok()
+    }
+
+    // Decode a Versioned payload, if it is a verified record transaction is allowed to carry.
+    pub fn decode(
+        payload: &[u8],
+        transaction: &Transaction<crate::bitnames::BitName>,
+    ) -> Option<Self> {
+        let record: Self = bincode::deserialize(payload).ok()?;
+        if !crate::bitnames::registered_keys(transaction).contains(&record.key) || !record.verify()
+        {
+            return None;
+        }
+        Some(record)
+    }
+
+    pub fn encode(&self) -> Vec<u8> {
+        bincode::serialize(self).expect("attestation records always serialize")
+    }
+}
+
+fn message(key: &Hash, sidechain_number: u32, address: &Address) -> Hash {
+    ddk::types::hash(&(key, sidechain_number, address))
+}
@@ -1,8 +1,26 @@
+use crate::aliases::{AliasRecord, ALIAS_VERSION};
+use crate::attestation::{AttestationRecord, ATTESTATION_VERSION};
+use crate::chain_params::ChainParams;
+use crate::deployments::DeploymentState;
+use crate::disputes::{Dispute, DisputeRecord, DISPUTE_VERSION};
+use crate::genesis::GenesisName;
+use crate::identity::{IdentityKey, IdentityRecord, IDENTITY_VERSION};
+use crate::lock_height::{self, LOCK_HEIGHT_VERSION};
+use crate::main_address::{MainAddressRecord, MAIN_ADDRESS_VERSION};
+use crate::metrics;
+use crate::offchain::{OffchainPointer, SequenceRecord, OFFCHAIN_VERSION};
+use crate::onion::{OnionBinding, OnionRecord, ONION_VERSION};
+use crate::records::{Record, TypedRecord, MAX_REDIRECT_HOPS, RECORDS_VERSION};
+use crate::tlsa::{TlsaRecord, TLSA_VERSION};
+use ddk::authorization::Authorization;
 use ddk::heed;
 use ddk::node::State;
-use ddk::types::{GetValue, Hash, Transaction};
+use ddk::types::{Address, Body, GetValue, Hash, OutPoint, Transaction, Txid};
 use heed::{types::*, Database};
 use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::path::Path;
+use std::sync::{Arc, Mutex};
 
 // Custom sidechain specific output type. It must derive all of these traits.
 //
@@ -26,7 +44,13 @@ use serde::{Deserialize, Serialize};
 // see ddk/src/types/types.rs for actual definitions of these types.
 #[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Eq)]
 pub enum BitName {
+    // Content version 0, the original registration.
     KeyValue { key: Hash, value: Hash },
+    // Operations added by later versions, see deployments.rs. Versions this node doesn't enforce
+    // rules for are accepted without interpretation.
+    Versioned { version: u8, payload: Vec<u8> },
+    // Version bits a miner signals readiness for, only valid in coinbase outputs.
+    Signal { bits: u32 },
 }
 
 // Custom output type must implement GetValue, which should return the value of this output in
@@ -39,7 +63,9 @@ impl GetValue for BitName {
     }
 }
 
-// Sustom sidechain specific state. It must derive clone, it should only contain heed databases.
+// Sustom sidechain specific state. It must derive clone, it should only contain heed databases
+// (plus value_cache, which is shared between clones rather than copied, and the network's chain
+// params).
 #[derive(Clone)]
 pub struct BitNamesState {
     // heed also let's you use arbitrary types implementing serde::Serialize and serde::Deserialize
@@ -53,50 +79,2410 @@ pub struct BitNamesState {
     // Since Hash is just a [u8; 32] we don't need to serialize it, since it is already a series of
     // bytes.
     key_to_value: Database<OwnedType<Hash>, OwnedType<Hash>>,
+    // Address index used by block explorers, so balance and history queries can be answered
+    // without a wallet. Every output ever sent to an address is recorded here, spent or not.
+    address_to_outpoints: Database<SerdeBincode<Address>, SerdeBincode<Vec<AddressOutPoint>>>,
+    // Reverse lookup so that when an outpoint is spent we know which address entry to update.
+    outpoint_to_address: Database<SerdeBincode<OutPoint>, SerdeBincode<Address>>,
+    // Explorer data, all keyed by big endian block height so that LMDB iterates them in height
+    // order.
+    //
+    // Bodies are stored here as well so explorers can get decoded transactions straight from the
+    // state, without going through ddk's archive.
+    height_to_body: Database<OwnedType<[u8; 4]>, SerdeBincode<Body<Authorization, BitName>>>,
+    height_to_stats: Database<OwnedType<[u8; 4]>, SerdeBincode<BlockStats>>,
+    // Keys registered in each block, in the order they appear in the body.
+    height_to_keys: Database<OwnedType<[u8; 4]>, SerdeBincode<Vec<Hash>>>,
+    // The reverse, height of the block that registered each key, so resolvers can tell how deeply
+    // a name is buried. Genesis and fast synced names aren't in here, see get_registration_height.
+    key_to_height: Database<OwnedType<Hash>, OwnedType<u32>>,
+    // Running totals over the whole chain, stored under CHAIN_STATS_KEY. They are updated in
+    // connect_body so that serving them never requires a scan.
+    chain_stats: Database<Str, SerdeBincode<ChainStats>>,
+    // Number of registrations per day, keyed by big endian day index (height / blocks_per_day).
+    day_to_registrations: Database<OwnedType<[u8; 4]>, OwnedType<u64>>,
+    // Fee rates (sats per 1000 bytes) of the transactions in each of the last
+    // FEE_ESTIMATION_WINDOW blocks, older entries are deleted as new blocks are connected.
+    height_to_fee_rates: Database<OwnedType<[u8; 4]>, SerdeBincode<Vec<u64>>>,
+    // Compact filter over the keys and addresses each block touches, see filters::build.
+    height_to_filter: Database<OwnedType<[u8; 4]>, SerdeBincode<Vec<u8>>>,
+    // Undo data for every connected block: the previous contents of every entry connect_body
+    // overwrote, so disconnect_body can restore the state exactly without replaying from genesis.
+    block_undo: Database<OwnedType<[u8; 4]>, SerdeBincode<Vec<UndoEntry>>>,
+    // Names assigned by the genesis allocation. They are in key_to_value like every other name,
+    // this keeps the allocation itself so later starts can check they were given the same one.
+    genesis_names: Database<OwnedType<Hash>, SerdeBincode<GenesisName>>,
+    // Dispute records attached to each key, oldest first, see disputes.rs. Not consensus, kept
+    // here so they reorg with the blocks that carry them.
+    key_to_disputes: Database<OwnedType<Hash>, SerdeBincode<Vec<Dispute>>>,
+    // The onion service each key was bound to at registration, see onion.rs.
+    key_to_onion: Database<OwnedType<Hash>, SerdeBincode<OnionBinding>>,
+    // Certificate pinning records of each key, in output order, see tlsa.rs.
+    key_to_tlsa: Database<OwnedType<Hash>, SerdeBincode<Vec<TlsaRecord>>>,
+    // Payment aliases of each key, by lowercase user, see aliases.rs.
+    key_to_aliases: Database<OwnedType<Hash>, SerdeBincode<BTreeMap<String, Address>>>,
+    // Cross-chain attestations each key was registered with, in output order, see
+    // attestation.rs.
+    key_to_attestations: Database<OwnedType<Hash>, SerdeBincode<Vec<AttestationRecord>>>,
+    // Mainchain addresses each key was registered with, in output order, see main_address.rs.
+    key_to_main_addresses: Database<OwnedType<Hash>, SerdeBincode<Vec<MainAddressRecord>>>,
+    // Typed records each key was registered with, in output order, see records.rs.
+    key_to_records: Database<OwnedType<Hash>, SerdeBincode<Vec<TypedRecord>>>,
+    // Minimum sequence of each key's off-chain data pointer, when a SequenceRecord raised it
+    // above the one it was registered with, see offchain.rs.
+    key_to_offchain_sequence: Database<OwnedType<Hash>, OwnedType<u64>>,
+    // Every identity key each key had, oldest first, see identity.rs.
+    key_to_identity: Database<OwnedType<Hash>, SerdeBincode<Vec<IdentityKey>>>,
+    // Append-only log of every registration and every registration undone by a disconnect, keyed
+    // by big endian sequence number, see get_name_log. Written in the same transaction as the
+    // change it records, so it never misses or duplicates one across restarts.
+    name_log: Database<OwnedType<[u8; 8]>, SerdeBincode<NameLogEvent>>,
+    // Miscellaneous single values, like the schema version.
+    metadata: Database<Str, SerdeBincode<u32>>,
+    value_cache: Arc<Mutex<ValueCache>>,
+    params: &'static ChainParams,
+}
+
+// In memory cache in front of key_to_value, so the handful of popular names a public resolver
+// answers over and over don't each need a walk down the (large) key_to_value tree.
+//
+// Every write to key_to_value happens under a new state generation, stored in metadata and so
+// part of each transaction's snapshot. Writes replace the key's entry with an Invalidated marker
+// for the new generation, and lookups only populate the cache from snapshots at or after it.
+// That way a reader that started before a block was committed can't put a value back that the
+// block changed.
+#[derive(Default)]
+struct ValueCache {
+    entries: HashMap<Hash, CachedValue>,
+    // Lookups from snapshots older than this can't populate the cache, raised whenever
+    // Invalidated markers are dropped.
+    floor: u32,
+}
+
+enum CachedValue {
+    // value was read from a snapshot at generation, and key hasn't been written since.
+    Value {
+        generation: u32,
+        value: Option<Hash>,
+    },
+    Invalidated {
+        generation: u32,
+    },
+}
+
+impl CachedValue {
+    fn generation(&self) -> u32 {
+        match self {
+            CachedValue::Value { generation, .. } | CachedValue::Invalidated { generation } => {
+                *generation
+            }
+        }
+    }
+}
+
+const VALUE_CACHE_CAPACITY: usize = 100_000;
+
+const SCHEMA_VERSION_KEY: &str = "schema_version";
+// Height of the snapshot the state was fast synced from, if any. Blocks up to this height are
+// already reflected in the state, so they are neither validated nor connected again.
+const FAST_SYNC_HEIGHT_KEY: &str = "fast_sync_height";
+// Height of the assume-valid block once it has been connected with the expected merkle root, see
+// set_assume_valid. Bodies at or below it skip BitNames validation.
+const ASSUME_VALID_HEIGHT_KEY: &str = "assume_valid_height";
+// Incremented by every operation that writes key_to_value, see ValueCache.
+const GENERATION_KEY: &str = "generation";
+// Number of most recent blocks whose bodies and undo data are kept, if pruning is enabled.
+const PRUNE_DEPTH_KEY: &str = "prune_depth";
+// Bodies and undo data below this height have been deleted.
+const PRUNED_HEIGHT_KEY: &str = "pruned_height";
+// Most blocks disconnect_body rolls back in a row, see set_max_rollback_depth.
+const MAX_ROLLBACK_DEPTH_KEY: &str = "max_rollback_depth";
+// Blocks disconnected since the last block was connected.
+const ROLLBACK_DEPTH_KEY: &str = "rollback_depth";
+// Set once the genesis allocation has been applied, see apply_genesis.
+const GENESIS_APPLIED_KEY: &str = "genesis_applied";
+
+// Pruning never deletes undo data for blocks that might still be reorged away.
+pub const MIN_PRUNE_DEPTH: u32 = 288;
+
+// Rolling back more blocks than this needs the operator's confirmation unless configured
+// otherwise. Well within MIN_PRUNE_DEPTH, so the undo data for them is always there.
+pub const DEFAULT_MAX_ROLLBACK_DEPTH: u32 = 100;
+
+// Whether connect_body checks the invariants between its databases after every block, see
+// check_invariants. Process wide like the slow threshold in metrics.rs, and off unless the node
+// is started with --check-invariants.
+#[cfg(feature = "check-invariants")]
+static CHECK_INVARIANTS: std::sync::atomic::AtomicBool = std::sync::atomic::AtomicBool::new(false);
+
+#[cfg(feature = "check-invariants")]
+pub fn set_check_invariants(enabled: bool) {
+    CHECK_INVARIANTS.store(enabled, std::sync::atomic::Ordering::Relaxed);
+}
+
+// Version of the database layout this code reads and writes. Bump it, and add a migration to
+// MIGRATIONS, whenever a change to BitNamesState would make existing datadirs unreadable.
+pub const SCHEMA_VERSION: u32 = 5;
+
+// Migration from version n - 1 to version n is MIGRATIONS[n - 1]. They run in order inside a
+// single write transaction when the state is opened, so an upgrade either fully happens or not at
+// all.
+type Migration = fn(&BitNamesState, &mut heed::RwTxn) -> Result<(), Error>;
+const MIGRATIONS: &[Migration] = &[
+    // 0 -> 1: datadirs from before schema versioning have the same layout as version 1.
+    |_, _| Ok(()),
+    // 1 -> 2: build filters for the blocks whose bodies are still stored.
+    |state, txn| {
+        let mut bodies = vec![];
+        for item in state.height_to_body.iter(txn)? {
+            let (height_key, body) = item?;
+            bodies.push((height_key, body));
+        }
+        for (height_key, body) in bodies {
+            let filter = state.build_filter(txn, &body)?;
+            state.height_to_filter.put(txn, &height_key, &filter)?;
+        }
+        Ok(())
+    },
+    // 2 -> 3: add total_burned to the chain stats, from the registrations of every block so far.
+    |state, txn| {
+        let legacy = state
+            .chain_stats
+            .remap_data_type::<SerdeBincode<LegacyChainStats>>();
+        let stats = match legacy.get(txn, CHAIN_STATS_KEY)? {
+            Some(stats) => stats,
+            None => return Ok(()),
+        };
+        let mut heights = vec![];
+        for item in state.height_to_keys.iter(txn)? {
+            let (height_key, _) = item?;
+            heights.push(u32::from_be_bytes(height_key));
+        }
+        let mut total_burned = 0;
+        for height in heights {
+            total_burned += state.block_burned(txn, height)?;
+        }
+        let stats = stats.with_total_burned(total_burned);
+        state.chain_stats.put(txn, CHAIN_STATS_KEY, &stats)?;
+        Ok(())
+    },
+    // 3 -> 4: index the registration height of every key registered in a block.
+    |state, txn| {
+        let mut registrations = vec![];
+        for item in state.height_to_keys.iter(txn)? {
+            let (height_key, keys) = item?;
+            registrations.push((u32::from_be_bytes(height_key), keys));
+        }
+        for (height, keys) in registrations {
+            for key in keys {
+                state.key_to_height.put(txn, &key, &height)?;
+            }
+        }
+        Ok(())
+    },
+    // 4 -> 5: start the name log with every name registered in a block so far, oldest first.
+    // Genesis and fast synced names were never registered in a block this node has.
+    |state, txn| {
+        let mut registrations = vec![];
+        for item in state.height_to_keys.iter(txn)? {
+            let (height_key, keys) = item?;
+            registrations.push((u32::from_be_bytes(height_key), keys));
+        }
+        for (height, keys) in registrations {
+            for key in keys {
+                if let Some(value) = state.key_to_value.get(txn, &key)? {
+                    state.append_name_log(txn, NameLogKind::Registered, key, value, height)?;
+                }
+            }
+        }
+        Ok(())
+    },
+];
+
+// The previous contents of a single database entry, None if the entry didn't exist.
+//
+// Entries keyed by the connected block's own height (body, stats, keys, fee rates) always start
+// out empty, so they don't need undo entries, disconnect_body just deletes them.
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Eq)]
+pub enum UndoEntry {
+    KeyValue {
+        key: Hash,
+        previous: Option<Hash>,
+    },
+    AddressOutPoints {
+        address: Address,
+        previous: Option<Vec<AddressOutPoint>>,
+    },
+    OutPointAddress {
+        outpoint: OutPoint,
+        previous: Option<Address>,
+    },
+    FeeRates {
+        height: u32,
+        previous: Option<Vec<u64>>,
+    },
+    // Chain stats from before schema version 3, kept in this position so undo data written by
+    // older versions still decodes.
+    LegacyChainStats {
+        previous: Option<LegacyChainStats>,
+    },
+    DayRegistrations {
+        day: u32,
+        previous: Option<u64>,
+    },
+    Metadata {
+        key: String,
+        previous: Option<u32>,
+    },
+    ChainStats {
+        previous: Option<ChainStats>,
+    },
+    Disputes {
+        key: Hash,
+        previous: Option<Vec<Dispute>>,
+    },
+    Onion {
+        key: Hash,
+        previous: Option<OnionBinding>,
+    },
+    Tlsa {
+        key: Hash,
+        previous: Option<Vec<TlsaRecord>>,
+    },
+    Aliases {
+        key: Hash,
+        previous: Option<BTreeMap<String, Address>>,
+    },
+    Identity {
+        key: Hash,
+        previous: Option<Vec<IdentityKey>>,
+    },
+    Attestations {
+        key: Hash,
+        previous: Option<Vec<AttestationRecord>>,
+    },
+    MainAddresses {
+        key: Hash,
+        previous: Option<Vec<MainAddressRecord>>,
+    },
+    Records {
+        key: Hash,
+        previous: Option<Vec<TypedRecord>>,
+    },
+    OffchainSequence {
+        key: Hash,
+        previous: Option<u64>,
+    },
+}
+
+// ddk's Node owns the custom state and never hands it out, so every BitNamesState::new registers a
+// reader here together with its heed env. This is how the RPC server (and anything else running
+// in the same process) gets read access to name data.
+static READERS: Mutex<Vec<StateReader>> = Mutex::new(Vec::new());
+
+#[derive(Clone)]
+pub struct StateReader {
+    env: heed::Env,
+    state: BitNamesState,
 }
 
-impl BitNamesState {
-    // Convenience method to avoid repeating the same code twice later.
-    fn validate_keys_unique(
+impl StateReader {
+    // Reader for the node whose data lives under datadir. If the state was opened more than once,
+    // like for a fast sync import before the node starts, this is the last one opened, as the
+    // value cache of any other one doesn't see the node's writes.
+    pub fn open(datadir: &Path) -> Result<Self, Error> {
+        let datadir = datadir
+            .canonicalize()
+            .map_err(|_| Error::StateNotOpen(datadir.display().to_string()))?;
+        let readers = READERS.lock().unwrap();
+        readers
+            .iter()
+            .rev()
+            .find(|reader| reader.env.path().starts_with(&datadir))
+            .cloned()
+            .ok_or_else(|| Error::StateNotOpen(datadir.display().to_string()))
+    }
+
+    // Run f inside a single read transaction, so everything it reads is from the same tip.
+    pub fn read<T>(
+        &self,
+        f: impl FnOnce(&heed::RoTxn, &BitNamesState) -> Result<T, Error>,
+    ) -> Result<T, Error> {
+        let txn = self.env.read_txn()?;
+        f(&txn, &self.state)
+    }
+
+    // Run f inside a write transaction, which is committed if f succeeds.
+    //
+    // Only meant for maintenance outside of ddk's own block processing, like startup recovery.
+    pub fn write<T>(
+        &self,
+        f: impl FnOnce(&mut heed::RwTxn, &BitNamesState) -> Result<T, Error>,
+    ) -> Result<T, Error> {
+        let mut txn = self.env.write_txn()?;
+        let result = f(&mut txn, &self.state)?;
+        txn.commit()?;
+        Ok(result)
+    }
+
+    pub fn env(&self) -> &heed::Env {
+        &self.env
+    }
+
+    pub(crate) fn state(&self) -> &BitNamesState {
+        &self.state
+    }
+
+    pub fn params(&self) -> &'static ChainParams {
+        self.state.params
+    }
+}
+
+pub(crate) const CHAIN_STATS_KEY: &str = "chain_stats";
+
+// Number of recent blocks considered for fee estimation.
+pub const FEE_ESTIMATION_WINDOW: u32 = 100;
+
+// value_in - value_out of a transaction, in sats.
+pub fn filled_transaction_fee(transaction: &ddk::types::FilledTransaction<BitName>) -> u64 {
+    let value_in: u64 = transaction.spent_utxos.iter().map(|o| o.get_value()).sum();
+    let value_out: u64 = transaction
+        .transaction
+        .outputs
+        .iter()
+        .map(|o| o.get_value())
+        .sum();
+    value_in.saturating_sub(value_out)
+}
+
+// Keys registered by a transaction, in output order.
+pub fn registered_keys(transaction: &Transaction<BitName>) -> Vec<Hash> {
+    transaction
+        .outputs
+        .iter()
+        .filter_map(|output| match output.content {
+            ddk::types::Content::Custom(BitName::KeyValue { key, .. }) => Some(key),
+            _ => None,
+        })
+        .collect()
+}
+
+// Key a record of version is attached to, for the versions that attach records to keys. Every
+// such record starts with its key, so it is decoded without checking the rest of the record.
+fn record_key(version: u8, payload: &[u8]) -> Option<Hash> {
+    match version {
+        DISPUTE_VERSION | ONION_VERSION | TLSA_VERSION | ALIAS_VERSION | IDENTITY_VERSION
+        | ATTESTATION_VERSION | MAIN_ADDRESS_VERSION | RECORDS_VERSION | OFFCHAIN_VERSION => {
+            bincode::deserialize(payload).ok()
+        }
+        _ => None,
+    }
+}
+
+// An entry in the address index.
+//
+// spent_height is None while the outpoint is still unspent.
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Eq)]
+pub struct AddressOutPoint {
+    pub outpoint: OutPoint,
+    pub value: u64,
+    pub funded_height: u32,
+    pub spent_height: Option<u32>,
+}
+
+// Per block statistics, computed once in connect_body.
+#[derive(Clone, Debug, Default, Serialize, Deserialize, PartialEq, Eq)]
+pub struct BlockStats {
+    pub height: u32,
+    pub transactions: u32,
+    pub registrations: u32,
+    // Sum of value_in - value_out over all transactions in the block.
+    pub fees: u64,
+    // Serialized size of the body in bytes.
+    pub size: u64,
+}
+
+// Aggregate statistics for the whole chain.
+#[derive(Clone, Debug, Default, Serialize, Deserialize, PartialEq, Eq)]
+pub struct ChainStats {
+    pub height: u32,
+    pub blocks: u64,
+    pub transactions: u64,
+    pub registered_names: u64,
+    pub total_fees: u64,
+    // Total serialized size of all connected bodies in bytes.
+    pub total_body_size: u64,
+    // Registration prices burned by all connected bodies, part of total_fees.
+    pub total_burned: u64,
+}
+
+// ChainStats as stored before schema version 3, which added total_burned. Still read from the
+// undo data of blocks connected before the upgrade.
+#[derive(Clone, Debug, Default, Serialize, Deserialize, PartialEq, Eq)]
+pub struct LegacyChainStats {
+    pub height: u32,
+    pub blocks: u64,
+    pub transactions: u64,
+    pub registered_names: u64,
+    pub total_fees: u64,
+    pub total_body_size: u64,
+}
+
+impl LegacyChainStats {
+    fn with_total_burned(self, total_burned: u64) -> ChainStats {
+        ChainStats {
+            height: self.height,
+            blocks: self.blocks,
+            transactions: self.transactions,
+            registered_names: self.registered_names,
+            total_fees: self.total_fees,
+            total_body_size: self.total_body_size,
+            total_burned,
+        }
+    }
+}
+
+// A mismatch between the databases and a replay of the stored block bodies.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Divergence {
+    KeyValue {
+        key: Hash,
+        expected: Option<Hash>,
+        actual: Option<Hash>,
+    },
+    AddressHistory {
+        address: Address,
+    },
+    RegisteredNames {
+        expected: u64,
+        actual: u64,
+    },
+    MissingBody {
+        height: u32,
+    },
+}
+
+impl std::fmt::Display for Divergence {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Divergence::KeyValue {
+                key,
+                expected,
+                actual,
+            } => write!(
+                f,
+                "key {} has value {:?}, replay gives {:?}",
+                hex::encode(key),
+                actual.as_ref().map(hex::encode),
+                expected.as_ref().map(hex::encode)
+            ),
+            Divergence::AddressHistory { address } => {
+                write!(f, "address index entry for {address} doesn't match replay")
+            }
+            Divergence::RegisteredNames { expected, actual } => write!(
+                f,
+                "chain stats count {actual} registered names, replay gives {expected}"
+            ),
+            Divergence::MissingBody { height } => write!(f, "no body stored for block {height}"),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum NameLogKind {
+    Registered,
+    // The block that registered the name was disconnected, the name is unregistered again.
+    Unregistered,
+}
+
+// An entry of the name log. Sequence numbers start at 1 and increase by one with every event,
+// also across reorgs, so a consumer that stores the last sequence it processed can resume from
+// there after either side restarts.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct NameLogEvent {
+    pub sequence: u64,
+    pub kind: NameLogKind,
+    pub key: Hash,
+    pub value: Hash,
+    pub height: u32,
+}
+
+// A registered key together with the height of the block that registered it.
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Eq)]
+pub struct RecentName {
+    pub key: Hash,
+    pub value: Hash,
+    pub height: u32,
+}
+
+impl BitNamesState {
+    // Convenience method to avoid repeating the same code twice later.
+    fn validate_keys_unique(
+        &self,
+        // txn is a heed db transaction that lets us do db operations atomically.
+        //
+        // txn is created by ddk and then passed to all of the necessary methods, then if there are
+        // no errors, ddk calls txn.commit()?;
+        txn: &heed::RoTxn,
+        // Transaction is a sidechain transaction. It is generic over the custom output type, that
+        // is why we must pass in the BitName type parameter.
+        transaction: &Transaction<BitName>,
+        // The keys transaction registers that are registered already, see registered_among.
+        registered: &HashSet<Hash>,
+    ) -> Result<(), Error> {
+        for output in &transaction.outputs {
+            match output.content {
+                ddk::types::Content::Custom(BitName::KeyValue { key, .. }) => {
+                    if registered.contains(&key) {
+                        return Err(Error::KeyAlreadyExists {
+                            key,
+                            registered_at_height: self.get_registration_height(txn, &key)?,
+                            conflicting_txid: transaction.txid().into(),
+                        });
+                    }
+                }
+                _ => continue,
+            }
+        }
+        Ok(())
+    }
+
+    // Which of keys are registered already.
+    //
+    // Keys are looked up in sorted order, so consecutive lookups walk key_to_value's B-tree from
+    // left to right and mostly hit pages the previous lookup just touched, instead of a random
+    // page per key. For a block full of registrations that is far fewer page faults than looking
+    // them up in body order. heed doesn't expose repositioning a single cursor, so each lookup is
+    // still its own get.
+    fn registered_among(
+        &self,
+        txn: &heed::RoTxn,
+        mut keys: Vec<Hash>,
+    ) -> Result<HashSet<Hash>, Error> {
+        keys.sort_unstable();
+        keys.dedup();
+        let mut registered = HashSet::new();
+        for key in keys {
+            if self.key_to_value.get(txn, &key)?.is_some() {
+                registered.insert(key);
+            }
+        }
+        Ok(registered)
+    }
+
+    // Content rules that don't depend on the names: signals only mean something in the coinbase.
+    // Once the content limits deployment is active for the block at height, also version 0 is
+    // only expressed as KeyValue, value outputs can't be dust, and there are at most
+    // max_custom_outputs BitName outputs. Once the record sizes deployment is active, records
+    // stay within their size limits.
+    fn validate_content(
+        &self,
+        txn: &heed::RoTxn,
+        height: u32,
+        transaction: &Transaction<BitName>,
+    ) -> Result<(), Error> {
+        let txid = transaction.txid().into();
+        let limits = self.is_deployment_active(txn, height, crate::deployments::CONTENT_LIMITS)?;
+        let custom_outputs = transaction
+            .outputs
+            .iter()
+            .filter(|output| matches!(output.content, ddk::types::Content::Custom(_)))
+            .count();
+        if limits && custom_outputs > self.params.max_custom_outputs {
+            return Err(Error::TooManyCustomOutputs {
+                txid,
+                outputs: custom_outputs,
+                max: self.params.max_custom_outputs,
+            });
+        }
+        for (vout, output) in transaction.outputs.iter().enumerate() {
+            let vout = vout as u32;
+            match &output.content {
+                ddk::types::Content::Value(value) if limits && *value < self.params.dust_limit => {
+                    return Err(Error::DustOutput {
+                        txid,
+                        vout,
+                        value: *value,
+                        dust_limit: self.params.dust_limit,
+                    })
+                }
+                ddk::types::Content::Custom(BitName::Versioned { version: 0, .. }) if limits => {
+                    return Err(Error::ReservedContentVersion { txid, vout })
+                }
+                ddk::types::Content::Custom(BitName::Signal { .. }) => {
+                    return Err(Error::SignalOutsideCoinbase { txid, vout })
+                }
+                _ => continue,
+            }
+        }
+        if self.is_deployment_active(txn, height, crate::deployments::RECORD_SIZES)? {
+            self.validate_record_sizes(transaction)?;
+        }
+        Ok(())
+    }
+
+    fn validate_record_sizes(&self, transaction: &Transaction<BitName>) -> Result<(), Error> {
+        let txid = transaction.txid().into();
+        let mut name_sizes: HashMap<Hash, usize> = HashMap::new();
+        for (vout, output) in transaction.outputs.iter().enumerate() {
+            let (version, payload) = match &output.content {
+                ddk::types::Content::Custom(BitName::Versioned { version, payload }) => {
+                    (*version, payload)
+                }
+                _ => continue,
+            };
+            if payload.len() > self.params.max_record_size {
+                return Err(Error::RecordTooLarge {
+                    txid,
+                    vout: vout as u32,
+                    size: payload.len(),
+                    max: self.params.max_record_size,
+                });
+            }
+            let key = match record_key(version, payload) {
+                Some(key) => key,
+                None => continue,
+            };
+            let size = name_sizes.entry(key).or_default();
+            *size += payload.len();
+            if *size > self.params.max_name_records_size {
+                return Err(Error::NameRecordsTooLarge {
+                    txid,
+                    key,
+                    size: *size,
+                    max: self.params.max_name_records_size,
+                });
+            }
+        }
+        Ok(())
+    }
+
+    // Sats burned by every registration in the block at height, nothing before the registration
+    // burn is active.
+    pub fn registration_price_at(&self, txn: &heed::RoTxn, height: u32) -> Result<u64, Error> {
+        if self.is_deployment_active(txn, height, crate::deployments::REGISTRATION_BURN)? {
+            Ok(self.params.registration_price(height))
+        } else {
+            Ok(0)
+        }
+    }
+
+    // Sats burned by the registrations of the connected block at height.
+    fn block_burned(&self, txn: &heed::RoTxn, height: u32) -> Result<u64, Error> {
+        let registrations = self
+            .height_to_keys
+            .get(txn, &height.to_be_bytes())?
+            .map_or(0, |keys| keys.len() as u64);
+        Ok(registrations * self.registration_price_at(txn, height)?)
+    }
+
+    // Rules of content versions whose deployment is active at height: onion bindings
+    // must be signed by their service key and registered in the same transaction, a height
+    // locked transaction can't be in a block at height below its lock, and mainchain address
+    // bindings and typed records have to be valid and in the transaction registering their key.
+    fn validate_active_versions(
+        &self,
+        txn: &heed::RoTxn,
+        height: u32,
+        transaction: &Transaction<BitName>,
+    ) -> Result<(), Error> {
+        if self.is_version_active(txn, height, LOCK_HEIGHT_VERSION)? {
+            let txid = transaction.txid().into();
+            match lock_height::lock_height(transaction) {
+                Err(vout) => return Err(Error::InvalidLockHeight { txid, vout }),
+                Ok(Some(lock_height)) if height < lock_height => {
+                    return Err(Error::TransactionLocked {
+                        txid,
+                        lock_height,
+                        height,
+                    })
+                }
+                Ok(_) => {}
+            }
+        }
+        let onion_active = self.is_version_active(txn, height, ONION_VERSION)?;
+        let main_address_active = self.is_version_active(txn, height, MAIN_ADDRESS_VERSION)?;
+        let records_active = self.is_version_active(txn, height, RECORDS_VERSION)?;
+        for (vout, output) in transaction.outputs.iter().enumerate() {
+            let txid = transaction.txid().into();
+            let vout = vout as u32;
+            match &output.content {
+                ddk::types::Content::Custom(BitName::Versioned {
+                    version: ONION_VERSION,
+                    payload,
+                }) if onion_active => {
+                    if OnionRecord::decode(payload, transaction).is_none() {
+                        return Err(Error::InvalidOnionRecord { txid, vout });
+                    }
+                }
+                ddk::types::Content::Custom(BitName::Versioned {
+                    version: MAIN_ADDRESS_VERSION,
+                    payload,
+                }) if main_address_active => {
+                    if MainAddressRecord::decode(payload, transaction).is_none() {
+                        return Err(Error::InvalidMainAddressRecord { txid, vout });
+                    }
+                }
+                ddk::types::Content::Custom(BitName::Versioned {
+                    version: RECORDS_VERSION,
+                    payload,
+                }) if records_active => {
+                    if TypedRecord::decode(payload, transaction).is_none() {
+                        return Err(Error::InvalidTypedRecord { txid, vout });
+                    }
+                }
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+
+    // Check that a transaction in the block at height pays at least the registration price of
+    // every key it registers out of fee, and return the amount burned.
+    fn validate_burn(
+        &self,
+        txn: &heed::RoTxn,
+        transaction: &Transaction<BitName>,
+        fee: u64,
+        height: u32,
+    ) -> Result<u64, Error> {
+        let registrations = registered_keys(transaction).len() as u64;
+        let burn = registrations * self.registration_price_at(txn, height)?;
+        if fee < burn {
+            return Err(Error::RegistrationUnderpaid {
+                txid: transaction.txid().into(),
+                fee,
+                required: burn,
+            });
+        }
+        Ok(burn)
+    }
+
+    // Count the block at height towards every deployment whose bit one of its coinbase signals
+    // sets.
+    fn count_signals(
+        &self,
+        txn: &mut heed::RwTxn,
+        height: u32,
+        coinbase: &[ddk::types::Output<BitName>],
+        undo: &mut Vec<UndoEntry>,
+    ) -> Result<(), Error> {
+        let bits = coinbase
+            .iter()
+            .filter_map(|output| match output.content {
+                ddk::types::Content::Custom(BitName::Signal { bits }) => Some(bits),
+                _ => None,
+            })
+            .fold(0, |all, bits| all | bits);
+        for deployment in self.params.deployments {
+            if bits & (1 << deployment.bit) == 0 {
+                continue;
+            }
+            let window = height / self.params.deployment_window;
+            let key = crate::deployments::signals_key(deployment, window);
+            let previous = self.metadata.get(txn, &key)?;
+            self.metadata.put(txn, &key, &(previous.unwrap_or(0) + 1))?;
+            undo.push(UndoEntry::Metadata { key, previous });
+        }
+        Ok(())
+    }
+
+    // State of deployment for the next block.
+    pub fn get_deployment_state(
+        &self,
+        txn: &heed::RoTxn,
+        deployment: &crate::deployments::Deployment,
+    ) -> Result<DeploymentState, Error> {
+        let height = self.get_tip_height(txn)? + 1;
+        self.get_deployment_state_at(txn, deployment, height)
+    }
+
+    // State of deployment for a block at height, which only depends on the signals of blocks in
+    // windows before the one height is in. So the state at some height above the tip is the one
+    // the chain would have there if no more blocks signalled.
+    pub fn get_deployment_state_at(
+        &self,
+        txn: &heed::RoTxn,
+        deployment: &crate::deployments::Deployment,
+        height: u32,
+    ) -> Result<DeploymentState, Error> {
+        crate::deployments::state(self.params, deployment, height, |window| {
+            let key = crate::deployments::signals_key(deployment, window);
+            Ok(self.metadata.get(txn, &key)?.unwrap_or(0))
+        })
+    }
+
+    // Whether the rules of content version are enforced for a block at height.
+    fn is_version_active(
+        &self,
+        txn: &heed::RoTxn,
+        height: u32,
+        version: u8,
+    ) -> Result<bool, Error> {
+        for deployment in self.params.deployments {
+            if deployment.version == Some(version) {
+                let state = self.get_deployment_state_at(txn, deployment, height)?;
+                return Ok(state == DeploymentState::Active);
+            }
+        }
+        Ok(false)
+    }
+
+    // Whether the rules of the deployment called name are enforced for a block at height, see
+    // the names in deployments.rs. Rules without a deployment on this network are never enforced.
+    fn is_deployment_active(
+        &self,
+        txn: &heed::RoTxn,
+        height: u32,
+        name: &str,
+    ) -> Result<bool, Error> {
+        for deployment in self.params.deployments {
+            if deployment.name == name {
+                let state = self.get_deployment_state_at(txn, deployment, height)?;
+                return Ok(state == DeploymentState::Active);
+            }
+        }
+        Ok(false)
+    }
+
+    // Record a new output in the address index.
+    fn index_output(
+        &self,
+        txn: &mut heed::RwTxn,
+        height: u32,
+        outpoint: OutPoint,
+        output: &ddk::types::Output<BitName>,
+        undo: &mut Vec<UndoEntry>,
+    ) -> Result<(), Error> {
+        let previous = self.address_to_outpoints.get(txn, &output.address)?;
+        undo.push(UndoEntry::AddressOutPoints {
+            address: output.address,
+            previous: previous.clone(),
+        });
+        undo.push(UndoEntry::OutPointAddress {
+            outpoint,
+            previous: self.outpoint_to_address.get(txn, &outpoint)?,
+        });
+        let mut outpoints = previous.unwrap_or_default();
+        outpoints.push(AddressOutPoint {
+            outpoint,
+            value: output.get_value(),
+            funded_height: height,
+            spent_height: None,
+        });
+        self.address_to_outpoints
+            .put(txn, &output.address, &outpoints)?;
+        self.outpoint_to_address
+            .put(txn, &outpoint, &output.address)?;
+        Ok(())
+    }
+
+    // Record a dispute against record.key, carried by transaction txid.
+    fn index_dispute(
+        &self,
+        txn: &mut heed::RwTxn,
+        height: u32,
+        txid: Hash,
+        record: DisputeRecord,
+        undo: &mut Vec<UndoEntry>,
+    ) -> Result<(), Error> {
+        let previous = self.key_to_disputes.get(txn, &record.key)?;
+        undo.push(UndoEntry::Disputes {
+            key: record.key,
+            previous: previous.clone(),
+        });
+        let mut disputes = previous.unwrap_or_default();
+        disputes.push(Dispute {
+            txid,
+            height,
+            evidence: record.evidence,
+            public_key: record.public_key,
+        });
+        self.key_to_disputes.put(txn, &record.key, &disputes)?;
+        Ok(())
+    }
+
+    // Record the onion binding of record.key, unless the key already has one from earlier in the
+    // same transaction.
+    fn index_onion(
+        &self,
+        txn: &mut heed::RwTxn,
+        height: u32,
+        txid: Hash,
+        record: OnionRecord,
+        undo: &mut Vec<UndoEntry>,
+    ) -> Result<(), Error> {
+        if self.key_to_onion.get(txn, &record.key)?.is_some() {
+            return Ok(());
+        }
+        undo.push(UndoEntry::Onion {
+            key: record.key,
+            previous: None,
+        });
+        let binding = OnionBinding {
+            service_key: record.service_key,
+            txid,
+            height,
+        };
+        self.key_to_onion.put(txn, &record.key, &binding)?;
+        Ok(())
+    }
+
+    fn index_tlsa(
+        &self,
+        txn: &mut heed::RwTxn,
+        record: TlsaRecord,
+        undo: &mut Vec<UndoEntry>,
+    ) -> Result<(), Error> {
+        let previous = self.key_to_tlsa.get(txn, &record.key)?;
+        undo.push(UndoEntry::Tlsa {
+            key: record.key,
+            previous: previous.clone(),
+        });
+        let key = record.key;
+        let mut records = previous.unwrap_or_default();
+        records.push(record);
+        self.key_to_tlsa.put(txn, &key, &records)?;
+        Ok(())
+    }
+
+    fn index_main_address(
+        &self,
+        txn: &mut heed::RwTxn,
+        record: MainAddressRecord,
+        undo: &mut Vec<UndoEntry>,
+    ) -> Result<(), Error> {
+        let previous = self.key_to_main_addresses.get(txn, &record.key)?;
+        undo.push(UndoEntry::MainAddresses {
+            key: record.key,
+            previous: previous.clone(),
+        });
+        let key = record.key;
+        let mut records = previous.unwrap_or_default();
+        records.push(record);
+        self.key_to_main_addresses.put(txn, &key, &records)?;
+        Ok(())
+    }
+
+    fn index_record(
+        &self,
+        txn: &mut heed::RwTxn,
+        record: TypedRecord,
+        undo: &mut Vec<UndoEntry>,
+    ) -> Result<(), Error> {
+        let previous = self.key_to_records.get(txn, &record.key)?;
+        undo.push(UndoEntry::Records {
+            key: record.key,
+            previous: previous.clone(),
+        });
+        let key = record.key;
+        let mut records = previous.unwrap_or_default();
+        records.push(record);
+        self.key_to_records.put(txn, &key, &records)?;
+        Ok(())
+    }
+
+    // Raise the minimum sequence of key's pointer, if the record is signed by the pointer's key
+    // and goes above both the registered minimum and any raise before it. Signed records stay
+    // valid forever, so without the second check anyone could replay an old one to lower the
+    // minimum again.
+    fn index_offchain_sequence(
+        &self,
+        txn: &mut heed::RwTxn,
+        record: SequenceRecord,
+        undo: &mut Vec<UndoEntry>,
+    ) -> Result<(), Error> {
+        // get_offchain_pointer already raises min_sequence to the stored one, both are compared
+        // here so this doesn't depend on it.
+        let pointer = match self.get_offchain_pointer(txn, &record.key)? {
+            Some(pointer) => pointer,
+            None => return Ok(()),
+        };
+        let previous = self.key_to_offchain_sequence.get(txn, &record.key)?;
+        let current = pointer.min_sequence.max(previous.unwrap_or(0));
+        if record.min_sequence <= current || !record.verify(&pointer) {
+            return Ok(());
+        }
+        undo.push(UndoEntry::OffchainSequence {
+            key: record.key,
+            previous,
+        });
+        self.key_to_offchain_sequence
+            .put(txn, &record.key, &record.min_sequence)?;
+        Ok(())
+    }
+
+    fn index_attestation(
+        &self,
+        txn: &mut heed::RwTxn,
+        record: AttestationRecord,
+        undo: &mut Vec<UndoEntry>,
+    ) -> Result<(), Error> {
+        let previous = self.key_to_attestations.get(txn, &record.key)?;
+        undo.push(UndoEntry::Attestations {
+            key: record.key,
+            previous: previous.clone(),
+        });
+        let key = record.key;
+        let mut records = previous.unwrap_or_default();
+        records.push(record);
+        self.key_to_attestations.put(txn, &key, &records)?;
+        Ok(())
+    }
+
+    // Record the identity key a registration binds, or a rotation signed by the current key.
+    fn index_identity(
+        &self,
+        txn: &mut heed::RwTxn,
+        height: u32,
+        txid: Hash,
+        record: IdentityRecord,
+        undo: &mut Vec<UndoEntry>,
+    ) -> Result<(), Error> {
+        let previous = self.key_to_identity.get(txn, &record.key)?;
+        let mut history = previous.clone().unwrap_or_default();
+        let qualifies = match record.signature {
+            None => history.is_empty(),
+            Some(_) => record.verify_rotation(&history),
+        };
+        if !qualifies {
+            return Ok(());
+        }
+        undo.push(UndoEntry::Identity {
+            key: record.key,
+            previous,
+        });
+        history.push(IdentityKey {
+            public_key: record.public_key,
+            txid,
+            height,
+        });
+        self.key_to_identity.put(txn, &record.key, &history)?;
+        Ok(())
+    }
+
+    // Record an alias, unless its user already has one under the same key.
+    fn index_alias(
+        &self,
+        txn: &mut heed::RwTxn,
+        record: AliasRecord,
+        undo: &mut Vec<UndoEntry>,
+    ) -> Result<(), Error> {
+        let previous = self.key_to_aliases.get(txn, &record.key)?;
+        let mut aliases = previous.clone().unwrap_or_default();
+        let user = record.user.to_lowercase();
+        if aliases.contains_key(&user) {
+            return Ok(());
+        }
+        undo.push(UndoEntry::Aliases {
+            key: record.key,
+            previous,
+        });
+        aliases.insert(user, record.address);
+        self.key_to_aliases.put(txn, &record.key, &aliases)?;
+        Ok(())
+    }
+
+    // Mark an outpoint as spent in the address index.
+    //
+    // Deposits are credited by ddk itself and never pass through connect_body as outputs, so
+    // spending one is simply not reflected in the index.
+    fn index_spend(
+        &self,
+        txn: &mut heed::RwTxn,
+        height: u32,
+        outpoint: &OutPoint,
+        undo: &mut Vec<UndoEntry>,
+    ) -> Result<(), Error> {
+        let address = match self.outpoint_to_address.get(txn, outpoint)? {
+            Some(address) => address,
+            None => return Ok(()),
+        };
+        let previous = self.address_to_outpoints.get(txn, &address)?;
+        undo.push(UndoEntry::AddressOutPoints {
+            address,
+            previous: previous.clone(),
+        });
+        let mut outpoints = previous.unwrap_or_default();
+        for entry in &mut outpoints {
+            if entry.outpoint == *outpoint {
+                entry.spent_height = Some(height);
+            }
+        }
+        self.address_to_outpoints.put(txn, &address, &outpoints)?;
+        Ok(())
+    }
+
+    // The checks of validate_filled_transaction, for transactions ddk hasn't filled itself, like
+    // orphans whose inputs are outputs of mempool transactions.
+    pub fn validate_transaction(
+        &self,
+        txn: &heed::RoTxn,
+        height: u32,
+        transaction: &ddk::types::FilledTransaction<BitName>,
+    ) -> Result<(), Error> {
+        self.validate_content(txn, height, &transaction.transaction)?;
+        self.validate_active_versions(txn, height, &transaction.transaction)?;
+        let registered = self.registered_among(txn, registered_keys(&transaction.transaction))?;
+        self.validate_keys_unique(txn, &transaction.transaction, &registered)?;
+        self.validate_burn(
+            txn,
+            &transaction.transaction,
+            filled_transaction_fee(transaction),
+            height,
+        )?;
+        Ok(())
+    }
+
+    // Value of an outpoint that is being spent.
+    //
+    // ddk's own utxo set is checked first, since that is the only place deposits are recorded,
+    // then the address index in case ddk has already removed the utxo.
+    fn spent_value(
+        &self,
+        txn: &heed::RoTxn,
+        state: &ddk::state::State<Authorization, BitName>,
+        outpoint: &OutPoint,
+    ) -> Result<u64, Error> {
+        if let Some(output) = state.utxos.get(txn, outpoint)? {
+            return Ok(output.get_value());
+        }
+        let address = match self.outpoint_to_address.get(txn, outpoint)? {
+            Some(address) => address,
+            None => return Ok(0),
+        };
+        let value = self
+            .get_address_history(txn, &address)?
+            .iter()
+            .find(|entry| entry.outpoint == *outpoint)
+            .map(|entry| entry.value)
+            .unwrap_or(0);
+        Ok(value)
+    }
+
+    // Value of the output input spends, for a transaction in a body with the transactions before
+    // it in body_transactions by txid. Their outputs aren't in ddk's utxo set yet when a later
+    // transaction spends them, so they are looked up in the body rather than copied, one entry
+    // per transaction and not per output.
+    fn input_value(
+        &self,
+        txn: &heed::RoTxn,
+        state: &ddk::state::State<Authorization, BitName>,
+        body_transactions: &HashMap<Txid, &Transaction<BitName>>,
+        input: &OutPoint,
+    ) -> Result<u64, Error> {
+        let in_body = match input {
+            OutPoint::Regular { txid, vout } => body_transactions
+                .get(txid)
+                .and_then(|spent| spent.outputs.get(*vout as usize)),
+            _ => None,
+        };
+        match in_body {
+            Some(output) => Ok(output.get_value()),
+            None => self.spent_value(txn, state, input),
+        }
+    }
+
+    // Revert the block at height, which must be the current tip, using its undo data.
+    //
+    // ddk has no reorg path that calls this at this revision, only the startup rollback in
+    // recovery.rs does.
+    pub fn disconnect_body(&self, txn: &mut heed::RwTxn, height: u32) -> Result<(), Error> {
+        let tip = self.get_chain_stats(txn)?.height;
+        if tip != height {
+            return Err(Error::NotTip { height, tip });
+        }
+        log::debug!("disconnecting block at height {height}");
+        // A resolver silently serving a namespace rolled back a long way is worse than one that
+        // stops, so past the limit the disconnect fails and the operator has to decide.
+        let depth = self.metadata.get(txn, ROLLBACK_DEPTH_KEY)?.unwrap_or(0) + 1;
+        if let Some(max) = self.metadata.get(txn, MAX_ROLLBACK_DEPTH_KEY)? {
+            if depth > max {
+                return Err(Error::RollbackTooDeep { height, depth, max });
+            }
+        }
+        self.metadata.put(txn, ROLLBACK_DEPTH_KEY, &depth)?;
+        let height_key = height.to_be_bytes();
+        let undo = self
+            .block_undo
+            .get(txn, &height_key)?
+            .ok_or(Error::MissingUndoData(height))?;
+        // Newest first, the reverse of the order they were registered in.
+        let keys = self
+            .height_to_keys
+            .get(txn, &height_key)?
+            .unwrap_or_default();
+        for key in keys.iter().rev() {
+            if let Some(value) = self.key_to_value.get(txn, key)? {
+                self.append_name_log(txn, NameLogKind::Unregistered, *key, value, height)?;
+            }
+        }
+        // Entries are restored newest first, so an entry touched several times within the block
+        // ends up with the value it had before the block.
+        let generation = self.next_generation(txn)?;
+        for entry in undo.into_iter().rev() {
+            self.apply_undo_entry(txn, generation, entry)?;
+        }
+        self.height_to_body.delete(txn, &height_key)?;
+        self.height_to_stats.delete(txn, &height_key)?;
+        for key in keys {
+            self.key_to_height.delete(txn, &key)?;
+        }
+        self.height_to_keys.delete(txn, &height_key)?;
+        self.height_to_fee_rates.delete(txn, &height_key)?;
+        self.height_to_filter.delete(txn, &height_key)?;
+        self.block_undo.delete(txn, &height_key)?;
+        crate::notify::publish(crate::notify::Event::Disconnected { height });
+        Ok(())
+    }
+
+    fn apply_undo_entry(
+        &self,
+        txn: &mut heed::RwTxn,
+        generation: u32,
+        entry: UndoEntry,
+    ) -> Result<(), Error> {
+        match entry {
+            UndoEntry::KeyValue { key, previous } => {
+                self.set_value(txn, generation, &key, previous.as_ref())?
+            }
+            UndoEntry::AddressOutPoints { address, previous } => match previous {
+                Some(outpoints) => self.address_to_outpoints.put(txn, &address, &outpoints)?,
+                None => {
+                    self.address_to_outpoints.delete(txn, &address)?;
+                }
+            },
+            UndoEntry::OutPointAddress { outpoint, previous } => match previous {
+                Some(address) => self.outpoint_to_address.put(txn, &outpoint, &address)?,
+                None => {
+                    self.outpoint_to_address.delete(txn, &outpoint)?;
+                }
+            },
+            UndoEntry::FeeRates { height, previous } => match previous {
+                Some(rates) => self
+                    .height_to_fee_rates
+                    .put(txn, &height.to_be_bytes(), &rates)?,
+                None => {
+                    self.height_to_fee_rates
+                        .delete(txn, &height.to_be_bytes())?;
+                }
+            },
+            UndoEntry::ChainStats { previous } => match previous {
+                Some(stats) => self.chain_stats.put(txn, CHAIN_STATS_KEY, &stats)?,
+                None => {
+                    self.chain_stats.delete(txn, CHAIN_STATS_KEY)?;
+                }
+            },
+            // The block was connected before total_burned was kept, so the total before it is the
+            // current one less what the block burned.
+            UndoEntry::LegacyChainStats { previous } => match previous {
+                Some(stats) => {
+                    let current = self.get_chain_stats(txn)?;
+                    let burned = self.block_burned(txn, current.height)?;
+                    let stats =
+                        stats.with_total_burned(current.total_burned.saturating_sub(burned));
+                    self.chain_stats.put(txn, CHAIN_STATS_KEY, &stats)?;
+                }
+                None => {
+                    self.chain_stats.delete(txn, CHAIN_STATS_KEY)?;
+                }
+            },
+            UndoEntry::DayRegistrations { day, previous } => match previous {
+                Some(count) => self
+                    .day_to_registrations
+                    .put(txn, &day.to_be_bytes(), &count)?,
+                None => {
+                    self.day_to_registrations.delete(txn, &day.to_be_bytes())?;
+                }
+            },
+            UndoEntry::Metadata { key, previous } => match previous {
+                Some(value) => self.metadata.put(txn, &key, &value)?,
+                None => {
+                    self.metadata.delete(txn, &key)?;
+                }
+            },
+            UndoEntry::Disputes { key, previous } => match previous {
+                Some(disputes) => self.key_to_disputes.put(txn, &key, &disputes)?,
+                None => {
+                    self.key_to_disputes.delete(txn, &key)?;
+                }
+            },
+            UndoEntry::Onion { key, previous } => match previous {
+                Some(binding) => self.key_to_onion.put(txn, &key, &binding)?,
+                None => {
+                    self.key_to_onion.delete(txn, &key)?;
+                }
+            },
+            UndoEntry::Tlsa { key, previous } => match previous {
+                Some(records) => self.key_to_tlsa.put(txn, &key, &records)?,
+                None => {
+                    self.key_to_tlsa.delete(txn, &key)?;
+                }
+            },
+            UndoEntry::Aliases { key, previous } => match previous {
+                Some(aliases) => self.key_to_aliases.put(txn, &key, &aliases)?,
+                None => {
+                    self.key_to_aliases.delete(txn, &key)?;
+                }
+            },
+            UndoEntry::Identity { key, previous } => match previous {
+                Some(history) => self.key_to_identity.put(txn, &key, &history)?,
+                None => {
+                    self.key_to_identity.delete(txn, &key)?;
+                }
+            },
+            UndoEntry::Attestations { key, previous } => match previous {
+                Some(records) => self.key_to_attestations.put(txn, &key, &records)?,
+                None => {
+                    self.key_to_attestations.delete(txn, &key)?;
+                }
+            },
+            UndoEntry::MainAddresses { key, previous } => match previous {
+                Some(records) => self.key_to_main_addresses.put(txn, &key, &records)?,
+                None => {
+                    self.key_to_main_addresses.delete(txn, &key)?;
+                }
+            },
+            UndoEntry::Records { key, previous } => match previous {
+                Some(records) => self.key_to_records.put(txn, &key, &records)?,
+                None => {
+                    self.key_to_records.delete(txn, &key)?;
+                }
+            },
+            UndoEntry::OffchainSequence { key, previous } => match previous {
+                Some(min_sequence) => {
+                    self.key_to_offchain_sequence
+                        .put(txn, &key, &min_sequence)?
+                }
+                None => {
+                    self.key_to_offchain_sequence.delete(txn, &key)?;
+                }
+            },
+        }
+        Ok(())
+    }
+
+    pub fn get_records(&self, txn: &heed::RoTxn, key: &Hash) -> Result<Vec<TypedRecord>, Error> {
+        Ok(self.key_to_records.get(txn, key)?.unwrap_or_default())
+    }
+
+    // The name key's first redirect record points at, if it has one.
+    pub fn get_redirect(&self, txn: &heed::RoTxn, key: &Hash) -> Result<Option<Hash>, Error> {
+        Ok(self
+            .get_records(txn, key)?
+            .into_iter()
+            .find_map(|record| match record.record {
+                Record::Redirect { target } => Some(target),
+                _ => None,
+            }))
+    }
+
+    // The name key's first off-chain data pointer, if it has one, with its current minimum
+    // sequence.
+    pub fn get_offchain_pointer(
+        &self,
+        txn: &heed::RoTxn,
+        key: &Hash,
+    ) -> Result<Option<OffchainPointer>, Error> {
+        let pointer =
+            self.get_records(txn, key)?
+                .into_iter()
+                .find_map(|record| match record.record {
+                    Record::Offchain(pointer) => Some(pointer),
+                    _ => None,
+                });
+        let mut pointer = match pointer {
+            Some(pointer) => pointer,
+            None => return Ok(None),
+        };
+        if let Some(min_sequence) = self.key_to_offchain_sequence.get(txn, key)? {
+            pointer.min_sequence = pointer.min_sequence.max(min_sequence);
+        }
+        Ok(Some(pointer))
+    }
+
+    // Follow redirects from key to the name they end at, see records.rs. Returns every key on the
+    // way, key first and the one without a redirect last, or an error if the redirects loop or
+    // take more than MAX_REDIRECT_HOPS hops.
+    pub fn follow_redirects(&self, txn: &heed::RoTxn, key: &Hash) -> Result<Vec<Hash>, Error> {
+        let mut chain = vec![*key];
+        while let Some(target) = self.get_redirect(txn, chain.last().unwrap())? {
+            if chain.contains(&target) {
+                return Err(Error::RedirectLoop { key: *key, target });
+            }
+            if chain.len() > MAX_REDIRECT_HOPS {
+                return Err(Error::TooManyRedirects {
+                    key: *key,
+                    max: MAX_REDIRECT_HOPS,
+                });
+            }
+            chain.push(target);
+        }
+        Ok(chain)
+    }
+
+    // What key resolves to: the value of the name its redirects end at. Unlike get_value, which
+    // is just the value registered under key.
+    pub fn resolve_value(&self, txn: &heed::RoTxn, key: &Hash) -> Result<Option<Hash>, Error> {
+        let chain = self.follow_redirects(txn, key)?;
+        self.get_value(txn, chain.last().unwrap())
+    }
+
+    pub fn get_main_addresses(
+        &self,
+        txn: &heed::RoTxn,
+        key: &Hash,
+    ) -> Result<Vec<MainAddressRecord>, Error> {
+        Ok(self
+            .key_to_main_addresses
+            .get(txn, key)?
+            .unwrap_or_default())
+    }
+
+    pub fn get_attestations(
+        &self,
+        txn: &heed::RoTxn,
+        key: &Hash,
+    ) -> Result<Vec<AttestationRecord>, Error> {
+        Ok(self.key_to_attestations.get(txn, key)?.unwrap_or_default())
+    }
+
+    // Identity keys of key, oldest first, the last one is current.
+    pub fn get_identity_keys(
+        &self,
+        txn: &heed::RoTxn,
+        key: &Hash,
+    ) -> Result<Vec<IdentityKey>, Error> {
+        Ok(self.key_to_identity.get(txn, key)?.unwrap_or_default())
+    }
+
+    pub fn get_onion_binding(
+        &self,
+        txn: &heed::RoTxn,
+        key: &Hash,
+    ) -> Result<Option<OnionBinding>, Error> {
+        Ok(self.key_to_onion.get(txn, key)?)
+    }
+
+    pub fn get_tlsa_records(
+        &self,
+        txn: &heed::RoTxn,
+        key: &Hash,
+    ) -> Result<Vec<TlsaRecord>, Error> {
+        Ok(self.key_to_tlsa.get(txn, key)?.unwrap_or_default())
+    }
+
+    pub fn get_aliases(
+        &self,
+        txn: &heed::RoTxn,
+        key: &Hash,
+    ) -> Result<BTreeMap<String, Address>, Error> {
+        Ok(self.key_to_aliases.get(txn, key)?.unwrap_or_default())
+    }
+
+    // Disputes filed against key, oldest first.
+    pub fn get_disputes(&self, txn: &heed::RoTxn, key: &Hash) -> Result<Vec<Dispute>, Error> {
+        Ok(self.key_to_disputes.get(txn, key)?.unwrap_or_default())
+    }
+
+    // Block body at a given height, with all of its transactions decoded.
+    pub fn get_block(
+        &self,
+        txn: &heed::RoTxn,
+        height: u32,
+    ) -> Result<Option<Body<Authorization, BitName>>, Error> {
+        Ok(self.height_to_body.get(txn, &height.to_be_bytes())?)
+    }
+
+    // Compact filter for the block at height, None for blocks connected before filters existed
+    // whose bodies were pruned, or that came from a snapshot.
+    pub fn get_block_filter(
+        &self,
+        txn: &heed::RoTxn,
+        height: u32,
+    ) -> Result<Option<Vec<u8>>, Error> {
+        Ok(self.height_to_filter.get(txn, &height.to_be_bytes())?)
+    }
+
+    // Filter items of a block are the keys it registers and the bincode encoded addresses of every
+    // output it creates or spends, keyed by the hash of the body merkle root. Spent addresses come
+    // from the address index, which never forgets an outpoint.
+    fn build_filter(
+        &self,
+        txn: &heed::RoTxn,
+        body: &Body<Authorization, BitName>,
+    ) -> Result<Vec<u8>, Error> {
+        let mut items = vec![];
+        for output in &body.coinbase {
+            items.push(bincode::serialize(&output.address)?);
+        }
+        for transaction in &body.transactions {
+            for input in &transaction.inputs {
+                if let Some(address) = self.outpoint_to_address.get(txn, input)? {
+                    items.push(bincode::serialize(&address)?);
+                }
+            }
+            for output in &transaction.outputs {
+                items.push(bincode::serialize(&output.address)?);
+            }
+            for key in registered_keys(transaction) {
+                items.push(key.to_vec());
+            }
+        }
+        let key = ddk::types::hash(&body.compute_merkle_root());
+        Ok(crate::filters::build(&key, &items))
+    }
+
+    pub fn get_block_stats(
         &self,
-        // txn is a heed db transaction that lets us do db operations atomically.
-        //
-        // txn is created by ddk and then passed to all of the necessary methods, then if there are
-        // no errors, ddk calls txn.commit()?;
         txn: &heed::RoTxn,
-        // Transaction is a sidechain transaction. It is generic over the custom output type, that
-        // is why we must pass in the BitName type parameter.
-        transaction: &Transaction<BitName>,
+        height: u32,
+    ) -> Result<Option<BlockStats>, Error> {
+        Ok(self.height_to_stats.get(txn, &height.to_be_bytes())?)
+    }
+
+    // The n most recently registered names, newest first.
+    pub fn get_recent_names(&self, txn: &heed::RoTxn, n: usize) -> Result<Vec<RecentName>, Error> {
+        let mut names = vec![];
+        for item in self.height_to_keys.rev_iter(txn)? {
+            let (height, keys) = item?;
+            let height = u32::from_be_bytes(height);
+            for key in keys.into_iter().rev() {
+                if names.len() >= n {
+                    return Ok(names);
+                }
+                // Keys can't be registered twice, so the current value is the registered one.
+                let value = match self.key_to_value.get(txn, &key)? {
+                    Some(value) => value,
+                    None => continue,
+                };
+                names.push(RecentName { key, value, height });
+            }
+        }
+        Ok(names)
+    }
+
+    // Names registered by the blocks above from up to and including to, oldest first, and the
+    // height the result goes up to. That is to, unless the names would be more than limit, then
+    // it stops after the last whole block that fits, but always includes at least one block.
+    //
+    // Names are never updated, transferred or expire, so registrations are the whole difference
+    // between the name sets at the two heights.
+    pub fn get_names_between(
+        &self,
+        txn: &heed::RoTxn,
+        from: u32,
+        to: u32,
+        limit: usize,
+    ) -> Result<(Vec<RecentName>, u32), Error> {
+        let mut names = vec![];
+        let mut reached = from;
+        if from >= to {
+            return Ok((names, reached));
+        }
+        let range = (from + 1).to_be_bytes()..=to.to_be_bytes();
+        for item in self.height_to_keys.range(txn, &range)? {
+            let (height, keys) = item?;
+            let height = u32::from_be_bytes(height);
+            if !names.is_empty() && names.len() + keys.len() > limit {
+                return Ok((names, reached));
+            }
+            for key in keys {
+                if let Some(value) = self.key_to_value.get(txn, &key)? {
+                    names.push(RecentName { key, value, height });
+                }
+            }
+            reached = height;
+        }
+        Ok((names, to))
+    }
+
+    fn append_name_log(
+        &self,
+        txn: &mut heed::RwTxn,
+        kind: NameLogKind,
+        key: Hash,
+        value: Hash,
+        height: u32,
     ) -> Result<(), Error> {
-        for output in &transaction.outputs {
-            match output.content {
-                ddk::types::Content::Custom(BitName::KeyValue { key, .. }) => {
-                    if self.key_to_value.get(txn, &key)?.is_some() {
-                        return Err(Error::KeyAlreadyExists);
+        let sequence = match self.name_log.last(txn)? {
+            Some((sequence, _)) => u64::from_be_bytes(sequence) + 1,
+            None => 1,
+        };
+        let event = NameLogEvent {
+            sequence,
+            kind,
+            key,
+            value,
+            height,
+        };
+        self.name_log.put(txn, &sequence.to_be_bytes(), &event)?;
+        Ok(())
+    }
+
+    // Name log events with a sequence number of from or above, oldest first, at most limit.
+    pub fn get_name_log(
+        &self,
+        txn: &heed::RoTxn,
+        from: u64,
+        limit: usize,
+    ) -> Result<Vec<NameLogEvent>, Error> {
+        let mut events = vec![];
+        for item in self.name_log.range(txn, &(from.to_be_bytes()..))? {
+            if events.len() >= limit {
+                break;
+            }
+            events.push(item?.1);
+        }
+        Ok(events)
+    }
+
+    // Keys registered by the block at height, in the order of its outputs.
+    pub fn get_registered_keys(&self, txn: &heed::RoTxn, height: u32) -> Result<Vec<Hash>, Error> {
+        Ok(self
+            .height_to_keys
+            .get(txn, &height.to_be_bytes())?
+            .unwrap_or_default())
+    }
+
+    // Height of the block that registered key. Keys are never updated after that, so this is also
+    // the height of the last change to its value. Keys imported from a snapshot were registered at
+    // or below the fast sync height, which is returned for them.
+    pub fn get_registration_height(
+        &self,
+        txn: &heed::RoTxn,
+        key: &Hash,
+    ) -> Result<Option<u32>, Error> {
+        if let Some(height) = self.key_to_height.get(txn, key)? {
+            return Ok(Some(height));
+        }
+        if self.key_to_value.get(txn, key)?.is_none() {
+            return Ok(None);
+        }
+        if self.genesis_names.get(txn, key)?.is_some() {
+            return Ok(Some(0));
+        }
+        Ok(Some(self.get_fast_sync_height(txn)?))
+    }
+
+    // Bring the databases up to SCHEMA_VERSION.
+    //
+    // A datadir without a schema version is either brand new, or from before versioning was
+    // introduced (version 0).
+    fn migrate(&self, txn: &mut heed::RwTxn) -> Result<(), Error> {
+        let version = match self.metadata.get(txn, SCHEMA_VERSION_KEY)? {
+            Some(version) => version,
+            None if self.chain_stats.get(txn, CHAIN_STATS_KEY)?.is_none() => SCHEMA_VERSION,
+            None => 0,
+        };
+        if version > SCHEMA_VERSION {
+            return Err(Error::UnsupportedSchemaVersion {
+                version,
+                supported: SCHEMA_VERSION,
+            });
+        }
+        for (from, migration) in MIGRATIONS.iter().enumerate().skip(version as usize) {
+            log::info!(
+                "migrating name state from schema version {from} to {}",
+                from + 1
+            );
+            migration(self, txn)?;
+        }
+        self.metadata
+            .put(txn, SCHEMA_VERSION_KEY, &SCHEMA_VERSION)?;
+        Ok(())
+    }
+
+    // Assign the names of the genesis allocation, unless they already were.
+    //
+    // This only happens once, on a brand new state or right after a fast sync (whose snapshot
+    // already holds the names). On every later start names must be the allocation that was
+    // applied, so a node pointed at the wrong allocation fails loudly instead of diverging.
+    pub fn apply_genesis(&self, txn: &mut heed::RwTxn, names: &[GenesisName]) -> Result<(), Error> {
+        if self.metadata.get(txn, GENESIS_APPLIED_KEY)?.is_some() {
+            let mut applied = vec![];
+            for item in self.genesis_names.iter(txn)? {
+                applied.push(item?.1);
+            }
+            let mut names = names.to_vec();
+            names.sort_by_key(|name| name.key);
+            if applied != names {
+                return Err(Error::GenesisMismatch);
+            }
+            return Ok(());
+        }
+        let fast_synced = self.get_fast_sync_height(txn)? > 0;
+        if !names.is_empty() && !fast_synced {
+            if self.get_tip_height(txn)? != 0 || !self.key_to_value.is_empty(txn)? {
+                return Err(Error::StateNotEmpty);
+            }
+            let generation = self.next_generation(txn)?;
+            for name in names {
+                self.set_value(txn, generation, &name.key, Some(&name.value))?;
+            }
+            let mut chain_stats = self.get_chain_stats(txn)?;
+            chain_stats.registered_names += names.len() as u64;
+            self.chain_stats.put(txn, CHAIN_STATS_KEY, &chain_stats)?;
+        }
+        for name in names {
+            if fast_synced && self.key_to_value.get(txn, &name.key)? != Some(name.value) {
+                return Err(Error::GenesisMismatch);
+            }
+            self.genesis_names.put(txn, &name.key, name)?;
+        }
+        self.metadata.put(txn, GENESIS_APPLIED_KEY, &1)?;
+        Ok(())
+    }
+
+    // Snapshot of the name map at the current tip.
+    pub fn create_snapshot(
+        &self,
+        txn: &heed::RoTxn,
+    ) -> Result<crate::snapshot::StateSnapshot, Error> {
+        let mut key_values = vec![];
+        for item in self.key_to_value.iter(txn)? {
+            key_values.push(item?);
+        }
+        key_values.sort_unstable();
+        Ok(crate::snapshot::StateSnapshot {
+            height: self.get_tip_height(txn)?,
+            key_values,
+        })
+    }
+
+    // Initialize an empty state from a verified snapshot.
+    //
+    // Only the name map is imported: the address index, disputes and explorer data start at the
+    // snapshot height.
+    pub fn import_snapshot(
+        &self,
+        txn: &mut heed::RwTxn,
+        snapshot: &crate::snapshot::StateSnapshot,
+    ) -> Result<(), Error> {
+        if self.get_tip_height(txn)? != 0 || !self.key_to_value.is_empty(txn)? {
+            return Err(Error::StateNotEmpty);
+        }
+        let generation = self.next_generation(txn)?;
+        for (key, value) in &snapshot.key_values {
+            self.set_value(txn, generation, key, Some(value))?;
+        }
+        let chain_stats = ChainStats {
+            height: snapshot.height,
+            registered_names: snapshot.key_values.len() as u64,
+            ..Default::default()
+        };
+        self.chain_stats.put(txn, CHAIN_STATS_KEY, &chain_stats)?;
+        self.metadata
+            .put(txn, FAST_SYNC_HEIGHT_KEY, &snapshot.height)?;
+        Ok(())
+    }
+
+    // Keep bodies and undo data only for the last `depth` blocks from now on, None to keep
+    // everything.
+    pub fn set_prune_depth(&self, txn: &mut heed::RwTxn, depth: Option<u32>) -> Result<(), Error> {
+        match depth {
+            Some(depth) if depth < MIN_PRUNE_DEPTH => Err(Error::PruneDepthTooLow {
+                depth,
+                min: MIN_PRUNE_DEPTH,
+            }),
+            Some(depth) => Ok(self.metadata.put(txn, PRUNE_DEPTH_KEY, &depth)?),
+            None => {
+                self.metadata.delete(txn, PRUNE_DEPTH_KEY)?;
+                Ok(())
+            }
+        }
+    }
+
+    // Refuse to disconnect more than depth blocks in a row, None to allow rollbacks of any depth.
+    pub fn set_max_rollback_depth(
+        &self,
+        txn: &mut heed::RwTxn,
+        depth: Option<u32>,
+    ) -> Result<(), Error> {
+        match depth {
+            Some(depth) => self.metadata.put(txn, MAX_ROLLBACK_DEPTH_KEY, &depth)?,
+            None => {
+                self.metadata.delete(txn, MAX_ROLLBACK_DEPTH_KEY)?;
+            }
+        }
+        Ok(())
+    }
+
+    pub fn get_max_rollback_depth(&self, txn: &heed::RoTxn) -> Result<Option<u32>, Error> {
+        Ok(self.metadata.get(txn, MAX_ROLLBACK_DEPTH_KEY)?)
+    }
+
+    pub fn get_pruned_height(&self, txn: &heed::RoTxn) -> Result<u32, Error> {
+        Ok(self.metadata.get(txn, PRUNED_HEIGHT_KEY)?.unwrap_or(0))
+    }
+
+    // Delete bodies and undo data that fell out of the prune window after connecting height.
+    fn prune(&self, txn: &mut heed::RwTxn, height: u32) -> Result<(), Error> {
+        let depth = match self.metadata.get(txn, PRUNE_DEPTH_KEY)? {
+            Some(depth) => depth,
+            None => return Ok(()),
+        };
+        let new_pruned_height = height.saturating_sub(depth);
+        let pruned_height = self.get_pruned_height(txn)?;
+        for pruned in pruned_height..new_pruned_height {
+            let height_key = pruned.to_be_bytes();
+            self.height_to_body.delete(txn, &height_key)?;
+            self.block_undo.delete(txn, &height_key)?;
+        }
+        if new_pruned_height > pruned_height {
+            self.metadata
+                .put(txn, PRUNED_HEIGHT_KEY, &new_pruned_height)?;
+        }
+        Ok(())
+    }
+
+    // Height of the assume-valid block, if it has been connected with the merkle root given to
+    // set_assume_valid.
+    pub fn get_assume_valid_height(&self, txn: &heed::RoTxn) -> Result<Option<u32>, Error> {
+        Ok(self.metadata.get(txn, ASSUME_VALID_HEIGHT_KEY)?)
+    }
+
+    // Record that the assume-valid block has been connected, if the body at height with
+    // merkle_root is it.
+    fn match_assume_valid(
+        &self,
+        txn: &mut heed::RwTxn,
+        height: u32,
+        merkle_root: Hash,
+        undo: &mut Vec<UndoEntry>,
+    ) -> Result<(), Error> {
+        let assume_valid = *ASSUME_VALID.lock().unwrap();
+        if assume_valid != Some((height, merkle_root)) {
+            return Ok(());
+        }
+        let key = ASSUME_VALID_HEIGHT_KEY.to_string();
+        let previous = self.metadata.get(txn, &key)?;
+        self.metadata.put(txn, &key, &height)?;
+        undo.push(UndoEntry::Metadata { key, previous });
+        Ok(())
+    }
+
+    // Height up to which the state came from a snapshot, 0 if it was synced from genesis.
+    pub fn get_fast_sync_height(&self, txn: &heed::RoTxn) -> Result<u32, Error> {
+        Ok(self.metadata.get(txn, FAST_SYNC_HEIGHT_KEY)?.unwrap_or(0))
+    }
+
+    pub fn get_schema_version(&self, txn: &heed::RoTxn) -> Result<Option<u32>, Error> {
+        Ok(self.metadata.get(txn, SCHEMA_VERSION_KEY)?)
+    }
+
+    // Height of the last block connected to the name state. This is the state's sequence number:
+    // it is written in the same transaction as everything else connect_body does, so it always
+    // matches the rest of the name databases.
+    pub fn get_tip_height(&self, txn: &heed::RoTxn) -> Result<u32, Error> {
+        Ok(self.get_chain_stats(txn)?.height)
+    }
+
+    pub fn get_chain_stats(&self, txn: &heed::RoTxn) -> Result<ChainStats, Error> {
+        Ok(self
+            .chain_stats
+            .get(txn, CHAIN_STATS_KEY)?
+            .unwrap_or_default())
+    }
+
+    // Registrations per day for the last `days` days, most recent day first.
+    //
+    // Returns (day index, registrations) pairs, days without registrations are skipped.
+    pub fn get_registrations_per_day(
+        &self,
+        txn: &heed::RoTxn,
+        days: usize,
+    ) -> Result<Vec<(u32, u64)>, Error> {
+        let mut registrations = vec![];
+        for item in self.day_to_registrations.rev_iter(txn)?.take(days) {
+            let (day, count) = item?;
+            registrations.push((u32::from_be_bytes(day), count));
+        }
+        Ok(registrations)
+    }
+
+    // Estimate the fee rate, in sats per 1000 bytes, needed for a transaction to be confirmed
+    // within target_blocks blocks.
+    //
+    // This is a simple percentile over the fee rates of transactions confirmed in the last
+    // FEE_ESTIMATION_WINDOW blocks: the 90th percentile for the next block, 10 points lower for
+    // every additional block, down to the median. Returns None if no transactions were confirmed
+    // within the window.
+    pub fn estimate_fee(
+        &self,
+        txn: &heed::RoTxn,
+        target_blocks: u32,
+    ) -> Result<Option<u64>, Error> {
+        let mut fee_rates = vec![];
+        for item in self.height_to_fee_rates.iter(txn)? {
+            let (_, rates) = item?;
+            fee_rates.extend(rates);
+        }
+        if fee_rates.is_empty() {
+            return Ok(None);
+        }
+        fee_rates.sort_unstable();
+        let percentile = 90u32
+            .saturating_sub(10 * target_blocks.saturating_sub(1))
+            .max(50);
+        let index = (fee_rates.len() - 1) * percentile as usize / 100;
+        Ok(Some(fee_rates[index]))
+    }
+
+    // Replay every stored block body from the first block to the tip into an in-memory model, and
+    // compare the result against key_to_value, the address index, and the chain stats.
+    //
+    // This only trusts height_to_body, so it catches any divergence in the derived databases
+    // caused by a crash or a bug in connect_body.
+    pub fn verify_state(&self, txn: &heed::RoTxn) -> Result<Vec<Divergence>, Error> {
+        use std::collections::HashMap;
+        if self.get_pruned_height(txn)? > 0 || self.get_fast_sync_height(txn)? > 0 {
+            return Err(Error::CannotReplay);
+        }
+        let mut divergences = vec![];
+        let mut key_to_value: HashMap<Hash, Hash> = HashMap::new();
+        let mut genesis_names = 0;
+        for item in self.genesis_names.iter(txn)? {
+            let (key, name) = item?;
+            key_to_value.insert(key, name.value);
+            genesis_names += 1;
+        }
+        let mut address_to_outpoints: HashMap<Address, Vec<AddressOutPoint>> = HashMap::new();
+        let mut outpoint_to_address: HashMap<OutPoint, Address> = HashMap::new();
+        let mut fund = |height, outpoint, output: &ddk::types::Output<BitName>| {
+            address_to_outpoints
+                .entry(output.address)
+                .or_default()
+                .push(AddressOutPoint {
+                    outpoint,
+                    value: output.get_value(),
+                    funded_height: height,
+                    spent_height: None,
+                });
+            outpoint_to_address.insert(outpoint, output.address);
+        };
+        let tip = self.get_chain_stats(txn)?.height;
+        let mut spends = vec![];
+        for height in 1..=tip {
+            let body = match self.get_block(txn, height)? {
+                Some(body) => body,
+                None => {
+                    divergences.push(Divergence::MissingBody { height });
+                    continue;
+                }
+            };
+            let merkle_root = body.compute_merkle_root();
+            for (vout, output) in body.coinbase.iter().enumerate() {
+                let outpoint = OutPoint::Coinbase {
+                    merkle_root,
+                    vout: vout as u32,
+                };
+                fund(height, outpoint, output);
+            }
+            for transaction in &body.transactions {
+                let txid = transaction.txid();
+                for input in &transaction.inputs {
+                    spends.push((height, *input));
+                }
+                for (vout, output) in transaction.outputs.iter().enumerate() {
+                    let outpoint = OutPoint::Regular {
+                        txid,
+                        vout: vout as u32,
+                    };
+                    fund(height, outpoint, output);
+                    if let ddk::types::Content::Custom(BitName::KeyValue { key, value }) =
+                        output.content
+                    {
+                        key_to_value.insert(key, value);
                     }
                 }
-                _ => continue,
             }
         }
+        // Outputs can't be spent before they exist, so applying all spends after all fundings
+        // gives the same result as interleaving them.
+        for (height, outpoint) in spends {
+            if let Some(address) = outpoint_to_address.get(&outpoint) {
+                for entry in address_to_outpoints.get_mut(address).into_iter().flatten() {
+                    if entry.outpoint == outpoint {
+                        entry.spent_height = Some(height);
+                    }
+                }
+            }
+        }
+
+        for item in self.key_to_value.iter(txn)? {
+            let (key, actual) = item?;
+            let expected = key_to_value.remove(&key);
+            if expected != Some(actual) {
+                divergences.push(Divergence::KeyValue {
+                    key,
+                    expected,
+                    actual: Some(actual),
+                });
+            }
+        }
+        for (key, expected) in key_to_value {
+            divergences.push(Divergence::KeyValue {
+                key,
+                expected: Some(expected),
+                actual: None,
+            });
+        }
+        for item in self.address_to_outpoints.iter(txn)? {
+            let (address, actual) = item?;
+            if address_to_outpoints.remove(&address) != Some(actual) {
+                divergences.push(Divergence::AddressHistory { address });
+            }
+        }
+        for address in address_to_outpoints.into_keys() {
+            divergences.push(Divergence::AddressHistory { address });
+        }
+        let actual = self.get_chain_stats(txn)?.registered_names;
+        let mut expected = genesis_names;
+        for item in self.height_to_keys.iter(txn)? {
+            let (_, keys) = item?;
+            expected += keys.len() as u64;
+        }
+        if expected != actual {
+            divergences.push(Divergence::RegisteredNames { expected, actual });
+        }
+        Ok(divergences)
+    }
+
+    // Check that what connect_body wrote for the block at height agrees between databases, if
+    // enabled with set_check_invariants:
+    // - every key the block registers has its registration's value in key_to_value, no stale
+    //   value in the cache, the registration height, and a Registered event at the end of the
+    //   name log
+    // - every output of the block is in the address index under its address, with its value and
+    //   height, and unspent unless the block spends it
+    // - every input of the block that is in the address index is spent at height
+    // - the chain stats and the undo data are at height
+    //
+    // The address index is checked against the block rather than against ddk's UTXO set, which
+    // ddk updates around connect_body in an order this revision doesn't promise. Names don't
+    // expire, so there is no expiry index to check. Unlike verify_state, only what the block
+    // touched is read, so the check is cheap enough to leave on for a canary node. A violation
+    // fails connect_body, so the block is rolled back and the state stays at the last block that
+    // passed.
+    #[cfg(feature = "check-invariants")]
+    fn check_invariants(
+        &self,
+        txn: &heed::RoTxn,
+        height: u32,
+        body: &Body<Authorization, BitName>,
+        keys: &[Hash],
+    ) -> Result<(), Error> {
+        if !CHECK_INVARIANTS.load(std::sync::atomic::Ordering::Relaxed) {
+            return Ok(());
+        }
+        let violation = |violation: String| Error::InvariantViolated { height, violation };
+        let mut registrations = HashMap::new();
+        let mut outputs = vec![];
+        let merkle_root = body.compute_merkle_root();
+        for (vout, output) in body.coinbase.iter().enumerate() {
+            let outpoint = OutPoint::Coinbase {
+                merkle_root,
+                vout: vout as u32,
+            };
+            outputs.push((outpoint, output));
+        }
+        let mut spent = HashSet::new();
+        for transaction in &body.transactions {
+            spent.extend(transaction.inputs.iter().copied());
+            let txid = transaction.txid();
+            for (vout, output) in transaction.outputs.iter().enumerate() {
+                let outpoint = OutPoint::Regular {
+                    txid,
+                    vout: vout as u32,
+                };
+                outputs.push((outpoint, output));
+                if let ddk::types::Content::Custom(BitName::KeyValue { key, value }) =
+                    output.content
+                {
+                    registrations.insert(key, value);
+                }
+            }
+        }
+
+        // The name log ends with this block's registrations, in the same order as keys.
+        let mut events = vec![];
+        for item in self.name_log.rev_iter(txn)?.take(keys.len()) {
+            let (_, event) = item?;
+            events.push(event);
+        }
+        events.reverse();
+        if events.len() != keys.len() || registrations.len() != keys.len() {
+            return Err(violation(format!(
+                "the block registers {} keys, {} were indexed and the name log has {} events",
+                registrations.len(),
+                keys.len(),
+                events.len()
+            )));
+        }
+        for (key, event) in keys.iter().zip(events) {
+            let expected = registrations.get(key).copied();
+            let actual = self.key_to_value.get(txn, key)?;
+            if actual.is_none() || actual != expected {
+                return Err(violation(format!(
+                    "key_to_value has {:?} for {}, its registration {:?}",
+                    actual.map(hex::encode),
+                    hex::encode(key),
+                    expected.map(hex::encode)
+                )));
+            }
+            if let Some(CachedValue::Value { .. }) =
+                self.value_cache.lock().unwrap().entries.get(key)
+            {
+                return Err(violation(format!(
+                    "the value cache kept a value for {}, which the block registered",
+                    hex::encode(key)
+                )));
+            }
+            let registered_at = self.key_to_height.get(txn, key)?;
+            if registered_at != Some(height) {
+                return Err(violation(format!(
+                    "key_to_height has {registered_at:?} for {}",
+                    hex::encode(key)
+                )));
+            }
+            let logged = event.kind == NameLogKind::Registered
+                && event.key == *key
+                && Some(event.value) == actual
+                && event.height == height;
+            if !logged {
+                return Err(violation(format!(
+                    "name log event {} doesn't record the registration of {}",
+                    event.sequence,
+                    hex::encode(key)
+                )));
+            }
+        }
+
+        for (outpoint, output) in outputs {
+            let address = self.outpoint_to_address.get(txn, &outpoint)?;
+            if address != Some(output.address) {
+                return Err(violation(format!(
+                    "outpoint_to_address has {address:?} for {outpoint:?}, paid to {}",
+                    output.address
+                )));
+            }
+            let expected_spent = spent.contains(&outpoint).then_some(height);
+            let indexed = self
+                .get_address_history(txn, &output.address)?
+                .into_iter()
+                .any(|entry| {
+                    entry.outpoint == outpoint
+                        && entry.value == output.get_value()
+                        && entry.funded_height == height
+                        && entry.spent_height == expected_spent
+                });
+            if !indexed {
+                return Err(violation(format!(
+                    "the history of {} doesn't have {outpoint:?} funded at {height} and spent at \
+                     {expected_spent:?}",
+                    output.address
+                )));
+            }
+        }
+        for outpoint in &spent {
+            let address = match self.outpoint_to_address.get(txn, outpoint)? {
+                Some(address) => address,
+                None => continue,
+            };
+            let marked = self
+                .get_address_history(txn, &address)?
+                .into_iter()
+                .any(|entry| entry.outpoint == *outpoint && entry.spent_height == Some(height));
+            if !marked {
+                return Err(violation(format!(
+                    "the history of {address} doesn't have {outpoint:?} spent at {height}"
+                )));
+            }
+        }
+
+        let tip = self.get_chain_stats(txn)?.height;
+        if tip != height {
+            return Err(violation(format!("the chain stats are at height {tip}")));
+        }
+        if self.block_undo.get(txn, &height.to_be_bytes())?.is_none() {
+            return Err(violation("there is no undo data".to_string()));
+        }
+        Ok(())
+    }
+
+    pub fn get_value(&self, txn: &heed::RoTxn, key: &Hash) -> Result<Option<Hash>, Error> {
+        let generation = self.metadata.get(txn, GENERATION_KEY)?.unwrap_or(0);
+        if let Some(CachedValue::Value {
+            generation: cached,
+            value,
+        }) = self.value_cache.lock().unwrap().entries.get(key)
+        {
+            if generation >= *cached {
+                return Ok(*value);
+            }
+        }
+        let value = self.key_to_value.get(txn, key)?;
+        let mut cache = self.value_cache.lock().unwrap();
+        let fresh = generation >= cache.floor
+            && match cache.entries.get(key) {
+                None => true,
+                Some(CachedValue::Invalidated { generation: since }) => generation >= *since,
+                // Only hit if an older snapshot than the cached one is reading.
+                Some(CachedValue::Value { .. }) => false,
+            };
+        if fresh {
+            if cache.entries.len() >= VALUE_CACHE_CAPACITY {
+                let newest = cache.entries.values().map(CachedValue::generation).max();
+                cache.floor = cache.floor.max(newest.unwrap_or(0));
+                cache.entries.clear();
+            }
+            if generation >= cache.floor {
+                let entry = CachedValue::Value { generation, value };
+                cache.entries.insert(*key, entry);
+            }
+        }
+        Ok(value)
+    }
+
+    fn next_generation(&self, txn: &mut heed::RwTxn) -> Result<u32, Error> {
+        let generation = self.metadata.get(txn, GENERATION_KEY)?.unwrap_or(0) + 1;
+        self.metadata.put(txn, GENERATION_KEY, &generation)?;
+        Ok(generation)
+    }
+
+    // The only way key_to_value is written, so the cache never serves a value that was replaced.
+    fn set_value(
+        &self,
+        txn: &mut heed::RwTxn,
+        generation: u32,
+        key: &Hash,
+        value: Option<&Hash>,
+    ) -> Result<(), Error> {
+        match value {
+            Some(value) => self.key_to_value.put(txn, key, value)?,
+            None => {
+                self.key_to_value.delete(txn, key)?;
+            }
+        }
+        let mut cache = self.value_cache.lock().unwrap();
+        cache
+            .entries
+            .insert(*key, CachedValue::Invalidated { generation });
         Ok(())
     }
+
+    // Full funding and spending history of an address, oldest first.
+    pub fn get_address_history(
+        &self,
+        txn: &heed::RoTxn,
+        address: &Address,
+    ) -> Result<Vec<AddressOutPoint>, Error> {
+        Ok(self
+            .address_to_outpoints
+            .get(txn, address)?
+            .unwrap_or_default())
+    }
+
+    // Outpoints sent to an address that haven't been spent yet.
+    pub fn get_address_utxos(
+        &self,
+        txn: &heed::RoTxn,
+        address: &Address,
+    ) -> Result<Vec<AddressOutPoint>, Error> {
+        let utxos = self
+            .get_address_history(txn, address)?
+            .into_iter()
+            .filter(|entry| entry.spent_height.is_none())
+            .collect();
+        Ok(utxos)
+    }
+
+    // Sum of the values of all unspent outpoints of an address, in sats.
+    pub fn get_address_balance(&self, txn: &heed::RoTxn, address: &Address) -> Result<u64, Error> {
+        let balance = self
+            .get_address_utxos(txn, address)?
+            .iter()
+            .map(|entry| entry.value)
+            .sum();
+        Ok(balance)
+    }
 }
 
 impl State<ddk::authorization::Authorization, BitName> for BitNamesState {
-    const NUM_DBS: u32 = 5;
+    const NUM_DBS: u32 = 24;
     type Error = Error;
 
     // Boilerplate method to create all heed databases.
     fn new(env: &heed::Env) -> Result<Self, Self::Error> {
         let key_to_value = env.create_database(Some("key_to_value"))?;
-        Ok(Self { key_to_value })
+        let address_to_outpoints = env.create_database(Some("address_to_outpoints"))?;
+        let outpoint_to_address = env.create_database(Some("outpoint_to_address"))?;
+        let height_to_body = env.create_database(Some("height_to_body"))?;
+        let height_to_stats = env.create_database(Some("height_to_stats"))?;
+        let height_to_keys = env.create_database(Some("height_to_keys"))?;
+        let key_to_height = env.create_database(Some("key_to_height"))?;
+        let chain_stats = env.create_database(Some("chain_stats"))?;
+        let day_to_registrations = env.create_database(Some("day_to_registrations"))?;
+        let height_to_fee_rates = env.create_database(Some("height_to_fee_rates"))?;
+        let height_to_filter = env.create_database(Some("height_to_filter"))?;
+        let block_undo = env.create_database(Some("block_undo"))?;
+        let genesis_names = env.create_database(Some("genesis_names"))?;
+        let key_to_disputes = env.create_database(Some("key_to_disputes"))?;
+        let key_to_onion = env.create_database(Some("key_to_onion"))?;
+        let key_to_tlsa = env.create_database(Some("key_to_tlsa"))?;
+        let key_to_aliases = env.create_database(Some("key_to_aliases"))?;
+        let key_to_attestations = env.create_database(Some("key_to_attestations"))?;
+        let key_to_main_addresses = env.create_database(Some("key_to_main_addresses"))?;
+        let key_to_records = env.create_database(Some("key_to_records"))?;
+        let key_to_offchain_sequence = env.create_database(Some("key_to_offchain_sequence"))?;
+        let key_to_identity = env.create_database(Some("key_to_identity"))?;
+        let name_log = env.create_database(Some("name_log"))?;
+        let metadata = env.create_database(Some("metadata"))?;
+        let state = Self {
+            key_to_value,
+            address_to_outpoints,
+            outpoint_to_address,
+            height_to_body,
+            height_to_stats,
+            height_to_keys,
+            key_to_height,
+            chain_stats,
+            day_to_registrations,
+            height_to_fee_rates,
+            height_to_filter,
+            block_undo,
+            genesis_names,
+            key_to_disputes,
+            key_to_onion,
+            key_to_tlsa,
+            key_to_aliases,
+            key_to_attestations,
+            key_to_main_addresses,
+            key_to_records,
+            key_to_offchain_sequence,
+            key_to_identity,
+            name_log,
+            metadata,
+            value_cache: Arc::new(Mutex::new(ValueCache::default())),
+            params: crate::chain_params::selected(),
+        };
+        let mut txn = env.write_txn()?;
+        state.migrate(&mut txn)?;
+        txn.commit()?;
+        READERS.lock().unwrap().push(StateReader {
+            env: env.clone(),
+            state: state.clone(),
+        });
+        Ok(state)
     }
 
     // Validate an individual transaction.
     fn validate_filled_transaction(
         &self,
         txn: &heed::RoTxn,
-        _height: u32,
+        height: u32,
         _state: &ddk::state::State<ddk::authorization::Authorization, BitName>,
         // A FilledTransaction includes actual output data for spent utxos:
         //
@@ -116,8 +2502,14 @@ impl State<ddk::authorization::Authorization, BitName> for BitNamesState {
         // see ddk/src/types/types.rs for actual definitions of these types.
         transaction: &ddk::types::FilledTransaction<BitName>,
     ) -> Result<(), Self::Error> {
-        self.validate_keys_unique(txn, &transaction.transaction)?;
-        Ok(())
+        let _timer = metrics::Timer::start(
+            "transaction validation",
+            format!(
+                "a transaction with {} outputs",
+                transaction.transaction.outputs.len()
+            ),
+        );
+        self.validate_transaction(txn, height, transaction)
     }
 
     // Validate a block body. A body is just a block without a header. Headers are handled
@@ -125,12 +2517,95 @@ impl State<ddk::authorization::Authorization, BitName> for BitNamesState {
     fn validate_body(
         &self,
         txn: &heed::RoTxn,
-        _height: u32,
-        _state: &ddk::state::State<ddk::authorization::Authorization, BitName>,
+        height: u32,
+        state: &ddk::state::State<ddk::authorization::Authorization, BitName>,
         body: &ddk::types::Body<ddk::authorization::Authorization, BitName>,
     ) -> Result<(), Self::Error> {
+        let _timer = metrics::Timer::start(
+            "block validation",
+            format!(
+                "block {height} with {} transactions",
+                body.transactions.len()
+            ),
+        );
+        if height <= self.get_fast_sync_height(txn)? {
+            return Ok(());
+        }
+        if let Some(merkle_root) = self.params.checkpoint(height) {
+            let actual: Hash = body.compute_merkle_root().into();
+            if actual != merkle_root {
+                return Err(Error::CheckpointMismatch {
+                    height,
+                    expected: merkle_root,
+                    actual,
+                });
+            }
+        }
+        if let Some(assume_valid_height) = self.get_assume_valid_height(txn)? {
+            if height <= assume_valid_height {
+                return Ok(());
+            }
+        }
+        // Two transactions in the same body registering the same key would each pass
+        // validate_keys_unique on their own, so duplicates within the body are checked here.
+        // The registration limit is checked as keys are added, so the set stays bounded by it
+        // however large the body is.
+        let mut body_keys = HashSet::new();
+        // Transactions earlier in the body, see input_value.
+        let mut body_transactions = HashMap::new();
+        let mut fees = 0;
+        let mut burned = 0;
+        // Looked up for the whole body at once, errors are still reported in body order below.
+        // Keys past the registration limit aren't looked up: a body that has any has either a
+        // duplicate or too many registrations before them, and is rejected for that.
+        let registered = self.registered_among(
+            txn,
+            body.transactions
+                .iter()
+                .flat_map(registered_keys)
+                .take(self.params.max_registrations_per_block + 1)
+                .collect(),
+        )?;
         for transaction in &body.transactions {
-            self.validate_keys_unique(txn, transaction)?;
+            let txid = transaction.txid();
+            self.validate_content(txn, height, transaction)?;
+            self.validate_active_versions(txn, height, transaction)?;
+            self.validate_keys_unique(txn, transaction, &registered)?;
+            let mut value_in = 0;
+            for input in &transaction.inputs {
+                value_in += self.input_value(txn, state, &body_transactions, input)?;
+            }
+            body_transactions.insert(txid, transaction);
+            let value_out: u64 = transaction.outputs.iter().map(|o| o.get_value()).sum();
+            let fee = value_in.saturating_sub(value_out);
+            fees += fee;
+            burned += self.validate_burn(txn, transaction, fee, height)?;
+            for key in registered_keys(transaction) {
+                if !body_keys.insert(key) {
+                    return Err(Error::DuplicateKeyInBlock {
+                        key,
+                        height,
+                        conflicting_txid: txid.into(),
+                    });
+                }
+                if body_keys.len() > self.params.max_registrations_per_block {
+                    return Err(Error::TooManyRegistrations {
+                        height,
+                        registrations: body_keys.len(),
+                        max: self.params.max_registrations_per_block,
+                    });
+                }
+            }
+        }
+        let coinbase: u64 = body.coinbase.iter().map(|o| o.get_value()).sum();
+        let burn_active =
+            self.is_deployment_active(txn, height, crate::deployments::REGISTRATION_BURN)?;
+        if burn_active && coinbase > fees - burned {
+            return Err(Error::CoinbaseClaimsBurn {
+                height,
+                coinbase,
+                max: fees - burned,
+            });
         }
         Ok(())
     }
@@ -143,22 +2618,224 @@ impl State<ddk::authorization::Authorization, BitName> for BitNamesState {
     fn connect_body(
         &self,
         txn: &mut heed::RwTxn,
-        _height: u32,
-        _state: &ddk::state::State<ddk::authorization::Authorization, BitName>,
+        height: u32,
+        state: &ddk::state::State<ddk::authorization::Authorization, BitName>,
         body: &ddk::types::Body<ddk::authorization::Authorization, BitName>,
     ) -> Result<(), Self::Error> {
+        let _timer = metrics::Timer::start(
+            "block connection",
+            format!(
+                "block {height} with {} transactions",
+                body.transactions.len()
+            ),
+        );
+        if height <= self.get_fast_sync_height(txn)? {
+            return Ok(());
+        }
+        log::debug!(
+            "connecting block at height {height} with {} transactions",
+            body.transactions.len()
+        );
+        let mut stats = BlockStats {
+            height,
+            transactions: body.transactions.len() as u32,
+            size: bincode::serialized_size(body)?,
+            ..Default::default()
+        };
+        let mut keys = vec![];
+        let mut fee_rates = vec![];
+        let mut undo = vec![];
+        let mut name_changes = vec![];
+        let generation = self.next_generation(txn)?;
+        let merkle_root = body.compute_merkle_root();
+        for (vout, output) in body.coinbase.iter().enumerate() {
+            let outpoint = OutPoint::Coinbase {
+                merkle_root,
+                vout: vout as u32,
+            };
+            self.index_output(txn, height, outpoint, output, &mut undo)?;
+        }
+        self.count_signals(txn, height, &body.coinbase, &mut undo)?;
+        self.match_assume_valid(txn, height, merkle_root.into(), &mut undo)?;
+        // Transactions earlier in the body, see input_value.
+        let mut body_transactions = HashMap::new();
         for transaction in &body.transactions {
+            let txid = transaction.txid();
+            let mut value_in: u64 = 0;
+            for input in &transaction.inputs {
+                value_in += self.input_value(txn, state, &body_transactions, input)?;
+                self.index_spend(txn, height, input, &mut undo)?;
+            }
+            body_transactions.insert(txid, transaction);
+            let value_out: u64 = transaction.outputs.iter().map(|o| o.get_value()).sum();
+            let fee = value_in.saturating_sub(value_out);
+            stats.fees += fee;
+            let size = bincode::serialized_size(transaction)?.max(1);
+            fee_rates.push(fee * 1000 / size);
+            for (vout, output) in transaction.outputs.iter().enumerate() {
+                let outpoint = OutPoint::Regular {
+                    txid,
+                    vout: vout as u32,
+                };
+                self.index_output(txn, height, outpoint, output, &mut undo)?;
+            }
             for output in &transaction.outputs {
                 match output.content {
                     ddk::types::Content::Custom(BitName::KeyValue { key, value }) => {
                         // In practice this means just updating all of the heed dbs according to
                         // consensus rules.
-                        self.key_to_value.put(txn, &key, &value)?;
+                        let previous = self.key_to_value.get(txn, &key)?;
+                        undo.push(UndoEntry::KeyValue { key, previous });
+                        name_changes.push(crate::notify::NameChange {
+                            key,
+                            previous,
+                            value,
+                            height,
+                        });
+                        self.set_value(txn, generation, &key, Some(&value))?;
+                        keys.push(key);
+                        stats.registrations += 1;
+                    }
+                    ddk::types::Content::Custom(BitName::Versioned {
+                        version: DISPUTE_VERSION,
+                        ref payload,
+                    }) => {
+                        if let Some(record) = DisputeRecord::decode(payload) {
+                            self.index_dispute(txn, height, txid.into(), record, &mut undo)?;
+                        }
+                    }
+                    ddk::types::Content::Custom(BitName::Versioned {
+                        version: ONION_VERSION,
+                        ref payload,
+                    }) => {
+                        if let Some(record) = OnionRecord::decode(payload, transaction) {
+                            self.index_onion(txn, height, txid.into(), record, &mut undo)?;
+                        }
+                    }
+                    ddk::types::Content::Custom(BitName::Versioned {
+                        version: TLSA_VERSION,
+                        ref payload,
+                    }) => {
+                        if let Some(record) = TlsaRecord::decode(payload, transaction) {
+                            self.index_tlsa(txn, record, &mut undo)?;
+                        }
+                    }
+                    ddk::types::Content::Custom(BitName::Versioned {
+                        version: ALIAS_VERSION,
+                        ref payload,
+                    }) => {
+                        if let Some(record) = AliasRecord::decode(payload, transaction) {
+                            self.index_alias(txn, record, &mut undo)?;
+                        }
+                    }
+                    ddk::types::Content::Custom(BitName::Versioned {
+                        version: IDENTITY_VERSION,
+                        ref payload,
+                    }) => {
+                        if let Some(record) = IdentityRecord::decode(payload, transaction) {
+                            self.index_identity(txn, height, txid.into(), record, &mut undo)?;
+                        }
+                    }
+                    ddk::types::Content::Custom(BitName::Versioned {
+                        version: ATTESTATION_VERSION,
+                        ref payload,
+                    }) => {
+                        if let Some(record) = AttestationRecord::decode(payload, transaction) {
+                            self.index_attestation(txn, record, &mut undo)?;
+                        }
+                    }
+                    ddk::types::Content::Custom(BitName::Versioned {
+                        version: MAIN_ADDRESS_VERSION,
+                        ref payload,
+                    }) => {
+                        if let Some(record) = MainAddressRecord::decode(payload, transaction) {
+                            self.index_main_address(txn, record, &mut undo)?;
+                        }
+                    }
+                    ddk::types::Content::Custom(BitName::Versioned {
+                        version: RECORDS_VERSION,
+                        ref payload,
+                    }) => {
+                        if let Some(record) = TypedRecord::decode(payload, transaction) {
+                            self.index_record(txn, record, &mut undo)?;
+                        }
+                    }
+                    ddk::types::Content::Custom(BitName::Versioned {
+                        version: OFFCHAIN_VERSION,
+                        ref payload,
+                    }) => {
+                        if let Some(record) = SequenceRecord::decode(payload) {
+                            self.index_offchain_sequence(txn, record, &mut undo)?;
+                        }
                     }
                     _ => continue,
                 }
             }
         }
+        let height_key = height.to_be_bytes();
+        self.height_to_body.put(txn, &height_key, body)?;
+        self.height_to_stats.put(txn, &height_key, &stats)?;
+        for key in &keys {
+            self.key_to_height.put(txn, key, &height)?;
+            if let Some(value) = self.key_to_value.get(txn, key)? {
+                self.append_name_log(txn, NameLogKind::Registered, *key, value, height)?;
+            }
+        }
+        self.height_to_keys.put(txn, &height_key, &keys)?;
+        self.height_to_fee_rates.put(txn, &height_key, &fee_rates)?;
+        let filter = self.build_filter(txn, body)?;
+        self.height_to_filter.put(txn, &height_key, &filter)?;
+        if let Some(expired) = height.checked_sub(FEE_ESTIMATION_WINDOW) {
+            undo.push(UndoEntry::FeeRates {
+                height: expired,
+                previous: self.height_to_fee_rates.get(txn, &expired.to_be_bytes())?,
+            });
+            self.height_to_fee_rates
+                .delete(txn, &expired.to_be_bytes())?;
+        }
+
+        undo.push(UndoEntry::ChainStats {
+            previous: self.chain_stats.get(txn, CHAIN_STATS_KEY)?,
+        });
+        let mut chain_stats = self.get_chain_stats(txn)?;
+        chain_stats.height = height;
+        chain_stats.blocks += 1;
+        chain_stats.transactions += stats.transactions as u64;
+        chain_stats.registered_names += stats.registrations as u64;
+        chain_stats.total_fees += stats.fees;
+        chain_stats.total_body_size += stats.size;
+        chain_stats.total_burned += self.block_burned(txn, height)?;
+        self.chain_stats.put(txn, CHAIN_STATS_KEY, &chain_stats)?;
+
+        if stats.registrations > 0 {
+            let day = height / self.params.blocks_per_day;
+            let previous = self.day_to_registrations.get(txn, &day.to_be_bytes())?;
+            undo.push(UndoEntry::DayRegistrations { day, previous });
+            let count = previous.unwrap_or(0) + stats.registrations as u64;
+            self.day_to_registrations
+                .put(txn, &day.to_be_bytes(), &count)?;
+        }
+        self.block_undo.put(txn, &height_key, &undo)?;
+        self.prune(txn, height)?;
+        // Any rollback in progress is over.
+        self.metadata.delete(txn, ROLLBACK_DEPTH_KEY)?;
+        #[cfg(feature = "check-invariants")]
+        if let Err(err) = self.check_invariants(txn, height, body, &keys) {
+            log::error!("{err}");
+            return Err(err);
+        }
+
+        crate::notify::publish(crate::notify::Event::Block {
+            height,
+            body: std::sync::Arc::new(body.clone()),
+        });
+        for transaction in &body.transactions {
+            let transaction = std::sync::Arc::new(transaction.clone());
+            crate::notify::publish(crate::notify::Event::Transaction(transaction));
+        }
+        for change in name_changes {
+            crate::notify::publish(crate::notify::Event::Name(change));
+        }
         Ok(())
     }
 }
@@ -168,8 +2845,252 @@ impl State<ddk::authorization::Authorization, BitName> for BitNamesState {
 pub enum Error {
     #[error("heed error")]
     Heed(#[from] heed::Error),
-    #[error("key already exists")]
-    KeyAlreadyExists,
+    #[error("bincode error")]
+    Bincode(#[from] bincode::Error),
+    #[error(
+        "transaction {} registers key {}, which was already registered at height {}",
+        hex::encode(.conflicting_txid),
+        hex::encode(.key),
+        .registered_at_height.map_or("unknown".to_string(), |height| height.to_string())
+    )]
+    KeyAlreadyExists {
+        key: Hash,
+        registered_at_height: Option<u32>,
+        conflicting_txid: Hash,
+    },
+    #[error(
+        "transaction {} in block {height} registers key {}, which an earlier transaction in the block registers",
+        hex::encode(.conflicting_txid),
+        hex::encode(.key)
+    )]
+    DuplicateKeyInBlock {
+        key: Hash,
+        height: u32,
+        conflicting_txid: Hash,
+    },
+    #[error("can't disconnect block {height}, the tip is at {tip}")]
+    NotTip { height: u32, tip: u32 },
+    #[error("database schema version {version} is newer than the supported version {supported}")]
+    UnsupportedSchemaVersion { version: u32, supported: u32 },
+    #[error("snapshot state root is {actual}, expected {expected}")]
+    SnapshotStateRootMismatch { expected: String, actual: String },
+    #[error("snapshot is not signed by the expected key")]
+    InvalidSnapshotSignature,
+    #[error("can't import a snapshot into a state that already has blocks")]
+    StateNotEmpty,
+    #[error("prune depth {depth} is below the minimum of {min} blocks")]
+    PruneDepthTooLow { depth: u32, min: u32 },
+    #[error(
+        "disconnecting block {height} would roll back {depth} blocks, more than the limit of \
+         {max}"
+    )]
+    RollbackTooDeep { height: u32, depth: u32, max: u32 },
+    #[error("state can't be replayed from genesis, it was pruned or fast synced")]
+    CannotReplay,
+    #[error(
+        "block {height} has merkle root {}, the checkpoint requires {}",
+        hex::encode(.actual),
+        hex::encode(.expected)
+    )]
+    CheckpointMismatch {
+        height: u32,
+        expected: Hash,
+        actual: Hash,
+    },
+    #[error(
+        "output {vout} of transaction {} uses content version 0, which only KeyValue can use",
+        hex::encode(.txid)
+    )]
+    ReservedContentVersion { txid: Hash, vout: u32 },
+    #[error(
+        "output {vout} of transaction {} is a version signal, those are only allowed in the coinbase",
+        hex::encode(.txid)
+    )]
+    SignalOutsideCoinbase { txid: Hash, vout: u32 },
+    #[error(
+        "output {vout} of transaction {} is an onion binding without a valid service signature or registration",
+        hex::encode(.txid)
+    )]
+    InvalidOnionRecord { txid: Hash, vout: u32 },
+    #[error(
+        "output {vout} of transaction {} is a mainchain address binding without a valid signature or registration",
+        hex::encode(.txid)
+    )]
+    InvalidMainAddressRecord { txid: Hash, vout: u32 },
+    #[error(
+        "output {vout} of transaction {} is a typed record that doesn't validate or isn't in its registration",
+        hex::encode(.txid)
+    )]
+    InvalidTypedRecord { txid: Hash, vout: u32 },
+    #[error(
+        "output {vout} of transaction {} is a height lock without a valid height",
+        hex::encode(.txid)
+    )]
+    InvalidLockHeight { txid: Hash, vout: u32 },
+    #[error(
+        "output {vout} of transaction {} is worth {value} sats, less than the dust limit of {dust_limit}",
+        hex::encode(.txid)
+    )]
+    DustOutput {
+        txid: Hash,
+        vout: u32,
+        value: u64,
+        dust_limit: u64,
+    },
+    #[error(
+        "output {vout} of transaction {} is a record of {size} bytes, at most {max} are allowed",
+        hex::encode(.txid)
+    )]
+    RecordTooLarge {
+        txid: Hash,
+        vout: u32,
+        size: usize,
+        max: usize,
+    },
+    #[error(
+        "transaction {} attaches {size} bytes of records to key {}, at most {max} are allowed",
+        hex::encode(.txid),
+        hex::encode(.key)
+    )]
+    NameRecordsTooLarge {
+        txid: Hash,
+        key: Hash,
+        size: usize,
+        max: usize,
+    },
+    #[error(
+        "transaction {} has {outputs} BitName outputs, at most {max} are allowed",
+        hex::encode(.txid)
+    )]
+    TooManyCustomOutputs {
+        txid: Hash,
+        outputs: usize,
+        max: usize,
+    },
+    #[error(
+        "transaction {} is locked until height {lock_height}, it can't be in block {height}",
+        hex::encode(.txid)
+    )]
+    TransactionLocked {
+        txid: Hash,
+        lock_height: u32,
+        height: u32,
+    },
+    #[error("the state was initialized from a different genesis allocation")]
+    GenesisMismatch,
+    #[error("no undo data for block {0}")]
+    MissingUndoData(u32),
+    #[error("no state is open under {0}")]
+    StateNotOpen(String),
+    #[error("read pool error: {0}")]
+    ReadPool(String),
+    #[error(
+        "the redirects of {} loop back to {}",
+        hex::encode(.key),
+        hex::encode(.target)
+    )]
+    RedirectLoop { key: Hash, target: Hash },
+    #[error("{} redirects more than {max} times", hex::encode(.key))]
+    TooManyRedirects { key: Hash, max: usize },
+    #[error("simulation error: {0}")]
+    Sim(String),
+    #[error(
+        "block {height} has at least {registrations} registrations, at most {max} are allowed"
+    )]
+    TooManyRegistrations {
+        height: u32,
+        registrations: usize,
+        max: usize,
+    },
+    #[error(
+        "transaction {} pays a fee of {fee} sats, its registrations burn {required}",
+        hex::encode(.txid)
+    )]
+    RegistrationUnderpaid { txid: Hash, fee: u64, required: u64 },
+    #[error("coinbase of block {height} claims {coinbase} sats, at most {max} are allowed")]
+    CoinbaseClaimsBurn {
+        height: u32,
+        coinbase: u64,
+        max: u64,
+    },
+    #[error("state invariant violated after connecting block {height}: {violation}")]
+    InvariantViolated { height: u32, violation: String },
+}
+
+// Machine readable details of an Error, sent as the data of RPC errors so clients don't have to
+// parse messages. Fields that don't apply to an error are None.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ErrorContext {
+    pub key: Option<Hash>,
+    pub txid: Option<Hash>,
+    pub vout: Option<u32>,
+    pub height: Option<u32>,
+    pub registered_at_height: Option<u32>,
+}
+
+impl Error {
+    pub fn context(&self) -> ErrorContext {
+        match self {
+            Error::KeyAlreadyExists {
+                key,
+                registered_at_height,
+                conflicting_txid,
+            } => ErrorContext {
+                key: Some(*key),
+                txid: Some(*conflicting_txid),
+                registered_at_height: *registered_at_height,
+                ..Default::default()
+            },
+            Error::DuplicateKeyInBlock {
+                key,
+                height,
+                conflicting_txid,
+            } => ErrorContext {
+                key: Some(*key),
+                txid: Some(*conflicting_txid),
+                height: Some(*height),
+                ..Default::default()
+            },
+            Error::ReservedContentVersion { txid, vout }
+            | Error::SignalOutsideCoinbase { txid, vout }
+            | Error::InvalidOnionRecord { txid, vout }
+            | Error::InvalidMainAddressRecord { txid, vout }
+            | Error::InvalidTypedRecord { txid, vout }
+            | Error::InvalidLockHeight { txid, vout }
+            | Error::DustOutput { txid, vout, .. }
+            | Error::RecordTooLarge { txid, vout, .. } => ErrorContext {
+                txid: Some(*txid),
+                vout: Some(*vout),
+                ..Default::default()
+            },
+            Error::NameRecordsTooLarge { txid, key, .. } => ErrorContext {
+                key: Some(*key),
+                txid: Some(*txid),
+                ..Default::default()
+            },
+            Error::TransactionLocked { txid, height, .. } => ErrorContext {
+                txid: Some(*txid),
+                height: Some(*height),
+                ..Default::default()
+            },
+            Error::RegistrationUnderpaid { txid, .. }
+            | Error::TooManyCustomOutputs { txid, .. } => ErrorContext {
+                txid: Some(*txid),
+                ..Default::default()
+            },
+            Error::NotTip { height, .. }
+            | Error::CheckpointMismatch { height, .. }
+            | Error::RollbackTooDeep { height, .. }
+            | Error::TooManyRegistrations { height, .. }
+            | Error::CoinbaseClaimsBurn { height, .. }
+            | Error::InvariantViolated { height, .. }
+            | Error::MissingUndoData(height) => ErrorContext {
+                height: Some(*height),
+                ..Default::default()
+            },
+            _ => ErrorContext::default(),
+        }
+    }
 }
 
 // This is just a hack to make the type checker happy.
@@ -0,0 +1,144 @@
+use crate::bitnames::{registered_keys, BitName};
+use ddk::authorization::Authorization;
+use ddk::types::{AuthorizedTransaction, Hash, OutPoint, Txid};
+use std::collections::{HashMap, HashSet};
+
+// A mempool transaction together with the fee it pays the miner, net of any burned registration
+// price, as input to the template builder.
+pub struct TemplateCandidate {
+    pub transaction: AuthorizedTransaction<Authorization, BitName>,
+    pub fee: u64,
+}
+
+impl TemplateCandidate {
+    fn size(&self) -> u64 {
+        bincode::serialized_size(&self.transaction).unwrap_or(u64::MAX)
+    }
+}
+
+// Pick the transactions to include in the next block.
+//
+// A candidate that spends outputs of other candidates can only go in after them, so every
+// candidate is taken as a package together with its ancestors among the candidates, and packages
+// are taken in order of decreasing fee rate of the whole package. A high fee child can pay for a
+// low fee parent that way. Among equal fee rates packages carrying registrations go first.
+// Package fee rates aren't updated as ancestors get selected as part of other packages, like
+// Bitcoin Core's ancestor score this is an approximation.
+//
+// A package is skipped if it:
+//
+// * registers a key that an already selected transaction registers, or registers one twice,
+// * spends an input that an already selected transaction spends, or spends one twice,
+// * would push the block over max_registrations.
+//
+// So the result always passes BitNamesState::validate_body, as long as every candidate passed
+// validate_filled_transaction on its own, with the outputs of its ancestors counted as spendable.
+// Ancestors always come before their descendants in the result.
+pub fn select_transactions(
+    candidates: Vec<TemplateCandidate>,
+    max_registrations: usize,
+) -> Vec<TemplateCandidate> {
+    let index: HashMap<Txid, usize> = candidates
+        .iter()
+        .enumerate()
+        .map(|(i, candidate)| (candidate.transaction.transaction.txid(), i))
+        .collect();
+    let ancestors: Vec<Vec<usize>> = (0..candidates.len())
+        .map(|i| ancestors(&candidates, &index, i))
+        .collect();
+    let mut order: Vec<usize> = (0..candidates.len()).collect();
+    order.sort_by_cached_key(|&i| {
+        let (fee, size) = ancestors[i]
+            .iter()
+            .chain([&i])
+            .fold((0u64, 0u64), |(fee, size), &j| {
+                (
+                    fee + candidates[j].fee,
+                    size.saturating_add(candidates[j].size()),
+                )
+            });
+        let fee_rate = fee * 1000 / size.max(1);
+        let registrations = registered_keys(&candidates[i].transaction.transaction).len();
+        std::cmp::Reverse((fee_rate, registrations))
+    });
+    let mut selected = vec![false; candidates.len()];
+    let mut included = vec![];
+    let mut keys: HashSet<Hash> = HashSet::new();
+    let mut spent: HashSet<OutPoint> = HashSet::new();
+    'packages: for i in order {
+        if selected[i] {
+            continue;
+        }
+        let package: Vec<usize> = ancestors[i]
+            .iter()
+            .copied()
+            .filter(|&j| !selected[j])
+            .chain([i])
+            .collect();
+        let mut package_keys = HashSet::new();
+        let mut package_spent = HashSet::new();
+        for &j in &package {
+            let transaction = &candidates[j].transaction.transaction;
+            for key in registered_keys(transaction) {
+                if keys.contains(&key) || !package_keys.insert(key) {
+                    continue 'packages;
+                }
+            }
+            for input in &transaction.inputs {
+                if spent.contains(input) || !package_spent.insert(input.clone()) {
+                    continue 'packages;
+                }
+            }
+        }
+        if keys.len() + package_keys.len() > max_registrations {
+            continue;
+        }
+        keys.extend(package_keys);
+        spent.extend(package_spent);
+        for j in package {
+            selected[j] = true;
+            included.push(j);
+        }
+    }
+    let mut candidates: Vec<Option<TemplateCandidate>> = candidates.into_iter().map(Some).collect();
+    included
+        .into_iter()
+        .map(|i| {
+            candidates[i]
+                .take()
+                .expect("every candidate is included once")
+        })
+        .collect()
+}
+
+// Candidates whose outputs candidate i spends, directly or through other candidates, parents
+// before children.
+fn ancestors(
+    candidates: &[TemplateCandidate],
+    index: &HashMap<Txid, usize>,
+    i: usize,
+) -> Vec<usize> {
+    fn visit(
+        candidates: &[TemplateCandidate],
+        index: &HashMap<Txid, usize>,
+        i: usize,
+        visited: &mut HashSet<usize>,
+        ancestors: &mut Vec<usize>,
+    ) {
+        for input in &candidates[i].transaction.transaction.inputs {
+            let parent = match input {
+                OutPoint::Regular { txid, .. } => index.get(txid),
+                _ => None,
+            };
+            if let Some(&parent) = parent {
+                if visited.insert(parent) {
+                    visit(candidates, index, parent, visited, ancestors);
+                    ancestors.push(parent);
+                }
+            }
+        }
+    }
+    let mut ancestors = vec![];
+    visit(candidates, index, i, &mut HashSet::new(), &mut ancestors);
+    ancestors
+}
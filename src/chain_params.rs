@@ -0,0 +1,200 @@
+use crate::deployments::Deployment;
+use ddk::types::Hash;
+use std::sync::Mutex;
+
+// Consensus parameters of a network. Every node of a network must use the same ones.
+pub struct ChainParams {
+    // Blocks are BMMed once per mainchain block, so a day is roughly 144 sidechain blocks. Used
+    // wherever a rule or statistic is about a period of time rather than a number of blocks.
+    pub blocks_per_day: u32,
+    // Maximum number of key value pairs a single block can register, to bound the growth of the
+    // key_to_value database.
+    pub max_registrations_per_block: usize,
+    // Value outputs worth less than dust_limit sats are rejected, since keeping them in the utxo
+    // set costs every node more than they are worth. Wallets don't create change below it either,
+    // see coin_selection::DUST.
+    pub dust_limit: u64,
+    // Maximum number of BitName outputs, registrations and records, in a single transaction.
+    pub max_custom_outputs: usize,
+    // Once the record sizes deployment is active, maximum payload size in bytes of a single
+    // BitName::Versioned record, and of all records a transaction attaches to one key together,
+    // so names can't be used to store arbitrary data on every node.
+    pub max_record_size: usize,
+    pub max_name_records_size: usize,
+    // Deployment states only change at multiples of deployment_window, and a deployment locks in
+    // once deployment_threshold blocks of a window signal for it, see deployments.rs.
+    pub deployment_window: u32,
+    pub deployment_threshold: u32,
+    pub deployments: &'static [Deployment],
+    // Known good (height, body merkle root) pairs. A body at a checkpoint height with any other
+    // merkle root is rejected, so a long range fork can't rewrite history below the last
+    // checkpoint.
+    //
+    // ddk doesn't hand block hashes to BitNamesState, so checkpoints pin the body merkle root
+    // instead, which commits to every transaction in the block just the same.
+    pub checkpoints: &'static [(u32, &'static str)],
+    // Names assigned at genesis, as JSON, see genesis::parse.
+    pub genesis_allocation: &'static str,
+    // Once the registration burn deployment is active, every registration burns
+    // registration_price(height) sats on top of its fee: the transaction has to pay it as fee, and
+    // the coinbase can't claim it. The price starts at initial_registration_price, halves every
+    // price_halving_interval blocks, and never drops below min_registration_price, so names are
+    // expensive at launch when squatting pays best and get cheaper as the namespace settles.
+    pub initial_registration_price: u64,
+    pub price_halving_interval: u32,
+    pub min_registration_price: u64,
+}
+
+// Roughly two years.
+const DEPLOYMENT_TIMEOUT: u32 = 2 * 365 * 144;
+
+const REGISTRATION_BURN_DEPLOYMENT: Deployment = Deployment {
+    name: crate::deployments::REGISTRATION_BURN,
+    version: None,
+    bit: 0,
+    start_height: 0,
+    timeout_height: DEPLOYMENT_TIMEOUT,
+    active_height: None,
+};
+
+const ONION_DEPLOYMENT: Deployment = Deployment {
+    name: "onion",
+    version: Some(crate::onion::ONION_VERSION),
+    bit: 1,
+    ..REGISTRATION_BURN_DEPLOYMENT
+};
+
+const LOCK_HEIGHT_DEPLOYMENT: Deployment = Deployment {
+    name: "lock_height",
+    version: Some(crate::lock_height::LOCK_HEIGHT_VERSION),
+    bit: 2,
+    ..REGISTRATION_BURN_DEPLOYMENT
+};
+
+const CONTENT_LIMITS_DEPLOYMENT: Deployment = Deployment {
+    name: crate::deployments::CONTENT_LIMITS,
+    version: None,
+    bit: 3,
+    ..REGISTRATION_BURN_DEPLOYMENT
+};
+
+const RECORD_SIZES_DEPLOYMENT: Deployment = Deployment {
+    name: crate::deployments::RECORD_SIZES,
+    version: None,
+    bit: 4,
+    ..REGISTRATION_BURN_DEPLOYMENT
+};
+
+const MAIN_ADDRESS_DEPLOYMENT: Deployment = Deployment {
+    name: "main_address",
+    version: Some(crate::main_address::MAIN_ADDRESS_VERSION),
+    bit: 5,
+    ..REGISTRATION_BURN_DEPLOYMENT
+};
+
+const RECORDS_DEPLOYMENT: Deployment = Deployment {
+    name: "records",
+    version: Some(crate::records::RECORDS_VERSION),
+    bit: 6,
+    ..REGISTRATION_BURN_DEPLOYMENT
+};
+
+pub const MAINNET: ChainParams = ChainParams {
+    blocks_per_day: 144,
+    max_registrations_per_block: 1000,
+    dust_limit: crate::coin_selection::DUST,
+    // A block's worth of registrations with one record each still fits in one batch.
+    max_custom_outputs: 2000,
+    max_record_size: 1024,
+    max_name_records_size: 4096,
+    deployment_window: 14 * 144,
+    deployment_threshold: 14 * 144 * 9 / 10,
+    deployments: &[
+        REGISTRATION_BURN_DEPLOYMENT,
+        ONION_DEPLOYMENT,
+        LOCK_HEIGHT_DEPLOYMENT,
+        CONTENT_LIMITS_DEPLOYMENT,
+        RECORD_SIZES_DEPLOYMENT,
+        MAIN_ADDRESS_DEPLOYMENT,
+        RECORDS_DEPLOYMENT,
+    ],
+    checkpoints: &[],
+    genesis_allocation: "[]",
+    initial_registration_price: 1_000_000,
+    // Roughly four years.
+    price_halving_interval: 4 * 365 * 144,
+    min_registration_price: 1000,
+};
+
+pub const TESTNET: ChainParams = MAINNET;
+
+pub const SIGNET: ChainParams = MAINNET;
+
+// Short deployment windows and price halvings, so activation and pricing can be tested by
+// generating blocks. Regtest chains start from scratch, so the rules for every transaction are
+// active from genesis rather than signalled for.
+pub const REGTEST: ChainParams = ChainParams {
+    deployment_window: 144,
+    deployment_threshold: 108,
+    deployments: &[
+        Deployment {
+            active_height: Some(0),
+            ..REGISTRATION_BURN_DEPLOYMENT
+        },
+        ONION_DEPLOYMENT,
+        LOCK_HEIGHT_DEPLOYMENT,
+        Deployment {
+            active_height: Some(0),
+            ..CONTENT_LIMITS_DEPLOYMENT
+        },
+        Deployment {
+            active_height: Some(0),
+            ..RECORD_SIZES_DEPLOYMENT
+        },
+        MAIN_ADDRESS_DEPLOYMENT,
+        RECORDS_DEPLOYMENT,
+    ],
+    initial_registration_price: 10_000,
+    price_halving_interval: 144,
+    min_registration_price: 0,
+    ..MAINNET
+};
+
+// ddk constructs BitNamesState itself and only hands it the heed env, so the parameters of the
+// network the node runs on are selected here once at startup, before the node is created.
+static SELECTED: Mutex<Option<&'static ChainParams>> = Mutex::new(None);
+
+pub fn select(params: &'static ChainParams) {
+    *SELECTED.lock().unwrap() = Some(params);
+}
+
+pub fn selected() -> &'static ChainParams {
+    SELECTED
+        .lock()
+        .unwrap()
+        .expect("chain params are selected at startup")
+}
+
+impl ChainParams {
+    // Merkle root of the checkpointed block at height, if there is one.
+    pub fn checkpoint(&self, height: u32) -> Option<Hash> {
+        self.checkpoints
+            .iter()
+            .find(|(checkpoint_height, _)| *checkpoint_height == height)
+            .map(|(_, merkle_root)| {
+                let bytes = hex::decode(merkle_root).expect("checkpoints are valid hex");
+                bytes.try_into().expect("checkpoints are 32 bytes")
+            })
+    }
+
+    // Sats burned by every registration in the block at height, once the registration burn is
+    // active, see BitNamesState::registration_price_at.
+    pub fn registration_price(&self, height: u32) -> u64 {
+        let halvings = height / self.price_halving_interval;
+        let price = self
+            .initial_registration_price
+            .checked_shr(halvings)
+            .unwrap_or(0);
+        price.max(self.min_registration_price)
+    }
+}
@@ -0,0 +1,78 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, RwLock};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+// Where the library gets the time, so tests and simulations can fast-forward it.
+//
+// No consensus rule reads the clock. Every rule that depends on where the chain is, like
+// registration prices, height locks and which deployments are active, is evaluated at the height
+// of the block it applies to, which callers pass in explicitly, see BitNamesState::validate_body
+// and get_deployment_state_at. The clock is only used for node bookkeeping like the age of the tip
+// in status.rs and when peers were last seen.
+//
+// The clock is process wide like the selected chain params, since it is read deep inside types
+// that don't carry any configuration. Tests that install a VirtualClock share it with every other
+// test in the same binary.
+pub trait Clock: Send + Sync {
+    // Seconds since the unix epoch.
+    fn now(&self) -> u64;
+}
+
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> u64 {
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|duration| duration.as_secs())
+            .unwrap_or(0)
+    }
+}
+
+// A clock that only moves when told to.
+#[derive(Debug, Default)]
+pub struct VirtualClock {
+    now: AtomicU64,
+}
+
+impl VirtualClock {
+    pub fn new(start: u64) -> Self {
+        Self {
+            now: AtomicU64::new(start),
+        }
+    }
+
+    pub fn set(&self, now: u64) {
+        self.now.store(now, Ordering::Relaxed);
+    }
+
+    pub fn advance(&self, seconds: u64) {
+        self.now.fetch_add(seconds, Ordering::Relaxed);
+    }
+}
+
+impl Clock for VirtualClock {
+    fn now(&self) -> u64 {
+        self.now.load(Ordering::Relaxed)
+    }
+}
+
+static CLOCK: RwLock<Option<Arc<dyn Clock>>> = RwLock::new(None);
+
+// Use clock instead of the system clock from now on.
+pub fn install(clock: Arc<dyn Clock>) {
+    *CLOCK.write().unwrap() = Some(clock);
+}
+
+// Go back to the system clock.
+pub fn reset() {
+    *CLOCK.write().unwrap() = None;
+}
+
+// Seconds since the unix epoch, by the installed clock.
+pub fn now() -> u64 {
+    match &*CLOCK.read().unwrap() {
+        Some(clock) => clock.now(),
+        None => SystemClock.now(),
+    }
+}
@@ -0,0 +1,153 @@
+use crate::bitnames::BitName;
+use ddk::types::{Content, GetValue, OutPoint, Output};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+// Change below this is added to the fee instead of creating an output that costs more to spend
+// than it is worth.
+pub const DUST: u64 = 546;
+// Upper bound on the subsets branch and bound looks at before falling back to largest first.
+const MAX_TRIES: usize = 100_000;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize, clap::ValueEnum)]
+pub enum CoinSelection {
+    // Biggest coins first, fewest inputs.
+    #[default]
+    LargestFirst,
+    // Search for a set of coins that covers the amount with less than DUST left over, so no
+    // change output is needed, falling back to largest first.
+    BranchAndBound,
+    // The smallest single coin that covers the amount, then branch and bound.
+    AvoidChange,
+}
+
+// How wallet transactions are funded.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct SpendPolicy {
+    pub strategy: CoinSelection,
+    // Outputs carrying a BitName are frozen unless this is set, so a routine payment can't spend
+    // a registration output by accident.
+    pub allow_name_spend: bool,
+}
+
+// Whether output may be used to fund a transaction under policy. Withdrawal outputs are never
+// used, spending one would cancel the withdrawal.
+pub fn is_spendable(output: &Output<BitName>, policy: SpendPolicy) -> bool {
+    match output.content {
+        Content::Value(_) => true,
+        Content::Custom(_) => policy.allow_name_spend,
+        Content::Withdrawal { .. } => false,
+    }
+}
+
+// Pick coins from utxos worth at least amount, returning their total value and the coins.
+//
+// Frozen outputs, see is_spendable, are never picked. Name outputs are worth 0 sats, so even when
+// they are allowed the strategies below only reach for them last.
+pub fn select(
+    utxos: HashMap<OutPoint, Output<BitName>>,
+    amount: u64,
+    policy: SpendPolicy,
+) -> anyhow::Result<(u64, HashMap<OutPoint, Output<BitName>>)> {
+    let mut coins: Vec<(OutPoint, Output<BitName>)> = utxos
+        .into_iter()
+        .filter(|(_, output)| is_spendable(output, policy))
+        .collect();
+    coins.sort_by_key(|(_, output)| std::cmp::Reverse(output.get_value()));
+    let available: u64 = coins.iter().map(|(_, output)| output.get_value()).sum();
+    if available < amount {
+        anyhow::bail!("not enough funds, need {amount} sats but only {available} are spendable");
+    }
+    let selected = match policy.strategy {
+        CoinSelection::LargestFirst => largest_first(&coins, amount),
+        CoinSelection::BranchAndBound => {
+            branch_and_bound(&coins, amount).unwrap_or_else(|| largest_first(&coins, amount))
+        }
+        CoinSelection::AvoidChange => coins
+            .iter()
+            .enumerate()
+            .rev()
+            .find(|(_, (_, output))| output.get_value() >= amount)
+            .map(|(index, _)| vec![index])
+            .filter(|selected| value(&coins, selected) - amount < DUST)
+            .or_else(|| branch_and_bound(&coins, amount))
+            .unwrap_or_else(|| largest_first(&coins, amount)),
+    };
+    let total = value(&coins, &selected);
+    let mut coins: Vec<Option<(OutPoint, Output<BitName>)>> = coins.into_iter().map(Some).collect();
+    let selected = selected
+        .into_iter()
+        .filter_map(|index| coins[index].take())
+        .collect();
+    Ok((total, selected))
+}
+
+fn value(coins: &[(OutPoint, Output<BitName>)], selected: &[usize]) -> u64 {
+    selected
+        .iter()
+        .map(|index| coins[*index].1.get_value())
+        .sum()
+}
+
+// Indexes into coins, which are sorted by decreasing value.
+fn largest_first(coins: &[(OutPoint, Output<BitName>)], amount: u64) -> Vec<usize> {
+    let mut selected = vec![];
+    let mut total = 0;
+    for (index, (_, output)) in coins.iter().enumerate() {
+        if total >= amount {
+            break;
+        }
+        total += output.get_value();
+        selected.push(index);
+    }
+    selected
+}
+
+// Depth first search over include/exclude decisions, largest coins first, for a subset worth
+// between amount and amount + DUST.
+fn branch_and_bound(coins: &[(OutPoint, Output<BitName>)], amount: u64) -> Option<Vec<usize>> {
+    let values: Vec<u64> = coins.iter().map(|(_, output)| output.get_value()).collect();
+    // remaining[i] is the total value of coins i.. , to prune branches that can't reach amount.
+    let mut remaining = vec![0; values.len() + 1];
+    for i in (0..values.len()).rev() {
+        remaining[i] = remaining[i + 1] + values[i];
+    }
+    let mut tries = 0;
+    let mut selected = vec![];
+    fn search(
+        values: &[u64],
+        remaining: &[u64],
+        index: usize,
+        total: u64,
+        amount: u64,
+        selected: &mut Vec<usize>,
+        tries: &mut usize,
+    ) -> bool {
+        *tries += 1;
+        if total >= amount {
+            return total - amount < DUST;
+        }
+        if index == values.len() || total + remaining[index] < amount || *tries > MAX_TRIES {
+            return false;
+        }
+        selected.push(index);
+        if search(
+            values,
+            remaining,
+            index + 1,
+            total + values[index],
+            amount,
+            selected,
+            tries,
+        ) {
+            return true;
+        }
+        selected.pop();
+        search(values, remaining, index + 1, total, amount, selected, tries)
+    }
+    if search(&values, &remaining, 0, 0, amount, &mut selected, &mut tries) {
+        Some(selected)
+    } else {
+        None
+    }
+}
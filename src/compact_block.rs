@@ -0,0 +1,130 @@
+use crate::bitnames::BitName;
+use ddk::authorization::Authorization;
+use ddk::types::{AuthorizedTransaction, Body, Hash, Header, Output};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+type Transaction = AuthorizedTransaction<Authorization, BitName>;
+
+// Short transaction ids are the first 6 bytes of hash(salt, txid), with the salt derived from the
+// header so that a peer can't precompute colliding transactions for future blocks.
+pub type ShortId = [u8; 6];
+
+// A block as announced to a peer that is likely to have most of its transactions in its mempool
+// already. Only the header, the coinbase and short ids are sent, plus any transactions the sender
+// expects the peer not to have.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct CompactBlock {
+    pub header: Header,
+    pub coinbase: Vec<Output<BitName>>,
+    pub short_ids: Vec<ShortId>,
+    // Transactions sent in full, with their index in the block.
+    pub prefilled: Vec<(u32, Transaction)>,
+}
+
+// Result of trying to rebuild a compact block from the mempool.
+pub enum Reconstruction {
+    Complete(Body<Authorization, BitName>),
+    // Indexes of the transactions that have to be requested from the peer.
+    Missing(Vec<u32>),
+}
+
+impl CompactBlock {
+    pub fn new(header: Header, body: &Body<Authorization, BitName>, prefill: &[u32]) -> Self {
+        let salt = salt(&header);
+        let mut short_ids = vec![];
+        let mut prefilled = vec![];
+        for (index, transaction) in body.transactions.iter().enumerate() {
+            let index = index as u32;
+            if prefill.contains(&index) {
+                let authorized = AuthorizedTransaction {
+                    transaction: transaction.clone(),
+                    authorizations: authorizations(body, index as usize),
+                };
+                prefilled.push((index, authorized));
+            } else {
+                short_ids.push(short_id(&salt, transaction));
+            }
+        }
+        Self {
+            header,
+            coinbase: body.coinbase.clone(),
+            short_ids,
+            prefilled,
+        }
+    }
+
+    // Rebuild the full body from mempool transactions, plus missing ones fetched from the peer.
+    //
+    // A short id collision inside the mempool is treated as missing, so the worst case is an extra
+    // round trip. The rebuilt body is checked against the header merkle root.
+    pub fn reconstruct(
+        &self,
+        mempool: &[Transaction],
+        fetched: &[(u32, Transaction)],
+    ) -> Reconstruction {
+        let salt = salt(&self.header);
+        let mut by_short_id: HashMap<ShortId, Option<&Transaction>> = HashMap::new();
+        for transaction in mempool {
+            by_short_id
+                .entry(short_id(&salt, &transaction.transaction))
+                .and_modify(|entry| *entry = None)
+                .or_insert(Some(transaction));
+        }
+        let total = self.short_ids.len() + self.prefilled.len();
+        let mut slots: Vec<Option<&Transaction>> = vec![None; total];
+        for (index, transaction) in &self.prefilled {
+            if let Some(slot) = slots.get_mut(*index as usize) {
+                *slot = Some(transaction);
+            }
+        }
+        // Short ids are in block order over the positions that weren't prefilled.
+        let short_id_indexes: Vec<usize> =
+            (0..total).filter(|index| slots[*index].is_none()).collect();
+        for (index, short_id) in short_id_indexes.iter().zip(&self.short_ids) {
+            slots[*index] = by_short_id.get(short_id).copied().flatten();
+        }
+        for (index, transaction) in fetched {
+            if let Some(slot) = slots.get_mut(*index as usize) {
+                *slot = Some(transaction);
+            }
+        }
+        let missing: Vec<u32> = slots
+            .iter()
+            .enumerate()
+            .filter(|(_, slot)| slot.is_none())
+            .map(|(index, _)| index as u32)
+            .collect();
+        if !missing.is_empty() {
+            return Reconstruction::Missing(missing);
+        }
+        let transactions = slots.into_iter().flatten().cloned().collect();
+        let body = Body::new(transactions, self.coinbase.clone());
+        if body.compute_merkle_root() != self.header.merkle_root {
+            // Some short id matched the wrong mempool transaction, fall back to fetching all of
+            // them.
+            let all = short_id_indexes.iter().map(|index| *index as u32).collect();
+            return Reconstruction::Missing(all);
+        }
+        Reconstruction::Complete(body)
+    }
+}
+
+fn salt(header: &Header) -> Hash {
+    ddk::types::hash(header)
+}
+
+fn short_id(salt: &Hash, transaction: &ddk::types::Transaction<BitName>) -> ShortId {
+    let hash = ddk::types::hash(&(salt, transaction.txid()));
+    hash[..6].try_into().unwrap()
+}
+
+// Authorizations belonging to the transaction at index, Body stores them flattened in input order.
+fn authorizations(body: &Body<Authorization, BitName>, index: usize) -> Vec<Authorization> {
+    let start: usize = body.transactions[..index]
+        .iter()
+        .map(|transaction| transaction.inputs.len())
+        .sum();
+    let len = body.transactions[index].inputs.len();
+    body.authorizations[start..start + len].to_vec()
+}
@@ -0,0 +1,163 @@
+use crate::status::StatusMonitor;
+use std::net::SocketAddr;
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::net::{UnixListener, UnixStream};
+use tokio::sync::Notify;
+
+// A node run with `bitnames daemon` writes its pid to <datadir>/bitnames.pid and listens on the
+// unix socket <datadir>/control.sock. The process stays in the foreground, so a service manager
+// like systemd can supervise it, detaching is left to the service manager.
+//
+// The control protocol is one request per line, answered with one line of JSON:
+//   status       the pid and the node's sync status
+//   stop         shut the node down cleanly
+//   {...}        a JSON-RPC request, forwarded to the node's RPC server with its credentials
+//
+// Only the owner of the data directory can connect to the socket, so it needs no credentials of
+// its own.
+const PID_FILE: &str = "bitnames.pid";
+const CONTROL_SOCKET: &str = "control.sock";
+// How long `bitnames daemon stop` waits for the node to finish shutting down.
+const STOP_TIMEOUT: Duration = Duration::from_secs(60);
+
+pub struct Control {
+    datadir: PathBuf,
+    status: Arc<StatusMonitor>,
+    rpc_addr: SocketAddr,
+    authorization: String,
+    client: hyper::Client<hyper::client::HttpConnector>,
+    stop: Notify,
+}
+
+impl Control {
+    // Write the pid file and start answering on the control socket. Fails if another daemon is
+    // already answering on it.
+    pub async fn start(
+        datadir: &Path,
+        status: Arc<StatusMonitor>,
+        rpc_addr: SocketAddr,
+        authorization: String,
+    ) -> anyhow::Result<Arc<Self>> {
+        let socket_path = datadir.join(CONTROL_SOCKET);
+        if UnixStream::connect(&socket_path).await.is_ok() {
+            anyhow::bail!("a daemon is already running in {}", datadir.display());
+        }
+        // Left behind by a daemon that didn't shut down cleanly.
+        let _ = std::fs::remove_file(&socket_path);
+        let listener = UnixListener::bind(&socket_path)?;
+        std::fs::set_permissions(&socket_path, std::fs::Permissions::from_mode(0o600))?;
+        std::fs::write(datadir.join(PID_FILE), format!("{}\n", std::process::id()))?;
+        let control = Arc::new(Self {
+            datadir: datadir.to_path_buf(),
+            status,
+            rpc_addr,
+            authorization,
+            client: hyper::Client::new(),
+            stop: Notify::new(),
+        });
+        tokio::spawn(accept(listener, control.clone()));
+        Ok(control)
+    }
+
+    // Resolves once a client asked the daemon to stop.
+    pub async fn stopped(&self) {
+        self.stop.notified().await
+    }
+
+    // Remove the socket and the pid file, once the node has shut down.
+    pub fn remove(&self) -> anyhow::Result<()> {
+        std::fs::remove_file(self.datadir.join(CONTROL_SOCKET))?;
+        std::fs::remove_file(self.datadir.join(PID_FILE))?;
+        Ok(())
+    }
+
+    async fn answer(&self, request: &str) -> anyhow::Result<String> {
+        let response = match request {
+            "status" => serde_json::json!({
+                "pid": std::process::id(),
+                "status": self.status.status()?,
+            })
+            .to_string(),
+            "stop" => {
+                self.stop.notify_one();
+                serde_json::json!({ "stopping": true }).to_string()
+            }
+            _ if request.starts_with('{') || request.starts_with('[') => {
+                self.forward(request).await?
+            }
+            _ => anyhow::bail!("unknown control request {request}"),
+        };
+        Ok(response)
+    }
+
+    async fn forward(&self, request: &str) -> anyhow::Result<String> {
+        let http_request = hyper::Request::post(format!("http://{}/", self.rpc_addr))
+            .header(hyper::header::CONTENT_TYPE, "application/json")
+            .header(hyper::header::AUTHORIZATION, &self.authorization)
+            .body(hyper::Body::from(request.to_string()))?;
+        let response = self.client.request(http_request).await?;
+        let body = hyper::body::to_bytes(response.into_body()).await?;
+        Ok(String::from_utf8(body.to_vec())?.trim_end().to_string())
+    }
+}
+
+async fn accept(listener: UnixListener, control: Arc<Control>) {
+    loop {
+        let stream = match listener.accept().await {
+            Ok((stream, _)) => stream,
+            Err(err) => {
+                log::error!("control socket stopped: {err}");
+                return;
+            }
+        };
+        let control = control.clone();
+        tokio::spawn(async move {
+            if let Err(err) = serve(stream, &control).await {
+                log::warn!("control connection failed: {err}");
+            }
+        });
+    }
+}
+
+async fn serve(stream: UnixStream, control: &Control) -> anyhow::Result<()> {
+    let (read, mut write) = stream.into_split();
+    let mut lines = BufReader::new(read).lines();
+    while let Some(line) = lines.next_line().await? {
+        let response = match control.answer(line.trim()).await {
+            Ok(response) => response,
+            Err(err) => serde_json::json!({ "error": err.to_string() }).to_string(),
+        };
+        write.write_all(format!("{response}\n").as_bytes()).await?;
+    }
+    Ok(())
+}
+
+// Send one request to the daemon running in datadir and return its answer.
+pub async fn send(datadir: &Path, request: &str) -> anyhow::Result<String> {
+    let socket_path = datadir.join(CONTROL_SOCKET);
+    let stream = UnixStream::connect(&socket_path)
+        .await
+        .map_err(|err| anyhow::anyhow!("no daemon is running in {}: {err}", datadir.display()))?;
+    let (read, mut write) = stream.into_split();
+    write.write_all(format!("{request}\n").as_bytes()).await?;
+    let mut response = String::new();
+    BufReader::new(read).read_line(&mut response).await?;
+    Ok(response.trim_end().to_string())
+}
+
+// Wait until the daemon in datadir removed its pid file after being asked to stop.
+pub async fn wait_stopped(datadir: &Path) -> anyhow::Result<()> {
+    let pid_file = datadir.join(PID_FILE);
+    let started = std::time::Instant::now();
+    while pid_file.exists() {
+        if started.elapsed() > STOP_TIMEOUT {
+            anyhow::bail!("daemon is still shutting down after {STOP_TIMEOUT:?}");
+        }
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+    Ok(())
+}
@@ -0,0 +1,170 @@
+use crate::notify::{self, Event};
+use ddk::heed;
+use heed::flags::Flags;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use tokio::sync::broadcast;
+
+// ddk opens its heed env inside Node::new with a small fixed map size, which will eventually make
+// a busy node abort with MDB_MAP_FULL. heed only opens each env path once per process and hands
+// the existing env to anyone opening the same path again, so by opening it here first, with our
+// own options, ddk ends up using the env configured below.
+//
+// This must match the path ddk uses under the datadir.
+const ENV_DIR: &str = "data.mdb";
+
+// ddk and BitNamesState together use well under this many named databases.
+const MAX_DBS: u32 = 64;
+
+// When the data file is bigger than this fraction of the map size at startup, the map size is
+// doubled until it isn't.
+const GROW_THRESHOLD: f64 = 0.8;
+
+// With SyncMode::Auto, the env is synced once no block has been connected for this long. Blocks
+// at the tip are a mainchain block apart, minutes rather than seconds, so there that is shortly
+// after every block, while catching up it is once the node has caught up.
+const CATCH_UP_GAP: Duration = Duration::from_secs(5);
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, clap::ValueEnum)]
+pub enum SyncMode {
+    // Like NoMetaSync, but sync the env once blocks stop arriving for CATCH_UP_GAP, see
+    // run_auto_sync, so at the tip the last block is only at risk for a few seconds.
+    Auto,
+    // fsync data and metadata on every commit.
+    Full,
+    // fsync data but not metadata on every commit. An OS crash or power loss can undo the last
+    // commit, but never corrupts the database.
+    NoMetaSync,
+    // Never fsync. An OS crash or power loss can lose any number of recent commits, and on
+    // filesystems that don't preserve write order can corrupt the database, so the datadir may
+    // have to be synced again from scratch. Only sensible for an initial sync that can be redone.
+    NoSync,
+}
+
+#[derive(Clone, Debug, clap::Args)]
+pub struct EnvConfig {
+    /// LMDB map size in MiB. This only reserves address space, disk is used as data is written.
+    #[arg(long = "db-map-size", default_value_t = 16 * 1024)]
+    pub map_size_mib: usize,
+    /// Maximum number of concurrent LMDB read transactions.
+    #[arg(long = "db-max-readers", default_value_t = 126)]
+    pub max_readers: u32,
+    /// LMDB durability mode. full fsyncs every commit, auto skips the metadata fsync and syncs
+    /// once blocks stop arriving, see SyncMode.
+    #[arg(long = "db-sync", value_enum, default_value_t = SyncMode::Full)]
+    pub sync: SyncMode,
+}
+
+// The command line defaults, for nodes embedded with embed::NodeHandle.
+impl Default for EnvConfig {
+    fn default() -> Self {
+        Self {
+            map_size_mib: 16 * 1024,
+            max_readers: 126,
+            sync: SyncMode::Full,
+        }
+    }
+}
+
+pub fn env_path(datadir: &Path) -> PathBuf {
+    datadir.join(ENV_DIR)
+}
+
+// Open the node's heed env with the configured options. Must be called before Node::new.
+pub fn open(datadir: &Path, config: &EnvConfig) -> anyhow::Result<heed::Env> {
+    let path = env_path(datadir);
+    std::fs::create_dir_all(&path)?;
+    let mut map_size = config.map_size_mib * 1024 * 1024;
+    let data_size = std::fs::metadata(path.join("data.mdb"))
+        .map(|metadata| metadata.len() as usize)
+        .unwrap_or(0);
+    while data_size as f64 > map_size as f64 * GROW_THRESHOLD {
+        map_size *= 2;
+    }
+    if map_size != config.map_size_mib * 1024 * 1024 {
+        log::info!(
+            "database is {} MiB, growing map size to {} MiB",
+            data_size / (1024 * 1024),
+            map_size / (1024 * 1024)
+        );
+    }
+    let mut options = heed::EnvOpenOptions::new();
+    options
+        .map_size(map_size)
+        .max_readers(config.max_readers)
+        .max_dbs(MAX_DBS);
+    // Safety: both flags only relax durability, they can't cause memory unsafety.
+    unsafe {
+        match config.sync {
+            SyncMode::Full => {}
+            SyncMode::NoMetaSync | SyncMode::Auto => {
+                options.flag(Flags::MdbNoMetaSync);
+            }
+            SyncMode::NoSync => {
+                options.flag(Flags::MdbNoSync);
+            }
+        }
+    }
+    Ok(options.open(path)?)
+}
+
+// Sync env as SyncMode::Auto describes, for as long as blocks are announced.
+//
+// Blocks are announced from connect_body, before ddk commits them, so nothing here waits for a
+// particular commit. It doesn't have to: every commit fsyncs its data, which also flushes the
+// metadata of the commit before it, so a sync that happens to come before the last commit only
+// leaves that one commit unflushed, as NoMetaSync would.
+pub async fn run_auto_sync(env: heed::Env) {
+    let mut events = notify::subscribe();
+    // When to sync, CATCH_UP_GAP after the last block, None if everything has been synced.
+    let mut sync_at: Option<tokio::time::Instant> = None;
+    loop {
+        let event = match sync_at {
+            Some(deadline) => match tokio::time::timeout_at(deadline, events.recv()).await {
+                Ok(event) => Some(event),
+                Err(_) => None,
+            },
+            None => Some(events.recv().await),
+        };
+        match event {
+            Some(Ok(Event::Block { .. }))
+            | Some(Ok(Event::Disconnected { .. }))
+            | Some(Err(broadcast::error::RecvError::Lagged(_))) => {
+                sync_at = Some(tokio::time::Instant::now() + CATCH_UP_GAP);
+                continue;
+            }
+            // Blocks stopped arriving, sync the last ones.
+            None => {}
+            Some(Ok(_)) => continue,
+            Some(Err(broadcast::error::RecvError::Closed)) => return,
+        }
+        let syncing = env.clone();
+        match tokio::task::spawn_blocking(move || syncing.force_sync()).await {
+            Ok(Ok(())) => {}
+            Ok(Err(err)) => log::error!("failed to sync the database: {err}"),
+            Err(err) => log::error!("database sync task failed: {err}"),
+        }
+        sync_at = None;
+    }
+}
+
+// Open the heed env of a node running in another process, read-only. LMDB lets any number of
+// processes read an env while one writes it, readers see every commit as soon as it is made and
+// never block the writer. The process still needs write access to the env's lock.mdb, which is
+// where LMDB keeps track of its readers.
+//
+// The map size is taken from the env itself. If the node grows it on a restart, read
+// transactions fail with MDB_MAP_RESIZED until the env is opened again.
+pub fn open_read_only(datadir: &Path) -> anyhow::Result<heed::Env> {
+    let path = env_path(datadir);
+    if !path.join("data.mdb").exists() {
+        anyhow::bail!("no node database in {}", datadir.display());
+    }
+    let mut options = heed::EnvOpenOptions::new();
+    options.max_dbs(MAX_DBS);
+    // Safety: a read-only env never writes to the data file.
+    unsafe {
+        options.flag(Flags::MdbRdOnly);
+    }
+    Ok(options.open(path)?)
+}
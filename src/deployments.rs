@@ -0,0 +1,97 @@
+// Version bits style activation of new BitName content versions, modelled on BIP 9.
+//
+// New operation types are carried in BitName::Versioned outputs. Nodes accept versions they don't
+// know without interpreting them, so rules for a new version can be turned on later as a soft
+// fork: miners signal readiness by setting the deployment's bit in a BitName::Signal coinbase
+// output, and once deployment_threshold blocks of a deployment_window (see ChainParams) signal,
+// the deployment locks in and becomes active one window later. Nodes that implement the version
+// enforce its rules from then on.
+//
+// New rules for every transaction, like the registration burn, are deployed the same way, without
+// a version. A rule that applied from genesis would reject blocks a network has already accepted,
+// so on networks with a history every new rule has to be signalled for. Networks that start out
+// with a rule, like regtest, set active_height instead.
+use crate::chain_params::ChainParams;
+use serde::{Deserialize, Serialize};
+
+// Deployments of rules that don't belong to a content version, by name.
+//
+// Every registration burns ChainParams::registration_price out of its fee, which the coinbase
+// can't claim.
+pub const REGISTRATION_BURN: &str = "registration_burn";
+
+// Value outputs of at least dust_limit, at most max_custom_outputs BitName outputs per
+// transaction, and no Versioned content of version 0.
+pub const CONTENT_LIMITS: &str = "content_limits";
+
+// Records of at most max_record_size bytes, and at most max_name_records_size bytes of records
+// attached to one key by a transaction.
+pub const RECORD_SIZES: &str = "record_sizes";
+
+pub struct Deployment {
+    pub name: &'static str,
+    // Content version whose rules activate with the deployment, None for a deployment of rules
+    // that apply to every transaction.
+    pub version: Option<u8>,
+    // Bit of BitName::Signal bits miners set to signal for the deployment, below 32.
+    pub bit: u8,
+    // Signalling counts from the first window starting at or after start_height.
+    pub start_height: u32,
+    // The deployment fails if it hasn't locked in by the first window starting at or after this.
+    pub timeout_height: u32,
+    // If set, the deployment is active from this height on without any signalling.
+    pub active_height: Option<u32>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum DeploymentState {
+    Defined,
+    Started,
+    LockedIn,
+    Active,
+    Failed,
+}
+
+// Key under which the state's metadata keeps the number of blocks in window that signalled for
+// deployment.
+pub fn signals_key(deployment: &Deployment, window: u32) -> String {
+    format!("signals:{}:{window}", deployment.name)
+}
+
+// State of deployment for the block at height, given the number of signalling blocks in each
+// window.
+pub fn state<E>(
+    params: &ChainParams,
+    deployment: &Deployment,
+    height: u32,
+    mut signals: impl FnMut(u32) -> Result<u32, E>,
+) -> Result<DeploymentState, E> {
+    if let Some(active_height) = deployment.active_height {
+        return Ok(if height >= active_height {
+            DeploymentState::Active
+        } else {
+            DeploymentState::Defined
+        });
+    }
+    let mut state = DeploymentState::Defined;
+    for window in 1..=height / params.deployment_window {
+        let window_start = window * params.deployment_window;
+        state = match state {
+            DeploymentState::Defined if window_start >= deployment.timeout_height => {
+                DeploymentState::Failed
+            }
+            DeploymentState::Defined if window_start >= deployment.start_height => {
+                DeploymentState::Started
+            }
+            DeploymentState::Started if signals(window - 1)? >= params.deployment_threshold => {
+                DeploymentState::LockedIn
+            }
+            DeploymentState::Started if window_start >= deployment.timeout_height => {
+                DeploymentState::Failed
+            }
+            DeploymentState::LockedIn => DeploymentState::Active,
+            state => state,
+        };
+    }
+    Ok(state)
+}
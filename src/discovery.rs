@@ -0,0 +1,61 @@
+use crate::peer_store::PeerStore;
+use crate::Node;
+use std::collections::HashSet;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Duration;
+
+// How often DNS seeds are re-resolved, so that a long running node picks up new peers as the seed
+// operators rotate them.
+pub const DISCOVERY_INTERVAL: Duration = Duration::from_secs(30 * 60);
+
+// Resolve every DNS seed to peer addresses on port. A seed that fails to resolve is skipped
+// rather than failing the whole round.
+pub async fn resolve(seeds: &[String], port: u16) -> Vec<SocketAddr> {
+    let mut addrs = vec![];
+    for seed in seeds {
+        match tokio::net::lookup_host((seed.as_str(), port)).await {
+            Ok(resolved) => addrs.extend(resolved),
+            Err(err) => log::warn!("failed to resolve DNS seed {seed}: {err}"),
+        }
+    }
+    addrs
+}
+
+// Dial bootstrap peers and DNS seed results every DISCOVERY_INTERVAL, forever.
+//
+// Only addresses that weren't successfully dialed before are tried, since ddk gives no way to
+// tell whether an earlier connection is still alive. Outcomes go into the peer store, so
+// discovered peers are also tried directly on the next startup.
+pub async fn run(
+    node: Arc<Node>,
+    peers: Arc<PeerStore>,
+    seeds: Vec<String>,
+    bootstrap: Vec<SocketAddr>,
+    port: u16,
+) {
+    let mut dialed: HashSet<SocketAddr> = HashSet::new();
+    loop {
+        let mut candidates = bootstrap.clone();
+        candidates.extend(resolve(&seeds, port).await);
+        for addr in candidates {
+            if dialed.contains(&addr) || peers.is_banned(&addr) {
+                continue;
+            }
+            let result = match node.connect(addr).await {
+                Ok(()) => {
+                    dialed.insert(addr);
+                    peers.record_success(addr)
+                }
+                Err(err) => {
+                    log::debug!("failed to connect to discovered peer {addr}: {err}");
+                    peers.record_failure(addr)
+                }
+            };
+            if let Err(err) = result {
+                log::error!("failed to update peer store: {err}");
+            }
+        }
+        tokio::time::sleep(DISCOVERY_INTERVAL).await;
+    }
+}
@@ -0,0 +1,64 @@
+use ddk::types::Hash;
+use ed25519_dalek::{Keypair, PublicKey, Signature, Signer, Verifier};
+use serde::{Deserialize, Serialize};
+
+// BitName::Versioned content version of dispute records.
+//
+// Disputes have no consensus rules, ownership of a disputed name doesn't change, so nodes that
+// don't know this version accept them like any other. Nodes that do index them and serve them
+// over get_disputes, so resolvers and wallets can warn before trusting a name someone reported.
+pub const DISPUTE_VERSION: u8 = 1;
+
+// Evidence against a name, say a trademark registration showing it was squatted, attached by
+// anyone holding the signing key. The signature ties the evidence to a reporter that readers can
+// recognize, independently of whoever paid for the transaction carrying it.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct DisputeRecord {
+    pub key: Hash,
+    // Free form, typically a statement with links to or hashes of documents kept elsewhere, since
+    // every byte of it is paid for on chain.
+    pub evidence: String,
+    pub public_key: PublicKey,
+    pub signature: Signature,
+}
+
+impl DisputeRecord {
+    pub fn new(keypair: &Keypair, key: Hash, evidence: String) -> Self {
+        let signature = keypair.sign(&message(&key, &evidence));
+        Self {
+            key,
+            evidence,
+            public_key: keypair.public,
+            signature,
+        }
+    }
+
+    // The record carried by a Versioned payload, if the payload is a well formed record with a
+    // valid signature. Anything else is ignored rather than rejected, since disputes aren't
+    // consensus.
+    pub fn decode(payload: &[u8]) -> Option<Self> {
+        let record: Self = bincode::deserialize(payload).ok()?;
+        record
+            .public_key
+            .verify(&message(&record.key, &record.evidence), &record.signature)
+            .ok()?;
+        Some(record)
+    }
+
+    pub fn encode(&self) -> Vec<u8> {
+        bincode::serialize(self).expect("dispute records always serialize")
+    }
+}
+
+// A dispute record as indexed, with where it was found.
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Eq)]
+pub struct Dispute {
+    pub txid: Hash,
+    pub height: u32,
+    pub evidence: String,
+    pub public_key: PublicKey,
+}
+
+fn message(key: &Hash, evidence: &str) -> Hash {
+    ddk::types::hash(&(key, evidence))
+}
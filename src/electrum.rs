@@ -0,0 +1,210 @@
+use crate::bitnames::{Error, StateReader};
+use crate::notify::{self, Event};
+use ddk::types::{Address, Hash};
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::broadcast;
+
+// Most addresses a single connection can subscribe to.
+const MAX_SUBSCRIPTIONS: usize = 1000;
+
+// Light wallet server speaking an Electrum-like protocol: newline delimited JSON-RPC over plain
+// TCP, where after a subscribe call the server pushes notifications as the chain moves.
+//
+// Methods:
+//
+// * server.version -> ["bitnames", "1.0"]
+// * blockchain.headers.subscribe -> {"height": tip}, then pushes on every connected block
+// * blockchain.address.subscribe [address] -> status, pushes when the status changes
+// * blockchain.address.get_history [address] -> [AddressOutPoint]
+// * blockchain.address.listunspent [address] -> [AddressOutPoint]
+// * blockchain.address.get_balance [address] -> sats
+// * blockchain.name.get [key] -> value or null
+// * blockchain.block.filter [height] -> hex encoded compact filter or null
+//
+// An address status is the hex encoded hash of its full history, or null if it has none, so
+// clients only refetch history when it changed. Addresses are used directly where Electrum uses
+// script hashes, since ddk has no scripts.
+pub async fn serve(listener: TcpListener, state: StateReader) -> anyhow::Result<()> {
+    loop {
+        let (stream, addr) = listener.accept().await?;
+        let state = state.clone();
+        tokio::spawn(async move {
+            if let Err(err) = handle_connection(stream, state).await {
+                log::warn!("electrum connection {addr} failed: {err}");
+            }
+        });
+    }
+}
+
+async fn handle_connection(stream: TcpStream, state: StateReader) -> anyhow::Result<()> {
+    let (reader, mut writer) = stream.into_split();
+    let mut lines = BufReader::new(reader).lines();
+    let mut events = notify::subscribe();
+    let mut headers_subscribed = false;
+    let mut subscriptions: HashMap<Address, Option<String>> = HashMap::new();
+    loop {
+        let messages = tokio::select! {
+            line = lines.next_line() => {
+                let line = match line? {
+                    Some(line) => line,
+                    // The client closed the connection.
+                    None => return Ok(()),
+                };
+                let response = handle_request(
+                    &line,
+                    &state,
+                    &mut headers_subscribed,
+                    &mut subscriptions,
+                );
+                vec![response]
+            }
+            event = events.recv() => {
+                let height = match event {
+                    Ok(Event::Block { height, .. }) => height,
+                    Ok(_) => continue,
+                    // Statuses are recomputed from scratch, so missed blocks don't matter.
+                    Err(broadcast::error::RecvError::Lagged(_)) => {
+                        state.read(|txn, state| state.get_tip_height(txn))?
+                    }
+                    Err(broadcast::error::RecvError::Closed) => return Ok(()),
+                };
+                notifications(height, &state, headers_subscribed, &mut subscriptions)?
+            }
+        };
+        for message in messages {
+            let mut bytes = serde_json::to_vec(&message)?;
+            bytes.push(b'\n');
+            writer.write_all(&bytes).await?;
+        }
+    }
+}
+
+fn handle_request(
+    line: &str,
+    state: &StateReader,
+    headers_subscribed: &mut bool,
+    subscriptions: &mut HashMap<Address, Option<String>>,
+) -> Value {
+    let request: Value = match serde_json::from_str(line) {
+        Ok(request) => request,
+        Err(err) => return error_response(Value::Null, &format!("invalid request: {err}")),
+    };
+    let id = request.get("id").cloned().unwrap_or(Value::Null);
+    let method = request.get("method").and_then(Value::as_str).unwrap_or("");
+    let params = request.get("params").cloned().unwrap_or(json!([]));
+    let result = match method {
+        "server.version" => Ok(json!(["bitnames", "1.0"])),
+        "blockchain.headers.subscribe" => {
+            *headers_subscribed = true;
+            state
+                .read(|txn, state| state.get_tip_height(txn))
+                .map(|height| json!({ "height": height }))
+                .map_err(|err| err.to_string())
+        }
+        "blockchain.address.subscribe" => param(&params).and_then(|address: Address| {
+            if subscriptions.len() >= MAX_SUBSCRIPTIONS && !subscriptions.contains_key(&address) {
+                return Err(format!(
+                    "at most {MAX_SUBSCRIPTIONS} subscriptions per connection"
+                ));
+            }
+            let status = address_status(state, &address).map_err(|err| err.to_string())?;
+            subscriptions.insert(address, status.clone());
+            Ok(json!(status))
+        }),
+        "blockchain.address.get_history" => param(&params).and_then(|address: Address| {
+            read_json(state, |txn, state| state.get_address_history(txn, &address))
+        }),
+        "blockchain.address.listunspent" => param(&params).and_then(|address: Address| {
+            read_json(state, |txn, state| state.get_address_utxos(txn, &address))
+        }),
+        "blockchain.address.get_balance" => param(&params).and_then(|address: Address| {
+            read_json(state, |txn, state| state.get_address_balance(txn, &address))
+        }),
+        "blockchain.name.get" => param(&params)
+            .and_then(|key: Hash| read_json(state, |txn, state| state.get_value(txn, &key))),
+        "blockchain.block.filter" => param(&params).and_then(|height: u32| {
+            read_json(state, |txn, state| {
+                Ok(state.get_block_filter(txn, height)?.map(hex::encode))
+            })
+        }),
+        _ => Err(format!("unknown method {method}")),
+    };
+    match result {
+        Ok(result) => json!({ "jsonrpc": "2.0", "id": id, "result": result }),
+        Err(err) => error_response(id, &err),
+    }
+}
+
+// Pushes owed to this connection after the block at height was connected.
+fn notifications(
+    height: u32,
+    state: &StateReader,
+    headers_subscribed: bool,
+    subscriptions: &mut HashMap<Address, Option<String>>,
+) -> Result<Vec<Value>, Error> {
+    let mut messages = vec![];
+    if headers_subscribed {
+        messages.push(json!({
+            "jsonrpc": "2.0",
+            "method": "blockchain.headers.subscribe",
+            "params": [{ "height": height }],
+        }));
+    }
+    for (address, status) in subscriptions.iter_mut() {
+        let current = address_status(state, address)?;
+        if current != *status {
+            *status = current.clone();
+            messages.push(json!({
+                "jsonrpc": "2.0",
+                "method": "blockchain.address.subscribe",
+                "params": [address, current],
+            }));
+        }
+    }
+    Ok(messages)
+}
+
+fn address_status(state: &StateReader, address: &Address) -> Result<Option<String>, Error> {
+    let history = state.read(|txn, state| state.get_address_history(txn, address))?;
+    if history.is_empty() {
+        return Ok(None);
+    }
+    Ok(Some(hex::encode(ddk::types::hash(&history))))
+}
+
+// The first positional parameter.
+fn param<T: serde::de::DeserializeOwned>(params: &Value) -> Result<T, String> {
+    let param = params.get(0).cloned().ok_or("missing parameter")?;
+    serde_json::from_value(param).map_err(|err| format!("invalid parameter: {err}"))
+}
+
+fn read_json<T: serde::Serialize>(
+    state: &StateReader,
+    f: impl FnOnce(&ddk::heed::RoTxn, &crate::bitnames::BitNamesState) -> Result<T, Error>,
+) -> Result<Value, String> {
+    let result = state.read(f).map_err(|err| err.to_string())?;
+    serde_json::to_value(result).map_err(|err| err.to_string())
+}
+
+fn error_response(id: Value, message: &str) -> Value {
+    json!({
+        "jsonrpc": "2.0",
+        "id": id,
+        "error": { "code": -32000, "message": message },
+    })
+}
+
+// Bind addr now, so a taken port fails startup, and serve on it in the background.
+pub async fn spawn(addr: SocketAddr, state: StateReader) -> anyhow::Result<()> {
+    let listener = TcpListener::bind(addr).await?;
+    tokio::spawn(async move {
+        if let Err(err) = serve(listener, state).await {
+            log::error!("electrum server stopped: {err}");
+        }
+    });
+    Ok(())
+}
@@ -0,0 +1,90 @@
+use crate::bitnames::{BitName, BitNamesState, StateReader};
+use crate::chain_params::{self, ChainParams};
+use crate::db_env::{self, EnvConfig};
+use crate::mainchain::MainchainEndpoint;
+use crate::notify::{self, Event};
+use crate::{genesis, recovery};
+use ddk::authorization::Authorization;
+use ddk::heed;
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::sync::Arc;
+use tokio::sync::broadcast;
+
+pub type Node = ddk::node::Node<Authorization, BitName, BitNamesState>;
+
+// Everything an embedded node needs to start. The binary adds the wallet, miner and RPC server
+// on top of the same steps.
+pub struct NodeConfig {
+    pub chain_params: &'static ChainParams,
+    pub datadir: PathBuf,
+    // Sidechain P2P address to listen on.
+    pub net_addr: SocketAddr,
+    pub mainchain: MainchainEndpoint,
+    // Peers to connect to once started, they must be reachable.
+    pub connect: Vec<SocketAddr>,
+    pub db: EnvConfig,
+}
+
+// A BitNames node running inside another application, say a browser resolving names without a
+// node process next to it. Only one node can run per process, chain params and notifications are
+// process wide.
+//
+//     let handle = NodeHandle::start(config).await?;
+//     let mut events = handle.subscribe();
+//     let value = handle.state().read(|txn, state| state.get_value(txn, &key))?;
+//     handle.stop()?;
+pub struct NodeHandle {
+    env: heed::Env,
+    node: Arc<Node>,
+    state: StateReader,
+}
+
+impl NodeHandle {
+    // Open the datadir, bring the name state in line with the chain and connect to peers. ddk
+    // syncs in the background from then on.
+    pub async fn start(config: NodeConfig) -> anyhow::Result<Self> {
+        chain_params::select(config.chain_params);
+        let env = db_env::open(&config.datadir, &config.db)?;
+        if config.db.sync == db_env::SyncMode::Auto {
+            tokio::spawn(db_env::run_auto_sync(env.clone()));
+        }
+        let node = Arc::new(Node::new(
+            &config.datadir,
+            config.net_addr,
+            &config.mainchain.host,
+            config.mainchain.port.into(),
+        )?);
+        let state = StateReader::open(&config.datadir)?;
+        let genesis_names = genesis::parse(config.chain_params.genesis_allocation)?;
+        state.write(|txn, state| state.apply_genesis(txn, &genesis_names))?;
+        recovery::recover(&node, &state)?;
+        for peer in &config.connect {
+            node.connect(*peer).await?;
+        }
+        Ok(Self { env, node, state })
+    }
+
+    pub fn node(&self) -> &Arc<Node> {
+        &self.node
+    }
+
+    // Consistent reads of the name state, for resolution.
+    pub fn state(&self) -> &StateReader {
+        &self.state
+    }
+
+    // Blocks, transactions and name changes as they are connected, before they are committed,
+    // see notify::Event.
+    pub fn subscribe(&self) -> broadcast::Receiver<Event> {
+        notify::subscribe()
+    }
+
+    // Flush the databases and let go of the node. ddk has no shutdown at this revision, its
+    // network tasks keep running until the tokio runtime they were spawned on is shut down, so
+    // drop the runtime after this to stop them.
+    pub fn stop(self) -> anyhow::Result<()> {
+        self.env.force_sync()?;
+        Ok(())
+    }
+}
@@ -0,0 +1,138 @@
+// C interface to BitNames resolution, built into the bitnames cdylib, so that software that
+// can't link Rust (NSS modules, C resolvers, bindings for other languages) can resolve names.
+//
+// Names are resolved by asking a running node over its JSON-RPC interface, at the address in the
+// BITNAMES_RPC_ADDR environment variable, or the mainnet default 127.0.0.1:36000. get_value and
+// resolve_signed are public methods, so no credentials are needed. Callers that don't trust the
+// node's connection can fetch a signed resolution instead and check it with
+// bitnames_verify_resolution against the node's key.
+//
+// Every function returns a negative value on error and never panics across the boundary.
+use crate::rpc_api::Resolution;
+use ddk::types::Hash;
+use ed25519_dalek::{PublicKey, Signature, Verifier};
+use std::io::{Read, Write};
+use std::net::TcpStream;
+use std::os::raw::c_int;
+use std::time::Duration;
+
+const DEFAULT_RPC_ADDR: &str = "127.0.0.1:36000";
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+pub const BITNAMES_NOT_FOUND: c_int = 0;
+pub const BITNAMES_FOUND: c_int = 1;
+pub const BITNAMES_ERROR: c_int = -1;
+
+// Resolve the 32 byte key to its 32 byte value, written to out_buf, following its redirects.
+//
+// Returns BITNAMES_FOUND if the key resolves, BITNAMES_NOT_FOUND if it isn't registered, and
+// BITNAMES_ERROR if the node couldn't be asked or the key's redirects loop.
+//
+// # Safety
+//
+// key must point to 32 readable bytes and out_buf to 32 writable bytes.
+#[no_mangle]
+pub unsafe extern "C" fn bitnames_resolve(key: *const u8, out_buf: *mut u8) -> c_int {
+    if key.is_null() || out_buf.is_null() {
+        return BITNAMES_ERROR;
+    }
+    let mut key_bytes = [0; 32];
+    std::ptr::copy_nonoverlapping(key, key_bytes.as_mut_ptr(), 32);
+    let result = std::panic::catch_unwind(|| resolve(&key_bytes));
+    match result {
+        Ok(Ok(Some(value))) => {
+            std::ptr::copy_nonoverlapping(value.as_ptr(), out_buf, 32);
+            BITNAMES_FOUND
+        }
+        Ok(Ok(None)) => BITNAMES_NOT_FOUND,
+        _ => BITNAMES_ERROR,
+    }
+}
+
+// Check a signed resolution, as returned by the resolve_signed RPC method: that public_key signed
+// key resolving to value at height. value is null for a key that wasn't registered.
+//
+// Returns 1 if the signature is valid, 0 if it isn't, and BITNAMES_ERROR for a malformed public
+// key or signature.
+//
+// # Safety
+//
+// key, value (unless null) and public_key must point to 32 readable bytes, signature to 64.
+#[no_mangle]
+pub unsafe extern "C" fn bitnames_verify_resolution(
+    key: *const u8,
+    value: *const u8,
+    height: u32,
+    public_key: *const u8,
+    signature: *const u8,
+) -> c_int {
+    if key.is_null() || public_key.is_null() || signature.is_null() {
+        return BITNAMES_ERROR;
+    }
+    let key: Hash = read_array(key);
+    let value: Option<Hash> = if value.is_null() {
+        None
+    } else {
+        Some(read_array(value))
+    };
+    let public_key: [u8; 32] = read_array(public_key);
+    let signature: [u8; 64] = read_array(signature);
+    let (public_key, signature) = match (
+        PublicKey::from_bytes(&public_key),
+        Signature::from_bytes(&signature),
+    ) {
+        (Ok(public_key), Ok(signature)) => (public_key, signature),
+        _ => return BITNAMES_ERROR,
+    };
+    // Must match the message resolution::SignedResolution signs.
+    let message = ddk::types::hash(&(key, value, height));
+    match public_key.verify(&message, &signature) {
+        Ok(()) => 1,
+        Err(_) => 0,
+    }
+}
+
+unsafe fn read_array<const N: usize>(ptr: *const u8) -> [u8; N] {
+    let mut bytes = [0; N];
+    std::ptr::copy_nonoverlapping(ptr, bytes.as_mut_ptr(), N);
+    bytes
+}
+
+fn resolve(key: &Hash) -> anyhow::Result<Option<Hash>> {
+    let addr = std::env::var("BITNAMES_RPC_ADDR").unwrap_or_else(|_| DEFAULT_RPC_ADDR.to_string());
+    let request = serde_json::json!({
+        "jsonrpc": "2.0",
+        "id": 1,
+        "method": "resolve",
+        "params": [key],
+    });
+    let response = call(&addr, &request.to_string())?;
+    let response: serde_json::Value = serde_json::from_str(&response)?;
+    if let Some(error) = response.get("error") {
+        anyhow::bail!("node returned an error: {error}");
+    }
+    let resolution: Option<Resolution> = serde_json::from_value(response["result"].clone())?;
+    Ok(resolution.map(|resolution| resolution.value))
+}
+
+// POST body to the node and return the response body. HTTP/1.0 keeps the response unchunked and
+// closes the connection after it, so reading to the end gets exactly one response.
+fn call(addr: &str, body: &str) -> anyhow::Result<String> {
+    let mut stream = TcpStream::connect(addr)?;
+    stream.set_read_timeout(Some(TIMEOUT))?;
+    stream.set_write_timeout(Some(TIMEOUT))?;
+    write!(
+        stream,
+        "POST / HTTP/1.0\r\nHost: {addr}\r\nContent-Type: application/json\r\nContent-Length: {}\r\n\r\n{body}",
+        body.len()
+    )?;
+    let mut response = String::new();
+    stream.read_to_string(&mut response)?;
+    let (head, body) = response
+        .split_once("\r\n\r\n")
+        .ok_or_else(|| anyhow::anyhow!("malformed HTTP response"))?;
+    if !head.starts_with("HTTP/1.1 200") && !head.starts_with("HTTP/1.0 200") {
+        anyhow::bail!("node answered {}", head.lines().next().unwrap_or(""));
+    }
+    Ok(body.to_string())
+}
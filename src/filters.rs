@@ -0,0 +1,132 @@
+use ddk::types::Hash;
+use std::collections::BTreeSet;
+
+// Golomb-Rice coded set parameters, the same false positive rate as BIP158 basic filters
+// (1 in 784931).
+const P: u8 = 19;
+const M: u64 = 784_931;
+
+// Compact filter over the items a block touches, in the shape of a BIP158 filter: the item count
+// as a big endian u32, followed by the sorted, hashed items as Golomb-Rice coded deltas.
+//
+// Items are hashed with blake3 keyed by key instead of SipHash, with key derived from the block,
+// so filters for different blocks don't share false positives.
+pub fn build(key: &Hash, items: &[Vec<u8>]) -> Vec<u8> {
+    let items: BTreeSet<&Vec<u8>> = items.iter().collect();
+    let range = items.len() as u64 * M;
+    let mut values: Vec<u64> = items
+        .iter()
+        .map(|item| hash_to_range(key, item, range))
+        .collect();
+    values.sort_unstable();
+    let mut writer = BitWriter::default();
+    let mut last = 0;
+    for value in values {
+        let delta = value - last;
+        for _ in 0..delta >> P {
+            writer.write_bit(true);
+        }
+        writer.write_bit(false);
+        writer.write_bits(delta, P);
+        last = value;
+    }
+    let mut filter = (items.len() as u32).to_be_bytes().to_vec();
+    filter.extend(writer.finish());
+    filter
+}
+
+// Whether item may be in the set filter was built from. False positives happen at a rate of
+// about 1/M, false negatives never.
+pub fn matches(filter: &[u8], key: &Hash, item: &[u8]) -> bool {
+    if filter.len() < 4 {
+        return false;
+    }
+    let count = u32::from_be_bytes(filter[..4].try_into().unwrap()) as u64;
+    let target = hash_to_range(key, item, count * M);
+    let mut reader = BitReader::new(&filter[4..]);
+    let mut value = 0;
+    for _ in 0..count {
+        let mut quotient = 0;
+        loop {
+            match reader.read_bit() {
+                Some(true) => quotient += 1,
+                Some(false) => break,
+                None => return false,
+            }
+        }
+        let remainder = match reader.read_bits(P) {
+            Some(remainder) => remainder,
+            None => return false,
+        };
+        value += (quotient << P) | remainder;
+        if value == target {
+            return true;
+        }
+        if value > target {
+            return false;
+        }
+    }
+    false
+}
+
+fn hash_to_range(key: &Hash, item: &[u8], range: u64) -> u64 {
+    let hash = blake3::keyed_hash(key, item);
+    let value = u64::from_le_bytes(hash.as_bytes()[..8].try_into().unwrap());
+    ((value as u128 * range as u128) >> 64) as u64
+}
+
+#[derive(Default)]
+struct BitWriter {
+    bytes: Vec<u8>,
+    bits: u8,
+}
+
+impl BitWriter {
+    fn write_bit(&mut self, bit: bool) {
+        if self.bits % 8 == 0 {
+            self.bytes.push(0);
+            self.bits = 0;
+        }
+        if bit {
+            *self.bytes.last_mut().unwrap() |= 0x80 >> self.bits;
+        }
+        self.bits += 1;
+    }
+
+    // The low n bits of value, most significant first.
+    fn write_bits(&mut self, value: u64, n: u8) {
+        for i in (0..n).rev() {
+            self.write_bit((value >> i) & 1 == 1);
+        }
+    }
+
+    fn finish(self) -> Vec<u8> {
+        self.bytes
+    }
+}
+
+struct BitReader<'a> {
+    bytes: &'a [u8],
+    position: usize,
+}
+
+impl<'a> BitReader<'a> {
+    fn new(bytes: &'a [u8]) -> Self {
+        Self { bytes, position: 0 }
+    }
+
+    fn read_bit(&mut self) -> Option<bool> {
+        let byte = self.bytes.get(self.position / 8)?;
+        let bit = byte & (0x80 >> (self.position % 8)) != 0;
+        self.position += 1;
+        Some(bit)
+    }
+
+    fn read_bits(&mut self, n: u8) -> Option<u64> {
+        let mut value = 0;
+        for _ in 0..n {
+            value = (value << 1) | self.read_bit()? as u64;
+        }
+        Some(value)
+    }
+}
@@ -0,0 +1,34 @@
+use ddk::types::{Address, Hash};
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::path::Path;
+
+// A name assigned before the first block, for honoring legacy claims or reservations at launch.
+//
+// Genesis names have no registration output, owner is only recorded so that wallets and explorers
+// can attribute them.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct GenesisName {
+    pub key: Hash,
+    pub value: Hash,
+    pub owner: Address,
+}
+
+// Parse a genesis allocation, a JSON array of GenesisName.
+//
+// The allocation is part of consensus: every node of a network must start from the same one, or
+// their name states diverge from the first block on.
+pub fn parse(json: &str) -> anyhow::Result<Vec<GenesisName>> {
+    let names: Vec<GenesisName> = serde_json::from_str(json)?;
+    let mut keys = HashSet::new();
+    for name in &names {
+        if !keys.insert(name.key) {
+            anyhow::bail!("genesis allocation assigns {} twice", hex::encode(name.key));
+        }
+    }
+    Ok(names)
+}
+
+pub fn load(path: &Path) -> anyhow::Result<Vec<GenesisName>> {
+    parse(&std::fs::read_to_string(path)?)
+}
@@ -0,0 +1,478 @@
+use crate::bitnames::{BitName, RecentName, StateReader};
+use crate::parse_hex_32;
+use async_graphql::{EmptyMutation, EmptySubscription, Enum, InputObject, Json, Object, Schema};
+use ddk::types::{Address, Content, Hash, Transaction};
+use hyper::service::{make_service_fn, service_fn};
+use hyper::{Body, Method, Request, Response, StatusCode};
+use std::convert::Infallible;
+use std::net::SocketAddr;
+
+// Names returned by a single names query if the client doesn't ask for fewer.
+const DEFAULT_PAGE_SIZE: usize = 100;
+// Most names a single names query returns.
+const MAX_PAGE_SIZE: usize = 1000;
+
+// GraphQL endpoint for explorers, which want to combine several filters with the records and
+// transactions of each name in one request, where the JSON-RPC API would need a call per name
+// and record type. Queries are POSTed as JSON to any path, like with most GraphQL servers.
+//
+// Example:
+//
+//   { names(filter: {recordType: ONION}, first: 10) {
+//       names { key value height onion transaction { txid owner } }
+//       endCursor hasNextPage } }
+//
+// Names are never updated, transferred or expire, so there is no expiry window to filter by, and
+// the owner of a name is the address its registration output paid to.
+pub type BitNamesSchema = Schema<Query, EmptyMutation, EmptySubscription>;
+
+pub fn schema(state: StateReader) -> BitNamesSchema {
+    Schema::build(Query, EmptyMutation, EmptySubscription)
+        .data(state)
+        .finish()
+}
+
+pub async fn spawn(addr: SocketAddr, state: StateReader) -> anyhow::Result<()> {
+    let schema = schema(state);
+    let make_service = make_service_fn(move |_| {
+        let schema = schema.clone();
+        async move {
+            Ok::<_, Infallible>(service_fn(move |request| {
+                let schema = schema.clone();
+                async move { Ok::<_, Infallible>(handle(&schema, request).await) }
+            }))
+        }
+    });
+    let server = hyper::Server::try_bind(&addr)?.serve(make_service);
+    tokio::spawn(async move {
+        if let Err(err) = server.await {
+            log::error!("graphql server stopped: {err}");
+        }
+    });
+    Ok(())
+}
+
+async fn handle(schema: &BitNamesSchema, request: Request<Body>) -> Response<Body> {
+    if request.method() != Method::POST {
+        return response(StatusCode::METHOD_NOT_ALLOWED, "queries must be POSTed");
+    }
+    let body = match hyper::body::to_bytes(request.into_body()).await {
+        Ok(body) => body,
+        Err(err) => return response(StatusCode::BAD_REQUEST, &err.to_string()),
+    };
+    let query: async_graphql::Request = match serde_json::from_slice(&body) {
+        Ok(query) => query,
+        Err(err) => return response(StatusCode::BAD_REQUEST, &err.to_string()),
+    };
+    let result = schema.execute(query).await;
+    match serde_json::to_vec(&result) {
+        Ok(body) => Response::builder()
+            .header(hyper::header::CONTENT_TYPE, "application/json")
+            .body(Body::from(body))
+            .expect("response parts are valid"),
+        Err(err) => response(StatusCode::INTERNAL_SERVER_ERROR, &err.to_string()),
+    }
+}
+
+fn response(status: StatusCode, message: &str) -> Response<Body> {
+    let mut response = Response::new(Body::from(message.to_string()));
+    *response.status_mut() = status;
+    response
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Enum)]
+pub enum RecordType {
+    Onion,
+    Tlsa,
+    Alias,
+    Identity,
+    Attestation,
+    MainAddress,
+    Typed,
+    Dispute,
+}
+
+#[derive(Default, InputObject)]
+pub struct NameFilter {
+    // Only names registered at or above this height.
+    from_height: Option<u32>,
+    // Only names registered at or below this height.
+    to_height: Option<u32>,
+    // Only names whose registration output paid to this address. Names whose registering block
+    // was pruned never match, their owner isn't known anymore.
+    owner: Option<Json<Address>>,
+    // Only names with at least one record of this type.
+    record_type: Option<RecordType>,
+}
+
+pub struct Query;
+
+#[Object]
+impl Query {
+    async fn tip_height(&self, ctx: &async_graphql::Context<'_>) -> async_graphql::Result<u32> {
+        let state = ctx.data::<StateReader>()?;
+        Ok(state.read(|txn, state| state.get_tip_height(txn))?)
+    }
+
+    // Name by hex encoded key.
+    async fn name(
+        &self,
+        ctx: &async_graphql::Context<'_>,
+        key: String,
+    ) -> async_graphql::Result<Option<Name>> {
+        let state = ctx.data::<StateReader>()?;
+        let key = parse_hex_32(&key)?;
+        Ok(state.read(|txn, state| {
+            let value = match state.get_value(txn, &key)? {
+                Some(value) => value,
+                None => return Ok(None),
+            };
+            let height = state.get_registration_height(txn, &key)?.unwrap_or(0);
+            Ok(Some(Name(RecentName { key, value, height })))
+        })?)
+    }
+
+    // Names matching filter in order of registration, first at most, after the name with cursor
+    // after if given. Pass endCursor of a page as after to get the next one.
+    async fn names(
+        &self,
+        ctx: &async_graphql::Context<'_>,
+        filter: Option<NameFilter>,
+        first: Option<usize>,
+        after: Option<String>,
+    ) -> async_graphql::Result<NamePage> {
+        let state = ctx.data::<StateReader>()?;
+        let filter = filter.unwrap_or_default();
+        let first = first.unwrap_or(DEFAULT_PAGE_SIZE).min(MAX_PAGE_SIZE);
+        let after = after.as_deref().map(parse_cursor).transpose()?;
+        Ok(state.read(|txn, state| {
+            let from = match after {
+                Some((height, _)) => height,
+                None => filter.from_height.unwrap_or(0),
+            };
+            let to = filter
+                .to_height
+                .unwrap_or(u32::MAX)
+                .min(state.get_tip_height(txn)?);
+            let mut page = NamePage {
+                names: vec![],
+                end_cursor: None,
+                has_next_page: false,
+            };
+            for height in from..=to {
+                let keys = state.get_registered_keys(txn, height)?;
+                if keys.is_empty() {
+                    continue;
+                }
+                let body = match &filter.owner {
+                    Some(_) => state.get_block(txn, height)?,
+                    None => None,
+                };
+                for (index, key) in keys.into_iter().enumerate() {
+                    let index = index as u32;
+                    if after.map_or(false, |after| (height, index) <= after) {
+                        continue;
+                    }
+                    if let Some(Json(owner)) = &filter.owner {
+                        let transactions = body.iter().flat_map(|body| &body.transactions);
+                        let registration = transactions
+                            .find_map(|transaction| registration_address(transaction, &key));
+                        if registration.as_ref() != Some(owner) {
+                            continue;
+                        }
+                    }
+                    if let Some(record_type) = filter.record_type {
+                        if !has_record(txn, state, &key, record_type)? {
+                            continue;
+                        }
+                    }
+                    let value = match state.get_value(txn, &key)? {
+                        Some(value) => value,
+                        None => continue,
+                    };
+                    if page.names.len() >= first {
+                        page.has_next_page = true;
+                        return Ok(page);
+                    }
+                    page.end_cursor = Some(format!("{height}:{index}"));
+                    page.names.push(Name(RecentName { key, value, height }));
+                }
+            }
+            Ok(page)
+        })?)
+    }
+}
+
+// Cursors are the height a name was registered at and its position among that block's
+// registrations, which stay put as blocks are added.
+fn parse_cursor(cursor: &str) -> anyhow::Result<(u32, u32)> {
+    let (height, index) = cursor
+        .split_once(':')
+        .ok_or_else(|| anyhow::anyhow!("invalid cursor {cursor}"))?;
+    Ok((height.parse()?, index.parse()?))
+}
+
+fn has_record(
+    txn: &heed::RoTxn,
+    state: &crate::bitnames::BitNamesState,
+    key: &Hash,
+    record_type: RecordType,
+) -> Result<bool, crate::bitnames::Error> {
+    Ok(match record_type {
+        RecordType::Onion => state.get_onion_binding(txn, key)?.is_some(),
+        RecordType::Tlsa => !state.get_tlsa_records(txn, key)?.is_empty(),
+        RecordType::Alias => !state.get_aliases(txn, key)?.is_empty(),
+        RecordType::Identity => !state.get_identity_keys(txn, key)?.is_empty(),
+        RecordType::Attestation => !state.get_attestations(txn, key)?.is_empty(),
+        RecordType::MainAddress => !state.get_main_addresses(txn, key)?.is_empty(),
+        RecordType::Typed => !state.get_records(txn, key)?.is_empty(),
+        RecordType::Dispute => !state.get_disputes(txn, key)?.is_empty(),
+    })
+}
+
+// Address of the output of transaction registering key, if it has one.
+fn registration_address(transaction: &Transaction<BitName>, key: &Hash) -> Option<Address> {
+    transaction
+        .outputs
+        .iter()
+        .find_map(|output| match &output.content {
+            Content::Custom(BitName::KeyValue { key: k, .. }) if k == key => {
+                Some(output.address.clone())
+            }
+            _ => None,
+        })
+}
+
+pub struct NamePage {
+    names: Vec<Name>,
+    end_cursor: Option<String>,
+    has_next_page: bool,
+}
+
+#[Object]
+impl NamePage {
+    async fn names(&self) -> &[Name] {
+        &self.names
+    }
+
+    async fn end_cursor(&self) -> Option<&str> {
+        self.end_cursor.as_deref()
+    }
+
+    async fn has_next_page(&self) -> bool {
+        self.has_next_page
+    }
+}
+
+pub struct Name(RecentName);
+
+#[Object]
+impl Name {
+    async fn key(&self) -> String {
+        hex::encode(self.0.key)
+    }
+
+    async fn value(&self) -> String {
+        hex::encode(self.0.value)
+    }
+
+    // Height of the block that registered the name, names imported from a snapshot report the
+    // fast sync height.
+    async fn height(&self) -> u32 {
+        self.0.height
+    }
+
+    // Transaction that registered the name, null if its block was pruned or came from a snapshot.
+    async fn transaction(
+        &self,
+        ctx: &async_graphql::Context<'_>,
+    ) -> async_graphql::Result<Option<RegistrationTransaction>> {
+        let state = ctx.data::<StateReader>()?;
+        let body = state.read(|txn, state| state.get_block(txn, self.0.height))?;
+        let transaction = body
+            .into_iter()
+            .flat_map(|body| body.transactions)
+            .find_map(|transaction| {
+                let owner = registration_address(&transaction, &self.0.key)?;
+                Some(RegistrationTransaction {
+                    height: self.0.height,
+                    owner,
+                    transaction,
+                })
+            });
+        Ok(transaction)
+    }
+
+    // Every transaction that changed what the name resolves to, oldest first.
+    async fn history(
+        &self,
+        ctx: &async_graphql::Context<'_>,
+    ) -> async_graphql::Result<Vec<HistoryEntry>> {
+        let state = ctx.data::<StateReader>()?;
+        let key = self.0.key;
+        Ok(state.read(|txn, state| {
+            let mut history = vec![];
+            if let Some(body) = state.get_block(txn, self.0.height)? {
+                for transaction in &body.transactions {
+                    if registration_address(transaction, &key).is_some() {
+                        history.push(HistoryEntry {
+                            kind: "registration",
+                            txid: transaction.txid().into(),
+                            height: self.0.height,
+                        });
+                    }
+                }
+            }
+            if let Some(binding) = state.get_onion_binding(txn, &key)? {
+                history.push(HistoryEntry {
+                    kind: "onion",
+                    txid: binding.txid,
+                    height: binding.height,
+                });
+            }
+            for identity_key in state.get_identity_keys(txn, &key)? {
+                history.push(HistoryEntry {
+                    kind: "identity",
+                    txid: identity_key.txid,
+                    height: identity_key.height,
+                });
+            }
+            for dispute in state.get_disputes(txn, &key)? {
+                history.push(HistoryEntry {
+                    kind: "dispute",
+                    txid: dispute.txid,
+                    height: dispute.height,
+                });
+            }
+            history.sort_by_key(|entry| entry.height);
+            Ok(history)
+        })?)
+    }
+
+    async fn onion(
+        &self,
+        ctx: &async_graphql::Context<'_>,
+    ) -> async_graphql::Result<Option<Json<crate::onion::OnionBinding>>> {
+        let state = ctx.data::<StateReader>()?;
+        let binding = state.read(|txn, state| state.get_onion_binding(txn, &self.0.key))?;
+        Ok(binding.map(Json))
+    }
+
+    async fn tlsa(
+        &self,
+        ctx: &async_graphql::Context<'_>,
+    ) -> async_graphql::Result<Json<Vec<crate::tlsa::TlsaRecord>>> {
+        let state = ctx.data::<StateReader>()?;
+        Ok(Json(state.read(|txn, state| {
+            state.get_tlsa_records(txn, &self.0.key)
+        })?))
+    }
+
+    async fn aliases(
+        &self,
+        ctx: &async_graphql::Context<'_>,
+    ) -> async_graphql::Result<Json<std::collections::BTreeMap<String, Address>>> {
+        let state = ctx.data::<StateReader>()?;
+        Ok(Json(
+            state.read(|txn, state| state.get_aliases(txn, &self.0.key))?,
+        ))
+    }
+
+    async fn identity_keys(
+        &self,
+        ctx: &async_graphql::Context<'_>,
+    ) -> async_graphql::Result<Json<Vec<crate::identity::IdentityKey>>> {
+        let state = ctx.data::<StateReader>()?;
+        Ok(Json(state.read(|txn, state| {
+            state.get_identity_keys(txn, &self.0.key)
+        })?))
+    }
+
+    async fn attestations(
+        &self,
+        ctx: &async_graphql::Context<'_>,
+    ) -> async_graphql::Result<Json<Vec<crate::attestation::AttestationRecord>>> {
+        let state = ctx.data::<StateReader>()?;
+        Ok(Json(state.read(|txn, state| {
+            state.get_attestations(txn, &self.0.key)
+        })?))
+    }
+
+    async fn main_addresses(
+        &self,
+        ctx: &async_graphql::Context<'_>,
+    ) -> async_graphql::Result<Json<Vec<crate::main_address::MainAddressRecord>>> {
+        let state = ctx.data::<StateReader>()?;
+        Ok(Json(state.read(|txn, state| {
+            state.get_main_addresses(txn, &self.0.key)
+        })?))
+    }
+
+    async fn records(
+        &self,
+        ctx: &async_graphql::Context<'_>,
+    ) -> async_graphql::Result<Json<Vec<crate::records::TypedRecord>>> {
+        let state = ctx.data::<StateReader>()?;
+        Ok(Json(
+            state.read(|txn, state| state.get_records(txn, &self.0.key))?,
+        ))
+    }
+
+    async fn disputes(
+        &self,
+        ctx: &async_graphql::Context<'_>,
+    ) -> async_graphql::Result<Json<Vec<crate::disputes::Dispute>>> {
+        let state = ctx.data::<StateReader>()?;
+        Ok(Json(
+            state.read(|txn, state| state.get_disputes(txn, &self.0.key))?,
+        ))
+    }
+}
+
+pub struct RegistrationTransaction {
+    height: u32,
+    owner: Address,
+    transaction: Transaction<BitName>,
+}
+
+#[Object]
+impl RegistrationTransaction {
+    async fn txid(&self) -> String {
+        hex::encode(Hash::from(self.transaction.txid()))
+    }
+
+    async fn height(&self) -> u32 {
+        self.height
+    }
+
+    async fn owner(&self) -> Json<Address> {
+        Json(self.owner.clone())
+    }
+
+    // The whole transaction, as the JSON-RPC API returns it.
+    async fn raw(&self) -> Json<Transaction<BitName>> {
+        Json(self.transaction.clone())
+    }
+}
+
+pub struct HistoryEntry {
+    kind: &'static str,
+    txid: Hash,
+    height: u32,
+}
+
+#[Object]
+impl HistoryEntry {
+    // One of registration, onion, identity or dispute.
+    async fn kind(&self) -> &str {
+        self.kind
+    }
+
+    async fn txid(&self) -> String {
+        hex::encode(self.txid)
+    }
+
+    async fn height(&self) -> u32 {
+        self.height
+    }
+}
@@ -0,0 +1,240 @@
+use crate::bitnames::{BitName, RecentName, StateReader};
+use crate::notify::{self, Event};
+use crate::orphans::OrphanPool;
+use crate::rpc_api::NameCursor;
+use crate::{rpc, rpc_auth, Node};
+use ddk::authorization::Authorization;
+use ddk::types::{AuthorizedTransaction, Hash};
+use proto::bit_names_server::{BitNames, BitNamesServer};
+use std::net::SocketAddr;
+use std::sync::Arc;
+use tokio::sync::{broadcast, mpsc};
+use tokio_stream::wrappers::ReceiverStream;
+use tonic::{Request, Response, Status};
+
+// Code generated from proto/bitnames.proto by build.rs.
+pub mod proto {
+    tonic::include_proto!("bitnames.v1");
+}
+
+// Name events buffered per subscriber before catching up waits for the client.
+const SUBSCRIPTION_BUFFER: usize = 16;
+
+// gRPC service for backend integrations that prefer typed, streaming calls over JSON-RPC. It
+// covers resolution, transaction submission and following registrations, the calls a service
+// built on names needs, sharing their implementation with the JSON-RPC server. Everything else
+// stays JSON-RPC only.
+pub struct GrpcService {
+    node: Arc<Node>,
+    orphans: Arc<OrphanPool>,
+    state: StateReader,
+    authorization: String,
+}
+
+pub async fn spawn(
+    addr: SocketAddr,
+    node: Arc<Node>,
+    orphans: Arc<OrphanPool>,
+    state: StateReader,
+    authorization: String,
+) -> anyhow::Result<()> {
+    let service = GrpcService {
+        node,
+        orphans,
+        state,
+        authorization,
+    };
+    // Bound here, so a port that is taken fails startup like the other servers.
+    let listener = tokio::net::TcpListener::bind(addr).await?;
+    let incoming = tokio_stream::wrappers::TcpListenerStream::new(listener);
+    tokio::spawn(async move {
+        let result = tonic::transport::Server::builder()
+            .add_service(BitNamesServer::new(service))
+            .serve_with_incoming(incoming)
+            .await;
+        if let Err(err) = result {
+            log::error!("grpc server stopped: {err}");
+        }
+    });
+    Ok(())
+}
+
+fn internal(err: impl std::fmt::Display) -> Status {
+    Status::internal(err.to_string())
+}
+
+fn parse_hash(bytes: &[u8]) -> Result<Hash, Status> {
+    bytes
+        .try_into()
+        .map_err(|_| Status::invalid_argument("expected a 32 byte hash"))
+}
+
+fn cursor_to_proto(cursor: NameCursor) -> proto::NameCursor {
+    proto::NameCursor {
+        height: cursor.height,
+        merkle_root: cursor.merkle_root.map(|root| root.to_vec()),
+    }
+}
+
+fn names_to_proto(names: Vec<RecentName>) -> Vec<proto::Name> {
+    names
+        .into_iter()
+        .map(|name| proto::Name {
+            key: name.key.to_vec(),
+            value: name.value.to_vec(),
+            height: name.height,
+        })
+        .collect()
+}
+
+#[tonic::async_trait]
+impl BitNames for GrpcService {
+    async fn get_height(
+        &self,
+        _request: Request<proto::GetHeightRequest>,
+    ) -> Result<Response<proto::GetHeightResponse>, Status> {
+        let height = self
+            .state
+            .read(|txn, state| state.get_tip_height(txn))
+            .map_err(internal)?;
+        Ok(Response::new(proto::GetHeightResponse { height }))
+    }
+
+    async fn resolve(
+        &self,
+        request: Request<proto::ResolveRequest>,
+    ) -> Result<Response<proto::ResolveResponse>, Status> {
+        let key = parse_hash(&request.into_inner().key)?;
+        let resolution = rpc::resolution(&self.state, &key).map_err(internal)?;
+        let resolution = resolution.map(|resolution| proto::Resolution {
+            value: resolution.value.to_vec(),
+            changed_height: resolution.changed_height,
+            confirmations: resolution.confirmations,
+            finalized: resolution.finalized,
+            redirects: resolution
+                .redirects
+                .iter()
+                .map(|key| key.to_vec())
+                .collect(),
+        });
+        Ok(Response::new(proto::ResolveResponse { resolution }))
+    }
+
+    async fn submit_transaction(
+        &self,
+        request: Request<proto::SubmitTransactionRequest>,
+    ) -> Result<Response<proto::SubmitTransactionResponse>, Status> {
+        let authorized = request
+            .metadata()
+            .get("authorization")
+            .map_or(false, |value| {
+                rpc_auth::constant_time_eq(value.as_bytes(), self.authorization.as_bytes())
+            });
+        if !authorized {
+            return Err(Status::unauthenticated("invalid authorization"));
+        }
+        let transaction: AuthorizedTransaction<Authorization, BitName> =
+            bincode::deserialize(&request.into_inner().transaction)
+                .map_err(|err| Status::invalid_argument(err.to_string()))?;
+        let txid = Hash::from(transaction.transaction.txid());
+        rpc::submit_transaction(&self.node, &self.orphans, transaction)
+            .await
+            .map_err(|rejection| {
+                Status::failed_precondition(format!("{:?}: {}", rejection.code, rejection.message))
+            })?;
+        Ok(Response::new(proto::SubmitTransactionResponse {
+            txid: txid.to_vec(),
+        }))
+    }
+
+    type SubscribeNamesStream = ReceiverStream<Result<proto::NameEvent, Status>>;
+
+    async fn subscribe_names(
+        &self,
+        request: Request<proto::SubscribeNamesRequest>,
+    ) -> Result<Response<Self::SubscribeNamesStream>, Status> {
+        let cursor = match request.into_inner().cursor {
+            Some(cursor) => NameCursor {
+                height: cursor.height,
+                merkle_root: cursor.merkle_root.as_deref().map(parse_hash).transpose()?,
+            },
+            None => NameCursor {
+                height: 0,
+                merkle_root: None,
+            },
+        };
+        // Subscribed before catching up, so no block connected meanwhile is missed.
+        let events = notify::subscribe();
+        let fast_sync_height = self
+            .state
+            .read(|txn, state| state.get_fast_sync_height(txn))
+            .map_err(internal)?;
+        if cursor.height < fast_sync_height {
+            return Err(Status::invalid_argument(format!(
+                "this node was fast synced to {fast_sync_height}, followers have to start there"
+            )));
+        }
+        if !rpc::cursor_on_chain(&self.state, &cursor).map_err(internal)? {
+            return Err(Status::invalid_argument(
+                "cursor is not on this node's chain, resync with diff_names",
+            ));
+        }
+        let (sender, receiver) = mpsc::channel(SUBSCRIPTION_BUFFER);
+        let state = self.state.clone();
+        tokio::spawn(async move {
+            if let Err(err) = follow_names(state, cursor, events, &sender).await {
+                let _ = sender.send(Err(internal(err))).await;
+            }
+        });
+        Ok(Response::new(ReceiverStream::new(receiver)))
+    }
+}
+
+// Send the names registered after cursor, then wait for the tip to move and repeat, until the
+// client goes away. Like the subscribe_names JSON-RPC method.
+async fn follow_names(
+    state: StateReader,
+    mut cursor: NameCursor,
+    mut events: broadcast::Receiver<Event>,
+    sender: &mpsc::Sender<Result<proto::NameEvent, Status>>,
+) -> anyhow::Result<()> {
+    use proto::name_event::Event as NameEvent;
+    loop {
+        while let Some((registered, next)) = rpc::names_after(&state, cursor.height)? {
+            cursor = next;
+            let event = NameEvent::Registered(proto::Registered {
+                names: names_to_proto(registered),
+                cursor: Some(cursor_to_proto(cursor.clone())),
+            });
+            let event = proto::NameEvent { event: Some(event) };
+            if sender.send(Ok(event)).await.is_err() {
+                return Ok(());
+            }
+        }
+        loop {
+            match events.recv().await {
+                Ok(Event::Block { .. }) => break,
+                Ok(Event::Disconnected { height }) if height <= cursor.height => {
+                    cursor = NameCursor {
+                        height: height.saturating_sub(1),
+                        merkle_root: None,
+                    };
+                    let event = NameEvent::Disconnected(proto::Disconnected { height });
+                    let event = proto::NameEvent { event: Some(event) };
+                    if sender.send(Ok(event)).await.is_err() {
+                        return Ok(());
+                    }
+                }
+                Ok(_) => continue,
+                Err(broadcast::error::RecvError::Lagged(_)) => {
+                    if !rpc::cursor_on_chain(&state, &cursor)? {
+                        log::info!("name follower fell behind a reorg, ending subscription");
+                        return Ok(());
+                    }
+                    break;
+                }
+                Err(broadcast::error::RecvError::Closed) => return Ok(()),
+            }
+        }
+    }
+}
@@ -0,0 +1,277 @@
+use crate::bitnames::{BitName, RecentName, StateReader};
+use crate::coin_selection::SpendPolicy;
+use crate::status::{NodeStatus, StatusMonitor};
+use crate::wallet::{self, Wallets};
+use crate::Node;
+use ddk::types::{Content, GetValue, Hash};
+use eframe::egui;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+// How often the window reloads its data from the node.
+const REFRESH_INTERVAL: Duration = Duration::from_secs(1);
+// Number of latest registrations shown when browsing the namespace.
+const RECENT_NAMES: usize = 50;
+
+// Everything the window reads from and acts on, the same instances the RPC server uses.
+pub struct Gui {
+    pub node: Arc<Node>,
+    pub wallets: Arc<Wallets>,
+    pub status: Arc<StatusMonitor>,
+    pub state: StateReader,
+    pub spend_policy: SpendPolicy,
+    // Registrations are async, they are spawned on the node's runtime.
+    pub runtime: tokio::runtime::Handle,
+}
+
+// Data shown in the window, reloaded every REFRESH_INTERVAL.
+#[derive(Default)]
+struct View {
+    status: Option<NodeStatus>,
+    wallets: Vec<String>,
+    balance: u64,
+    names: Vec<(Hash, Hash)>,
+    recent: Vec<RecentName>,
+    error: Option<String>,
+}
+
+struct App {
+    gui: Gui,
+    view: View,
+    refreshed: Option<Instant>,
+    // None for the default wallet.
+    selected_wallet: Option<String>,
+    new_wallet: String,
+    key: String,
+    value: String,
+    fee: String,
+    search: String,
+    search_result: String,
+    // Outcome of the last registration, written by the task that submits it.
+    message: Arc<Mutex<String>>,
+}
+
+// Open the window and run until it is closed. Must be called on the main thread.
+pub fn run(gui: Gui) -> anyhow::Result<()> {
+    let app = App {
+        gui,
+        view: View::default(),
+        refreshed: None,
+        selected_wallet: None,
+        new_wallet: String::new(),
+        key: String::new(),
+        value: String::new(),
+        fee: "1000".to_string(),
+        search: String::new(),
+        search_result: String::new(),
+        message: Arc::new(Mutex::new(String::new())),
+    };
+    eframe::run_native(
+        "BitNames",
+        eframe::NativeOptions::default(),
+        Box::new(|_| Box::new(app)),
+    )
+    .map_err(|err| anyhow::anyhow!("GUI failed: {err}"))
+}
+
+impl App {
+    fn refresh(&mut self) {
+        match self.load() {
+            Ok(view) => self.view = view,
+            Err(err) => self.view.error = Some(err.to_string()),
+        }
+        self.refreshed = Some(Instant::now());
+    }
+
+    fn load(&self) -> anyhow::Result<View> {
+        let wallet = self.gui.wallets.get(self.selected_wallet.as_deref())?;
+        wallet::sync(&self.gui.node, &wallet)?;
+        let mut balance = 0;
+        let mut names = vec![];
+        for output in wallet.get_utxos()?.into_values() {
+            match output.content {
+                Content::Custom(BitName::KeyValue { key, value }) => names.push((key, value)),
+                _ => balance += output.get_value(),
+            }
+        }
+        names.sort();
+        let recent = self
+            .gui
+            .state
+            .read(|txn, state| state.get_recent_names(txn, RECENT_NAMES))?;
+        Ok(View {
+            status: Some(self.gui.status.status()?),
+            wallets: self.gui.wallets.list()?,
+            balance,
+            names,
+            recent,
+            error: None,
+        })
+    }
+
+    fn register(&self) {
+        let message = self.message.clone();
+        let parsed = (
+            crate::parse_hex_32(self.key.trim()),
+            crate::parse_hex_32(self.value.trim()),
+            self.fee.trim().parse::<u64>(),
+        );
+        let (key, value, fee) = match parsed {
+            (Ok(key), Ok(value), Ok(fee)) => (key, value, fee),
+            _ => {
+                *message.lock().unwrap() =
+                    "key and value must be 32 bytes of hex, fee a number of sats".to_string();
+                return;
+            }
+        };
+        let wallet = match self.gui.wallets.get(self.selected_wallet.as_deref()) {
+            Ok(wallet) => wallet,
+            Err(err) => {
+                *message.lock().unwrap() = err.to_string();
+                return;
+            }
+        };
+        let node = self.gui.node.clone();
+        let state = self.gui.state.clone();
+        let policy = self.gui.spend_policy;
+        *message.lock().unwrap() = "submitting registration".to_string();
+        self.gui.runtime.spawn(async move {
+            let result =
+                wallet::register_name(&node, &wallet, &state, key, value, fee, policy).await;
+            *message.lock().unwrap() = match result {
+                Ok(()) => format!("registration of {} submitted", hex::encode(key)),
+                Err(err) => format!("registration failed: {err}"),
+            };
+        });
+    }
+
+    fn search(&mut self) {
+        let key: Hash = match crate::parse_hex_32(self.search.trim()) {
+            Ok(key) => key,
+            Err(_) => {
+                self.search_result = "keys are 32 bytes of hex".to_string();
+                return;
+            }
+        };
+        self.search_result = match self.gui.state.read(|txn, state| state.get_value(txn, &key)) {
+            Ok(Some(value)) => format!("registered, value {}", hex::encode(value)),
+            Ok(None) => "available".to_string(),
+            Err(err) => format!("lookup failed: {err}"),
+        };
+    }
+
+    fn wallets_panel(&mut self, ui: &mut egui::Ui) {
+        ui.heading("Wallet");
+        let selected = self.selected_wallet.clone();
+        egui::ComboBox::from_label("wallet")
+            .selected_text(selected.as_deref().unwrap_or("default"))
+            .show_ui(ui, |ui| {
+                ui.selectable_value(&mut self.selected_wallet, None, "default");
+                for name in &self.view.wallets {
+                    ui.selectable_value(&mut self.selected_wallet, Some(name.clone()), name);
+                }
+            });
+        if self.selected_wallet != selected {
+            self.refresh();
+        }
+        ui.horizontal(|ui| {
+            ui.text_edit_singleline(&mut self.new_wallet);
+            if ui.button("Create wallet").clicked() {
+                let name = self.new_wallet.trim().to_string();
+                match self.gui.wallets.create(&name) {
+                    Ok(()) => {
+                        self.selected_wallet = Some(name);
+                        self.new_wallet.clear();
+                        self.refresh();
+                    }
+                    Err(err) => *self.message.lock().unwrap() = err.to_string(),
+                }
+            }
+        });
+        ui.label(format!("Balance: {} sats", self.view.balance));
+        ui.separator();
+        ui.label("Names in this wallet");
+        egui::ScrollArea::vertical()
+            .id_source("owned")
+            .show(ui, |ui| {
+                for (key, value) in &self.view.names {
+                    ui.monospace(format!("{} → {}", hex::encode(key), hex::encode(value)));
+                }
+            });
+    }
+
+    fn names_panel(&mut self, ui: &mut egui::Ui) {
+        ui.heading("Register a name");
+        egui::Grid::new("register").show(ui, |ui| {
+            ui.label("Key");
+            ui.text_edit_singleline(&mut self.key);
+            ui.end_row();
+            ui.label("Value");
+            ui.text_edit_singleline(&mut self.value);
+            ui.end_row();
+            ui.label("Fee (sats)");
+            ui.text_edit_singleline(&mut self.fee);
+            ui.end_row();
+        });
+        if ui.button("Register").clicked() {
+            self.register();
+        }
+        ui.label(self.message.lock().unwrap().clone());
+        ui.separator();
+        ui.heading("Browse");
+        ui.horizontal(|ui| {
+            ui.text_edit_singleline(&mut self.search);
+            if ui.button("Look up").clicked() {
+                self.search();
+            }
+        });
+        ui.label(self.search_result.as_str());
+        ui.label("Latest registrations");
+        egui::ScrollArea::vertical()
+            .id_source("recent")
+            .show(ui, |ui| {
+                for name in &self.view.recent {
+                    ui.monospace(format!(
+                        "{} {} → {}",
+                        name.height,
+                        hex::encode(name.key),
+                        hex::encode(name.value)
+                    ));
+                }
+            });
+    }
+}
+
+impl eframe::App for App {
+    fn update(&mut self, ctx: &egui::Context, _frame: &mut eframe::Frame) {
+        if self
+            .refreshed
+            .map_or(true, |refreshed| refreshed.elapsed() >= REFRESH_INTERVAL)
+        {
+            self.refresh();
+        }
+        ctx.request_repaint_after(REFRESH_INTERVAL);
+        egui::TopBottomPanel::top("status").show(ctx, |ui| {
+            let text = match &self.view.status {
+                Some(status) => format!(
+                    "Height {} · last block {}s ago · mainchain {}{}",
+                    status.height,
+                    status.seconds_since_last_block,
+                    if status.mainchain_connected {
+                        "connected"
+                    } else {
+                        "unreachable"
+                    },
+                    if status.stale { " · out of date" } else { "" },
+                ),
+                None => "Loading".to_string(),
+            };
+            ui.label(text);
+            if let Some(error) = &self.view.error {
+                ui.colored_label(egui::Color32::RED, error.as_str());
+            }
+        });
+        egui::SidePanel::left("wallet").show(ctx, |ui| self.wallets_panel(ui));
+        egui::CentralPanel::default().show(ctx, |ui| self.names_panel(ui));
+    }
+}
@@ -0,0 +1,39 @@
+use crate::notify::{self, Event};
+use ddk::types::Hash;
+use tokio::sync::broadcast;
+
+// Run command for every change to one of watched, or to any name if watched is empty, in the
+// order they were connected.
+//
+// The command gets the hex encoded key, previous value (empty for a first registration), new
+// value, and the height as arguments. It runs through the notification channel rather than
+// inside connect_body, so a slow or failing hook never holds up or breaks block connection.
+pub async fn run(command: String, watched: Vec<Hash>) {
+    let mut events = notify::subscribe();
+    loop {
+        let change = match events.recv().await {
+            Ok(Event::Name(change)) => change,
+            Ok(_) => continue,
+            Err(broadcast::error::RecvError::Lagged(missed)) => {
+                log::warn!("name hook missed {missed} events");
+                continue;
+            }
+            Err(broadcast::error::RecvError::Closed) => return,
+        };
+        if !watched.is_empty() && !watched.contains(&change.key) {
+            continue;
+        }
+        let status = tokio::process::Command::new(&command)
+            .arg(hex::encode(change.key))
+            .arg(change.previous.map(hex::encode).unwrap_or_default())
+            .arg(hex::encode(change.value))
+            .arg(change.height.to_string())
+            .status()
+            .await;
+        match status {
+            Ok(status) if status.success() => {}
+            Ok(status) => log::warn!("name hook {command} exited with {status}"),
+            Err(err) => log::error!("failed to run name hook {command}: {err}"),
+        }
+    }
+}
@@ -0,0 +1,79 @@
+use ddk::types::{Hash, Transaction};
+use ed25519_dalek::{Keypair, PublicKey, Signature, Signer, Verifier};
+use serde::{Deserialize, Serialize};
+
+// BitName::Versioned content version of identity key records.
+//
+// An identity key is an ed25519 key a name vouches for, so that messages signed with it can be
+// attributed to the name without involving the wallet keys that hold its outputs. The first key
+// is bound by the transaction registering the name, like onion and TLSA records. After that the
+// key can only be rotated by a record signed with the current one, which anyone may carry since
+// the signature is what counts. The name keeps every key it ever had, so consumers can follow
+// the chain from a key they trusted to the current one.
+//
+// There are no consensus rules for identity records, records that don't qualify are ignored.
+pub const IDENTITY_VERSION: u8 = 6;
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct IdentityRecord {
+    pub key: Hash,
+    pub public_key: PublicKey,
+    // None for the binding in the registration, otherwise the current key's signature over
+    // rotation_message.
+    pub signature: Option<Signature>,
+}
+
+impl IdentityRecord {
+    // Rotate key's identity key to public_key. sequence is the number of identity keys key had so
+    // far, which keeps a rotation from being replayed after the key rotated back.
+    pub fn rotation(current: &Keypair, key: Hash, sequence: u32, public_key: PublicKey) -> Self {
+        let signature = current.sign(&rotation_message(&key, sequence, &public_key));
+        Self {
+            key,
+            public_key,
+            signature: Some(signature),
+        }
+    }
+
+    // Decode a Versioned payload, if it is a record transaction is allowed to carry. Whether a
+    // rotation is signed by the current key depends on the state, see verify_rotation.
+    pub fn decode(
+        payload: &[u8],
+        transaction: &Transaction<crate::bitnames::BitName>,
+    ) -> Option<Self> {
+        let record: Self = bincode::deserialize(payload).ok()?;
+        if record.signature.is_none()
+            && !crate::bitnames::registered_keys(transaction).contains(&record.key)
+        {
+            return None;
+        }
+        Some(record)
+    }
+
+    pub fn encode(&self) -> Vec<u8> {
+        bincode::serialize(self).expect("identity records always serialize")
+    }
+
+    // Whether this record rotates away from history's current key, with a signature by it.
+    pub fn verify_rotation(&self, history: &[IdentityKey]) -> bool {
+        match (&self.signature, history.last()) {
+            (Some(signature), Some(current)) => {
+                let message = rotation_message(&self.key, history.len() as u32, &self.public_key);
+                current.public_key.verify(&message, signature).is_ok()
+            }
+            _ => false,
+        }
+    }
+}
+
+// An identity key as indexed, with where it was bound or rotated to.
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Eq)]
+pub struct IdentityKey {
+    pub public_key: PublicKey,
+    pub txid: Hash,
+    pub height: u32,
+}
+
+pub fn rotation_message(key: &Hash, sequence: u32, public_key: &PublicKey) -> Hash {
+    ddk::types::hash(&(key, sequence, public_key))
+}
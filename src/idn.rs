@@ -0,0 +1,78 @@
+use serde::{Deserialize, Serialize};
+
+// Internationalized names.
+//
+// A name with non-ASCII characters can be written two ways: in its display form, the Unicode a
+// user reads and types, or in its raw form, where every non-ASCII label is punycode encoded with
+// an xn-- prefix (RFC 3492), as DNS and anything else limited to ASCII carries it. Both forms of
+// a name are the same name, so aliases::name_key hashes the display form whichever one it is
+// given, and everything that takes a plaintext name takes either form. Where a name is printed,
+// NameForm says which form.
+//
+// Conversion follows UTS 46, like browsers do, so a raw form is lowercase and a display form is
+// case folded and NFC normalized.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize, clap::ValueEnum)]
+#[serde(rename_all = "snake_case")]
+pub enum NameForm {
+    // Unicode, for people.
+    #[default]
+    Display,
+    // ASCII with punycode labels, for DNS and other ASCII only systems.
+    Raw,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Eq)]
+pub struct NameForms {
+    pub display: String,
+    pub raw: String,
+    pub key: ddk::types::Hash,
+}
+
+// The raw form of name, given in either form.
+pub fn to_raw(name: &str) -> anyhow::Result<String> {
+    idna::domain_to_ascii(name).map_err(|err| anyhow::anyhow!("{name:?} isn't a valid name: {err}"))
+}
+
+// The display form of name, given in either form.
+pub fn to_display(name: &str) -> anyhow::Result<String> {
+    match idna::domain_to_unicode(name) {
+        (display, Ok(())) => Ok(display),
+        (_, Err(err)) => anyhow::bail!("{name:?} isn't a valid name: {err}"),
+    }
+}
+
+pub fn format(name: &str, form: NameForm) -> anyhow::Result<String> {
+    match form {
+        NameForm::Display => to_display(name),
+        NameForm::Raw => to_raw(name),
+    }
+}
+
+pub fn forms(name: &str) -> anyhow::Result<NameForms> {
+    Ok(NameForms {
+        display: to_display(name)?,
+        raw: to_raw(name)?,
+        key: crate::aliases::name_key(name),
+    })
+}
+
+// The form of name that is hashed into its key.
+//
+// Keys were the hash of the lowercase name before punycode was understood, so only names with a
+// punycode label are converted, and every other name keeps the key it always had. A name that
+// doesn't convert is taken as it is, it can't have been typed in the other form. A display form
+// that isn't already NFC normalized keeps the key of its exact lowercase too, so names should be
+// registered in the form to_display returns.
+pub fn canonical(name: &str) -> String {
+    let punycode = name.split('.').any(|label| {
+        label
+            .get(..4)
+            .map_or(false, |prefix| prefix.eq_ignore_ascii_case("xn--"))
+    });
+    if punycode {
+        if let Ok(display) = to_display(name) {
+            return display;
+        }
+    }
+    name.to_lowercase()
+}
@@ -0,0 +1,72 @@
+// The BitNames library: everything about names that doesn't need a running node, so that other
+// projects can validate names, verify resolutions or build their own frontends. The node binary
+// in main.rs is built on top of it, adding networking, the wallet, mining and the RPC server.
+//
+// - bitnames: the name state, its validation rules and the BitName output type
+// - aliases, attestation, disputes, identity, lock_height, main_address, offchain, onion, records,
+//   tlsa: records carried in Versioned outputs
+// - chain_params, deployments, genesis: consensus parameters of each network
+// - clock: the time the library reads, which tests and simulations can replace
+// - idn: the display and raw punycode forms of internationalized names
+// - resolution, snapshot, filters, compact_block: signed answers and data exchanged with other
+//   nodes and light clients
+// - block_template, coin_selection, rejection, tx_builder: building and explaining transactions
+//   and blocks
+// - rpc_api: the node's JSON-RPC interface and a typed client for it, with the address_book,
+//   peer_store, status, watchlist and mainchain types it exposes
+// - db_env, read_pool, recovery, embed, sidecar: opening a node's databases, serving reads from
+//   them, running a node inside another application and reading a running node's names from
+//   another process
+// - analytics: aggregate reports over the namespace
+// - metrics: latency and error counts of RPC calls and state operations
+// - sim, vectors: a deterministic multi-node simulation of the state machine, and fixed test
+//   vectors of its rules
+// - ffi: the C interface, which the cdylib build of this library exports
+pub mod address_book;
+pub mod aliases;
+pub mod analytics;
+pub mod attestation;
+pub mod bitnames;
+pub mod block_template;
+pub mod chain_params;
+pub mod clock;
+pub mod coin_selection;
+pub mod compact_block;
+pub mod db_env;
+pub mod deployments;
+pub mod disputes;
+pub mod embed;
+pub mod ffi;
+pub mod filters;
+pub mod genesis;
+pub mod identity;
+pub mod idn;
+pub mod lock_height;
+pub mod main_address;
+pub mod mainchain;
+pub mod metrics;
+pub mod notify;
+pub mod offchain;
+pub mod onion;
+pub mod peer_store;
+pub mod read_pool;
+pub mod records;
+pub mod recovery;
+pub mod rejection;
+pub mod resolution;
+pub mod rpc_api;
+pub mod sidecar;
+pub mod sim;
+pub mod snapshot;
+pub mod status;
+pub mod tlsa;
+pub mod tx_builder;
+pub mod vectors;
+pub mod watchlist;
+
+pub fn parse_hex_32(s: &str) -> anyhow::Result<[u8; 32]> {
+    let bytes = hex::decode(s)?;
+    bytes
+        .try_into()
+        .map_err(|_| anyhow::anyhow!("expected 32 hex encoded bytes"))
+}
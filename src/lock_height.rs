@@ -0,0 +1,39 @@
+use crate::bitnames::BitName;
+use ddk::types::{Content, Transaction};
+
+// BitName::Versioned content version of height locks.
+//
+// A lock makes its transaction invalid in any block below the lock height, so a registration can
+// be signed and handed over now but only be mined from an agreed height on. Wallets can also lock
+// a transaction to the height of the next block, so it can't be mined in a block replacing the
+// current tip, which takes away a miner's reason to reorg the tip just to collect its fees. The
+// payload is the lock height as a big endian u32, and a transaction with several locks is locked
+// until the highest one. Enforced once the lock_height deployment is active, see deployments.rs.
+//
+// Names are never updated or transferred, so registrations are the only name operations to lock.
+pub const LOCK_HEIGHT_VERSION: u8 = 5;
+
+pub fn encode(lock_height: u32) -> Vec<u8> {
+    lock_height.to_be_bytes().to_vec()
+}
+
+pub fn decode(payload: &[u8]) -> Option<u32> {
+    Some(u32::from_be_bytes(payload.try_into().ok()?))
+}
+
+// The height transaction is locked until, None if it has no lock, or Err with the vout of a lock
+// whose payload isn't a height.
+pub fn lock_height(transaction: &Transaction<BitName>) -> Result<Option<u32>, u32> {
+    let mut lock_height = None;
+    for (vout, output) in transaction.outputs.iter().enumerate() {
+        if let Content::Custom(BitName::Versioned {
+            version: LOCK_HEIGHT_VERSION,
+            payload,
+        }) = &output.content
+        {
+            let height = decode(payload).ok_or(vout as u32)?;
+            lock_height = lock_height.max(Some(height));
+        }
+    }
+    Ok(lock_height)
+}
@@ -0,0 +1,240 @@
+use log::{LevelFilter, Log, Metadata, Record};
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::{Mutex, RwLock};
+use std::time::SystemTime;
+
+// Log records go to stderr and, with --log-file, to a file that is rotated once it grows past
+// --log-max-size.
+//
+// Which records are written is decided by a filter like `info,mining=debug,rpc=warn`: a default
+// level followed by levels for targets. Targets are module names of this crate, `mining` for
+// src/mining.rs, or names of dependencies like `ddk` and `jsonrpsee`, and the longest matching
+// target wins. The filter can be changed on a running node with the set_log_filter RPC method.
+
+// Records logged by this crate have targets like bitnames::mining.
+const CRATE_PREFIX: &str = "bitnames::";
+
+#[derive(Clone, Debug, clap::Args)]
+pub struct LogConfig {
+    /// Log filter, a default level followed by per-target levels, e.g. `info,mining=debug`.
+    #[arg(long = "log-filter", default_value = "info")]
+    pub filter: Filter,
+    /// Also write the log to this file, relative to the data directory.
+    #[arg(long = "log-file")]
+    pub file: Option<PathBuf>,
+    /// Rotate the log file once it is bigger than this many MiB.
+    #[arg(long = "log-max-size", default_value_t = 64)]
+    pub max_size_mib: u64,
+    /// Number of rotated log files to keep, as <log-file>.1 (the newest) to <log-file>.<n>.
+    #[arg(long = "log-keep", default_value_t = 5)]
+    pub keep: u32,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Filter {
+    default: LevelFilter,
+    targets: Vec<(String, LevelFilter)>,
+}
+
+impl Filter {
+    fn level(&self, target: &str) -> LevelFilter {
+        let target = target.strip_prefix(CRATE_PREFIX).unwrap_or(target);
+        self.targets
+            .iter()
+            .filter(|(prefix, _)| match target.strip_prefix(prefix.as_str()) {
+                Some(rest) => rest.is_empty() || rest.starts_with("::"),
+                None => false,
+            })
+            .max_by_key(|(prefix, _)| prefix.len())
+            .map_or(self.default, |(_, level)| *level)
+    }
+
+    // The most verbose level of any target, records above it are skipped without asking the
+    // logger.
+    fn max_level(&self) -> LevelFilter {
+        self.targets
+            .iter()
+            .map(|(_, level)| *level)
+            .fold(self.default, Ord::max)
+    }
+}
+
+impl FromStr for Filter {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let parse_level = |level: &str| {
+            LevelFilter::from_str(level.trim())
+                .map_err(|_| anyhow::anyhow!("unknown log level {level}"))
+        };
+        let mut filter = Filter {
+            default: LevelFilter::Info,
+            targets: vec![],
+        };
+        for directive in s.split(',').map(str::trim).filter(|d| !d.is_empty()) {
+            match directive.split_once('=') {
+                Some((target, level)) => {
+                    let target = target.trim();
+                    let target = target.strip_prefix(CRATE_PREFIX).unwrap_or(target);
+                    filter
+                        .targets
+                        .push((target.to_string(), parse_level(level)?));
+                }
+                None => filter.default = parse_level(directive)?,
+            }
+        }
+        Ok(filter)
+    }
+}
+
+impl std::fmt::Display for Filter {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.default.as_str().to_lowercase())?;
+        for (target, level) in &self.targets {
+            write!(f, ",{target}={}", level.as_str().to_lowercase())?;
+        }
+        Ok(())
+    }
+}
+
+// Log file that is renamed to <path>.1 once it is bigger than max_size, shifting older files up
+// and deleting the one past keep.
+struct RotatingFile {
+    path: PathBuf,
+    file: File,
+    size: u64,
+    max_size: u64,
+    keep: u32,
+}
+
+impl RotatingFile {
+    fn open(path: &Path, max_size: u64, keep: u32) -> std::io::Result<Self> {
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        let size = file.metadata()?.len();
+        Ok(Self {
+            path: path.to_path_buf(),
+            file,
+            size,
+            max_size,
+            keep,
+        })
+    }
+
+    fn write(&mut self, line: &str) -> std::io::Result<()> {
+        if self.size > 0 && self.size + line.len() as u64 > self.max_size {
+            self.rotate()?;
+        }
+        self.file.write_all(line.as_bytes())?;
+        self.size += line.len() as u64;
+        Ok(())
+    }
+
+    fn rotate(&mut self) -> std::io::Result<()> {
+        if self.keep > 0 {
+            for i in (1..self.keep).rev() {
+                match std::fs::rename(self.rotated(i), self.rotated(i + 1)) {
+                    Err(err) if err.kind() != std::io::ErrorKind::NotFound => return Err(err),
+                    _ => {}
+                }
+            }
+            std::fs::rename(&self.path, self.rotated(1))?;
+        }
+        self.file = OpenOptions::new()
+            .create(true)
+            .write(true)
+            .truncate(true)
+            .open(&self.path)?;
+        self.size = 0;
+        Ok(())
+    }
+
+    fn rotated(&self, i: u32) -> PathBuf {
+        let mut path = self.path.clone().into_os_string();
+        path.push(format!(".{i}"));
+        PathBuf::from(path)
+    }
+}
+
+struct Logger {
+    filter: RwLock<Filter>,
+    file: Option<Mutex<RotatingFile>>,
+}
+
+impl Log for Logger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= self.filter.read().unwrap().level(metadata.target())
+    }
+
+    fn log(&self, record: &Record) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+        let now = SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .unwrap_or_default();
+        let line = format!(
+            "{}.{:03} {} {}: {}\n",
+            now.as_secs(),
+            now.subsec_millis(),
+            record.level(),
+            record.target(),
+            record.args()
+        );
+        // There is nowhere left to report a failed log write to.
+        let _ = std::io::stderr().write_all(line.as_bytes());
+        if let Some(file) = &self.file {
+            let _ = file.lock().unwrap().write(&line);
+        }
+    }
+
+    fn flush(&self) {
+        if let Some(file) = &self.file {
+            let _ = file.lock().unwrap().file.flush();
+        }
+    }
+}
+
+static LOGGER: Mutex<Option<&'static Logger>> = Mutex::new(None);
+
+// Install the logger. Must be called once, before anything is logged.
+pub fn init(config: &LogConfig, datadir: &Path) -> anyhow::Result<()> {
+    let file = match &config.file {
+        Some(path) => Some(Mutex::new(RotatingFile::open(
+            &datadir.join(path),
+            config.max_size_mib * 1024 * 1024,
+            config.keep,
+        )?)),
+        None => None,
+    };
+    let logger: &'static Logger = Box::leak(Box::new(Logger {
+        filter: RwLock::new(config.filter.clone()),
+        file,
+    }));
+    log::set_logger(logger)?;
+    log::set_max_level(config.filter.max_level());
+    *LOGGER.lock().unwrap() = Some(logger);
+    Ok(())
+}
+
+pub fn filter() -> Option<Filter> {
+    let logger = (*LOGGER.lock().unwrap())?;
+    let filter = logger.filter.read().unwrap().clone();
+    Some(filter)
+}
+
+// Replace the filter of the running logger.
+pub fn set_filter(filter: Filter) -> anyhow::Result<()> {
+    let logger = LOGGER
+        .lock()
+        .unwrap()
+        .ok_or_else(|| anyhow::anyhow!("logging is not initialized"))?;
+    log::set_max_level(filter.max_level());
+    *logger.filter.write().unwrap() = filter;
+    Ok(())
+}
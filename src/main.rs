@@ -1,26 +1,839 @@
-use bitnames::{BitName, BitNamesState};
+use clap::{Parser, Subcommand};
 use ddk::authorization::Authorization;
+use ddk::node::State;
+use ddk::types::Hash;
+use idn::NameForm;
+use mainchain::MainchainEndpoint;
+use mempool_store::MempoolStore;
+use network::Network;
+use peer_store::PeerStore;
 use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::Mutex;
+// The node is a consumer of the library: these modules live in src/lib.rs, and are imported here
+// so that the node's own modules reach them as crate::bitnames and so on.
+use ::bitnames::bitnames::{BitName, BitNamesState, StateReader};
+use ::bitnames::{
+    address_book, aliases, analytics, attestation, bitnames, block_template, chain_params,
+    coin_selection, db_env, deployments, disputes, genesis, identity, idn, main_address, mainchain,
+    metrics, notify, onion, parse_hex_32, peer_store, read_pool, records, recovery, rejection,
+    resolution, rpc_api, snapshot, status, tlsa, vectors, watchlist,
+};
 
-mod bitnames;
+mod archive;
+mod backup;
+#[cfg(unix)]
+mod daemon;
+mod discovery;
+mod electrum;
+mod graphql;
+mod grpc;
+#[cfg(feature = "gui")]
+mod gui;
+mod hooks;
+mod logging;
+mod mempool_store;
+mod mempool_watch;
+mod mining;
+mod network;
+mod orphans;
+mod rate_limit;
+mod rebroadcast;
+mod rpc;
+mod rpc_auth;
+mod signer;
+mod tui;
+mod vault;
+mod wallet;
+mod withdrawals;
 
 type Node = ddk::node::Node<Authorization, BitName, BitNamesState>;
 type Wallet = ddk::wallet::Wallet<BitName>;
 type Miner = ddk::miner::Miner<Authorization, BitName>;
 
-// This doesn't do anything, it just shows how to get node, wallet, and miner instances.
-//
+#[derive(Parser)]
+#[command(name = "bitnames")]
+struct Cli {
+    /// Network to run on, selects default ports, sidechain number and data directory.
+    #[arg(long, value_enum, default_value_t = Network::Regtest)]
+    network: Network,
+    /// Shorthand for --network regtest.
+    #[arg(long, conflicts_with = "network")]
+    regtest: bool,
+    /// Data directory, defaults to target/bitnames/<network> in the project root.
+    #[arg(long)]
+    datadir: Option<PathBuf>,
+    /// Sidechain peers to connect to on startup.
+    #[arg(long)]
+    connect: Vec<SocketAddr>,
+    /// Named wallet the miner pays coinbases to, instead of the default wallet. RPC clients pick
+    /// a wallet per call.
+    #[arg(long)]
+    wallet: Option<String>,
+    /// How wallet transactions pick the coins they spend.
+    #[arg(long, value_enum, default_value_t)]
+    coin_selection: coin_selection::CoinSelection,
+    /// Let wallet transactions spend outputs holding a name. They are frozen by default so that a
+    /// routine payment can't spend a registration output.
+    #[arg(long)]
+    allow_name_spend: bool,
+    /// Unix socket of an external signer for the vault, like `bitnames vault serve` or a bridge
+    /// to a hardware signing device, see signer.rs. Enables sign_vault_registration and
+    /// sign_vault_send.
+    #[arg(long)]
+    vault_signer: Option<PathBuf>,
+    /// Sidechain P2P port, defaults to the network's port.
+    #[arg(long)]
+    net_port: Option<u16>,
+    /// DNS seed to discover sidechain peers from. Can be given multiple times, replaces the
+    /// network's default seeds.
+    #[arg(long = "dns-seed")]
+    dns_seeds: Vec<String>,
+    /// Don't discover peers from DNS seeds and the bootstrap list.
+    #[arg(long)]
+    no_discovery: bool,
+    /// JSON-RPC port, defaults to the network's port.
+    #[arg(long)]
+    rpc_port: Option<u16>,
+    /// JSON-RPC user name. Without --rpc-user and --rpc-password a random password is written
+    /// to <datadir>/.cookie on every start instead.
+    #[arg(long, requires = "rpc_password")]
+    rpc_user: Option<String>,
+    /// JSON-RPC password.
+    #[arg(long, requires = "rpc_user")]
+    rpc_password: Option<String>,
+    /// Run as a public resolver: the RPC server only serves the public methods, to everyone and
+    /// within the rate limits, and the miner, TUI and GUI can't be started. Put it behind a
+    /// reverse proxy that appends to X-Forwarded-For, see --rpc-trusted-proxies, GET /readyz
+    /// tells it when the tip is current.
+    #[arg(long)]
+    resolver_only: bool,
+    /// Requests per second each anonymous client may make to the public RPC methods.
+    #[arg(long, default_value_t = 10)]
+    rpc_public_rate: u32,
+    /// Number of requests an anonymous client may burst above --rpc-public-rate.
+    #[arg(long, default_value_t = 20)]
+    rpc_public_burst: u32,
+    /// Number of reverse proxies in front of the RPC server that append the client address to
+    /// X-Forwarded-For. Anonymous clients are rate limited by the entry this many hops from the
+    /// right, with 0 the header is ignored and all of them share one limit.
+    #[arg(long, default_value_t = 0)]
+    rpc_trusted_proxies: usize,
+    /// Threads serving name lookups over RPC, each keeping a read transaction open between
+    /// blocks. Every one takes a reader slot, see --db-max-readers.
+    #[arg(long, default_value_t = 4)]
+    rpc_read_workers: usize,
+    /// Mainchain node RPC endpoint as host:port. Can be given multiple times, the first reachable
+    /// endpoint is used until it fails, then the next reachable one. Defaults to localhost on the
+    /// network's mainchain RPC port.
+    #[arg(long = "mainchain")]
+    mainchain: Vec<MainchainEndpoint>,
+    /// Number of rounds to retry all mainchain endpoints before giving up.
+    #[arg(long, default_value_t = 10)]
+    mainchain_retries: u32,
+    /// Serve the Electrum-style light wallet protocol on this address.
+    #[arg(long)]
+    electrum: Option<SocketAddr>,
+    /// Serve GraphQL queries over name data for explorers on this address, see graphql.rs.
+    #[arg(long)]
+    graphql: Option<SocketAddr>,
+    /// Serve the gRPC interface in proto/bitnames.proto on this address.
+    #[arg(long)]
+    grpc: Option<SocketAddr>,
+    /// Publish every connected block on this address, see notify::serve for the wire format.
+    /// Events are published before the block is committed and may be rolled back, see
+    /// notify::Event.
+    #[arg(long)]
+    notify_block: Option<SocketAddr>,
+    /// Publish every transaction in connected blocks on this address.
+    #[arg(long)]
+    notify_tx: Option<SocketAddr>,
+    /// Publish every name registration or update in connected blocks on this address.
+    #[arg(long)]
+    notify_name: Option<SocketAddr>,
+    /// Publish the height of every disconnected block on this address.
+    #[arg(long)]
+    notify_disconnect: Option<SocketAddr>,
+    /// Publish operator alerts, like a stale tip or an unreachable mainchain, on this address.
+    #[arg(long)]
+    notify_alert: Option<SocketAddr>,
+    /// Consider the tip stale when no block arrived for this many seconds.
+    #[arg(long, default_value_t = 3600)]
+    stale_after: u64,
+    /// GET /readyz fails while the name state is more than this many blocks behind the headers.
+    #[arg(long, default_value_t = 2)]
+    ready_max_lag: u32,
+    /// Log any RPC call, name resolution or block or transaction validation that takes at least
+    /// this many milliseconds. Timings of all of them are served on GET /metrics.
+    #[arg(long, default_value_t = 1000)]
+    slow_threshold_ms: u64,
+    /// Check that the state's databases agree after every connected block, and stop connecting
+    /// blocks at the first one that breaks them.
+    #[cfg(feature = "check-invariants")]
+    #[arg(long)]
+    check_invariants: bool,
+    /// Command to run whenever a watched name changes, with the key, previous value, new value and
+    /// height as arguments.
+    #[arg(long)]
+    name_hook: Option<String>,
+    /// Hex encoded key to run --name-hook for. Can be given multiple times, without it the hook
+    /// runs for every name.
+    #[arg(long = "watch-name", requires = "name_hook")]
+    watch_names: Vec<String>,
+    /// http URL to POST every watchlist event to as JSON.
+    #[arg(long)]
+    watchlist_webhook: Option<hyper::Uri>,
+    /// Directory to archive every connected block body and periodic signed snapshots to, see
+    /// archive.rs.
+    #[arg(long)]
+    archive_dir: Option<PathBuf>,
+    /// Command to run for every archived file, with its path and its path relative to
+    /// --archive-dir as arguments, for example to upload it to object storage.
+    #[arg(long, requires = "archive_dir")]
+    archive_upload: Option<String>,
+    /// Blocks between archived snapshots, 0 to archive blocks only.
+    #[arg(long, default_value_t = 1000, requires = "archive_dir")]
+    archive_snapshot_interval: u32,
+    #[command(flatten)]
+    db: db_env::EnvConfig,
+    #[command(flatten)]
+    log: logging::LogConfig,
+    /// Only keep block bodies and undo data for the most recent <PRUNE> blocks. Must be at least
+    /// 288.
+    #[arg(long)]
+    prune: Option<u32>,
+    /// Stop on startup instead of rolling the name state back more than this many blocks to
+    /// where the chain is, see recovery.rs. ddk doesn't reorg the name state of a running node at
+    /// this revision, so this doesn't limit reorgs while running.
+    #[arg(long, default_value_t = bitnames::DEFAULT_MAX_ROLLBACK_DEPTH)]
+    max_rollback_depth: u32,
+    /// Roll back further than --max-rollback-depth on startup, after checking that the chain is
+    /// the one to follow.
+    #[arg(long)]
+    allow_deep_rollback: bool,
+    /// Skip BitNames validation for blocks at or below this height, once the block at this height
+    /// has been connected with --assume-valid-merkle-root. Until then, and on any other chain,
+    /// blocks are validated as usual.
+    #[arg(long, requires = "assume_valid_merkle_root")]
+    assume_valid: Option<u32>,
+    /// Hex encoded body merkle root of the block at --assume-valid.
+    #[arg(long, requires = "assume_valid")]
+    assume_valid_merkle_root: Option<String>,
+    /// JSON file with the names assigned at genesis, replacing the network's allocation. Every
+    /// node of the network has to use the same file.
+    #[arg(long)]
+    genesis_allocation: Option<PathBuf>,
+    /// Initialize an empty name state from a signed snapshot file instead of validating every
+    /// block. Requires --snapshot-root and --snapshot-public-key.
+    #[arg(long, requires_all = ["snapshot_root", "snapshot_public_key"])]
+    fast_sync: Option<PathBuf>,
+    /// Hex encoded state root the fast sync snapshot must match.
+    #[arg(long)]
+    snapshot_root: Option<String>,
+    /// Hex encoded ed25519 public key of the trusted snapshot publisher.
+    #[arg(long)]
+    snapshot_public_key: Option<String>,
+    #[command(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    Miner {
+        #[command(subcommand)]
+        command: MinerCommand,
+    },
+    /// Replay all stored block bodies and cross-check the name databases against the result.
+    /// Runs without starting the node.
+    VerifyState,
+    /// Print a report of name ownership and registration bursts as CSV. Runs without starting the
+    /// node.
+    Report,
+    /// Run the node with a terminal dashboard of sync status, peers, the mempool and the wallet.
+    Tui,
+    /// Run the node with a window for managing wallets and names, and browsing the namespace.
+    #[cfg(feature = "gui")]
+    Gui,
+    Snapshot {
+        #[command(subcommand)]
+        command: SnapshotCommand,
+    },
+    /// Write test vectors of the consensus rules for the selected network as JSON, see vectors.rs.
+    /// Runs without starting the node.
+    GenVectors {
+        /// File to write the vectors to.
+        out: PathBuf,
+    },
+    /// Print a name in display or raw punycode form, given in either form, with its key. Runs
+    /// without starting the node.
+    Name {
+        name: String,
+        /// Form to print the name in.
+        #[arg(long, value_enum, default_value_t)]
+        form: NameForm,
+    },
+    /// Offline signer for a cold storage wallet, see vault.rs. Runs without starting the node.
+    Vault {
+        #[command(subcommand)]
+        command: VaultCommand,
+    },
+    /// Run the node under a service manager, with a pid file and a control socket in the data
+    /// directory. The node stays in the foreground, detaching is up to the service manager.
+    #[cfg(unix)]
+    Daemon {
+        #[command(subcommand)]
+        command: Option<DaemonCommand>,
+    },
+}
+
+#[derive(Subcommand)]
+enum VaultCommand {
+    /// Write new addresses of the wallet to a file, for import_vault_addresses on the online node.
+    ExportAddresses {
+        /// File to write the addresses to.
+        out: PathBuf,
+        /// Number of addresses to export.
+        #[arg(long, default_value_t = 20)]
+        count: usize,
+    },
+    /// Sign an operation written by build_vault_registration or build_vault_send. The fee it
+    /// prints relies on output values sent by the online node, see vault.rs.
+    Sign {
+        /// File containing the unsigned operation.
+        input: PathBuf,
+        /// File to write the signed transaction to, for submit_vault_transaction.
+        output: PathBuf,
+        #[command(flatten)]
+        rules: vault::SigningRules,
+    },
+    /// Sign operations sent by a node started with --vault-signer on a unix socket, see
+    /// signer.rs, until stopped.
+    #[cfg(unix)]
+    Serve {
+        /// Socket to listen on.
+        socket: PathBuf,
+        #[command(flatten)]
+        rules: vault::SigningRules,
+    },
+}
+
+#[cfg(unix)]
+#[derive(Subcommand)]
+enum DaemonCommand {
+    /// Print the pid and sync status of the daemon running in the data directory.
+    Status,
+    /// Shut down the daemon running in the data directory and wait until it has stopped.
+    Stop,
+    /// Send a JSON-RPC request to the daemon's RPC server, without needing its credentials.
+    Call {
+        /// The request as JSON, e.g. '{"jsonrpc":"2.0","id":1,"method":"get_height","params":[]}'.
+        request: String,
+    },
+}
+
+#[derive(Subcommand)]
+enum SnapshotCommand {
+    /// Write a signed snapshot of the name state at the current tip. Runs without starting the
+    /// node.
+    Create {
+        /// File to write the snapshot to.
+        #[arg(long)]
+        out: PathBuf,
+        /// File containing the hex encoded ed25519 secret key to sign the snapshot with.
+        #[arg(long)]
+        secret_key_file: PathBuf,
+    },
+}
+
+#[derive(Subcommand)]
+enum MinerCommand {
+    /// Continuously build blocks and submit BMM requests to the mainchain.
+    Run {
+        /// Amount in sats paid to the mainchain miner for including each BMM request.
+        #[arg(long, default_value_t = 1000)]
+        bribe: u64,
+        /// Seconds to wait between submitting a BMM request and checking whether it was included.
+        #[arg(long, default_value_t = 10)]
+        interval: u64,
+    },
+}
+
 // After we've got node, wallet, and miner instances we are completely free to use whatever tools
 // we want for API, CLI, TUI, or GUI.
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
-    const DEFAULT_NET_PORT: u16 = 4000;
-    let net_port = DEFAULT_NET_PORT;
+    let mut cli = Cli::parse();
+    if cli.resolver_only {
+        match &cli.command {
+            Some(Command::Miner { .. }) | Some(Command::Tui) => {
+                anyhow::bail!("--resolver-only nodes don't run the miner or the TUI")
+            }
+            #[cfg(feature = "gui")]
+            Some(Command::Gui) => anyhow::bail!("--resolver-only nodes don't run the GUI"),
+            _ => {}
+        }
+    }
+    if cli.regtest {
+        cli.network = Network::Regtest;
+    }
+    let params = cli.network.params();
+    chain_params::select(cli.network.chain_params());
+    let net_port = cli.net_port.unwrap_or(params.net_port);
+    let rpc_port = cli.rpc_port.unwrap_or(params.rpc_port);
     let net_addr: SocketAddr = format!("127.0.0.1:{net_port}").parse()?;
-    let datadir = project_root::get_project_root()?.join("target/bitnames");
+    let rpc_addr: SocketAddr = format!("127.0.0.1:{rpc_port}").parse()?;
+    let datadir = match cli.datadir {
+        Some(datadir) => datadir,
+        None => project_root::get_project_root()?
+            .join("target/bitnames")
+            .join(params.datadir_name),
+    };
     let wallet_path = datadir.join("wallet.mdb");
-    let _node = Node::new(&datadir, net_addr, "localhost", 18443)?;
-    let _wallet = Wallet::new(&wallet_path)?;
-    let _miner = Miner::new(0, "localhost", 18443)?;
+    #[cfg(unix)]
+    if let Some(Command::Daemon {
+        command: Some(command),
+    }) = &cli.command
+    {
+        let request = match command {
+            DaemonCommand::Status => "status",
+            DaemonCommand::Stop => "stop",
+            DaemonCommand::Call { request } => request.as_str(),
+        };
+        println!("{}", daemon::send(&datadir, request).await?);
+        if let DaemonCommand::Stop = command {
+            daemon::wait_stopped(&datadir).await?;
+        }
+        return Ok(());
+    }
+    if let Some(Command::GenVectors { out }) = &cli.command {
+        vectors::write(out)?;
+        return Ok(());
+    }
+    if let Some(Command::Name { name, form }) = &cli.command {
+        println!("{}", idn::format(name, *form)?);
+        println!("{}", hex::encode(aliases::name_key(name)));
+        return Ok(());
+    }
+    if let Some(Command::Vault { command }) = &cli.command {
+        let wallets = wallet::Wallets::new(&datadir, Arc::new(Wallet::new(&wallet_path)?));
+        let wallet = wallets.get(cli.wallet.as_deref())?;
+        match command {
+            VaultCommand::ExportAddresses { out, count } => {
+                vault::export_addresses(&wallet, *count, out)?
+            }
+            VaultCommand::Sign {
+                input,
+                output,
+                rules,
+            } => vault::sign(&wallet, input, output, rules)?,
+            #[cfg(unix)]
+            VaultCommand::Serve { socket, rules } => {
+                signer::serve(wallet, socket, rules.clone()).await?
+            }
+        }
+        return Ok(());
+    }
+    logging::init(&cli.log, &datadir)?;
+    metrics::set_slow_threshold(Duration::from_millis(cli.slow_threshold_ms));
+    #[cfg(feature = "check-invariants")]
+    bitnames::set_check_invariants(cli.check_invariants);
+    let assume_valid = match (cli.assume_valid, cli.assume_valid_merkle_root.as_deref()) {
+        (Some(height), Some(merkle_root)) => Some((height, Hash::from(parse_hex_32(merkle_root)?))),
+        _ => None,
+    };
+    bitnames::set_assume_valid(assume_valid);
+    // These only read the name state, so they run on the env without starting the node or any
+    // networking.
+    if matches!(
+        cli.command,
+        Some(Command::VerifyState) | Some(Command::Report) | Some(Command::Snapshot { .. })
+    ) {
+        let env = db_env::open(&datadir, &cli.db)?;
+        BitNamesState::new(&env)?;
+        let state_reader = StateReader::open(&datadir)?;
+        match &cli.command {
+            Some(Command::Snapshot {
+                command:
+                    SnapshotCommand::Create {
+                        out,
+                        secret_key_file,
+                    },
+            }) => {
+                let secret = parse_hex_32(std::fs::read_to_string(secret_key_file)?.trim())?;
+                let secret = ed25519_dalek::SecretKey::from_bytes(&secret)?;
+                let public = ed25519_dalek::PublicKey::from(&secret);
+                let keypair = ed25519_dalek::Keypair { secret, public };
+                let snapshot = state_reader.read(|txn, state| state.create_snapshot(txn))?;
+                let state_root = snapshot.state_root();
+                std::fs::write(out, bincode::serialize(&snapshot.sign(&keypair))?)?;
+                println!("state root {}", hex::encode(state_root));
+            }
+            Some(Command::VerifyState) => {
+                let divergences = state_reader.read(|txn, state| state.verify_state(txn))?;
+                for divergence in &divergences {
+                    eprintln!("{divergence}");
+                }
+                if !divergences.is_empty() {
+                    anyhow::bail!(
+                        "state is inconsistent, found {} divergences",
+                        divergences.len()
+                    );
+                }
+                println!("state is consistent");
+            }
+            Some(Command::Report) => {
+                let report = state_reader.read(analytics::build)?;
+                print!("{}", report.to_csv());
+            }
+            _ => unreachable!(),
+        }
+        return Ok(());
+    }
+    if cli.mainchain.is_empty() {
+        cli.mainchain.push(MainchainEndpoint {
+            host: "localhost".to_string(),
+            port: params.mainchain_port,
+        });
+    }
+    let selected = mainchain::select_endpoint(&cli.mainchain, cli.mainchain_retries).await?;
+    let failover = mainchain::Failover::new(cli.mainchain.clone(), selected);
+    let mainchain = failover.start().await?;
+    // Publishers are started before the node, so they see the blocks it connects while syncing.
+    let publishers = [
+        (cli.notify_block, notify::Topic::Block),
+        (cli.notify_tx, notify::Topic::Transaction),
+        (cli.notify_name, notify::Topic::Name),
+        (cli.notify_disconnect, notify::Topic::Disconnect),
+        (cli.notify_alert, notify::Topic::Alert),
+    ];
+    for (addr, topic) in publishers {
+        if let Some(addr) = addr {
+            // Bind here so a taken port fails startup instead of a background task.
+            let listener = tokio::net::TcpListener::bind(addr).await?;
+            tokio::spawn(async move {
+                if let Err(err) = notify::serve(listener, topic).await {
+                    log::error!("{} publisher stopped: {err}", topic.name());
+                }
+            });
+        }
+    }
+    if let Some(command) = &cli.name_hook {
+        let watched = cli
+            .watch_names
+            .iter()
+            .map(|key| parse_hex_32(key).map(Hash::from))
+            .collect::<anyhow::Result<Vec<Hash>>>()?;
+        tokio::spawn(hooks::run(command.clone(), watched));
+    }
+    let watchlist = Arc::new(watchlist::Watchlist::open(&datadir)?);
+    tokio::spawn(watchlist::run(
+        watchlist.clone(),
+        cli.watchlist_webhook.clone(),
+    ));
+    let env = db_env::open(&datadir, &cli.db)?;
+    // Imported before the node starts, which connects blocks from peers and the mainchain and so
+    // leaves the state no longer empty.
+    if let Some(path) = &cli.fast_sync {
+        let signed: snapshot::SignedSnapshot = bincode::deserialize(&std::fs::read(path)?)?;
+        let state_root = parse_hex_32(cli.snapshot_root.as_deref().unwrap())?;
+        let public_key = parse_hex_32(cli.snapshot_public_key.as_deref().unwrap())?;
+        let public_key = ed25519_dalek::PublicKey::from_bytes(&public_key)?;
+        signed.verify(&state_root, &public_key)?;
+        let state = BitNamesState::new(&env)?;
+        let mut txn = env.write_txn()?;
+        state.import_snapshot(&mut txn, &signed.snapshot)?;
+        txn.commit()?;
+        log::info!("imported snapshot at height {}", signed.snapshot.height);
+    }
+    if cli.db.sync == db_env::SyncMode::Auto {
+        tokio::spawn(db_env::run_auto_sync(env.clone()));
+    }
+    let node = Arc::new(Node::new(
+        &datadir,
+        net_addr,
+        &mainchain.host,
+        mainchain.port.into(),
+    )?);
+    let wallets = Arc::new(wallet::Wallets::new(
+        &datadir,
+        Arc::new(Wallet::new(&wallet_path)?),
+    ));
+    // Wallet the miner pays coinbases to, created on first use.
+    let wallet = match &cli.wallet {
+        Some(name) => {
+            if !wallets.list()?.contains(name) {
+                wallets.create(name)?;
+            }
+            wallets.get(Some(name))?
+        }
+        None => wallets.get(None)?,
+    };
+    let miner = Miner::new(
+        params.sidechain_number,
+        &mainchain.host,
+        mainchain.port.into(),
+    )?;
+    let miner = Arc::new(Mutex::new(miner));
+
+    // Resubmit transactions that were pending when the node last shut down. Children of
+    // transactions that were mined meanwhile, or that come before their parent, wait as orphans.
+    let orphans = Arc::new(orphans::OrphanPool::new());
+    tokio::spawn(orphans::run(orphans.clone(), node.clone()));
+    let mempool_store = MempoolStore::new(&datadir);
+    for transaction in mempool_store.load()? {
+        if node.fill_transaction(&transaction.transaction).is_err()
+            && orphans.add(transaction.clone())
+        {
+            continue;
+        }
+        if let Err(err) = node.submit_transaction(&transaction).await {
+            log::warn!("dropping persisted mempool transaction: {err}");
+        }
+    }
+
+    // Peers given on the command line must be reachable, stored ones are best effort.
+    let peer_store = Arc::new(PeerStore::open(&datadir)?);
+    for peer in &cli.connect {
+        node.connect(*peer).await?;
+        peer_store.record_success(*peer)?;
+    }
+    for peer in peer_store.best(peer_store::STARTUP_PEERS) {
+        if cli.connect.contains(&peer) {
+            continue;
+        }
+        match node.connect(peer).await {
+            Ok(()) => peer_store.record_success(peer)?,
+            Err(err) => {
+                log::warn!("failed to connect to stored peer {peer}: {err}");
+                peer_store.record_failure(peer)?;
+            }
+        }
+    }
+
+    if !cli.no_discovery {
+        let seeds = if cli.dns_seeds.is_empty() {
+            params
+                .dns_seeds
+                .iter()
+                .map(|seed| seed.to_string())
+                .collect()
+        } else {
+            cli.dns_seeds.clone()
+        };
+        let mut bootstrap = vec![];
+        for peer in params.bootstrap_peers {
+            match tokio::net::lookup_host(peer).await {
+                Ok(addrs) => bootstrap.extend(addrs),
+                Err(err) => log::warn!("failed to resolve bootstrap peer {peer}: {err}"),
+            }
+        }
+        tokio::spawn(discovery::run(
+            node.clone(),
+            peer_store.clone(),
+            seeds,
+            bootstrap,
+            params.net_port,
+        ));
+    }
+
+    let state_reader = StateReader::open(&datadir)?;
+    let genesis_names = match &cli.genesis_allocation {
+        Some(path) => genesis::load(path)?,
+        None => genesis::parse(cli.network.chain_params().genesis_allocation)?,
+    };
+    state_reader.write(|txn, state| state.apply_genesis(txn, &genesis_names))?;
+    let max_rollback_depth = (!cli.allow_deep_rollback).then_some(cli.max_rollback_depth);
+    state_reader.write(|txn, state| state.set_max_rollback_depth(txn, max_rollback_depth))?;
+    recovery::recover(&node, &state_reader)?;
+    state_reader.write(|txn, state| state.set_prune_depth(txn, cli.prune))?;
+
+    if let Some(addr) = cli.electrum {
+        electrum::spawn(addr, state_reader.clone()).await?;
+    }
+    if let Some(addr) = cli.graphql {
+        graphql::spawn(addr, state_reader.clone()).await?;
+    }
+    if let Some(dir) = &cli.archive_dir {
+        let archive = archive::Archive::new(
+            dir.clone(),
+            cli.archive_upload.clone(),
+            cli.archive_snapshot_interval,
+            resolution::load_or_create_key(&datadir)?,
+            state_reader.clone(),
+        )?;
+        tokio::spawn(archive::run(archive));
+    }
+
+    let status_monitor = Arc::new(status::StatusMonitor::new(
+        state_reader.clone(),
+        node.clone(),
+        peer_store.clone(),
+        Duration::from_secs(cli.stale_after),
+        cli.ready_max_lag,
+    )?);
+    tokio::spawn(status::run(status_monitor.clone(), failover.clone()));
+
+    let withdrawals = Arc::new(withdrawals::Withdrawals::open(&datadir)?);
+    tokio::spawn(withdrawals::run(withdrawals.clone(), node.clone()));
+    tokio::spawn(rebroadcast::run(node.clone()));
+    let mempool_watch = Arc::new(mempool_watch::MempoolWatch::new());
+    tokio::spawn(mempool_watch::run(mempool_watch.clone(), node.clone()));
+    let reports = Arc::new(analytics::ReportCache::default());
+    tokio::spawn(analytics::run(reports.clone(), state_reader.clone()));
+    let read_pool = Arc::new(read_pool::ReadPool::new(
+        &state_reader,
+        cli.rpc_read_workers,
+    ));
+    tokio::spawn(read_pool::run(read_pool.clone()));
+    let rpc = rpc::RpcServerImpl::new(
+        node.clone(),
+        wallets.clone(),
+        miner.clone(),
+        peer_store.clone(),
+        watchlist,
+        mempool_watch,
+        orphans.clone(),
+        address_book::AddressBook::open(&datadir)?,
+        withdrawals,
+        resolution::load_or_create_key(&datadir)?,
+        status_monitor.clone(),
+        reports,
+        vault::VaultAddresses::open(&datadir)?,
+        cli.vault_signer.clone().map(signer::ExternalSigner::new),
+        coin_selection::SpendPolicy {
+            strategy: cli.coin_selection,
+            allow_name_spend: cli.allow_name_spend,
+        },
+        state_reader.clone(),
+        read_pool,
+        datadir.clone(),
+        cli.network == Network::Regtest,
+    );
+    let (authorization, cookie) = match (&cli.rpc_user, &cli.rpc_password) {
+        (Some(user), Some(password)) => (rpc_api::basic_authorization(user, password), None),
+        _ => {
+            let cookie = rpc_auth::Cookie::create(&datadir)?;
+            let authorization =
+                rpc_api::basic_authorization(rpc_auth::COOKIE_USER, &cookie.password);
+            (authorization, Some(cookie))
+        }
+    };
+    if let Some(addr) = cli.grpc {
+        grpc::spawn(
+            addr,
+            node.clone(),
+            orphans.clone(),
+            state_reader.clone(),
+            authorization.clone(),
+        )
+        .await?;
+    }
+    let rate_limiter = rate_limit::RateLimiter::new(cli.rpc_public_rate, cli.rpc_public_burst);
+    let rpc_handle = rpc::run_server(
+        rpc,
+        rpc_addr,
+        authorization.clone(),
+        rate_limiter,
+        cli.resolver_only,
+        cli.rpc_trusted_proxies,
+    )
+    .await?;
+
+    // Removed last, so that `bitnames daemon stop` returns once shutdown has finished.
+    #[cfg(unix)]
+    let mut daemon_control = None;
+    match cli.command {
+        Some(Command::Miner {
+            command: MinerCommand::Run { bribe, interval },
+        }) => {
+            let interval = Duration::from_secs(interval);
+            // Dropping the miner future at an await point is safe: heed transactions are never
+            // held across one, and an interrupted BMM attempt is simply retried next start.
+            tokio::select! {
+                result = mining::run(&node, &wallet, &miner, &state_reader, &orphans, bribe, interval) => result?,
+                result = shutdown_signal() => result?,
+            }
+        }
+        Some(Command::Tui) => {
+            let dashboard = tui::Dashboard {
+                node: node.clone(),
+                wallet: wallet.clone(),
+                peers: peer_store,
+                status: status_monitor,
+                state: state_reader,
+            };
+            tokio::task::spawn_blocking(move || tui::run(dashboard)).await??;
+        }
+        #[cfg(feature = "gui")]
+        Some(Command::Gui) => {
+            let gui = gui::Gui {
+                node: node.clone(),
+                wallets,
+                status: status_monitor,
+                state: state_reader,
+                spend_policy: coin_selection::SpendPolicy {
+                    strategy: cli.coin_selection,
+                    allow_name_spend: cli.allow_name_spend,
+                },
+                runtime: tokio::runtime::Handle::current(),
+            };
+            // Windowing systems want the event loop on the main thread, which is the one running
+            // this future, so block it in place instead of moving the GUI to a blocking thread.
+            tokio::task::block_in_place(move || gui::run(gui))?;
+        }
+        #[cfg(unix)]
+        Some(Command::Daemon { command: None }) => {
+            let control =
+                daemon::Control::start(&datadir, status_monitor, rpc_addr, authorization).await?;
+            tokio::select! {
+                result = shutdown_signal() => result?,
+                _ = control.stopped() => {}
+            }
+            daemon_control = Some(control);
+        }
+        #[cfg(unix)]
+        Some(Command::Daemon { command: Some(_) }) => unreachable!(),
+        Some(Command::VerifyState)
+        | Some(Command::Report)
+        | Some(Command::Snapshot { .. })
+        | Some(Command::GenVectors { .. })
+        | Some(Command::Name { .. })
+        | Some(Command::Vault { .. }) => {
+            unreachable!()
+        }
+        None => shutdown_signal().await?,
+    }
+    log::info!("shutting down");
+    rpc_handle.stop()?;
+    rpc_handle.stopped().await;
+    if let Some(cookie) = cookie {
+        cookie.remove()?;
+    }
+
+    mempool_store.save(&node.get_all_transactions()?)?;
+    // With --db-sync auto/no-sync/no-meta-sync the last commits may only be in the OS page cache.
+    env.force_sync()?;
+    #[cfg(unix)]
+    if let Some(control) = daemon_control {
+        control.remove()?;
+    }
+    Ok(())
+}
+
+// Resolves on SIGINT (ctrl-c) or, on unix, SIGTERM.
+async fn shutdown_signal() -> anyhow::Result<()> {
+    #[cfg(unix)]
+    {
+        let mut terminate =
+            tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate())?;
+        tokio::select! {
+            result = tokio::signal::ctrl_c() => result?,
+            _ = terminate.recv() => {}
+        }
+    }
+    #[cfg(not(unix))]
+    tokio::signal::ctrl_c().await?;
     Ok(())
 }
@@ -0,0 +1,109 @@
+use base64::Engine;
+use ddk::bitcoin;
+use ddk::types::{Hash, Transaction};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+// BitName::Versioned content version of mainchain address bindings.
+//
+// A binding tells mainchain payers which Bitcoin address pays the owner of a name. It has to
+// carry a proof that the holder of the address agreed: a `bitcoin-cli signmessage` signature by the
+// address over proof_message(key). Only legacy (P2PKH) addresses can sign messages that way. The
+// signature format needs key recovery, so the record carries the public key as well and the
+// signature is checked against it.
+//
+// A binding is only valid in the transaction registering its key. Once the main_address
+// deployment is active, a transaction carrying a binding that isn't valid is rejected, so every
+// indexed binding has been checked by consensus. Before that, invalid bindings are ignored.
+pub const MAIN_ADDRESS_VERSION: u8 = 8;
+
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Eq)]
+pub struct MainAddressRecord {
+    pub key: Hash,
+    pub address: String,
+    // SEC1 encoded, compressed or not.
+    pub public_key: Vec<u8>,
+    // Base64, as printed by signmessage.
+    pub signature: String,
+}
+
+impl MainAddressRecord {
+    // Whether address is the P2PKH address of public_key, on the mainnet or the test networks,
+    // and signature is its signature over proof_message(key).
+    pub fn verify(&self) -> bool {
+        let public_key = match bitcoin::PublicKey::from_slice(&self.public_key) {
+            Ok(public_key) => public_key,
+            Err(_) => return false,
+        };
+        let derives_address = [bitcoin::Network::Bitcoin, bitcoin::Network::Testnet]
+            .into_iter()
+            .any(|network| {
+                bitcoin::Address::p2pkh(&public_key, network).to_string() == self.address
+            });
+        if !derives_address {
+            return false;
+        }
+        // A header byte, then the compact (r, s) signature.
+        let signature = match base64::engine::general_purpose::STANDARD.decode(&self.signature) {
+            Ok(signature) if signature.len() == 65 => signature,
+            _ => return false,
+        };
+        let mut signature =
+            match bitcoin::secp256k1::ecdsa::Signature::from_compact(&signature[1..]) {
+                Ok(signature) => signature,
+                Err(_) => return false,
+            };
+        signature.normalize_s();
+        let message = signed_message_hash(&proof_message(&self.key));
+        let message = match bitcoin::secp256k1::Message::from_slice(&message) {
+            Ok(message) => message,
+            Err(_) => return false,
+        };
+        bitcoin::secp256k1::Secp256k1::verification_only()
+            .verify_ecdsa(&message, &signature, &public_key.inner)
+            .is_ok()
+    }
+
+    // Decode a Versioned payload, if it is a valid binding transaction is allowed to carry.
+    pub fn decode(
+        payload: &[u8],
+        transaction: &Transaction<crate::bitnames::BitName>,
+    ) -> Option<Self> {
+        let record: Self = bincode::deserialize(payload).ok()?;
+        if !crate::bitnames::registered_keys(transaction).contains(&record.key) || !record.verify()
+        {
+            return None;
+        }
+        Some(record)
+    }
+
+    pub fn encode(&self) -> Vec<u8> {
+        bincode::serialize(self).expect("mainchain address records always serialize")
+    }
+}
+
+// The message the address signs, `bitnames:` followed by the hex encoded key, short enough to
+// paste into any wallet's sign message dialog.
+pub fn proof_message(key: &Hash) -> String {
+    format!("bitnames:{}", hex::encode(key))
+}
+
+// Bitcoin's signed message hash: double SHA-256 of a magic prefix, the message length as a
+// compact size and the message.
+fn signed_message_hash(message: &str) -> [u8; 32] {
+    let mut data = b"\x18Bitcoin Signed Message:\n".to_vec();
+    let len = message.len();
+    match len {
+        0..=0xfc => data.push(len as u8),
+        0xfd..=0xffff => {
+            data.push(0xfd);
+            data.extend((len as u16).to_le_bytes());
+        }
+        _ => {
+            data.push(0xfe);
+            data.extend((len as u32).to_le_bytes());
+        }
+    }
+    data.extend(message.as_bytes());
+    Sha256::digest(Sha256::digest(&data)).into()
+}
@@ -0,0 +1,180 @@
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::net::{TcpListener, TcpStream};
+
+// Delay before the first retry when no mainchain endpoint is reachable, doubled after every
+// failed round up to MAX_BACKOFF.
+pub const INITIAL_BACKOFF: Duration = Duration::from_secs(1);
+pub const MAX_BACKOFF: Duration = Duration::from_secs(60);
+
+// How long to wait for a mainchain node to accept a connection before considering it down.
+const HEALTH_CHECK_TIMEOUT: Duration = Duration::from_secs(5);
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct MainchainEndpoint {
+    pub host: String,
+    pub port: u16,
+}
+
+impl std::fmt::Display for MainchainEndpoint {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}:{}", self.host, self.port)
+    }
+}
+
+impl FromStr for MainchainEndpoint {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (host, port) = s
+            .rsplit_once(':')
+            .ok_or_else(|| Error::InvalidEndpoint(s.to_string()))?;
+        let port = port
+            .parse()
+            .map_err(|_| Error::InvalidEndpoint(s.to_string()))?;
+        Ok(Self {
+            host: host.to_string(),
+            port,
+        })
+    }
+}
+
+impl MainchainEndpoint {
+    // An endpoint is considered healthy if it accepts a TCP connection, ddk itself reports any
+    // RPC level problems once it starts talking to it.
+    pub async fn is_healthy(&self) -> bool {
+        matches!(connect(self).await, Ok(Ok(_)))
+    }
+}
+
+// Pick the first healthy endpoint, in the order they were given.
+//
+// If none are healthy, retry with exponential backoff for up to max_rounds rounds before giving
+// up with an error listing every endpoint that was tried.
+pub async fn select_endpoint(
+    endpoints: &[MainchainEndpoint],
+    max_rounds: u32,
+) -> Result<MainchainEndpoint, Error> {
+    let mut backoff = INITIAL_BACKOFF;
+    for round in 0..max_rounds {
+        for endpoint in endpoints {
+            if endpoint.is_healthy().await {
+                return Ok(endpoint.clone());
+            }
+            log::warn!("mainchain node at {endpoint} is unreachable");
+        }
+        if round + 1 < max_rounds {
+            log::info!("retrying mainchain endpoints in {}s", backoff.as_secs());
+            tokio::time::sleep(backoff).await;
+            backoff = (backoff * 2).min(MAX_BACKOFF);
+        }
+    }
+    Err(Error::NoHealthyEndpoint(
+        endpoints
+            .iter()
+            .map(|endpoint| endpoint.to_string())
+            .collect::<Vec<_>>()
+            .join(", "),
+    ))
+}
+
+// Failover between mainchain endpoints while the node runs.
+//
+// ddk connects to the mainchain endpoint it is constructed with for as long as it runs, so with
+// more than one endpoint it is pointed at a relay on localhost instead, which forwards every
+// connection to the active endpoint. When the active endpoint stops accepting connections the
+// relay moves on to the first healthy one, in the order they were given, so the node and the
+// miner follow without a restart. Connections open at the time fail and are made again by ddk.
+pub struct Failover {
+    endpoints: Vec<MainchainEndpoint>,
+    active: Mutex<MainchainEndpoint>,
+}
+
+impl Failover {
+    pub fn new(endpoints: Vec<MainchainEndpoint>, active: MainchainEndpoint) -> Arc<Self> {
+        Arc::new(Self {
+            endpoints,
+            active: Mutex::new(active),
+        })
+    }
+
+    pub fn active(&self) -> MainchainEndpoint {
+        self.active.lock().unwrap().clone()
+    }
+
+    // The endpoint for ddk to connect to: the active endpoint if it is the only one, else a relay
+    // started on localhost.
+    pub async fn start(self: &Arc<Self>) -> Result<MainchainEndpoint, Error> {
+        if self.endpoints.len() < 2 {
+            return Ok(self.active());
+        }
+        let listener = TcpListener::bind(("127.0.0.1", 0)).await?;
+        let relay = MainchainEndpoint {
+            host: "127.0.0.1".to_string(),
+            port: listener.local_addr()?.port(),
+        };
+        let failover = self.clone();
+        tokio::spawn(async move {
+            loop {
+                let inbound = match listener.accept().await {
+                    Ok((inbound, _)) => inbound,
+                    Err(err) => {
+                        log::error!("mainchain relay stopped: {err}");
+                        return;
+                    }
+                };
+                tokio::spawn(failover.clone().relay(inbound));
+            }
+        });
+        Ok(relay)
+    }
+
+    async fn relay(self: Arc<Self>, mut inbound: TcpStream) {
+        let mut outbound = match self.connect().await {
+            Some(outbound) => outbound,
+            None => {
+                log::warn!("no mainchain node is reachable");
+                return;
+            }
+        };
+        let _ = tokio::io::copy_bidirectional(&mut inbound, &mut outbound).await;
+    }
+
+    // Connect to the active endpoint, or else to the first healthy one, which becomes active.
+    async fn connect(&self) -> Option<TcpStream> {
+        let active = self.active();
+        if let Ok(Ok(stream)) = connect(&active).await {
+            return Some(stream);
+        }
+        log::warn!("mainchain node at {active} is unreachable");
+        for endpoint in &self.endpoints {
+            if *endpoint == active {
+                continue;
+            }
+            if let Ok(Ok(stream)) = connect(endpoint).await {
+                log::warn!("failing over to mainchain node at {endpoint}");
+                *self.active.lock().unwrap() = endpoint.clone();
+                return Some(stream);
+            }
+        }
+        None
+    }
+}
+
+async fn connect(
+    endpoint: &MainchainEndpoint,
+) -> Result<std::io::Result<TcpStream>, tokio::time::error::Elapsed> {
+    let connect = TcpStream::connect((endpoint.host.as_str(), endpoint.port));
+    tokio::time::timeout(HEALTH_CHECK_TIMEOUT, connect).await
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("invalid mainchain endpoint {0}, expected host:port")]
+    InvalidEndpoint(String),
+    #[error("no mainchain node is reachable, tried: {0}")]
+    NoHealthyEndpoint(String),
+    #[error("failed to start the mainchain relay: {0}")]
+    Relay(#[from] std::io::Error),
+}
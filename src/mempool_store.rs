@@ -0,0 +1,43 @@
+use crate::bitnames::BitName;
+use ddk::authorization::Authorization;
+use ddk::types::AuthorizedTransaction;
+use std::path::{Path, PathBuf};
+
+// ddk keeps the mempool in memory only, so without this pending registrations are silently
+// dropped whenever the node restarts.
+//
+// The whole mempool is written out as a single bincode encoded Vec on shutdown, and read back and
+// resubmitted on startup. Resubmitting goes through the regular ddk validation path, which is
+// what drops transactions that became invalid while the node was down (for example because the
+// key got registered by someone else in the meantime).
+pub struct MempoolStore {
+    path: PathBuf,
+}
+
+impl MempoolStore {
+    pub fn new(datadir: &Path) -> Self {
+        Self {
+            path: datadir.join("mempool.dat"),
+        }
+    }
+
+    pub fn load(&self) -> anyhow::Result<Vec<AuthorizedTransaction<Authorization, BitName>>> {
+        if !self.path.exists() {
+            return Ok(vec![]);
+        }
+        let bytes = std::fs::read(&self.path)?;
+        Ok(bincode::deserialize(&bytes)?)
+    }
+
+    pub fn save(
+        &self,
+        transactions: &[AuthorizedTransaction<Authorization, BitName>],
+    ) -> anyhow::Result<()> {
+        // Write to a temporary file first, so a crash halfway through doesn't leave a truncated
+        // mempool file behind.
+        let tmp_path = self.path.with_extension("dat.tmp");
+        std::fs::write(&tmp_path, bincode::serialize(transactions)?)?;
+        std::fs::rename(&tmp_path, &self.path)?;
+        Ok(())
+    }
+}
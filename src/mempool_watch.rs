@@ -0,0 +1,98 @@
+use crate::bitnames::registered_keys;
+use crate::rpc_api::MempoolEvent;
+use crate::Node;
+use ddk::types::{Hash, Txid};
+use std::collections::{BTreeMap, HashMap};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::broadcast;
+
+// ddk accepts transactions into its mempool without telling anyone, from the RPC server and from
+// peers alike, so the mempool is polled and compared with what it held the last time.
+const POLL_INTERVAL: Duration = Duration::from_secs(1);
+// Events a WebSocket subscriber that falls further behind than this misses.
+const CHANNEL_CAPACITY: usize = 1024;
+
+// Mempool changes for subscribe_mempool subscribers. Nothing is kept for subscribers that connect
+// later, get_all_transactions already answers what is in the mempool right now.
+pub struct MempoolWatch {
+    sender: broadcast::Sender<MempoolEvent>,
+}
+
+impl MempoolWatch {
+    pub fn new() -> Self {
+        Self {
+            sender: broadcast::channel(CHANNEL_CAPACITY).0,
+        }
+    }
+
+    pub fn subscribe(&self) -> broadcast::Receiver<MempoolEvent> {
+        self.sender.subscribe()
+    }
+
+    fn publish(&self, event: MempoolEvent) {
+        // No receivers just means no WebSocket subscribers right now.
+        let _ = self.sender.send(event);
+    }
+}
+
+// Publish an Accepted event for every transaction that entered the mempool and a Removed event for
+// every one that left it, by being mined, replaced or dropped. A new transaction registering a key
+// that another unconfirmed transaction registers as well also gets a RegistrationConflict event, at
+// most one of them can make it into a block.
+pub async fn run(watch: Arc<MempoolWatch>, node: Arc<Node>) {
+    let mut previous: HashMap<Txid, Vec<Hash>> = HashMap::new();
+    let mut interval = tokio::time::interval(POLL_INTERVAL);
+    loop {
+        interval.tick().await;
+        let transactions = match node.get_all_transactions() {
+            Ok(transactions) => transactions,
+            Err(err) => {
+                log::error!("failed to read the mempool: {err}");
+                continue;
+            }
+        };
+        let current: HashMap<Txid, Vec<Hash>> = transactions
+            .iter()
+            .map(|transaction| {
+                let keys = registered_keys(&transaction.transaction);
+                (transaction.transaction.txid(), keys)
+            })
+            .collect();
+        let mut registrations: BTreeMap<Hash, Vec<Txid>> = BTreeMap::new();
+        for (txid, keys) in &current {
+            for key in keys {
+                registrations.entry(*key).or_default().push(*txid);
+            }
+        }
+        for txid in previous.keys() {
+            if !current.contains_key(txid) {
+                watch.publish(MempoolEvent::Removed { txid: *txid });
+            }
+        }
+        for (txid, keys) in &current {
+            if previous.contains_key(txid) {
+                continue;
+            }
+            watch.publish(MempoolEvent::Accepted {
+                txid: *txid,
+                keys: keys.clone(),
+            });
+            for key in keys {
+                let txids = &registrations[key];
+                if txids.len() > 1 {
+                    log::info!(
+                        "{} unconfirmed transactions register key {}",
+                        txids.len(),
+                        hex::encode(key)
+                    );
+                    watch.publish(MempoolEvent::RegistrationConflict {
+                        key: *key,
+                        txids: txids.clone(),
+                    });
+                }
+            }
+        }
+        previous = current;
+    }
+}
@@ -0,0 +1,112 @@
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+// Latency and error counts of RPC methods and of state operations like block validation, served
+// in the Prometheus text format on GET /metrics. Anything slower than the slow threshold is also
+// logged, with what it was working on.
+//
+// Operations run inside BitNamesState, which ddk constructs, so like notify the registry is
+// global rather than passed around. Peer messages are handled entirely inside ddk, which doesn't
+// report them, so there are no per peer metrics.
+#[derive(Clone, Debug, Default)]
+struct Timing {
+    count: u64,
+    errors: u64,
+    total: Duration,
+    max: Duration,
+}
+
+// Keyed by (kind, name), kind being "rpc" or "operation".
+static TIMINGS: Mutex<BTreeMap<(&'static str, String), Timing>> = Mutex::new(BTreeMap::new());
+
+static SLOW_THRESHOLD_MS: AtomicU64 = AtomicU64::new(1000);
+
+pub fn set_slow_threshold(threshold: Duration) {
+    SLOW_THRESHOLD_MS.store(threshold.as_millis() as u64, Ordering::Relaxed);
+}
+
+fn is_slow(elapsed: Duration) -> bool {
+    elapsed.as_millis() as u64 >= SLOW_THRESHOLD_MS.load(Ordering::Relaxed)
+}
+
+fn record(kind: &'static str, name: &str, elapsed: Duration, success: bool) {
+    let mut timings = TIMINGS.lock().unwrap();
+    let timing = timings.entry((kind, name.to_string())).or_default();
+    timing.count += 1;
+    if !success {
+        timing.errors += 1;
+    }
+    timing.total += elapsed;
+    timing.max = timing.max.max(elapsed);
+}
+
+pub fn record_rpc(method: &str, elapsed: Duration, success: bool) {
+    record("rpc", method, elapsed, success);
+    if is_slow(elapsed) {
+        log::warn!("slow RPC call: {method} took {} ms", elapsed.as_millis());
+    }
+}
+
+// Times an operation from creation until it is dropped, so every return path of the operation
+// is counted. Which way it ended isn't known then, so operations have no error counts, rejected
+// blocks and transactions are logged with their reasons anyway.
+pub struct Timer {
+    operation: &'static str,
+    detail: String,
+    started: Instant,
+}
+
+impl Timer {
+    pub fn start(operation: &'static str, detail: String) -> Self {
+        Self {
+            operation,
+            detail,
+            started: Instant::now(),
+        }
+    }
+}
+
+impl Drop for Timer {
+    fn drop(&mut self) {
+        let elapsed = self.started.elapsed();
+        record("operation", self.operation, elapsed, true);
+        if is_slow(elapsed) {
+            log::warn!(
+                "slow {}: {} took {} ms",
+                self.operation,
+                self.detail,
+                elapsed.as_millis()
+            );
+        }
+    }
+}
+
+// Every timing recorded so far, in the Prometheus text exposition format.
+pub fn render() -> String {
+    let timings = TIMINGS.lock().unwrap();
+    let mut text = String::new();
+    for (kind, label) in [("rpc", "method"), ("operation", "operation")] {
+        let metrics: [(&str, &str, fn(&Timing) -> f64); 4] = [
+            ("calls_total", "counter", |timing| timing.count as f64),
+            ("errors_total", "counter", |timing| timing.errors as f64),
+            ("seconds_total", "counter", |timing| {
+                timing.total.as_secs_f64()
+            }),
+            ("seconds_max", "gauge", |timing| timing.max.as_secs_f64()),
+        ];
+        for (suffix, metric_type, value) in metrics {
+            if kind == "operation" && suffix == "errors_total" {
+                continue;
+            }
+            let name = format!("bitnames_{kind}_{suffix}");
+            let _ = writeln!(text, "# TYPE {name} {metric_type}");
+            for ((_, subject), timing) in timings.iter().filter(|((k, _), _)| *k == kind) {
+                let _ = writeln!(text, "{name}{{{label}=\"{subject}\"}} {}", value(timing));
+            }
+        }
+    }
+    text
+}
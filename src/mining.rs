@@ -0,0 +1,244 @@
+use crate::bitnames::{filled_transaction_fee, registered_keys, BitName, StateReader};
+use crate::block_template::{select_transactions, TemplateCandidate};
+use crate::deployments::DeploymentState;
+use crate::mainchain::{INITIAL_BACKOFF, MAX_BACKOFF};
+use crate::orphans::OrphanPool;
+use crate::rejection;
+use crate::{Miner, Node, Wallet};
+use ddk::authorization::Authorization;
+use ddk::types::{
+    Address, AuthorizedTransaction, Body, Content, FilledTransaction, Header, OutPoint, Output,
+    Transaction,
+};
+use std::collections::HashMap;
+use std::time::Duration;
+use tokio::sync::Mutex;
+
+// Continuously mine blocks with blind merged mining.
+//
+// Every round builds a block template from the mempool, asks the mainchain node to include a BMM
+// request paying `bribe` sats to the mainchain miner, waits `interval`, and then checks whether
+// the request made it into a mainchain block. If it did, the block is connected locally and
+// broadcast to peers.
+pub async fn run(
+    node: &Node,
+    wallet: &Wallet,
+    miner: &Mutex<Miner>,
+    state: &StateReader,
+    orphans: &OrphanPool,
+    bribe: u64,
+    interval: Duration,
+) -> anyhow::Result<()> {
+    let mut backoff = INITIAL_BACKOFF;
+    loop {
+        match mine_round(node, wallet, miner, state, orphans, bribe, interval).await {
+            Ok(()) => backoff = INITIAL_BACKOFF,
+            // A flaky mainchain node shouldn't stop the miner, keep retrying with exponential
+            // backoff until it comes back.
+            Err(err) => {
+                log::warn!(
+                    "mining round failed, retrying in {}s: {err}",
+                    backoff.as_secs()
+                );
+                tokio::time::sleep(backoff).await;
+                backoff = (backoff * 2).min(MAX_BACKOFF);
+            }
+        }
+    }
+}
+
+async fn mine_round(
+    node: &Node,
+    wallet: &Wallet,
+    miner: &Mutex<Miner>,
+    state: &StateReader,
+    orphans: &OrphanPool,
+    bribe: u64,
+    interval: Duration,
+) -> anyhow::Result<()> {
+    // The miner is only locked while talking to the mainchain, so block templates can still be
+    // served over RPC while we wait.
+    {
+        let mut miner = miner.lock().await;
+        let address = wallet.get_new_address()?;
+        let (header, body) = build_block(node, &miner, state, orphans, address).await?;
+        miner.attempt_bmm(bribe, 0, header, body).await?;
+    }
+    tokio::time::sleep(interval).await;
+    let confirmed = miner.lock().await.confirm_bmm().await?;
+    if let Some((header, body)) = confirmed {
+        node.submit_block(&header, &body).await?;
+    }
+    Ok(())
+}
+
+// Mine n blocks right away against a regtest mainchain, by generating a mainchain block after
+// every BMM request instead of waiting for one.
+pub async fn generate(
+    node: &Node,
+    wallet: &Wallet,
+    miner: &Mutex<Miner>,
+    state: &StateReader,
+    orphans: &OrphanPool,
+    n: u32,
+) -> anyhow::Result<()> {
+    let mut miner = miner.lock().await;
+    for _ in 0..n {
+        let address = wallet.get_new_address()?;
+        let (header, body) = build_block(node, &miner, state, orphans, address).await?;
+        miner.attempt_bmm(0, 0, header, body).await?;
+        miner.generate().await?;
+        if let Some((header, body)) = miner.confirm_bmm().await? {
+            node.submit_block(&header, &body).await?;
+        }
+    }
+    Ok(())
+}
+
+// Build a block on top of the current sidechain and mainchain tips, with the coinbase paying all
+// collected fees, less the burned registration prices, to coinbase_address.
+//
+// Orphans spending outputs of mempool transactions are candidates too, mined in the same block as
+// their parents, so a child paying a high fee can get a stuck parent mined. Only this node's RPC
+// clients and mempool.dat put transactions into the orphan pool, and ddk hasn't checked their
+// authorizations yet, so a block including a badly signed orphan is rejected by ddk when it is
+// submitted.
+//
+// Mempool transactions were valid when they were accepted, but blocks connected since may have
+// made them invalid, say by registering the same key, so every candidate is validated again for
+// this block and left out if it fails. Conflicts between candidates are up to
+// select_transactions.
+//
+// The coinbase also signals for every deployment that is waiting for miner support, since
+// supporting a deployment just means running code that implements its rules, which this node
+// does.
+pub async fn build_block(
+    node: &Node,
+    miner: &Miner,
+    state: &StateReader,
+    orphans: &OrphanPool,
+    coinbase_address: Address,
+) -> anyhow::Result<(Header, Body<ddk::authorization::Authorization, BitName>)> {
+    let params = state.params();
+    let height = node.get_height()? + 1;
+    let price = state.read(|txn, state| state.registration_price_at(txn, height))?;
+    let mempool = node.get_all_transactions()?;
+    let mut mempool_outputs = HashMap::new();
+    for transaction in &mempool {
+        let txid = transaction.transaction.txid();
+        for (vout, output) in transaction.transaction.outputs.iter().enumerate() {
+            let vout = vout as u32;
+            mempool_outputs.insert(OutPoint::Regular { txid, vout }, output.clone());
+        }
+    }
+    let candidates = mempool
+        .into_iter()
+        .chain(orphans.transactions())
+        .filter_map(|transaction| {
+            template_candidate(node, state, &mempool_outputs, height, price, transaction)
+        })
+        .collect();
+    let selected = select_transactions(candidates, params.max_registrations_per_block);
+    let fee = selected.iter().map(|candidate| candidate.fee).sum();
+    let transactions = selected
+        .into_iter()
+        .map(|candidate| candidate.transaction)
+        .collect();
+    let mut coinbase = vec![Output {
+        address: coinbase_address,
+        content: Content::Value(fee),
+    }];
+    let bits = state.read(|txn, state| {
+        let mut bits = 0;
+        for deployment in params.deployments {
+            if state.get_deployment_state(txn, deployment)? == DeploymentState::Started {
+                bits |= 1 << deployment.bit;
+            }
+        }
+        Ok(bits)
+    })?;
+    if bits != 0 {
+        coinbase.push(Output {
+            address: coinbase_address,
+            content: Content::Custom(BitName::Signal { bits }),
+        });
+    }
+    let body = Body::new(transactions, coinbase);
+    let header = Header {
+        merkle_root: body.compute_merkle_root(),
+        prev_side_hash: node.get_best_hash()?,
+        prev_main_hash: miner.drivechain.get_mainchain_tip().await?,
+    };
+    Ok((header, body))
+}
+
+// transaction as a candidate for the block at height, None if its inputs can't be found or it
+// doesn't pass validate_transaction there.
+fn template_candidate(
+    node: &Node,
+    state: &StateReader,
+    mempool_outputs: &HashMap<OutPoint, Output<BitName>>,
+    height: u32,
+    price: u64,
+    transaction: AuthorizedTransaction<Authorization, BitName>,
+) -> Option<TemplateCandidate> {
+    let filled = match node.fill_transaction(&transaction.transaction) {
+        Ok(filled) => filled,
+        Err(_) => fill_from_mempool(node, mempool_outputs, &transaction.transaction)?,
+    };
+    let result = rejection::check_funding(&filled)
+        .map_err(|rejection| rejection.message)
+        .and_then(|()| {
+            state
+                .read(|txn, state| state.validate_transaction(txn, height, &filled))
+                .map_err(|err| err.to_string())
+        });
+    if let Err(err) = result {
+        let txid = hex::encode(ddk::types::Hash::from(transaction.transaction.txid()));
+        log::debug!("leaving {txid} out of the block template: {err}");
+        return None;
+    }
+    // Registration prices are burned, the miner only gets what is left of the fee.
+    let burn = registered_keys(&transaction.transaction).len() as u64 * price;
+    Some(TemplateCandidate {
+        fee: filled_transaction_fee(&filled).saturating_sub(burn),
+        transaction,
+    })
+}
+
+// Fill transaction from mempool_outputs for inputs that are outputs of mempool transactions, and
+// from the utxo set for the rest. None if any input is in neither, or none is from the mempool,
+// ddk fills those itself.
+fn fill_from_mempool(
+    node: &Node,
+    mempool_outputs: &HashMap<OutPoint, Output<BitName>>,
+    transaction: &Transaction<BitName>,
+) -> Option<FilledTransaction<BitName>> {
+    let (from_mempool, confirmed): (Vec<OutPoint>, Vec<OutPoint>) = transaction
+        .inputs
+        .iter()
+        .cloned()
+        .partition(|input| mempool_outputs.contains_key(input));
+    if from_mempool.is_empty() {
+        return None;
+    }
+    let filled = node
+        .fill_transaction(&Transaction {
+            inputs: confirmed,
+            outputs: vec![],
+        })
+        .ok()?;
+    let mut confirmed = filled.spent_utxos.into_iter();
+    let spent_utxos = transaction
+        .inputs
+        .iter()
+        .map(|input| match mempool_outputs.get(input) {
+            Some(output) => Some(output.clone()),
+            None => confirmed.next(),
+        })
+        .collect::<Option<Vec<_>>>()?;
+    Some(FilledTransaction {
+        spent_utxos,
+        transaction: transaction.clone(),
+    })
+}
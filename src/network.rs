@@ -0,0 +1,79 @@
+use crate::chain_params::{self, ChainParams};
+
+// Per network defaults, so the same binary can run a regtest devnet and a production deployment.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, clap::ValueEnum)]
+pub enum Network {
+    Mainnet,
+    Testnet,
+    Signet,
+    Regtest,
+}
+
+pub struct NetworkParams {
+    // Default sidechain P2P port.
+    pub net_port: u16,
+    // Default sidechain JSON-RPC port.
+    pub rpc_port: u16,
+    // Default RPC port of the mainchain node this network pegs to.
+    pub mainchain_port: u16,
+    // Sidechain slot number on the mainchain.
+    pub sidechain_number: u32,
+    // Subfolder of the data directory, so data for different networks never gets mixed up.
+    pub datadir_name: &'static str,
+    // DNS names resolving to sidechain peers, queried on the network's P2P port.
+    pub dns_seeds: &'static [&'static str],
+    // Peers dialed on every start, as host:port.
+    pub bootstrap_peers: &'static [&'static str],
+}
+
+impl Network {
+    pub fn chain_params(self) -> &'static ChainParams {
+        match self {
+            Network::Mainnet => &chain_params::MAINNET,
+            Network::Testnet => &chain_params::TESTNET,
+            Network::Signet => &chain_params::SIGNET,
+            Network::Regtest => &chain_params::REGTEST,
+        }
+    }
+
+    pub fn params(self) -> NetworkParams {
+        match self {
+            Network::Mainnet => NetworkParams {
+                net_port: 34000,
+                rpc_port: 36000,
+                mainchain_port: 8332,
+                sidechain_number: 0,
+                datadir_name: "mainnet",
+                dns_seeds: &[],
+                bootstrap_peers: &[],
+            },
+            Network::Testnet => NetworkParams {
+                net_port: 14000,
+                rpc_port: 16000,
+                mainchain_port: 18332,
+                sidechain_number: 0,
+                datadir_name: "testnet",
+                dns_seeds: &[],
+                bootstrap_peers: &[],
+            },
+            Network::Signet => NetworkParams {
+                net_port: 44000,
+                rpc_port: 46000,
+                mainchain_port: 38332,
+                sidechain_number: 0,
+                datadir_name: "signet",
+                dns_seeds: &[],
+                bootstrap_peers: &[],
+            },
+            Network::Regtest => NetworkParams {
+                net_port: 4000,
+                rpc_port: 6000,
+                mainchain_port: 18443,
+                sidechain_number: 0,
+                datadir_name: "regtest",
+                dns_seeds: &[],
+                bootstrap_peers: &[],
+            },
+        }
+    }
+}
@@ -0,0 +1,144 @@
+use crate::bitnames::BitName;
+use ddk::authorization::Authorization;
+use ddk::types::{Body, Hash, Transaction};
+use serde::{Deserialize, Serialize};
+use std::sync::{Arc, Mutex};
+use tokio::io::AsyncWriteExt;
+use tokio::sync::broadcast;
+
+// Events a subscriber that falls further behind than this misses, rather than slowing down block
+// connection.
+const CHANNEL_CAPACITY: usize = 1024;
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct NameChange {
+    pub key: Hash,
+    // Value before the change, None for a first registration.
+    pub previous: Option<Hash>,
+    pub value: Hash,
+    pub height: u32,
+}
+
+// Events are published by connect_body and disconnect_body, inside the write transaction ddk
+// connects or disconnects the block in and before ddk commits it. A subscriber can get an event
+// for a block that ends up never connected, when ddk fails to commit or rejects the block after
+// the name state accepted it, and reading the state right on an event can still show it as it
+// was before. Subscribers that need the change to be durable should check it against the state,
+// by the block's height and body merkle root.
+#[derive(Clone, Debug)]
+pub enum Event {
+    Block {
+        height: u32,
+        body: Arc<Body<Authorization, BitName>>,
+    },
+    Transaction(Arc<Transaction<BitName>>),
+    Name(NameChange),
+    // The block at height was disconnected, height - 1 is the new tip.
+    Disconnected {
+        height: u32,
+    },
+    // Operator facing warning, like a stale tip.
+    Alert(String),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Topic {
+    Block,
+    Transaction,
+    Name,
+    Disconnect,
+    Alert,
+}
+
+impl Topic {
+    pub fn name(self) -> &'static str {
+        match self {
+            Topic::Block => "rawblock",
+            Topic::Transaction => "rawtx",
+            Topic::Name => "name",
+            Topic::Disconnect => "disconnect",
+            Topic::Alert => "alert",
+        }
+    }
+}
+
+impl Event {
+    pub fn topic(&self) -> Topic {
+        match self {
+            Event::Block { .. } => Topic::Block,
+            Event::Transaction(_) => Topic::Transaction,
+            Event::Name(_) => Topic::Name,
+            Event::Disconnected { .. } => Topic::Disconnect,
+            Event::Alert(_) => Topic::Alert,
+        }
+    }
+
+    // Bincode encoded (height, body), transaction, NameChange, disconnected height, or alert
+    // message.
+    pub fn payload(&self) -> Result<Vec<u8>, bincode::Error> {
+        match self {
+            Event::Block { height, body } => bincode::serialize(&(height, body.as_ref())),
+            Event::Transaction(transaction) => bincode::serialize(transaction.as_ref()),
+            Event::Name(change) => bincode::serialize(change),
+            Event::Disconnected { height } => bincode::serialize(height),
+            Event::Alert(message) => bincode::serialize(message),
+        }
+    }
+}
+
+// BitNamesState is constructed by ddk, so like READERS the channel is global rather than passed
+// in. It only exists once something subscribed, until then publish is a no-op.
+static EVENTS: Mutex<Option<broadcast::Sender<Event>>> = Mutex::new(None);
+
+pub fn subscribe() -> broadcast::Receiver<Event> {
+    let mut events = EVENTS.lock().unwrap();
+    events
+        .get_or_insert_with(|| broadcast::channel(CHANNEL_CAPACITY).0)
+        .subscribe()
+}
+
+pub fn publish(event: Event) {
+    if let Some(events) = EVENTS.lock().unwrap().as_ref() {
+        // No receivers is fine, nobody is listening right now.
+        let _ = events.send(event);
+    }
+}
+
+// Accept subscribers on listener and stream every event of topic to each of them.
+//
+// Every event is sent as two frames, the topic name and then the payload, each prefixed with its
+// length as a big endian u32. Subscribers can't send anything, and one that can't keep up misses
+// events instead of being buffered for.
+pub async fn serve(listener: tokio::net::TcpListener, topic: Topic) -> anyhow::Result<()> {
+    loop {
+        let (mut stream, _) = listener.accept().await?;
+        let mut events = subscribe();
+        tokio::spawn(async move {
+            loop {
+                let event = match events.recv().await {
+                    Ok(event) => event,
+                    Err(broadcast::error::RecvError::Lagged(_)) => continue,
+                    Err(broadcast::error::RecvError::Closed) => return,
+                };
+                if event.topic() != topic {
+                    continue;
+                }
+                let payload = match event.payload() {
+                    Ok(payload) => payload,
+                    Err(err) => {
+                        log::error!("failed to encode {} notification: {err}", topic.name());
+                        continue;
+                    }
+                };
+                let mut frames = vec![];
+                for frame in [topic.name().as_bytes(), &payload] {
+                    frames.extend((frame.len() as u32).to_be_bytes());
+                    frames.extend(frame);
+                }
+                if stream.write_all(&frames).await.is_err() {
+                    return;
+                }
+            }
+        });
+    }
+}
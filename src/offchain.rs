@@ -0,0 +1,157 @@
+use ddk::types::Hash;
+use ed25519_dalek::{Keypair, PublicKey, Signature, Signer, Verifier};
+use serde::{Deserialize, Serialize};
+
+// Off-chain data pointers.
+//
+// Data that is large or changes often doesn't belong on chain, so a name can carry a
+// Record::Offchain instead, pointing at where its data is published, with the ed25519 key that
+// signs it and the lowest sequence number resolvers accept. The data is published as
+// OffchainData, with the name's key, a sequence number, an expiry time and the key's signature
+// over all of them, so it can be fetched from any server or mirror and checked by the resolver
+// rather than trusted.
+//
+// The minimum sequence anchored on chain keeps a server from passing off data the owner has
+// replaced since, and the expiry bounds how long any copy can be served at all, so owners should
+// republish before it runs out. Resolvers that fetch a name's data more than once should also
+// refuse sequence numbers below the highest they have seen.
+//
+// The minimum is raised by a SequenceRecord under OFFCHAIN_VERSION, signed by the pointer's key,
+// which any wallet may carry like identity key rotations. There are no consensus rules for them,
+// a record that isn't signed by the pointer's key or doesn't raise the minimum is ignored.
+pub const OFFCHAIN_VERSION: u8 = 10;
+
+pub const MAX_URL_LEN: usize = 512;
+
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Eq)]
+pub struct OffchainPointer {
+    // Where the data is published. Any scheme will do, the data is checked by its signature
+    // rather than by where it came from.
+    pub url: String,
+    pub public_key: PublicKey,
+    pub min_sequence: u64,
+}
+
+impl OffchainPointer {
+    pub fn validate(&self) -> anyhow::Result<()> {
+        let scheme_valid = match self.url.split_once("://") {
+            Some((scheme, rest)) => {
+                !scheme.is_empty()
+                    && !rest.is_empty()
+                    && scheme.bytes().all(|byte| {
+                        byte.is_ascii_lowercase()
+                            || byte.is_ascii_digit()
+                            || byte == b'+'
+                            || byte == b'-'
+                            || byte == b'.'
+                    })
+            }
+            None => false,
+        };
+        if !scheme_valid
+            || self.url.len() > MAX_URL_LEN
+            || self
+                .url
+                .chars()
+                .any(|c| c.is_whitespace() || c.is_control())
+        {
+            anyhow::bail!("{:?} isn't a URL", self.url);
+        }
+        Ok(())
+    }
+}
+
+// Raises the minimum sequence of key's pointer to min_sequence.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct SequenceRecord {
+    pub key: Hash,
+    pub min_sequence: u64,
+    // The pointer key's signature over sequence_message.
+    pub signature: Signature,
+}
+
+impl SequenceRecord {
+    pub fn new(keypair: &Keypair, key: Hash, min_sequence: u64) -> Self {
+        Self {
+            key,
+            min_sequence,
+            signature: keypair.sign(&sequence_message(&key, min_sequence)),
+        }
+    }
+
+    pub fn decode(payload: &[u8]) -> Option<Self> {
+        bincode::deserialize(payload).ok()
+    }
+
+    pub fn encode(&self) -> Vec<u8> {
+        bincode::serialize(self).expect("sequence records always serialize")
+    }
+
+    // Whether this record raises pointer's minimum, with a signature by its key.
+    pub fn verify(&self, pointer: &OffchainPointer) -> bool {
+        let message = sequence_message(&self.key, self.min_sequence);
+        self.min_sequence > pointer.min_sequence
+            && pointer.public_key.verify(&message, &self.signature).is_ok()
+    }
+}
+
+// Data published for a name, as fetched from its pointer's URL.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct OffchainData {
+    pub key: Hash,
+    pub sequence: u64,
+    // Unix time from which resolvers refuse the data.
+    pub expires: u64,
+    pub data: Vec<u8>,
+    // The pointer key's signature over data_message.
+    pub signature: Signature,
+}
+
+impl OffchainData {
+    pub fn sign(keypair: &Keypair, key: Hash, sequence: u64, expires: u64, data: Vec<u8>) -> Self {
+        let signature = keypair.sign(&data_message(&key, sequence, expires, &data));
+        Self {
+            key,
+            sequence,
+            expires,
+            data,
+            signature,
+        }
+    }
+
+    // Whether resolvers may take this as key's data, given key's pointer, or why not.
+    // pointer.min_sequence should be the current one, see BitNamesState::get_offchain_pointer.
+    pub fn verify(&self, key: &Hash, pointer: &OffchainPointer) -> anyhow::Result<()> {
+        if self.key != *key {
+            anyhow::bail!("data is published for another name");
+        }
+        let message = data_message(&self.key, self.sequence, self.expires, &self.data);
+        if pointer
+            .public_key
+            .verify(&message, &self.signature)
+            .is_err()
+        {
+            anyhow::bail!("data isn't signed by the pointer's key");
+        }
+        if self.sequence < pointer.min_sequence {
+            anyhow::bail!(
+                "sequence {} is below the minimum {}",
+                self.sequence,
+                pointer.min_sequence
+            );
+        }
+        let now = crate::clock::now();
+        if self.expires <= now {
+            anyhow::bail!("data expired at {}, it is {now}", self.expires);
+        }
+        Ok(())
+    }
+}
+
+pub fn sequence_message(key: &Hash, min_sequence: u64) -> Hash {
+    ddk::types::hash(&(key, min_sequence))
+}
+
+pub fn data_message(key: &Hash, sequence: u64, expires: u64, data: &[u8]) -> Hash {
+    ddk::types::hash(&(key, sequence, expires, data))
+}
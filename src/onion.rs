@@ -0,0 +1,57 @@
+use ddk::types::{Hash, Transaction};
+use ed25519_dalek::{PublicKey, Signature, Verifier};
+use serde::{Deserialize, Serialize};
+
+// BitName::Versioned content version of onion service bindings.
+//
+// A binding points a name at a Tor v3 onion service. The service's ed25519 identity key signs the
+// name's key, so the binding proves the service agreed to it rather than taking the registrant's
+// word for it. Names never change after registration, so neither do bindings: one is only valid
+// in the transaction that registers its key. Both rules are enforced once the onion deployment is
+// active, see deployments.rs.
+pub const ONION_VERSION: u8 = 2;
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct OnionRecord {
+    pub key: Hash,
+    // The identity key the v3 onion address encodes. Clients derive the address from it the usual
+    // way, base32 of the key, its checksum and the version byte.
+    pub service_key: PublicKey,
+    pub signature: Signature,
+}
+
+impl OnionRecord {
+    // Decode a Versioned payload and check it is a binding transaction is allowed to carry.
+    pub fn decode(
+        payload: &[u8],
+        transaction: &Transaction<crate::bitnames::BitName>,
+    ) -> Option<Self> {
+        let record: Self = bincode::deserialize(payload).ok()?;
+        record
+            .service_key
+            .verify(&message(&record.key), &record.signature)
+            .ok()?;
+        if !crate::bitnames::registered_keys(transaction).contains(&record.key) {
+            return None;
+        }
+        Some(record)
+    }
+
+    pub fn encode(&self) -> Vec<u8> {
+        bincode::serialize(self).expect("onion records always serialize")
+    }
+}
+
+// An onion binding as indexed, with where it was found.
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Eq)]
+pub struct OnionBinding {
+    pub service_key: PublicKey,
+    pub txid: Hash,
+    pub height: u32,
+}
+
+// What the service key signs to bind itself to key. Tagged, so the signature can't be replayed
+// as anything else the service key might sign.
+pub fn message(key: &Hash) -> Hash {
+    ddk::types::hash(&("bitnames onion binding", key))
+}
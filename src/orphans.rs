@@ -0,0 +1,98 @@
+use crate::bitnames::BitName;
+use crate::notify::{self, Event};
+use crate::Node;
+use ddk::authorization::Authorization;
+use ddk::types::{AuthorizedTransaction, Hash, Txid};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use tokio::sync::broadcast;
+
+// ddk only accepts transactions whose inputs are confirmed, so a child submitted before its parent
+// is mined, or resubmitted from mempool.dat before it, would otherwise be rejected and have to be
+// broadcast again by hand.
+//
+// Orphans are kept in memory only, and at most this many at a time.
+const MAX_ORPHANS: usize = 100;
+// Blocks an orphan is retried for before it is dropped, its parent will likely never confirm.
+const MAX_ORPHAN_AGE: u32 = 6;
+
+struct Orphan {
+    transaction: AuthorizedTransaction<Authorization, BitName>,
+    blocks_left: u32,
+}
+
+// Transactions whose inputs aren't known yet, retried after every block.
+pub struct OrphanPool {
+    orphans: Mutex<HashMap<Txid, Orphan>>,
+}
+
+impl OrphanPool {
+    pub fn new() -> Self {
+        Self {
+            orphans: Mutex::new(HashMap::new()),
+        }
+    }
+
+    // Hold transaction until its inputs are confirmed. Returns false if the pool is full.
+    pub fn add(&self, transaction: AuthorizedTransaction<Authorization, BitName>) -> bool {
+        let mut orphans = self.orphans.lock().unwrap();
+        let txid = transaction.transaction.txid();
+        if !orphans.contains_key(&txid) && orphans.len() >= MAX_ORPHANS {
+            return false;
+        }
+        orphans.insert(
+            txid,
+            Orphan {
+                transaction,
+                blocks_left: MAX_ORPHAN_AGE,
+            },
+        );
+        true
+    }
+
+    pub fn transactions(&self) -> Vec<AuthorizedTransaction<Authorization, BitName>> {
+        let orphans = self.orphans.lock().unwrap();
+        orphans
+            .values()
+            .map(|orphan| orphan.transaction.clone())
+            .collect()
+    }
+}
+
+// After every block, submit the orphans whose inputs are now all known. Orphans that ddk rejects
+// for another reason, or that are still missing inputs after MAX_ORPHAN_AGE blocks, are dropped.
+pub async fn run(pool: Arc<OrphanPool>, node: Arc<Node>) {
+    let mut events = notify::subscribe();
+    loop {
+        match events.recv().await {
+            Ok(Event::Block { .. }) | Err(broadcast::error::RecvError::Lagged(_)) => {}
+            Ok(_) => continue,
+            Err(broadcast::error::RecvError::Closed) => return,
+        }
+        let mut ready = vec![];
+        pool.orphans.lock().unwrap().retain(|txid, orphan| {
+            if node
+                .fill_transaction(&orphan.transaction.transaction)
+                .is_ok()
+            {
+                ready.push(orphan.transaction.clone());
+                return false;
+            }
+            orphan.blocks_left -= 1;
+            if orphan.blocks_left == 0 {
+                log::info!(
+                    "dropping orphan transaction {}, its inputs never confirmed",
+                    hex::encode(Hash::from(*txid))
+                );
+            }
+            orphan.blocks_left > 0
+        });
+        for transaction in ready {
+            let txid = hex::encode(Hash::from(transaction.transaction.txid()));
+            match node.submit_transaction(&transaction).await {
+                Ok(()) => log::info!("submitted orphan transaction {txid}"),
+                Err(err) => log::info!("dropping orphan transaction {txid}: {err}"),
+            }
+        }
+    }
+}
@@ -0,0 +1,100 @@
+use crate::clock;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+// Peers that are tried on startup, best scored first.
+pub const STARTUP_PEERS: usize = 8;
+// Peers below this score are not tried on startup anymore.
+const MIN_SCORE: i32 = -10;
+
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct PeerInfo {
+    // Unix time of the last successful connection, 0 if there never was one.
+    pub last_seen: u64,
+    // +1 for every successful connection, -1 for every failed one.
+    pub score: i32,
+    // Unix time until which the peer is banned.
+    pub banned_until: Option<u64>,
+}
+
+// Known peer addresses, persisted as a bincode encoded map in peers.dat so that a restarted node
+// can reconnect to peers it has seen before without needing --connect.
+//
+// ddk doesn't report which peers are connected, so the store only learns about peers that this
+// node dialed itself, either from --connect or from the add_peer RPC.
+pub struct PeerStore {
+    path: PathBuf,
+    peers: Mutex<HashMap<SocketAddr, PeerInfo>>,
+}
+
+impl PeerStore {
+    pub fn open(datadir: &Path) -> anyhow::Result<Self> {
+        let path = datadir.join("peers.dat");
+        let peers = if path.exists() {
+            bincode::deserialize(&std::fs::read(&path)?)?
+        } else {
+            HashMap::new()
+        };
+        Ok(Self {
+            path,
+            peers: Mutex::new(peers),
+        })
+    }
+
+    pub fn record_success(&self, addr: SocketAddr) -> anyhow::Result<()> {
+        self.update(addr, |info| {
+            info.last_seen = clock::now();
+            info.score = info.score.saturating_add(1);
+        })
+    }
+
+    pub fn record_failure(&self, addr: SocketAddr) -> anyhow::Result<()> {
+        self.update(addr, |info| info.score = info.score.saturating_sub(1))
+    }
+
+    pub fn ban(&self, addr: SocketAddr, seconds: u64) -> anyhow::Result<()> {
+        self.update(addr, |info| {
+            info.banned_until = Some(clock::now().saturating_add(seconds))
+        })
+    }
+
+    pub fn is_banned(&self, addr: &SocketAddr) -> bool {
+        let peers = self.peers.lock().unwrap();
+        peers
+            .get(addr)
+            .and_then(|info| info.banned_until)
+            .map_or(false, |until| until > clock::now())
+    }
+
+    pub fn list(&self) -> Vec<(SocketAddr, PeerInfo)> {
+        let peers = self.peers.lock().unwrap();
+        peers
+            .iter()
+            .map(|(addr, info)| (*addr, info.clone()))
+            .collect()
+    }
+
+    // Up to n peers worth trying, ordered by score and then by how recently they were seen.
+    pub fn best(&self, n: usize) -> Vec<SocketAddr> {
+        let mut peers: Vec<_> = self
+            .list()
+            .into_iter()
+            .filter(|(addr, info)| info.score >= MIN_SCORE && !self.is_banned(addr))
+            .collect();
+        peers.sort_by_key(|(_, info)| std::cmp::Reverse((info.score, info.last_seen)));
+        peers.into_iter().take(n).map(|(addr, _)| addr).collect()
+    }
+
+    fn update(&self, addr: SocketAddr, f: impl FnOnce(&mut PeerInfo)) -> anyhow::Result<()> {
+        let mut peers = self.peers.lock().unwrap();
+        f(peers.entry(addr).or_default());
+        // Same write-then-rename as MempoolStore::save.
+        let tmp_path = self.path.with_extension("dat.tmp");
+        std::fs::write(&tmp_path, bincode::serialize(&*peers)?)?;
+        std::fs::rename(&tmp_path, &self.path)?;
+        Ok(())
+    }
+}
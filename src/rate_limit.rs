@@ -0,0 +1,109 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::Instant;
+
+// Above this many tracked clients, buckets that have refilled completely are dropped, and if
+// none have, the one updated longest ago, so a flood of distinct addresses can't grow the map
+// without bound.
+const MAX_TRACKED_CLIENTS: usize = 10_000;
+
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+// Token bucket per client key. Every client starts with burst tokens, gets rate tokens per
+// second back up to burst, and every request costs one.
+pub struct RateLimiter {
+    rate: f64,
+    burst: f64,
+    buckets: Mutex<HashMap<String, Bucket>>,
+}
+
+impl RateLimiter {
+    pub fn new(rate: u32, burst: u32) -> Self {
+        Self {
+            rate: rate as f64,
+            burst: burst.max(1) as f64,
+            buckets: Mutex::new(HashMap::new()),
+        }
+    }
+
+    // Take a token for client, returns false if it has none left.
+    pub fn check(&self, client: &str) -> bool {
+        self.check_at(client, Instant::now())
+    }
+
+    fn check_at(&self, client: &str, now: Instant) -> bool {
+        let mut buckets = self.buckets.lock().unwrap();
+        if buckets.len() >= MAX_TRACKED_CLIENTS && !buckets.contains_key(client) {
+            let (rate, burst) = (self.rate, self.burst);
+            buckets.retain(|_, bucket| {
+                bucket.tokens + now.duration_since(bucket.updated).as_secs_f64() * rate < burst
+            });
+            if buckets.len() >= MAX_TRACKED_CLIENTS {
+                let oldest = buckets
+                    .iter()
+                    .min_by_key(|(_, bucket)| bucket.updated)
+                    .map(|(client, _)| client.clone());
+                if let Some(oldest) = oldest {
+                    buckets.remove(&oldest);
+                }
+            }
+        }
+        let bucket = buckets.entry(client.to_string()).or_insert(Bucket {
+            tokens: self.burst,
+            updated: now,
+        });
+        let elapsed = now.duration_since(bucket.updated).as_secs_f64();
+        bucket.tokens = (bucket.tokens + elapsed * self.rate).min(self.burst);
+        bucket.updated = now;
+        if bucket.tokens < 1.0 {
+            return false;
+        }
+        bucket.tokens -= 1.0;
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn refills_at_rate_up_to_burst() {
+        let limiter = RateLimiter::new(1, 2);
+        let start = Instant::now();
+        assert!(limiter.check_at("a", start));
+        assert!(limiter.check_at("a", start));
+        assert!(!limiter.check_at("a", start));
+        // Other clients have their own bucket.
+        assert!(limiter.check_at("b", start));
+        assert!(limiter.check_at("a", start + Duration::from_secs(1)));
+        assert!(!limiter.check_at("a", start + Duration::from_secs(1)));
+        // Never more than burst, however long the client was away.
+        let later = start + Duration::from_secs(60);
+        assert!(limiter.check_at("a", later));
+        assert!(limiter.check_at("a", later));
+        assert!(!limiter.check_at("a", later));
+    }
+
+    #[test]
+    fn tracks_a_bounded_number_of_clients() {
+        // With no refill no bucket ever fills up again, so only eviction keeps the map bounded.
+        let limiter = RateLimiter::new(0, 1);
+        let start = Instant::now();
+        for i in 0..MAX_TRACKED_CLIENTS {
+            let now = start + Duration::from_millis(i as u64);
+            assert!(limiter.check_at(&i.to_string(), now));
+        }
+        let now = start + Duration::from_secs(60);
+        assert!(limiter.check_at("new", now));
+        let buckets = limiter.buckets.lock().unwrap();
+        assert_eq!(buckets.len(), MAX_TRACKED_CLIENTS);
+        assert!(!buckets.contains_key("0"));
+        assert!(buckets.contains_key("1"));
+        assert!(buckets.contains_key("new"));
+    }
+}
@@ -0,0 +1,192 @@
+use crate::bitnames::{BitNamesState, Error, StateReader};
+use crate::notify::{self, Event};
+use ddk::heed;
+use serde::{Deserialize, Serialize};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{mpsc, Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::sync::broadcast;
+
+// A worker's transaction is renewed once it is this old even without a new block, so no read is
+// answered from older data, and so a long lived reader doesn't keep LMDB from reusing pages
+// freed by mempool writes.
+const MAX_TRANSACTION_AGE: Duration = Duration::from_secs(1);
+
+type Job = Box<dyn FnOnce(&heed::RoTxn, &BitNamesState) + Send>;
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ReadPoolStats {
+    pub workers: usize,
+    pub reads: u64,
+    // Read transactions the workers opened, reads / transactions is how many reads each served.
+    pub transactions: u64,
+    // Reads answered from a transaction that didn't include the latest block yet, because the
+    // block was announced before ddk committed it. Those are at most MAX_TRANSACTION_AGE behind.
+    pub stale_reads: u64,
+    // Age of the oldest transaction a worker currently holds, in milliseconds.
+    pub oldest_transaction_ms: u64,
+}
+
+// Long lived read transactions for the RPC server, so a busy resolver doesn't open one per
+// request. Each of a fixed number of worker threads keeps a transaction open and runs reads in
+// it, renewing it when a block is connected or disconnected and after MAX_TRANSACTION_AGE. heed
+// transactions can't move between threads, so reads are sent to the workers rather than the
+// transactions handed out, which also keeps reads off the async workers.
+//
+// Every worker holds an LMDB reader slot for as long as the node runs, see --db-max-readers.
+pub struct ReadPool {
+    jobs: Mutex<mpsc::Sender<Job>>,
+    shared: Arc<Shared>,
+    workers: usize,
+}
+
+struct Shared {
+    jobs: Mutex<mpsc::Receiver<Job>>,
+    // Bumped on every block connected or disconnected, together with the tip that results, if
+    // it is known.
+    latest: Mutex<(u64, Option<u32>)>,
+    // When each worker opened the transaction it holds, if it holds one.
+    opened: Mutex<Vec<Option<Instant>>>,
+    reads: AtomicU64,
+    transactions: AtomicU64,
+    stale_reads: AtomicU64,
+}
+
+impl ReadPool {
+    // At least one worker is started, whatever workers is.
+    pub fn new(state: &StateReader, workers: usize) -> Self {
+        let workers = workers.max(1);
+        let (sender, receiver) = mpsc::channel();
+        let shared = Arc::new(Shared {
+            jobs: Mutex::new(receiver),
+            latest: Mutex::new((0, None)),
+            opened: Mutex::new(vec![None; workers]),
+            reads: AtomicU64::new(0),
+            transactions: AtomicU64::new(0),
+            stale_reads: AtomicU64::new(0),
+        });
+        for index in 0..workers {
+            let env = state.env().clone();
+            let state = state.state().clone();
+            let shared = shared.clone();
+            std::thread::spawn(move || work(index, env, state, shared));
+        }
+        Self {
+            jobs: Mutex::new(sender),
+            shared,
+            workers,
+        }
+    }
+
+    // Run f in one of the workers' transactions, like StateReader::read.
+    pub async fn read<T: Send + 'static>(
+        &self,
+        f: impl FnOnce(&heed::RoTxn, &BitNamesState) -> Result<T, Error> + Send + 'static,
+    ) -> Result<T, Error> {
+        let (sender, receiver) = tokio::sync::oneshot::channel();
+        let job: Job = Box::new(move |txn, state| {
+            let _ = sender.send(f(txn, state));
+        });
+        self.jobs
+            .lock()
+            .unwrap()
+            .send(job)
+            .map_err(|_| Error::ReadPool("the read workers stopped".to_string()))?;
+        receiver
+            .await
+            .map_err(|_| Error::ReadPool("the read failed, see the log".to_string()))?
+    }
+
+    pub fn stats(&self) -> ReadPoolStats {
+        let now = Instant::now();
+        let oldest = self
+            .shared
+            .opened
+            .lock()
+            .unwrap()
+            .iter()
+            .flatten()
+            .map(|opened| now - *opened)
+            .max()
+            .unwrap_or_default();
+        ReadPoolStats {
+            workers: self.workers,
+            reads: self.shared.reads.load(Ordering::Relaxed),
+            transactions: self.shared.transactions.load(Ordering::Relaxed),
+            stale_reads: self.shared.stale_reads.load(Ordering::Relaxed),
+            oldest_transaction_ms: oldest.as_millis() as u64,
+        }
+    }
+}
+
+fn work(index: usize, env: heed::Env, state: BitNamesState, shared: Arc<Shared>) {
+    // The transaction, when it was opened, and the generation it is known to include, None if it
+    // was opened before a block the pool was told about got committed.
+    let mut current: Option<(heed::RoTxn, Instant, Option<u64>)> = None;
+    loop {
+        let job = shared
+            .jobs
+            .lock()
+            .unwrap()
+            .recv_timeout(MAX_TRANSACTION_AGE);
+        let (generation, tip) = *shared.latest.lock().unwrap();
+        let renew = current.as_ref().map_or(false, |(_, opened, known)| {
+            *known != Some(generation) || opened.elapsed() >= MAX_TRANSACTION_AGE
+        });
+        if renew {
+            current = None;
+            shared.opened.lock().unwrap()[index] = None;
+        }
+        let job = match job {
+            Ok(job) => job,
+            Err(mpsc::RecvTimeoutError::Timeout) => continue,
+            Err(mpsc::RecvTimeoutError::Disconnected) => return,
+        };
+        if current.is_none() {
+            let txn = match env.read_txn() {
+                Ok(txn) => txn,
+                Err(err) => {
+                    // Dropping the job fails the read.
+                    log::error!("read worker failed to open a transaction: {err}");
+                    continue;
+                }
+            };
+            let known = match state.get_tip_height(&txn) {
+                Ok(height) if tip.map_or(true, |tip| tip == height) => Some(generation),
+                _ => None,
+            };
+            let opened = Instant::now();
+            shared.opened.lock().unwrap()[index] = Some(opened);
+            shared.transactions.fetch_add(1, Ordering::Relaxed);
+            current = Some((txn, opened, known));
+        }
+        let (txn, _, known) = current.as_ref().unwrap();
+        shared.reads.fetch_add(1, Ordering::Relaxed);
+        if *known != Some(generation) {
+            shared.stale_reads.fetch_add(1, Ordering::Relaxed);
+        }
+        // A panicking read fails like any other, without taking the worker down with it.
+        let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| job(txn, &state)));
+        if result.is_err() {
+            log::error!("a read panicked");
+        }
+    }
+}
+
+// Tell the workers about every block, so they renew their transactions before the next read.
+pub async fn run(pool: Arc<ReadPool>) {
+    let mut events = notify::subscribe();
+    loop {
+        let tip = match events.recv().await {
+            Ok(Event::Block { height, .. }) => Some(height),
+            Ok(Event::Disconnected { height }) => Some(height.saturating_sub(1)),
+            // Whatever was missed, the workers renew their transactions and take the tip they
+            // find.
+            Err(broadcast::error::RecvError::Lagged(_)) => None,
+            Ok(_) => continue,
+            Err(broadcast::error::RecvError::Closed) => return,
+        };
+        let mut latest = pool.shared.latest.lock().unwrap();
+        *latest = (latest.0 + 1, tip);
+    }
+}
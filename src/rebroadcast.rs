@@ -0,0 +1,109 @@
+use crate::bitnames::BitName;
+use crate::Node;
+use ddk::authorization::Authorization;
+use ddk::types::{AuthorizedTransaction, Hash, Txid};
+use rand::Rng;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+// ddk relays a transaction to the peers it is connected to when it is submitted, and never again.
+// A node that was cut off from its peers at that moment, or whose peers dropped the transaction,
+// would keep a registration in its own mempool that nobody else ever sees.
+//
+// Average time between rebroadcasts of a transaction. Each one is spread by up to half of it
+// either way, so rebroadcasts don't reveal which transactions came from the same node.
+const REBROADCAST_INTERVAL: Duration = Duration::from_secs(10 * 60);
+// How often the schedule is checked.
+const TICK: Duration = Duration::from_secs(30);
+// Transactions still unconfirmed after this long are given up on, the user has to bump or replace
+// them.
+const MAX_AGE: Duration = Duration::from_secs(24 * 60 * 60);
+
+struct Pending {
+    transaction: AuthorizedTransaction<Authorization, BitName>,
+    submitted: Instant,
+    next: Instant,
+}
+
+// Transactions submitted by this node's wallets and not confirmed yet. Wallets submit from many
+// places, so like wallet::BUILDING this is global rather than passed to each of them.
+static PENDING: Mutex<Option<HashMap<Txid, Pending>>> = Mutex::new(None);
+
+fn next_rebroadcast(now: Instant) -> Instant {
+    let interval = REBROADCAST_INTERVAL.as_secs();
+    let delay = rand::thread_rng().gen_range(interval / 2..=interval + interval / 2);
+    now + Duration::from_secs(delay)
+}
+
+// Rebroadcast transaction until it is confirmed or MAX_AGE passed.
+pub fn track(transaction: &AuthorizedTransaction<Authorization, BitName>) {
+    let now = Instant::now();
+    PENDING
+        .lock()
+        .unwrap()
+        .get_or_insert_with(HashMap::new)
+        .insert(
+            transaction.transaction.txid(),
+            Pending {
+                transaction: transaction.clone(),
+                submitted: now,
+                next: next_rebroadcast(now),
+            },
+        );
+}
+
+// Resubmit tracked transactions whose time has come. A transaction is done once its inputs are
+// gone from the utxo set while it isn't in the mempool, because it or a conflicting transaction
+// was mined.
+pub async fn run(node: Arc<Node>) {
+    let mut interval = tokio::time::interval(TICK);
+    loop {
+        interval.tick().await;
+        let mempool = match node.get_all_transactions() {
+            Ok(transactions) => transactions
+                .into_iter()
+                .map(|transaction| transaction.transaction.txid())
+                .collect::<Vec<_>>(),
+            Err(err) => {
+                log::error!("failed to read the mempool: {err}");
+                continue;
+            }
+        };
+        let now = Instant::now();
+        let mut due = vec![];
+        if let Some(pending) = PENDING.lock().unwrap().as_mut() {
+            pending.retain(|txid, pending| {
+                let in_mempool = mempool.contains(txid);
+                let txid = hex::encode(Hash::from(*txid));
+                if !in_mempool
+                    && node
+                        .fill_transaction(&pending.transaction.transaction)
+                        .is_err()
+                {
+                    log::debug!("stopped rebroadcasting {txid}, it is confirmed or conflicted");
+                    return false;
+                }
+                if now.duration_since(pending.submitted) > MAX_AGE {
+                    log::warn!(
+                        "stopped rebroadcasting {txid}, still unconfirmed after {MAX_AGE:?}"
+                    );
+                    return false;
+                }
+                if now >= pending.next {
+                    pending.next = next_rebroadcast(now);
+                    due.push((txid, pending.transaction.clone()));
+                }
+                true
+            });
+        }
+        for (txid, transaction) in due {
+            // A transaction that is still in the mempool may be refused as a duplicate, whether
+            // ddk relays it again then is up to ddk.
+            match node.submit_transaction(&transaction).await {
+                Ok(()) => log::debug!("rebroadcast {txid}"),
+                Err(err) => log::debug!("rebroadcasting {txid} failed: {err}"),
+            }
+        }
+    }
+}
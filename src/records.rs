@@ -0,0 +1,242 @@
+use crate::offchain::OffchainPointer;
+use ddk::bitcoin;
+use ddk::types::{Address, Hash, Transaction};
+use serde::{Deserialize, Serialize};
+use std::net::{Ipv4Addr, Ipv6Addr};
+
+// BitName::Versioned content version of typed records.
+//
+// A name's value is a bare hash, so everything else a resolver wants to know about a name comes
+// from records, and a record without a schema is just bytes each resolver has to guess the
+// meaning of. Typed records give the common kinds, DNS records, public keys, payment addresses,
+// social handles, redirects to other names and pointers to off-chain data, a fixed bincode
+// encoded schema that serializes to JSON the same way over RPC, and rules for what a valid one
+// looks like, so every resolver reads them the same way.
+//
+// Like TLSA records, only records in the transaction registering their key are indexed. Once the
+// records deployment is active, a typed record that doesn't decode, doesn't pass
+// Record::validate or redirects its name to itself makes its transaction invalid, so clients
+// should validate records before submitting them. Before that, they are ignored like any other
+// record that doesn't qualify.
+pub const RECORDS_VERSION: u8 = 9;
+
+// Longest TXT record, the length of a single DNS character-string.
+pub const MAX_TXT_LEN: usize = 255;
+pub const MAX_SERVICE_LEN: usize = 32;
+pub const MAX_HANDLE_LEN: usize = 64;
+// Redirects resolvers follow from the name they were asked for before giving up.
+pub const MAX_REDIRECT_HOPS: usize = 8;
+
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Eq)]
+pub struct TypedRecord {
+    pub key: Hash,
+    pub record: Record,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum Record {
+    Dns(DnsRecord),
+    PublicKey {
+        algorithm: KeyAlgorithm,
+        public_key: Vec<u8>,
+    },
+    PaymentAddress(PaymentAddress),
+    // An account on another service, like ("github", "octocat"). This only says the name's
+    // owner claims the account, consumers have to check the claim with the service.
+    SocialHandle {
+        service: String,
+        handle: String,
+    },
+    // This name is another spelling of target, and resolves to whatever target resolves to, see
+    // BitNamesState::follow_redirects. A name's first redirect is the one that counts. Names are
+    // registered one at a time, so redirects can still form a loop through several names, which
+    // resolvers detect rather than consensus.
+    Redirect {
+        target: Hash,
+    },
+    // Where the name's off-chain data is published and the key that signs it, see offchain.rs.
+    Offchain(OffchainPointer),
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum DnsRecord {
+    A(Ipv4Addr),
+    Aaaa(Ipv6Addr),
+    Cname(String),
+    Txt(String),
+    Mx { preference: u16, exchange: String },
+}
+
+#[derive(Clone, Copy, Debug, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum KeyAlgorithm {
+    // 32 bytes.
+    Ed25519,
+    // SEC1 encoded, compressed or not.
+    Secp256k1,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum PaymentAddress {
+    // Any mainchain address, on the mainnet or the test networks. Unlike main_address.rs
+    // bindings, these carry no proof that the address holder agreed.
+    Bitcoin(String),
+    BitNames(Address),
+}
+
+impl Record {
+    // Whether the record is well formed, or why not.
+    pub fn validate(&self) -> anyhow::Result<()> {
+        match self {
+            Record::Dns(DnsRecord::A(_) | DnsRecord::Aaaa(_)) => {}
+            Record::Dns(DnsRecord::Cname(host) | DnsRecord::Mx { exchange: host, .. }) => {
+                if !is_hostname(host) {
+                    anyhow::bail!("{host:?} isn't a lowercase hostname");
+                }
+            }
+            Record::Dns(DnsRecord::Txt(text)) => {
+                if text.len() > MAX_TXT_LEN {
+                    anyhow::bail!("TXT record is longer than {MAX_TXT_LEN} bytes");
+                }
+            }
+            Record::PublicKey {
+                algorithm: KeyAlgorithm::Ed25519,
+                public_key,
+            } => {
+                if ed25519_dalek::PublicKey::from_bytes(public_key).is_err() {
+                    anyhow::bail!("not an ed25519 public key");
+                }
+            }
+            Record::PublicKey {
+                algorithm: KeyAlgorithm::Secp256k1,
+                public_key,
+            } => {
+                if bitcoin::secp256k1::PublicKey::from_slice(public_key).is_err() {
+                    anyhow::bail!("not a SEC1 encoded secp256k1 public key");
+                }
+            }
+            Record::PaymentAddress(PaymentAddress::Bitcoin(address)) => {
+                if address
+                    .parse::<bitcoin::Address<bitcoin::address::NetworkUnchecked>>()
+                    .is_err()
+                {
+                    anyhow::bail!("{address:?} isn't a Bitcoin address");
+                }
+            }
+            Record::PaymentAddress(PaymentAddress::BitNames(_)) | Record::Redirect { .. } => {}
+            Record::Offchain(pointer) => pointer.validate()?,
+            Record::SocialHandle { service, handle } => {
+                let service_valid = !service.is_empty()
+                    && service.len() <= MAX_SERVICE_LEN
+                    && service.bytes().all(|byte| {
+                        byte.is_ascii_lowercase()
+                            || byte.is_ascii_digit()
+                            || byte == b'.'
+                            || byte == b'-'
+                    });
+                if !service_valid {
+                    anyhow::bail!("{service:?} isn't a lowercase service name");
+                }
+                let handle_valid = !handle.is_empty()
+                    && handle.len() <= MAX_HANDLE_LEN
+                    && !handle.starts_with('@')
+                    && !handle.chars().any(|c| c.is_whitespace() || c.is_control());
+                if !handle_valid {
+                    anyhow::bail!("{handle:?} isn't a handle");
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+impl TypedRecord {
+    // Decode a Versioned payload, if it is a valid record transaction is allowed to carry.
+    pub fn decode(
+        payload: &[u8],
+        transaction: &Transaction<crate::bitnames::BitName>,
+    ) -> Option<Self> {
+        let record: Self = bincode::deserialize(payload).ok()?;
+        if !crate::bitnames::registered_keys(transaction).contains(&record.key)
+            || record.record.validate().is_err()
+            || record.record == (Record::Redirect { target: record.key })
+        {
+            return None;
+        }
+        Some(record)
+    }
+
+    pub fn encode(&self) -> Vec<u8> {
+        bincode::serialize(self).expect("typed records always serialize")
+    }
+}
+
+// At most 253 bytes of dot separated labels, each 1 to 63 lowercase letters, digits and inner
+// hyphens. Uppercase isn't allowed, so every host has a single encoding.
+fn is_hostname(host: &str) -> bool {
+    host.len() <= 253
+        && host.split('.').all(|label| {
+            !label.is_empty()
+                && label.len() <= 63
+                && !label.starts_with('-')
+                && !label.ends_with('-')
+                && label
+                    .bytes()
+                    .all(|byte| byte.is_ascii_lowercase() || byte.is_ascii_digit() || byte == b'-')
+        })
+}
+
+// One valid example of every kind of record, for clients to fill in.
+pub fn templates() -> Vec<Record> {
+    vec![
+        Record::Dns(DnsRecord::A(Ipv4Addr::new(192, 0, 2, 1))),
+        Record::Dns(DnsRecord::Aaaa(Ipv6Addr::new(
+            0x2001, 0xdb8, 0, 0, 0, 0, 0, 1,
+        ))),
+        Record::Dns(DnsRecord::Cname("example.com".to_string())),
+        Record::Dns(DnsRecord::Txt("v=spf1 -all".to_string())),
+        Record::Dns(DnsRecord::Mx {
+            preference: 10,
+            exchange: "mail.example.com".to_string(),
+        }),
+        // The ed25519 base point.
+        Record::PublicKey {
+            algorithm: KeyAlgorithm::Ed25519,
+            public_key: hex::decode(
+                "5866666666666666666666666666666666666666666666666666666666666666",
+            )
+            .expect("the template key is valid hex"),
+        },
+        // The secp256k1 generator, compressed.
+        Record::PublicKey {
+            algorithm: KeyAlgorithm::Secp256k1,
+            public_key: hex::decode(
+                "0279be667ef9dcbbac55a06295ce870b07029bfcdb2dce28d959f2815b16f81798",
+            )
+            .expect("the template key is valid hex"),
+        },
+        Record::PaymentAddress(PaymentAddress::Bitcoin(
+            "bc1qar0srrr7xfkvy5l643lydnw9re59gtzzwf5mdq".to_string(),
+        )),
+        Record::PaymentAddress(PaymentAddress::BitNames(Address::from([0; 32]))),
+        Record::SocialHandle {
+            service: "github".to_string(),
+            handle: "octocat".to_string(),
+        },
+        Record::Redirect {
+            target: crate::aliases::name_key("example"),
+        },
+        Record::Offchain(OffchainPointer {
+            url: "https://example.com/bitnames.json".to_string(),
+            public_key: ed25519_dalek::PublicKey::from_bytes(
+                &hex::decode("5866666666666666666666666666666666666666666666666666666666666666")
+                    .expect("the template key is valid hex"),
+            )
+            .expect("the template key is an ed25519 public key"),
+            min_sequence: 0,
+        }),
+    ]
+}
@@ -0,0 +1,45 @@
+use crate::bitnames::{BitName, Error, StateReader};
+use crate::embed::Node;
+use ddk::authorization::Authorization;
+use ddk::node::State;
+
+// Bring the name state back in line with ddk's chain after an unclean shutdown.
+//
+// The name state records the height of the last block it connected (see
+// BitNamesState::get_tip_height). If that is ahead of ddk's chain tip, the extra blocks are
+// disconnected using their undo data. If it is behind, the missing bodies are fetched from ddk's
+// archive and connected. Either way, by the time the RPC server starts nothing serves name data
+// from a state that doesn't match the chain.
+pub fn recover(node: &Node, reader: &StateReader) -> anyhow::Result<()> {
+    let chain_height = node.get_height()?;
+    let (state_height, fast_sync_height) = reader
+        .read(|txn, state| Ok((state.get_tip_height(txn)?, state.get_fast_sync_height(txn)?)))?;
+    // A fast synced state is legitimately ahead of the chain until ddk catches up with the
+    // snapshot height.
+    if state_height == fast_sync_height && chain_height <= fast_sync_height {
+        return Ok(());
+    }
+    if state_height > chain_height {
+        log::warn!("name state is at {state_height} but chain is at {chain_height}, rolling back");
+        let result = reader.write(|txn, state| {
+            for height in (chain_height + 1..=state_height).rev() {
+                state.disconnect_body(txn, height)?;
+            }
+            Ok(())
+        });
+        if let Err(err @ Error::RollbackTooDeep { .. }) = &result {
+            log::error!("{err}, restart with --allow-deep-rollback to roll back anyway");
+        }
+        result?;
+    } else if state_height < chain_height {
+        log::info!("name state is at {state_height} but chain is at {chain_height}, replaying");
+        let ddk_state = ddk::state::State::<Authorization, BitName>::new(reader.env())?;
+        for height in state_height + 1..=chain_height {
+            let body = node
+                .get_body(height)?
+                .ok_or_else(|| anyhow::anyhow!("block {height} is missing from the archive"))?;
+            reader.write(|txn, state| state.connect_body(txn, height, &ddk_state, &body))?;
+        }
+    }
+    Ok(())
+}
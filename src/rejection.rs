@@ -0,0 +1,111 @@
+use crate::bitnames::{BitName, Error, ErrorContext};
+use ddk::types::{FilledTransaction, GetValue};
+use serde::{Deserialize, Serialize};
+
+// Why a transaction or block was rejected, stable so that wallets can branch on it instead of on
+// messages.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RejectionCode {
+    // A registered key is already taken.
+    NameTaken,
+    // Another transaction in the same block registers the same key.
+    DuplicateName,
+    TooManyRegistrations,
+    // An output's BitName content is malformed or not allowed where it is.
+    InvalidContent,
+    // A value output is below the dust limit.
+    Dust,
+    // A record, or all records attached to one key, exceed the size limits.
+    RecordTooLarge,
+    // Outputs are worth more than the inputs, or the fee doesn't cover the registration price.
+    InsufficientFee,
+    // An input doesn't exist or is already spent.
+    MissingInputs,
+    // The transaction is height locked past the block it would go into, submit it again once the
+    // chain reaches the lock height.
+    HeightLocked,
+    CheckpointMismatch,
+    // Anything else, see the message.
+    Invalid,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Rejection {
+    pub code: RejectionCode,
+    pub message: String,
+    pub context: ErrorContext,
+}
+
+impl Rejection {
+    pub fn new(code: RejectionCode, message: impl ToString) -> Self {
+        Self {
+            code,
+            message: message.to_string(),
+            context: ErrorContext::default(),
+        }
+    }
+
+    pub fn from_state_error(err: &Error) -> Self {
+        let code = match err {
+            Error::KeyAlreadyExists { .. } => RejectionCode::NameTaken,
+            Error::DuplicateKeyInBlock { .. } => RejectionCode::DuplicateName,
+            Error::TooManyRegistrations { .. } => RejectionCode::TooManyRegistrations,
+            Error::ReservedContentVersion { .. }
+            | Error::SignalOutsideCoinbase { .. }
+            | Error::InvalidOnionRecord { .. }
+            | Error::InvalidMainAddressRecord { .. }
+            | Error::InvalidTypedRecord { .. }
+            | Error::InvalidLockHeight { .. }
+            | Error::TooManyCustomOutputs { .. } => RejectionCode::InvalidContent,
+            Error::DustOutput { .. } => RejectionCode::Dust,
+            Error::RecordTooLarge { .. } | Error::NameRecordsTooLarge { .. } => {
+                RejectionCode::RecordTooLarge
+            }
+            Error::TransactionLocked { .. } => RejectionCode::HeightLocked,
+            Error::CheckpointMismatch { .. } => RejectionCode::CheckpointMismatch,
+            Error::RegistrationUnderpaid { .. } => RejectionCode::InsufficientFee,
+            _ => RejectionCode::Invalid,
+        };
+        Self {
+            code,
+            message: err.to_string(),
+            context: err.context(),
+        }
+    }
+
+    // The rejection behind err: the bitnames::Error somewhere in its source chain if there is one,
+    // for transactions and blocks that ddk rejected because BitNamesState did.
+    pub fn from_error(err: &(dyn std::error::Error + 'static)) -> Self {
+        let mut source = Some(err);
+        while let Some(err) = source {
+            if let Some(state_err) = err.downcast_ref::<Error>() {
+                return Self::from_state_error(state_err);
+            }
+            source = err.source();
+        }
+        Self::new(RejectionCode::Invalid, err)
+    }
+}
+
+// Checks ddk would reject transaction for too, done up front so they get a precise code.
+pub fn check_funding(transaction: &FilledTransaction<BitName>) -> Result<(), Rejection> {
+    let value_in: u64 = transaction
+        .spent_utxos
+        .iter()
+        .map(GetValue::get_value)
+        .sum();
+    let value_out: u64 = transaction
+        .transaction
+        .outputs
+        .iter()
+        .map(GetValue::get_value)
+        .sum();
+    if value_out > value_in {
+        return Err(Rejection::new(
+            RejectionCode::InsufficientFee,
+            format!("outputs are worth {value_out} sats but the inputs only {value_in}"),
+        ));
+    }
+    Ok(())
+}
@@ -0,0 +1,58 @@
+use ddk::types::Hash;
+use ed25519_dalek::{Keypair, PublicKey, SecretKey, Signature, Signer, Verifier};
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+
+// The node's resolution signing key, kept hex encoded in <datadir>/node.key, the same format the
+// snapshot signing key file uses. It's created on first start.
+pub fn load_or_create_key(datadir: &Path) -> anyhow::Result<Keypair> {
+    let path = datadir.join("node.key");
+    if !path.exists() {
+        std::fs::write(&path, hex::encode(rand::random::<[u8; 32]>()))?;
+    }
+    let secret = crate::parse_hex_32(std::fs::read_to_string(&path)?.trim())?;
+    let secret = SecretKey::from_bytes(&secret)?;
+    let public = PublicKey::from(&secret);
+    Ok(Keypair { secret, public })
+}
+
+// What a key resolved to at a given tip, signed by the answering node, so that anything relaying
+// the answer can't change it without invalidating the signature.
+//
+// This vouches for the node's view, it is not a proof against the chain: the state keeps no
+// merkleized name tree to prove membership against.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct SignedResolution {
+    pub key: Hash,
+    pub value: Option<Hash>,
+    pub height: u32,
+    pub public_key: PublicKey,
+    pub signature: Signature,
+}
+
+impl SignedResolution {
+    pub fn new(keypair: &Keypair, key: Hash, value: Option<Hash>, height: u32) -> Self {
+        let signature = keypair.sign(&message(&key, &value, height));
+        Self {
+            key,
+            value,
+            height,
+            public_key: keypair.public,
+            signature,
+        }
+    }
+
+    pub fn verify(&self, public_key: &PublicKey) -> bool {
+        self.public_key == *public_key
+            && public_key
+                .verify(
+                    &message(&self.key, &self.value, self.height),
+                    &self.signature,
+                )
+                .is_ok()
+    }
+}
+
+fn message(key: &Hash, value: &Option<Hash>, height: u32) -> Hash {
+    ddk::types::hash(&(key, value, height))
+}
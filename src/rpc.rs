@@ -0,0 +1,1318 @@
+use crate::address_book::{AddressBook, Contact, ResolvedContact};
+use crate::aliases;
+use crate::analytics::{NameReport, ReportCache};
+use crate::attestation::AttestationRecord;
+use crate::bitnames::{BitName, BitNamesState, Error, NameLogEvent, StateReader};
+use crate::coin_selection::SpendPolicy;
+use crate::deployments::DeploymentState;
+use crate::disputes::{Dispute, DisputeRecord};
+use crate::identity::{IdentityKey, IdentityRecord};
+use crate::idn::{self, NameForms};
+use crate::main_address::MainAddressRecord;
+use crate::mempool_watch::MempoolWatch;
+use crate::metrics;
+use crate::notify::{self, Event};
+use crate::offchain::{OffchainData, OffchainPointer, SequenceRecord};
+use crate::onion::{OnionBinding, OnionRecord};
+use crate::orphans::OrphanPool;
+use crate::peer_store::{PeerInfo, PeerStore};
+use crate::rate_limit::RateLimiter;
+use crate::read_pool::{ReadPool, ReadPoolStats};
+use crate::records::{self, Record, TypedRecord};
+use crate::rejection::{self, Rejection, RejectionCode};
+use crate::resolution::SignedResolution;
+use crate::rpc_api::{
+    BlockTemplate, DeploymentInfo, NameCost, NameCursor, NameDelta, NameDiff, NameQuote,
+    RegistrationRequest, RegistrationStatus, Resolution, RpcServer, WalletTransaction, Withdrawal,
+    WithdrawalStatus, MAX_DIFF_NAMES, MAX_NAME_LOG_EVENTS, MAX_RESOLVE_MANY,
+};
+use crate::rpc_auth::AuthLayer;
+use crate::signer::ExternalSigner;
+use crate::status::{NodeStatus, StatusMonitor};
+use crate::tlsa::{self, TlsaRecord, TlsaVerdict};
+use crate::vault::{UnsignedOperation, VaultAddresses};
+use crate::wallet::Wallets;
+use crate::watchlist::{WatchEvent, Watchlist};
+use crate::withdrawals::Withdrawals;
+use crate::{backup, logging, mining, wallet, Miner, Node, Wallet};
+use ddk::authorization::Authorization;
+use ddk::heed;
+use ddk::types::{Address, AuthorizedTransaction, Body, Hash, Header, OutPoint};
+use ed25519_dalek::{PublicKey, Signature};
+use jsonrpsee::core::{async_trait, Error as RpcError, RpcResult, SubscriptionResult};
+use jsonrpsee::server::logger::{
+    HttpRequest, Logger, MethodKind, MethodResponseResult, Params, TransportProtocol,
+};
+use jsonrpsee::server::{ServerBuilder, ServerHandle};
+use jsonrpsee::types::error::{CallError, ErrorCode, ErrorObject};
+use jsonrpsee::{PendingSubscriptionSink, SubscriptionMessage};
+use std::collections::BTreeMap;
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Instant;
+use tokio::sync::Mutex;
+
+pub struct RpcServerImpl {
+    node: Arc<Node>,
+    wallets: Arc<Wallets>,
+    miner: Arc<Mutex<Miner>>,
+    peers: Arc<PeerStore>,
+    watchlist: Arc<Watchlist>,
+    mempool_watch: Arc<MempoolWatch>,
+    orphans: Arc<OrphanPool>,
+    address_book: AddressBook,
+    withdrawals: Arc<Withdrawals>,
+    node_key: ed25519_dalek::Keypair,
+    status: Arc<StatusMonitor>,
+    reports: Arc<ReportCache>,
+    vault: VaultAddresses,
+    vault_signer: Option<ExternalSigner>,
+    spend_policy: SpendPolicy,
+    state: StateReader,
+    reads: Arc<ReadPool>,
+    datadir: PathBuf,
+    regtest: bool,
+}
+
+impl RpcServerImpl {
+    pub fn new(
+        node: Arc<Node>,
+        wallets: Arc<Wallets>,
+        miner: Arc<Mutex<Miner>>,
+        peers: Arc<PeerStore>,
+        watchlist: Arc<Watchlist>,
+        mempool_watch: Arc<MempoolWatch>,
+        orphans: Arc<OrphanPool>,
+        address_book: AddressBook,
+        withdrawals: Arc<Withdrawals>,
+        node_key: ed25519_dalek::Keypair,
+        status: Arc<StatusMonitor>,
+        reports: Arc<ReportCache>,
+        vault: VaultAddresses,
+        vault_signer: Option<ExternalSigner>,
+        spend_policy: SpendPolicy,
+        state: StateReader,
+        reads: Arc<ReadPool>,
+        datadir: PathBuf,
+        regtest: bool,
+    ) -> Self {
+        Self {
+            node,
+            wallets,
+            miner,
+            peers,
+            watchlist,
+            mempool_watch,
+            orphans,
+            address_book,
+            withdrawals,
+            node_key,
+            status,
+            reports,
+            vault,
+            vault_signer,
+            spend_policy,
+            state,
+            reads,
+            datadir,
+            regtest,
+        }
+    }
+}
+
+impl RpcServerImpl {
+    fn wallet(&self, name: Option<String>) -> RpcResult<Arc<Wallet>> {
+        self.wallets.get(name.as_deref()).map_err(custom_err)
+    }
+
+    // Have operation signed by the external signer and submit it.
+    async fn sign_vault_operation(&self, operation: UnsignedOperation) -> RpcResult<Hash> {
+        let signer = self
+            .vault_signer
+            .as_ref()
+            .ok_or_else(|| custom_err("no --vault-signer configured"))?;
+        let transaction = signer.sign(&operation).await.map_err(custom_err)?;
+        let txid = transaction.transaction.txid().into();
+        submit_transaction(&self.node, &self.orphans, transaction)
+            .await
+            .map_err(rejected_err)?;
+        Ok(txid)
+    }
+
+    fn resolve_identifier_address(&self, identifier: &str) -> anyhow::Result<Address> {
+        let (user, key) = aliases::parse_identifier(identifier)?;
+        // user@spelling pays the same as user@canonical when spelling redirects to canonical.
+        let aliases = self.state.read(|txn, state| {
+            let chain = state.follow_redirects(txn, &key)?;
+            state.get_aliases(txn, chain.last().unwrap())
+        })?;
+        match aliases.get(&user) {
+            Some(address) => Ok(*address),
+            None => anyhow::bail!("{identifier} doesn't resolve to an address"),
+        }
+    }
+}
+
+fn custom_err(err: impl std::fmt::Display) -> RpcError {
+    RpcError::Custom(err.to_string())
+}
+
+// Error code of RPC errors for rejected transactions and blocks, which carry a Rejection as data.
+pub const REJECTED_CODE: i32 = -32001;
+
+fn rejected_err(rejection: Rejection) -> RpcError {
+    let object = ErrorObject::owned(REJECTED_CODE, rejection.message.clone(), Some(rejection));
+    RpcError::Call(CallError::Custom(object))
+}
+
+// Like custom_err, but a bitnames::Error behind err, for example a transaction or block that ddk
+// rejected because BitNamesState did, comes back as a Rejection with its code and context.
+fn node_err(err: &(dyn std::error::Error + 'static)) -> RpcError {
+    let rejection = Rejection::from_error(err);
+    if rejection.code == RejectionCode::Invalid {
+        return custom_err(err);
+    }
+    rejected_err(rejection)
+}
+
+#[async_trait]
+impl RpcServer for RpcServerImpl {
+    async fn get_block_template(&self, coinbase_address: Address) -> RpcResult<BlockTemplate> {
+        let miner = self.miner.lock().await;
+        let (header, body) = mining::build_block(
+            &self.node,
+            &miner,
+            &self.state,
+            &self.orphans,
+            coinbase_address,
+        )
+        .await
+        .map_err(custom_err)?;
+        Ok(BlockTemplate { header, body })
+    }
+
+    async fn submit_block(
+        &self,
+        header: Header,
+        body: Body<Authorization, BitName>,
+    ) -> RpcResult<()> {
+        if let Err(err) = self.node.submit_block(&header, &body).await {
+            let rejection = Rejection::from_error(&err);
+            log::info!(
+                "rejected block: {:?}: {}",
+                rejection.code,
+                rejection.message
+            );
+            return Err(rejected_err(rejection));
+        }
+        Ok(())
+    }
+
+    async fn generate(&self, n: u32, wallet: Option<String>) -> RpcResult<()> {
+        if !self.regtest {
+            return Err(custom_err("generate is only available on regtest"));
+        }
+        let wallet = self.wallet(wallet)?;
+        mining::generate(
+            &self.node,
+            &wallet,
+            &self.miner,
+            &self.state,
+            &self.orphans,
+            n,
+        )
+        .await
+        .map_err(custom_err)
+    }
+
+    async fn get_height(&self) -> RpcResult<u32> {
+        self.node.get_height().map_err(custom_err)
+    }
+
+    async fn get_node_status(&self) -> RpcResult<NodeStatus> {
+        self.status.status().map_err(custom_err)
+    }
+
+    async fn get_read_pool_stats(&self) -> RpcResult<ReadPoolStats> {
+        Ok(self.reads.stats())
+    }
+
+    async fn get_log_filter(&self) -> RpcResult<String> {
+        logging::filter()
+            .map(|filter| filter.to_string())
+            .ok_or_else(|| custom_err("logging is not initialized"))
+    }
+
+    async fn set_log_filter(&self, filter: String) -> RpcResult<()> {
+        let filter = filter.parse().map_err(custom_err)?;
+        logging::set_filter(filter).map_err(custom_err)
+    }
+
+    async fn get_deployments(&self) -> RpcResult<Vec<DeploymentInfo>> {
+        let deployments = self.state.params().deployments;
+        self.state
+            .read(|txn, state| {
+                deployments
+                    .iter()
+                    .map(|deployment| {
+                        Ok(DeploymentInfo {
+                            name: deployment.name.to_string(),
+                            version: deployment.version,
+                            bit: deployment.bit,
+                            state: state.get_deployment_state(txn, deployment)?,
+                        })
+                    })
+                    .collect()
+            })
+            .map_err(custom_err)
+    }
+
+    async fn get_value(&self, key: Hash) -> RpcResult<Option<Hash>> {
+        self.reads
+            .read(move |txn, state| state.get_value(txn, &key))
+            .await
+            .map_err(custom_err)
+    }
+
+    async fn resolve(&self, key: Hash) -> RpcResult<Option<Resolution>> {
+        self.reads
+            .read(move |txn, state| resolution_in(txn, state, &key))
+            .await
+            .map_err(custom_err)
+    }
+
+    async fn get_block_filter(&self, height: u32) -> RpcResult<Option<String>> {
+        let filter = self
+            .state
+            .read(|txn, state| state.get_block_filter(txn, height))
+            .map_err(custom_err)?;
+        Ok(filter.map(hex::encode))
+    }
+
+    async fn resolve_many(&self, keys: Vec<Hash>) -> RpcResult<Vec<Option<Hash>>> {
+        if keys.len() > MAX_RESOLVE_MANY {
+            return Err(custom_err(format!(
+                "at most {MAX_RESOLVE_MANY} keys can be resolved at once"
+            )));
+        }
+        self.reads
+            .read(move |txn, state| {
+                keys.iter()
+                    .map(|key| match state.resolve_value(txn, key) {
+                        Err(Error::RedirectLoop { .. } | Error::TooManyRedirects { .. }) => {
+                            Ok(None)
+                        }
+                        result => result,
+                    })
+                    .collect()
+            })
+            .await
+            .map_err(custom_err)
+    }
+
+    async fn diff_names(&self, from: u32, to: u32) -> RpcResult<NameDiff> {
+        let (fast_sync_height, diff) = self
+            .state
+            .read(|txn, state| {
+                // Names imported by a fast sync aren't recorded per block.
+                let fast_sync_height = state.get_fast_sync_height(txn)?;
+                if from < fast_sync_height {
+                    return Ok((fast_sync_height, None));
+                }
+                let to = to.min(state.get_tip_height(txn)?);
+                let diff = state.get_names_between(txn, from, to, MAX_DIFF_NAMES)?;
+                Ok((fast_sync_height, Some(diff)))
+            })
+            .map_err(custom_err)?;
+        let (registered, to) = diff.ok_or_else(|| {
+            custom_err(format!(
+                "this node was fast synced to {fast_sync_height}, diffs have to start there"
+            ))
+        })?;
+        Ok(NameDiff {
+            from,
+            to,
+            registered,
+        })
+    }
+
+    async fn get_name_log(&self, from: u64) -> RpcResult<Vec<NameLogEvent>> {
+        self.state
+            .read(|txn, state| state.get_name_log(txn, from, MAX_NAME_LOG_EVENTS))
+            .map_err(custom_err)
+    }
+
+    async fn get_name_report(&self) -> RpcResult<Option<NameReport>> {
+        Ok(self.reports.get())
+    }
+
+    async fn quote_name(&self, key: Hash) -> RpcResult<NameQuote> {
+        wallet::quote_name(&self.node, &self.state, key).map_err(custom_err)
+    }
+
+    async fn get_onion_binding(&self, key: Hash) -> RpcResult<Option<OnionBinding>> {
+        self.state
+            .read(|txn, state| state.get_onion_binding(txn, &key))
+            .map_err(custom_err)
+    }
+
+    async fn get_tlsa_records(&self, key: Hash) -> RpcResult<Vec<TlsaRecord>> {
+        self.state
+            .read(|txn, state| state.get_tlsa_records(txn, &key))
+            .map_err(custom_err)
+    }
+
+    async fn get_main_addresses(&self, key: Hash) -> RpcResult<Vec<MainAddressRecord>> {
+        self.state
+            .read(|txn, state| state.get_main_addresses(txn, &key))
+            .map_err(custom_err)
+    }
+
+    async fn get_name_forms(&self, name: String) -> RpcResult<NameForms> {
+        idn::forms(&name).map_err(custom_err)
+    }
+
+    async fn get_records(&self, key: Hash) -> RpcResult<Vec<TypedRecord>> {
+        self.state
+            .read(|txn, state| state.get_records(txn, &key))
+            .map_err(custom_err)
+    }
+
+    async fn get_record_templates(&self) -> RpcResult<Vec<Record>> {
+        Ok(records::templates())
+    }
+
+    async fn validate_record(&self, record: Record) -> RpcResult<()> {
+        record.validate().map_err(custom_err)
+    }
+
+    async fn get_offchain_pointer(&self, key: Hash) -> RpcResult<Option<OffchainPointer>> {
+        self.state
+            .read(|txn, state| state.get_offchain_pointer(txn, &key))
+            .map_err(custom_err)
+    }
+
+    async fn verify_offchain_data(&self, data: OffchainData) -> RpcResult<()> {
+        let pointer = self
+            .state
+            .read(|txn, state| state.get_offchain_pointer(txn, &data.key))
+            .map_err(custom_err)?
+            .ok_or_else(|| custom_err("key has no off-chain data pointer"))?;
+        data.verify(&data.key, &pointer).map_err(custom_err)
+    }
+
+    async fn raise_offchain_sequence(
+        &self,
+        key: Hash,
+        min_sequence: u64,
+        signature: Signature,
+        fee: u64,
+        wallet: Option<String>,
+    ) -> RpcResult<Hash> {
+        let record = SequenceRecord {
+            key,
+            min_sequence,
+            signature,
+        };
+        // Nodes ignore a record that doesn't raise the minimum or isn't signed by the pointer's
+        // key, so it would only waste the fee.
+        let pointer = self
+            .state
+            .read(|txn, state| state.get_offchain_pointer(txn, &key))
+            .map_err(custom_err)?
+            .ok_or_else(|| custom_err("key has no off-chain data pointer"))?;
+        if !record.verify(&pointer) {
+            return Err(custom_err(format!(
+                "signature isn't by the pointer's key, or {min_sequence} isn't above the minimum {}",
+                pointer.min_sequence
+            )));
+        }
+        let wallet = self.wallet(wallet)?;
+        let txid =
+            wallet::raise_offchain_sequence(&self.node, &wallet, &record, fee, self.spend_policy)
+                .await
+                .map_err(|err| node_err(err.as_ref()))?;
+        Ok(txid.into())
+    }
+
+    async fn get_attestations(&self, key: Hash) -> RpcResult<Vec<AttestationRecord>> {
+        self.state
+            .read(|txn, state| state.get_attestations(txn, &key))
+            .map_err(custom_err)
+    }
+
+    async fn verify_certificate(&self, key: Hash, certificate: String) -> RpcResult<TlsaVerdict> {
+        let certificate = hex::decode(certificate).map_err(custom_err)?;
+        let records = self
+            .state
+            .read(|txn, state| state.get_tlsa_records(txn, &key))
+            .map_err(custom_err)?;
+        Ok(tlsa::verify(&records, &certificate))
+    }
+
+    async fn resolve_identifier(&self, identifier: String) -> RpcResult<Address> {
+        self.resolve_identifier_address(&identifier)
+            .map_err(custom_err)
+    }
+
+    async fn get_disputes(&self, key: Hash) -> RpcResult<Vec<Dispute>> {
+        self.state
+            .read(|txn, state| state.get_disputes(txn, &key))
+            .map_err(custom_err)
+    }
+
+    async fn file_dispute(
+        &self,
+        key: Hash,
+        evidence: String,
+        fee: u64,
+        wallet: Option<String>,
+    ) -> RpcResult<Hash> {
+        let wallet = self.wallet(wallet)?;
+        let record = DisputeRecord::new(&self.node_key, key, evidence);
+        let txid = wallet::file_dispute(&self.node, &wallet, &record, fee, self.spend_policy)
+            .await
+            .map_err(|err| node_err(err.as_ref()))?;
+        Ok(txid.into())
+    }
+
+    async fn resolve_signed(&self, key: Hash) -> RpcResult<SignedResolution> {
+        let (value, height) = self
+            .reads
+            .read(move |txn, state| {
+                Ok((state.resolve_value(txn, &key)?, state.get_tip_height(txn)?))
+            })
+            .await
+            .map_err(custom_err)?;
+        Ok(SignedResolution::new(&self.node_key, key, value, height))
+    }
+
+    async fn submit_transaction(
+        &self,
+        transaction: AuthorizedTransaction<Authorization, BitName>,
+    ) -> RpcResult<()> {
+        submit_transaction(&self.node, &self.orphans, transaction)
+            .await
+            .map_err(rejected_err)
+    }
+
+    async fn register_name(
+        &self,
+        key: Hash,
+        value: Hash,
+        fee: u64,
+        wallet: Option<String>,
+    ) -> RpcResult<()> {
+        let wallet = self.wallet(wallet)?;
+        wallet::register_name(
+            &self.node,
+            &wallet,
+            &self.state,
+            key,
+            value,
+            fee,
+            self.spend_policy,
+        )
+        .await
+        .map_err(|err| node_err(err.as_ref()))
+    }
+
+    async fn get_new_address(&self, wallet: Option<String>) -> RpcResult<String> {
+        let wallet = self.wallet(wallet)?;
+        let address = wallet.get_new_address().map_err(custom_err)?;
+        Ok(address.to_string())
+    }
+
+    async fn register_name_for(
+        &self,
+        key: Hash,
+        value: Hash,
+        owner: Address,
+        fee: u64,
+        wallet: Option<String>,
+    ) -> RpcResult<()> {
+        let wallet = self.wallet(wallet)?;
+        let policy = self.spend_policy;
+        wallet::register_name_for(
+            &self.node,
+            &wallet,
+            &self.state,
+            key,
+            value,
+            owner,
+            fee,
+            policy,
+        )
+        .await
+        .map_err(|err| node_err(err.as_ref()))
+    }
+
+    async fn register_name_with_onion(
+        &self,
+        value: Hash,
+        record: OnionRecord,
+        fee: u64,
+        wallet: Option<String>,
+    ) -> RpcResult<()> {
+        let wallet = self.wallet(wallet)?;
+        let policy = self.spend_policy;
+        wallet::register_name_with_onion(
+            &self.node,
+            &wallet,
+            &self.state,
+            value,
+            &record,
+            fee,
+            policy,
+        )
+        .await
+        .map_err(|err| node_err(err.as_ref()))
+    }
+
+    async fn register_name_with_tlsa(
+        &self,
+        key: Hash,
+        value: Hash,
+        records: Vec<TlsaRecord>,
+        fee: u64,
+        wallet: Option<String>,
+    ) -> RpcResult<()> {
+        let wallet = self.wallet(wallet)?;
+        let policy = self.spend_policy;
+        wallet::register_name_with_tlsa(
+            &self.node,
+            &wallet,
+            &self.state,
+            key,
+            value,
+            records,
+            fee,
+            policy,
+        )
+        .await
+        .map_err(|err| node_err(err.as_ref()))
+    }
+
+    async fn register_name_with_main_addresses(
+        &self,
+        key: Hash,
+        value: Hash,
+        records: Vec<MainAddressRecord>,
+        fee: u64,
+        wallet: Option<String>,
+    ) -> RpcResult<()> {
+        let wallet = self.wallet(wallet)?;
+        let policy = self.spend_policy;
+        wallet::register_name_with_main_addresses(
+            &self.node,
+            &wallet,
+            &self.state,
+            key,
+            value,
+            records,
+            fee,
+            policy,
+        )
+        .await
+        .map_err(|err| node_err(err.as_ref()))
+    }
+
+    async fn register_name_with_records(
+        &self,
+        key: Hash,
+        value: Hash,
+        records: Vec<Record>,
+        fee: u64,
+        wallet: Option<String>,
+    ) -> RpcResult<()> {
+        let wallet = self.wallet(wallet)?;
+        let policy = self.spend_policy;
+        wallet::register_name_with_records(
+            &self.node,
+            &wallet,
+            &self.state,
+            key,
+            value,
+            records,
+            fee,
+            policy,
+        )
+        .await
+        .map_err(|err| node_err(err.as_ref()))
+    }
+
+    async fn register_name_with_attestations(
+        &self,
+        key: Hash,
+        value: Hash,
+        attestations: Vec<AttestationRecord>,
+        fee: u64,
+        wallet: Option<String>,
+    ) -> RpcResult<()> {
+        let wallet = self.wallet(wallet)?;
+        let policy = self.spend_policy;
+        wallet::register_name_with_attestations(
+            &self.node,
+            &wallet,
+            &self.state,
+            key,
+            value,
+            attestations,
+            fee,
+            policy,
+        )
+        .await
+        .map_err(|err| node_err(err.as_ref()))
+    }
+
+    async fn register_name_with_aliases(
+        &self,
+        key: Hash,
+        value: Hash,
+        aliases: BTreeMap<String, Address>,
+        fee: u64,
+        wallet: Option<String>,
+    ) -> RpcResult<()> {
+        let wallet = self.wallet(wallet)?;
+        let policy = self.spend_policy;
+        wallet::register_name_with_aliases(
+            &self.node,
+            &wallet,
+            &self.state,
+            key,
+            value,
+            aliases,
+            fee,
+            policy,
+        )
+        .await
+        .map_err(|err| node_err(err.as_ref()))
+    }
+
+    async fn register_name_with_identity(
+        &self,
+        key: Hash,
+        value: Hash,
+        public_key: PublicKey,
+        fee: u64,
+        wallet: Option<String>,
+    ) -> RpcResult<()> {
+        let wallet = self.wallet(wallet)?;
+        let policy = self.spend_policy;
+        wallet::register_name_with_identity(
+            &self.node,
+            &wallet,
+            &self.state,
+            key,
+            value,
+            public_key,
+            fee,
+            policy,
+        )
+        .await
+        .map_err(|err| node_err(err.as_ref()))
+    }
+
+    async fn get_identity_keys(&self, key: Hash) -> RpcResult<Vec<IdentityKey>> {
+        self.state
+            .read(|txn, state| state.get_identity_keys(txn, &key))
+            .map_err(custom_err)
+    }
+
+    async fn rotate_identity_key(
+        &self,
+        key: Hash,
+        public_key: PublicKey,
+        signature: Signature,
+        fee: u64,
+        wallet: Option<String>,
+    ) -> RpcResult<Hash> {
+        let record = IdentityRecord {
+            key,
+            public_key,
+            signature: Some(signature),
+        };
+        // Nodes ignore a rotation the current key didn't sign, so it would only waste the fee.
+        let history = self
+            .state
+            .read(|txn, state| state.get_identity_keys(txn, &key))
+            .map_err(custom_err)?;
+        if history.is_empty() {
+            return Err(custom_err("key has no identity key to rotate"));
+        }
+        if !record.verify_rotation(&history) {
+            return Err(custom_err(format!(
+                "signature isn't by the current identity key over rotation number {}",
+                history.len()
+            )));
+        }
+        let wallet = self.wallet(wallet)?;
+        let txid =
+            wallet::rotate_identity_key(&self.node, &wallet, &record, fee, self.spend_policy)
+                .await
+                .map_err(|err| node_err(err.as_ref()))?;
+        Ok(txid.into())
+    }
+
+    async fn register_names(
+        &self,
+        requests: Vec<RegistrationRequest>,
+        fee_per_item: u64,
+        wallet: Option<String>,
+    ) -> RpcResult<Vec<RegistrationStatus>> {
+        let wallet = self.wallet(wallet)?;
+        let policy = self.spend_policy;
+        wallet::register_names(
+            &self.node,
+            &wallet,
+            &self.state,
+            requests,
+            fee_per_item,
+            policy,
+        )
+        .await
+        .map_err(|err| node_err(err.as_ref()))
+    }
+
+    async fn create_wallet(&self, name: String) -> RpcResult<()> {
+        self.wallets.create(&name).map_err(custom_err)
+    }
+
+    async fn list_wallets(&self) -> RpcResult<Vec<String>> {
+        self.wallets.list().map_err(custom_err)
+    }
+
+    async fn import_vault_addresses(&self, path: PathBuf) -> RpcResult<usize> {
+        self.vault.import(&path).map_err(custom_err)
+    }
+
+    async fn build_vault_registration(
+        &self,
+        key: Hash,
+        value: Hash,
+        fee: u64,
+        dest_path: PathBuf,
+    ) -> RpcResult<()> {
+        let operation = self
+            .vault
+            .build_registration(&self.node, &self.state, key, value, fee, self.spend_policy)
+            .map_err(custom_err)?;
+        let bytes = bincode::serialize(&operation).map_err(custom_err)?;
+        std::fs::write(dest_path, bytes).map_err(custom_err)
+    }
+
+    async fn build_vault_send(
+        &self,
+        address: Address,
+        amount: u64,
+        fee: u64,
+        dest_path: PathBuf,
+    ) -> RpcResult<()> {
+        let operation = self
+            .vault
+            .build_send(
+                &self.node,
+                &self.state,
+                address,
+                amount,
+                fee,
+                self.spend_policy,
+            )
+            .map_err(custom_err)?;
+        let bytes = bincode::serialize(&operation).map_err(custom_err)?;
+        std::fs::write(dest_path, bytes).map_err(custom_err)
+    }
+
+    async fn submit_vault_transaction(&self, path: PathBuf) -> RpcResult<()> {
+        let bytes = std::fs::read(path).map_err(custom_err)?;
+        let transaction = bincode::deserialize(&bytes).map_err(custom_err)?;
+        submit_transaction(&self.node, &self.orphans, transaction)
+            .await
+            .map_err(rejected_err)
+    }
+
+    async fn sign_vault_registration(&self, key: Hash, value: Hash, fee: u64) -> RpcResult<Hash> {
+        let operation = self
+            .vault
+            .build_registration(&self.node, &self.state, key, value, fee, self.spend_policy)
+            .map_err(custom_err)?;
+        self.sign_vault_operation(operation).await
+    }
+
+    async fn sign_vault_send(&self, address: Address, amount: u64, fee: u64) -> RpcResult<Hash> {
+        let operation = self
+            .vault
+            .build_send(
+                &self.node,
+                &self.state,
+                address,
+                amount,
+                fee,
+                self.spend_policy,
+            )
+            .map_err(custom_err)?;
+        self.sign_vault_operation(operation).await
+    }
+
+    async fn add_contact(&self, label: String, contact: Contact) -> RpcResult<()> {
+        self.address_book.add(label, contact).map_err(custom_err)
+    }
+
+    async fn remove_contact(&self, label: String) -> RpcResult<()> {
+        self.address_book.remove(&label).map_err(custom_err)
+    }
+
+    async fn list_contacts(&self) -> RpcResult<Vec<(String, Contact)>> {
+        Ok(self.address_book.list())
+    }
+
+    async fn resolve_contact(&self, label: String) -> RpcResult<ResolvedContact> {
+        self.address_book
+            .resolve(&self.state, &label)
+            .map_err(custom_err)
+    }
+
+    async fn send_to_contact(
+        &self,
+        label: String,
+        amount: u64,
+        fee: u64,
+        confirmed_address: Address,
+        wallet: Option<String>,
+    ) -> RpcResult<()> {
+        let wallet = self.wallet(wallet)?;
+        let resolved = self
+            .address_book
+            .resolve(&self.state, &label)
+            .map_err(custom_err)?;
+        if resolved.address != confirmed_address {
+            return Err(custom_err(format!(
+                "contact {label} now resolves to {}, resolve it again and confirm the new address",
+                resolved.address
+            )));
+        }
+        wallet::send(
+            &self.node,
+            &wallet,
+            resolved.address,
+            amount,
+            fee,
+            self.spend_policy,
+        )
+        .await
+        .map_err(|err| node_err(err.as_ref()))
+    }
+
+    async fn send_to_identifier(
+        &self,
+        identifier: String,
+        amount: u64,
+        fee: u64,
+        wallet: Option<String>,
+    ) -> RpcResult<()> {
+        let wallet = self.wallet(wallet)?;
+        let address = self
+            .resolve_identifier_address(&identifier)
+            .map_err(custom_err)?;
+        wallet::send(&self.node, &wallet, address, amount, fee, self.spend_policy)
+            .await
+            .map_err(|err| node_err(err.as_ref()))
+    }
+
+    async fn get_wallet_history(
+        &self,
+        wallet: Option<String>,
+    ) -> RpcResult<Vec<WalletTransaction>> {
+        let wallet = self.wallet(wallet)?;
+        wallet::history(&self.state, &wallet).map_err(custom_err)
+    }
+
+    async fn get_name_costs(&self, wallet: Option<String>) -> RpcResult<Vec<NameCost>> {
+        let wallet = self.wallet(wallet)?;
+        wallet::name_costs(&self.state, &wallet).map_err(custom_err)
+    }
+
+    async fn export_name_costs(&self, wallet: Option<String>, dest_path: PathBuf) -> RpcResult<()> {
+        let wallet = self.wallet(wallet)?;
+        let costs = wallet::name_costs(&self.state, &wallet).map_err(custom_err)?;
+        std::fs::write(dest_path, wallet::name_costs_csv(&costs)).map_err(custom_err)
+    }
+
+    async fn list_deposits(&self, wallet: Option<String>) -> RpcResult<Vec<(OutPoint, u64)>> {
+        let wallet = self.wallet(wallet)?;
+        wallet::list_deposits(&self.node, &wallet).map_err(custom_err)
+    }
+
+    async fn create_withdrawal(
+        &self,
+        main_address: ddk::bitcoin::Address,
+        value: u64,
+        main_fee: u64,
+        fee: u64,
+        wallet: Option<String>,
+    ) -> RpcResult<Withdrawal> {
+        let wallet = self.wallet(wallet)?;
+        let withdrawal = wallet::create_withdrawal(
+            &self.node,
+            &wallet,
+            main_address,
+            value,
+            main_fee,
+            fee,
+            self.spend_policy,
+        )
+        .await
+        .map_err(|err| node_err(err.as_ref()))?;
+        self.withdrawals
+            .add(withdrawal.clone())
+            .map_err(custom_err)?;
+        Ok(withdrawal)
+    }
+
+    async fn list_withdrawals(&self) -> RpcResult<Vec<(Withdrawal, WithdrawalStatus)>> {
+        self.withdrawals.list(&self.node).map_err(custom_err)
+    }
+
+    async fn backup(&self, dest_path: PathBuf) -> RpcResult<()> {
+        let datadir = self.datadir.clone();
+        tokio::task::spawn_blocking(move || backup::backup(&datadir, &dest_path))
+            .await
+            .map_err(custom_err)?
+            .map_err(custom_err)
+    }
+
+    async fn add_peer(&self, addr: SocketAddr) -> RpcResult<()> {
+        if self.peers.is_banned(&addr) {
+            return Err(custom_err(format!("peer {addr} is banned")));
+        }
+        if let Err(err) = self.node.connect(addr).await {
+            self.peers.record_failure(addr).map_err(custom_err)?;
+            return Err(custom_err(err));
+        }
+        self.peers.record_success(addr).map_err(custom_err)
+    }
+
+    async fn list_peers(&self) -> RpcResult<Vec<(SocketAddr, PeerInfo)>> {
+        Ok(self.peers.list())
+    }
+
+    async fn ban_peer(&self, addr: SocketAddr, seconds: u64) -> RpcResult<()> {
+        self.peers.ban(addr, seconds).map_err(custom_err)
+    }
+
+    async fn watch_name(&self, key: Hash) -> RpcResult<()> {
+        self.watchlist.watch(key).map_err(custom_err)
+    }
+
+    async fn unwatch_name(&self, key: Hash) -> RpcResult<()> {
+        self.watchlist.unwatch(&key).map_err(custom_err)
+    }
+
+    async fn list_watched(&self) -> RpcResult<Vec<Hash>> {
+        Ok(self.watchlist.list())
+    }
+
+    async fn get_watch_events(&self, since: u64) -> RpcResult<Vec<WatchEvent>> {
+        Ok(self.watchlist.events_since(since))
+    }
+
+    async fn subscribe_watchlist(&self, pending: PendingSubscriptionSink) -> SubscriptionResult {
+        let mut events = self.watchlist.subscribe();
+        let sink = pending.accept().await?;
+        loop {
+            let event = match events.recv().await {
+                Ok(event) => event,
+                Err(tokio::sync::broadcast::error::RecvError::Lagged(_)) => continue,
+                Err(tokio::sync::broadcast::error::RecvError::Closed) => return Ok(()),
+            };
+            if sink
+                .send(SubscriptionMessage::from_json(&event)?)
+                .await
+                .is_err()
+            {
+                // The subscriber went away.
+                return Ok(());
+            }
+        }
+    }
+
+    async fn subscribe_names(
+        &self,
+        pending: PendingSubscriptionSink,
+        cursor: Option<NameCursor>,
+    ) -> SubscriptionResult {
+        // Subscribed before catching up, so no block connected meanwhile is missed.
+        let mut events = notify::subscribe();
+        let mut cursor = cursor.unwrap_or(NameCursor {
+            height: 0,
+            merkle_root: None,
+        });
+        let fast_sync_height = self
+            .state
+            .read(|txn, state| state.get_fast_sync_height(txn))?;
+        let refusal = if cursor.height < fast_sync_height {
+            Some(format!(
+                "this node was fast synced to {fast_sync_height}, followers have to start there"
+            ))
+        } else if !cursor_on_chain(&self.state, &cursor)? {
+            Some("cursor is not on this node's chain, resync with diff_names".to_string())
+        } else {
+            None
+        };
+        if let Some(message) = refusal {
+            let code = ErrorCode::InvalidParams.code();
+            pending
+                .reject(ErrorObject::owned(code, message, None::<()>))
+                .await;
+            return Ok(());
+        }
+        let sink = pending.accept().await?;
+        loop {
+            while let Some((registered, next)) = names_after(&self.state, cursor.height)? {
+                cursor = next;
+                let delta = NameDelta::Registered {
+                    registered,
+                    cursor: cursor.clone(),
+                };
+                if sink
+                    .send(SubscriptionMessage::from_json(&delta)?)
+                    .await
+                    .is_err()
+                {
+                    return Ok(());
+                }
+            }
+            // Wait for the tip to move.
+            loop {
+                match events.recv().await {
+                    Ok(Event::Block { .. }) => break,
+                    Ok(Event::Disconnected { height }) if height <= cursor.height => {
+                        cursor = NameCursor {
+                            height: height.saturating_sub(1),
+                            merkle_root: None,
+                        };
+                        let delta = NameDelta::Disconnected { height };
+                        if sink
+                            .send(SubscriptionMessage::from_json(&delta)?)
+                            .await
+                            .is_err()
+                        {
+                            return Ok(());
+                        }
+                    }
+                    Ok(_) => continue,
+                    Err(tokio::sync::broadcast::error::RecvError::Lagged(_)) => {
+                        // Disconnects may have been missed, which only shows if cursor's block
+                        // is gone.
+                        if !cursor_on_chain(&self.state, &cursor)? {
+                            log::info!("name follower fell behind a reorg, ending subscription");
+                            return Ok(());
+                        }
+                        break;
+                    }
+                    Err(tokio::sync::broadcast::error::RecvError::Closed) => return Ok(()),
+                }
+            }
+        }
+    }
+
+    async fn subscribe_mempool(&self, pending: PendingSubscriptionSink) -> SubscriptionResult {
+        let mut events = self.mempool_watch.subscribe();
+        let sink = pending.accept().await?;
+        loop {
+            let event = match events.recv().await {
+                Ok(event) => event,
+                Err(tokio::sync::broadcast::error::RecvError::Lagged(_)) => continue,
+                Err(tokio::sync::broadcast::error::RecvError::Closed) => return Ok(()),
+            };
+            if sink
+                .send(SubscriptionMessage::from_json(&event)?)
+                .await
+                .is_err()
+            {
+                return Ok(());
+            }
+        }
+    }
+}
+
+// The value key resolves to with how settled it is, shared with the gRPC service.
+pub fn resolution(state: &StateReader, key: &Hash) -> Result<Option<Resolution>, Error> {
+    state.read(|txn, state| resolution_in(txn, state, key))
+}
+
+fn resolution_in(
+    txn: &heed::RoTxn,
+    state: &BitNamesState,
+    key: &Hash,
+) -> Result<Option<Resolution>, Error> {
+    let _timer = metrics::Timer::start("resolution", format!("resolving {key:?}"));
+    let mut chain = state.follow_redirects(txn, key)?;
+    let value = match state.get_value(txn, chain.last().unwrap())? {
+        Some(value) => value,
+        None => return Ok(None),
+    };
+    // A reorg of any of the names on the way changes the answer.
+    let mut changed_height = 0;
+    for key in &chain {
+        match state.get_registration_height(txn, key)? {
+            Some(height) => changed_height = changed_height.max(height),
+            None => return Ok(None),
+        }
+    }
+    let confirmations = state.get_tip_height(txn)?.saturating_sub(changed_height) + 1;
+    // Without a rollback limit nothing is final.
+    let finalized = state
+        .get_max_rollback_depth(txn)?
+        .map_or(false, |depth| confirmations > depth);
+    chain.remove(0);
+    Ok(Some(Resolution {
+        value,
+        changed_height,
+        confirmations,
+        finalized,
+        redirects: chain,
+    }))
+}
+
+// Check and submit transaction to the mempool, holding it as an orphan if its inputs aren't
+// known yet. Shared with the gRPC service.
+pub async fn submit_transaction(
+    node: &Node,
+    orphans: &OrphanPool,
+    transaction: AuthorizedTransaction<Authorization, BitName>,
+) -> Result<(), Rejection> {
+    let txid = hex::encode(Hash::from(transaction.transaction.txid()));
+    let result = match node.fill_transaction(&transaction.transaction) {
+        Ok(filled) => rejection::check_funding(&filled),
+        // Most likely a child of a transaction that isn't mined yet.
+        Err(err) if orphans.add(transaction.clone()) => Err(Rejection::new(
+            RejectionCode::MissingInputs,
+            format!("{err}, held as an orphan until its inputs confirm"),
+        )),
+        Err(err) => Err(Rejection::new(RejectionCode::MissingInputs, err)),
+    };
+    let result = match result {
+        Ok(()) => node
+            .submit_transaction(&transaction)
+            .await
+            .map_err(|err| Rejection::from_error(&err)),
+        Err(rejection) => Err(rejection),
+    };
+    if let Err(rejection) = &result {
+        log::info!(
+            "rejected transaction {txid}: {:?}: {}",
+            rejection.code,
+            rejection.message
+        );
+    }
+    result
+}
+
+fn cursor_at(txn: &heed::RoTxn, state: &BitNamesState, height: u32) -> Result<NameCursor, Error> {
+    let merkle_root = state
+        .get_block(txn, height)?
+        .map(|body| body.compute_merkle_root().into());
+    Ok(NameCursor {
+        height,
+        merkle_root,
+    })
+}
+
+// Whether cursor's block is on this node's chain, as far as can be told.
+pub fn cursor_on_chain(state: &StateReader, cursor: &NameCursor) -> Result<bool, Error> {
+    state.read(|txn, state| {
+        if cursor.height > state.get_tip_height(txn)? {
+            return Ok(false);
+        }
+        let current = cursor_at(txn, state, cursor.height)?;
+        Ok(match (cursor.merkle_root, current.merkle_root) {
+            (Some(expected), Some(actual)) => expected == actual,
+            _ => true,
+        })
+    })
+}
+
+// The next batch of names registered after height and the cursor it goes up to, None at the tip.
+pub fn names_after(
+    state: &StateReader,
+    height: u32,
+) -> Result<Option<(Vec<crate::bitnames::RecentName>, NameCursor)>, Error> {
+    state.read(|txn, state| {
+        let tip = state.get_tip_height(txn)?;
+        let (registered, to) = state.get_names_between(txn, height, tip, MAX_DIFF_NAMES)?;
+        if to <= height {
+            return Ok(None);
+        }
+        Ok(Some((registered, cursor_at(txn, state, to)?)))
+    })
+}
+
+// Records how long every method call takes and whether it fails, see metrics.
+#[derive(Clone)]
+struct MetricsLogger;
+
+impl Logger for MetricsLogger {
+    type Instant = Instant;
+
+    fn on_connect(&self, _remote_addr: SocketAddr, _request: &HttpRequest, _t: TransportProtocol) {}
+
+    fn on_request(&self, _transport: TransportProtocol) -> Instant {
+        Instant::now()
+    }
+
+    fn on_call(
+        &self,
+        _method_name: &str,
+        _params: Params,
+        _kind: MethodKind,
+        _transport: TransportProtocol,
+    ) {
+    }
+
+    fn on_result(
+        &self,
+        method_name: &str,
+        success_or_error: MethodResponseResult,
+        started_at: Instant,
+        _transport: TransportProtocol,
+    ) {
+        metrics::record_rpc(
+            method_name,
+            started_at.elapsed(),
+            success_or_error.is_success(),
+        );
+    }
+
+    fn on_response(&self, _result: &str, _started_at: Instant, _transport: TransportProtocol) {}
+
+    fn on_disconnect(&self, _remote_addr: SocketAddr, _transport: TransportProtocol) {}
+}
+
+// Serve rpc on addr. Requests must carry authorization as their Authorization header, except for
+// calls to rpc_auth::PUBLIC_METHODS.
+pub async fn run_server(
+    rpc: RpcServerImpl,
+    addr: SocketAddr,
+    authorization: String,
+    rate_limiter: RateLimiter,
+    resolver_only: bool,
+    trusted_proxies: usize,
+) -> anyhow::Result<ServerHandle> {
+    let auth = AuthLayer::new(
+        authorization,
+        rate_limiter,
+        rpc.status.clone(),
+        resolver_only,
+        trusted_proxies,
+    );
+    let middleware = tower::ServiceBuilder::new().layer(auth);
+    let server = ServerBuilder::default()
+        .set_middleware(middleware)
+        .set_logger(MetricsLogger)
+        .build(addr)
+        .await?;
+    let handle = server.start(rpc.into_rpc())?;
+    Ok(handle)
+}
@@ -0,0 +1,738 @@
+// The node's JSON-RPC interface: the Rpc trait the server implements, and the types its methods
+// take and return. The trait also generates RpcClient, a typed client with one async method per
+// RPC method, which connect returns a connection for.
+//
+//     let client = rpc_api::connect("ws://127.0.0.1:36000", &authorization).await?;
+//     let height = client.get_height().await?;
+//     let value = client.get_value(key).await?;
+//
+// Every method other than the ones in rpc_auth::PUBLIC_METHODS of the node needs the
+// authorization from basic_authorization, with the node's --rpc-user and --rpc-password or its
+// cookie. Anonymous WebSocket connections are refused, anonymous callers of public methods have to
+// use plain HTTP requests.
+use crate::address_book::{Contact, ResolvedContact};
+use crate::analytics::NameReport;
+use crate::attestation::AttestationRecord;
+use crate::bitnames::{BitName, NameLogEvent, RecentName};
+use crate::deployments::DeploymentState;
+use crate::disputes::Dispute;
+use crate::identity::IdentityKey;
+use crate::idn::NameForms;
+use crate::main_address::MainAddressRecord;
+use crate::offchain::{OffchainData, OffchainPointer};
+use crate::onion::{OnionBinding, OnionRecord};
+use crate::peer_store::PeerInfo;
+use crate::read_pool::ReadPoolStats;
+use crate::records::{Record, TypedRecord};
+use crate::resolution::SignedResolution;
+use crate::status::NodeStatus;
+use crate::tlsa::{TlsaRecord, TlsaVerdict};
+use crate::watchlist::WatchEvent;
+use base64::Engine;
+use ddk::authorization::Authorization;
+use ddk::types::{Address, AuthorizedTransaction, Body, Hash, Header, OutPoint, Txid};
+use ed25519_dalek::{PublicKey, Signature};
+use jsonrpsee::core::{RpcResult, SubscriptionResult};
+use jsonrpsee::proc_macros::rpc;
+use jsonrpsee::ws_client::{HeaderMap, HeaderValue, WsClient, WsClientBuilder};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::net::SocketAddr;
+use std::path::PathBuf;
+
+// A block that is ready to be BMMed by an external coordinator.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct BlockTemplate {
+    pub header: Header,
+    pub body: Body<Authorization, BitName>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct DeploymentInfo {
+    pub name: String,
+    // None for deployments of rules for every transaction, see deployments.rs.
+    pub version: Option<u8>,
+    pub bit: u8,
+    pub state: DeploymentState,
+}
+
+// Most keys resolve_many answers in one call.
+pub const MAX_RESOLVE_MANY: usize = 1000;
+// Most names diff_names answers in one call, unless a single block registers more.
+pub const MAX_DIFF_NAMES: usize = 10_000;
+// Most name log events get_name_log answers in one call.
+pub const MAX_NAME_LOG_EVENTS: usize = 10_000;
+
+// What a key resolves to, with how deeply its last change is buried so consumers can decide
+// whether to trust a freshly changed record.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Resolution {
+    pub value: Hash,
+    // Height of the block that last changed the value. Names never change after registration, so
+    // this is the registration height, or the fast sync height for names imported from a snapshot.
+    pub changed_height: u32,
+    // 1 for a change in the tip block.
+    pub confirmations: u32,
+    // Whether the change is buried deeper than the node's --max-rollback-depth, the most it rolls
+    // the name state back without its operator.
+    pub finalized: bool,
+    // Names the key redirected through to the one value is registered under, in order, empty if
+    // key has no redirect. changed_height is the last change of any of them.
+    #[serde(default)]
+    pub redirects: Vec<Hash>,
+}
+
+// Where a subscribe_names follower is: the last block it applied, and that block's body merkle
+// root, so a follower resuming after a reorg is noticed. merkle_root is None where the node
+// pruned the body, those cursors are trusted.
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Eq)]
+pub struct NameCursor {
+    pub height: u32,
+    pub merkle_root: Option<Hash>,
+}
+
+// Pushed to subscribe_names followers.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(tag = "delta", rename_all = "snake_case")]
+pub enum NameDelta {
+    // Names registered after the previous cursor up to cursor, which the follower should store to
+    // resume from.
+    Registered {
+        registered: Vec<RecentName>,
+        cursor: NameCursor,
+    },
+    // The block at height was disconnected by a reorg. Drop the names registered at height, the
+    // follower is at height - 1 now.
+    Disconnected {
+        height: u32,
+    },
+}
+
+// Names registered between two heights.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct NameDiff {
+    pub from: u32,
+    // Height the diff goes up to. Below the requested height if there were more than
+    // MAX_DIFF_NAMES names, ask again from here for the rest.
+    pub to: u32,
+    pub registered: Vec<RecentName>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct RegistrationRequest {
+    pub key: Hash,
+    pub value: Hash,
+    pub owner: Address,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum RegistrationStatus {
+    // Included in the submitted batch transaction.
+    Submitted,
+    AlreadyRegistered,
+    // The same key appears earlier in the batch.
+    Duplicate,
+    // A registration for the key from this node is being built or is already in the mempool.
+    Pending,
+    // The batch already holds max_registrations_per_block registrations, submit it again once the
+    // current batch is in a block.
+    Deferred,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Availability {
+    Available,
+    Registered,
+    // A registration for the key is in the mempool or being built by this node.
+    Pending,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct NameQuote {
+    pub availability: Availability,
+    // Fee in sats expected to get a registration into the next block, None if there are no
+    // recent blocks to estimate from.
+    pub fee: Option<u64>,
+    // Registration price burned on top of the fee if the registration makes it into the next
+    // block, see wallet::registration_burn.
+    pub price: u64,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Withdrawal {
+    pub outpoint: OutPoint,
+    // Sidechain address of the withdrawal output, which gets the funds back if the bundle fails.
+    pub address: Address,
+    pub main_address: String,
+    pub value: u64,
+    pub main_fee: u64,
+}
+
+// A confirmed transaction that paid to or spent from a wallet, with what it did in terms of names.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct WalletTransaction {
+    // None for coinbase outputs, which have no transaction.
+    pub txid: Option<Hash>,
+    pub height: u32,
+    // Sum of the wallet's outputs the transaction spent.
+    pub sent: u64,
+    // Sum of the transaction's outputs paying to the wallet.
+    pub received: u64,
+    // Known if every input was the wallet's.
+    pub fee: Option<u64>,
+    pub labels: Vec<WalletLabel>,
+}
+
+// What a wallet transaction did. Names are never renewed or transferred, so registrations are
+// the only name operations that move a name to the wallet.
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum WalletLabel {
+    // The wallet paid for registering key, to one of its own addresses if owned.
+    Registered { key: Hash, owned: bool },
+    // Someone else registered key to one of the wallet's addresses.
+    ReceivedName { key: Hash },
+    Disputed { key: Hash },
+    RotatedIdentity { key: Hash },
+    RaisedOffchainSequence { key: Hash },
+    Sent { address: Address, value: u64 },
+    Received { value: u64 },
+    Withdrawal { main_address: String, value: u64 },
+    Mined { value: u64 },
+}
+
+// What a wallet spent on one name, for reconciling a name portfolio's costs.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct NameCost {
+    pub key: Hash,
+    // Fees, including the burned registration price, of every transaction of the wallet that
+    // registered key or filed a record for it. A transaction doing several of those is split
+    // evenly between them.
+    pub spent: u64,
+    pub transactions: Vec<Hash>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum WithdrawalStatus {
+    // The withdrawal transaction is waiting in the mempool.
+    Unconfirmed,
+    // The withdrawal output is confirmed and waiting to be put in a bundle.
+    Pending,
+    // ddk took the output out of the utxo set for a withdrawal bundle. ddk doesn't report what
+    // the mainchain did with the bundle, so this stays the final status once the bundle is paid
+    // out. A failed bundle puts the output back, and the withdrawal shows up as Pending again.
+    Bundled,
+}
+
+// Pushed to subscribe_mempool subscribers as the mempool changes.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum MempoolEvent {
+    // A transaction entered the mempool, registering keys.
+    Accepted { txid: Txid, keys: Vec<Hash> },
+    // A transaction left the mempool, because it was mined, replaced or dropped.
+    Removed { txid: Txid },
+    // Several unconfirmed transactions register key, and only one of them can be mined. Sent
+    // whenever another one arrives, with all of them in txids.
+    RegistrationConflict { key: Hash, txids: Vec<Txid> },
+}
+
+#[rpc(server, client)]
+pub trait Rpc {
+    // Build a block template from the current mempool, with the coinbase paying all fees to
+    // coinbase_address.
+    #[method(name = "get_block_template")]
+    async fn get_block_template(&self, coinbase_address: Address) -> RpcResult<BlockTemplate>;
+
+    // Connect a block whose BMM request was included in the mainchain, and broadcast it.
+    #[method(name = "submit_block")]
+    async fn submit_block(
+        &self,
+        header: Header,
+        body: Body<Authorization, BitName>,
+    ) -> RpcResult<()>;
+
+    // Mine n blocks immediately, paying the coinbase to wallet. Only available on regtest.
+    #[method(name = "generate")]
+    async fn generate(&self, n: u32, wallet: Option<String>) -> RpcResult<()>;
+
+    #[method(name = "get_height")]
+    async fn get_height(&self) -> RpcResult<u32>;
+
+    // Tip height, hash and age, header height, mainchain reachability and whether answers may be
+    // stale, with peer, mempool, database, name and utxo counts, for monitoring in one call.
+    #[method(name = "get_node_status")]
+    async fn get_node_status(&self) -> RpcResult<NodeStatus>;
+
+    // Reads served by the read pool and how far behind the tip its transactions were.
+    #[method(name = "get_read_pool_stats")]
+    async fn get_read_pool_stats(&self) -> RpcResult<ReadPoolStats>;
+
+    // Current log filter, like `info,mining=debug`.
+    #[method(name = "get_log_filter")]
+    async fn get_log_filter(&self) -> RpcResult<String>;
+
+    // Replace the log filter of the running node, a default level followed by
+    // per-target levels.
+    #[method(name = "set_log_filter")]
+    async fn set_log_filter(&self, filter: String) -> RpcResult<()>;
+
+    // State of every known soft fork deployment for the next block.
+    #[method(name = "get_deployments")]
+    async fn get_deployments(&self) -> RpcResult<Vec<DeploymentInfo>>;
+
+    // The value registered under key, without following its redirects.
+    #[method(name = "get_value")]
+    async fn get_value(&self, key: Hash) -> RpcResult<Option<Hash>>;
+
+    // The value key resolves to after following its redirect records, with the confirmations of
+    // the value, None if the name they end at isn't registered. Fails if the redirects loop or go
+    // deeper than records::MAX_REDIRECT_HOPS.
+    #[method(name = "resolve")]
+    async fn resolve(&self, key: Hash) -> RpcResult<Option<Resolution>>;
+
+    // Hex encoded compact filter of the block at height, for light clients to check whether the
+    // block touches their keys or addresses before fetching it.
+    #[method(name = "get_block_filter")]
+    async fn get_block_filter(&self, height: u32) -> RpcResult<Option<String>>;
+
+    // The values up to MAX_RESOLVE_MANY keys resolve to, following redirects like resolve, all
+    // read from the same tip. Keys whose redirects loop or go too deep resolve to None.
+    #[method(name = "resolve_many")]
+    async fn resolve_many(&self, keys: Vec<Hash>) -> RpcResult<Vec<Option<Hash>>>;
+
+    // Names registered above height from up to to, for mirrors that already have the names at
+    // from. Names are never updated, transferred or expire, so there is nothing else to report.
+    #[method(name = "diff_names")]
+    async fn diff_names(&self, from: u32, to: u32) -> RpcResult<NameDiff>;
+
+    // Name log events from sequence number from on, at most MAX_NAME_LOG_EVENTS. Consumers store
+    // the sequence of the last event they processed and ask for the one after it, so every event
+    // is delivered at least once, across restarts and reorgs, which show up as unregistrations.
+    #[method(name = "get_name_log")]
+    async fn get_name_log(&self, from: u64) -> RpcResult<Vec<NameLogEvent>>;
+
+    // The latest namespace report, rebuilt every analytics::REPORT_INTERVAL blocks. None right
+    // after startup, before the first one is built. `bitnames report` writes one as CSV.
+    #[method(name = "get_name_report")]
+    async fn get_name_report(&self) -> RpcResult<Option<NameReport>>;
+
+    // Whether key is available for registration, and the expected fee for registering it.
+    #[method(name = "quote_name")]
+    async fn quote_name(&self, key: Hash) -> RpcResult<NameQuote>;
+
+    // The onion service key was bound to at registration, if any.
+    #[method(name = "get_onion_binding")]
+    async fn get_onion_binding(&self, key: Hash) -> RpcResult<Option<OnionBinding>>;
+
+    // TLSA records key was registered with.
+    #[method(name = "get_tlsa_records")]
+    async fn get_tlsa_records(&self, key: Hash) -> RpcResult<Vec<TlsaRecord>>;
+
+    // Mainchain addresses that pay key's owner, each signed for by the address.
+    #[method(name = "get_main_addresses")]
+    async fn get_main_addresses(&self, key: Hash) -> RpcResult<Vec<MainAddressRecord>>;
+
+    // Display and raw forms of a plaintext name given in either form, and the key both hash to,
+    // see idn.rs.
+    #[method(name = "get_name_forms")]
+    async fn get_name_forms(&self, name: String) -> RpcResult<NameForms>;
+
+    // Typed records key was registered with, see records.rs.
+    #[method(name = "get_records")]
+    async fn get_records(&self, key: Hash) -> RpcResult<Vec<TypedRecord>>;
+
+    // An example of every kind of typed record, showing how each is written in JSON.
+    #[method(name = "get_record_templates")]
+    async fn get_record_templates(&self) -> RpcResult<Vec<Record>>;
+
+    // Check a typed record the way consensus will once the records deployment is active, with
+    // the reason if it is rejected.
+    #[method(name = "validate_record")]
+    async fn validate_record(&self, record: Record) -> RpcResult<()>;
+
+    // Where key's off-chain data is published and the key that signs it, with the current
+    // minimum sequence, see offchain.rs.
+    #[method(name = "get_offchain_pointer")]
+    async fn get_offchain_pointer(&self, key: Hash) -> RpcResult<Option<OffchainPointer>>;
+
+    // Check data fetched from the pointer of data.key against the pointer's current state, with
+    // the reason if resolvers should refuse it. Nodes never fetch the data themselves.
+    #[method(name = "verify_offchain_data")]
+    async fn verify_offchain_data(&self, data: OffchainData) -> RpcResult<()>;
+
+    // Raise the minimum sequence of key's off-chain data pointer, so resolvers refuse data
+    // published before. signature is the pointer key's signature over
+    // offchain::sequence_message(key, min_sequence), made wherever that key is kept. Returns the
+    // txid.
+    #[method(name = "raise_offchain_sequence")]
+    async fn raise_offchain_sequence(
+        &self,
+        key: Hash,
+        min_sequence: u64,
+        signature: Signature,
+        fee: u64,
+        wallet: Option<String>,
+    ) -> RpcResult<Hash>;
+
+    // Cross-chain attestations key was registered with, all of them verified.
+    #[method(name = "get_attestations")]
+    async fn get_attestations(&self, key: Hash) -> RpcResult<Vec<AttestationRecord>>;
+
+    // Check the hex encoded DER certificate a host presented for key against key's TLSA
+    // records, for clients and proxies that can't check records themselves.
+    #[method(name = "verify_certificate")]
+    async fn verify_certificate(&self, key: Hash, certificate: String) -> RpcResult<TlsaVerdict>;
+
+    // The address a user@name identifier pays.
+    #[method(name = "resolve_identifier")]
+    async fn resolve_identifier(&self, identifier: String) -> RpcResult<Address>;
+
+    // Dispute records filed against key, oldest first. They don't affect who owns the name, but
+    // a resolver or wallet should show them before trusting it.
+    #[method(name = "get_disputes")]
+    async fn get_disputes(&self, key: Hash) -> RpcResult<Vec<Dispute>>;
+
+    // Attach evidence against key, signed by this node's key, in a transaction paid for by
+    // wallet. Returns the txid.
+    #[method(name = "file_dispute")]
+    async fn file_dispute(
+        &self,
+        key: Hash,
+        evidence: String,
+        fee: u64,
+        wallet: Option<String>,
+    ) -> RpcResult<Hash>;
+
+    // The value key resolves to like resolve, but with the tip height and a signature by this
+    // node's key.
+    #[method(name = "resolve_signed")]
+    async fn resolve_signed(&self, key: Hash) -> RpcResult<SignedResolution>;
+
+    #[method(name = "submit_transaction")]
+    async fn submit_transaction(
+        &self,
+        transaction: AuthorizedTransaction<Authorization, BitName>,
+    ) -> RpcResult<()>;
+
+    // Register key with value from wallet, or the default wallet if it is omitted. Every wallet
+    // method takes the same optional trailing wallet name.
+    #[method(name = "register_name")]
+    async fn register_name(
+        &self,
+        key: Hash,
+        value: Hash,
+        fee: u64,
+        wallet: Option<String>,
+    ) -> RpcResult<()>;
+
+    // New address of wallet to receive on, the form mainchain deposit addresses are built from.
+    #[method(name = "get_new_address")]
+    async fn get_new_address(&self, wallet: Option<String>) -> RpcResult<String>;
+
+    // Register key with value to owner's address, with the node's wallet only paying the fee.
+    #[method(name = "register_name_for")]
+    async fn register_name_for(
+        &self,
+        key: Hash,
+        value: Hash,
+        owner: Address,
+        fee: u64,
+        wallet: Option<String>,
+    ) -> RpcResult<()>;
+
+    // Register record.key with value, bound to the onion service whose key signed record. The
+    // service signs onion::message(key) with its identity key.
+    #[method(name = "register_name_with_onion")]
+    async fn register_name_with_onion(
+        &self,
+        value: Hash,
+        record: OnionRecord,
+        fee: u64,
+        wallet: Option<String>,
+    ) -> RpcResult<()>;
+
+    // Register key with value and TLSA records pinning the certificates of its host.
+    #[method(name = "register_name_with_tlsa")]
+    async fn register_name_with_tlsa(
+        &self,
+        key: Hash,
+        value: Hash,
+        records: Vec<TlsaRecord>,
+        fee: u64,
+        wallet: Option<String>,
+    ) -> RpcResult<()>;
+
+    // Register key with value and mainchain addresses paying its owner. Each record's signature
+    // is `bitcoin-cli signmessage <address> bitnames:<hex encoded key>`.
+    #[method(name = "register_name_with_main_addresses")]
+    async fn register_name_with_main_addresses(
+        &self,
+        key: Hash,
+        value: Hash,
+        records: Vec<MainAddressRecord>,
+        fee: u64,
+        wallet: Option<String>,
+    ) -> RpcResult<()>;
+
+    // Register key with value and typed records, each of which has to validate.
+    #[method(name = "register_name_with_records")]
+    async fn register_name_with_records(
+        &self,
+        key: Hash,
+        value: Hash,
+        records: Vec<Record>,
+        fee: u64,
+        wallet: Option<String>,
+    ) -> RpcResult<()>;
+
+    // Register key with value and attestations, made with attestation::AttestationRecord::new by
+    // the holders of the attested addresses.
+    #[method(name = "register_name_with_attestations")]
+    async fn register_name_with_attestations(
+        &self,
+        key: Hash,
+        value: Hash,
+        attestations: Vec<AttestationRecord>,
+        fee: u64,
+        wallet: Option<String>,
+    ) -> RpcResult<()>;
+
+    // Register key with value and payment aliases, mapping users to the addresses user@name pays.
+    // Only names whose key is aliases::name_key of the name can be reached that way.
+    #[method(name = "register_name_with_aliases")]
+    async fn register_name_with_aliases(
+        &self,
+        key: Hash,
+        value: Hash,
+        aliases: BTreeMap<String, Address>,
+        fee: u64,
+        wallet: Option<String>,
+    ) -> RpcResult<()>;
+
+    // Register a name with identity key public_key bound to it.
+    #[method(name = "register_name_with_identity")]
+    async fn register_name_with_identity(
+        &self,
+        key: Hash,
+        value: Hash,
+        public_key: PublicKey,
+        fee: u64,
+        wallet: Option<String>,
+    ) -> RpcResult<()>;
+
+    // Every identity key bound to key or rotated to, oldest first. The last one is current.
+    #[method(name = "get_identity_keys")]
+    async fn get_identity_keys(&self, key: Hash) -> RpcResult<Vec<IdentityKey>>;
+
+    // Rotate key's identity key to public_key. signature is the current identity key's signature
+    // over identity::rotation_message(key, number of keys so far, public_key), made wherever that
+    // key is kept. Returns the txid.
+    #[method(name = "rotate_identity_key")]
+    async fn rotate_identity_key(
+        &self,
+        key: Hash,
+        public_key: PublicKey,
+        signature: Signature,
+        fee: u64,
+        wallet: Option<String>,
+    ) -> RpcResult<Hash>;
+
+    // Register many names in one transaction, paying fee_per_item for each one included. Returns
+    // the status of every request, in order.
+    #[method(name = "register_names")]
+    async fn register_names(
+        &self,
+        requests: Vec<RegistrationRequest>,
+        fee_per_item: u64,
+        wallet: Option<String>,
+    ) -> RpcResult<Vec<RegistrationStatus>>;
+
+    // Create a new named wallet.
+    #[method(name = "create_wallet")]
+    async fn create_wallet(&self, name: String) -> RpcResult<()>;
+
+    // Names of all named wallets, the default wallet isn't listed.
+    #[method(name = "list_wallets")]
+    async fn list_wallets(&self) -> RpcResult<Vec<String>>;
+
+    // Add the vault addresses in path, written by `bitnames vault export-addresses`, and return
+    // how many were new. See vault.rs for the whole workflow.
+    #[method(name = "import_vault_addresses")]
+    async fn import_vault_addresses(&self, path: PathBuf) -> RpcResult<usize>;
+
+    // Write an unsigned registration of key with value, paid from and to the vault, to
+    // dest_path, for `bitnames vault sign`.
+    #[method(name = "build_vault_registration")]
+    async fn build_vault_registration(
+        &self,
+        key: Hash,
+        value: Hash,
+        fee: u64,
+        dest_path: PathBuf,
+    ) -> RpcResult<()>;
+
+    // Write an unsigned payment of amount sats from the vault to address to dest_path.
+    #[method(name = "build_vault_send")]
+    async fn build_vault_send(
+        &self,
+        address: Address,
+        amount: u64,
+        fee: u64,
+        dest_path: PathBuf,
+    ) -> RpcResult<()>;
+
+    // Submit the transaction in path, signed by `bitnames vault sign`.
+    #[method(name = "submit_vault_transaction")]
+    async fn submit_vault_transaction(&self, path: PathBuf) -> RpcResult<()>;
+
+    // Build a registration like build_vault_registration, have it signed by the node's
+    // --vault-signer and submit it, returning its txid.
+    #[method(name = "sign_vault_registration")]
+    async fn sign_vault_registration(&self, key: Hash, value: Hash, fee: u64) -> RpcResult<Hash>;
+
+    // Build a payment like build_vault_send, have it signed by the node's --vault-signer and
+    // submit it, returning its txid.
+    #[method(name = "sign_vault_send")]
+    async fn sign_vault_send(&self, address: Address, amount: u64, fee: u64) -> RpcResult<Hash>;
+
+    // Save a contact under label, replacing any contact with the same label.
+    #[method(name = "add_contact")]
+    async fn add_contact(&self, label: String, contact: Contact) -> RpcResult<()>;
+
+    #[method(name = "remove_contact")]
+    async fn remove_contact(&self, label: String) -> RpcResult<()>;
+
+    #[method(name = "list_contacts")]
+    async fn list_contacts(&self) -> RpcResult<Vec<(String, Contact)>>;
+
+    // The address label would be paid at right now, and for name contacts when the name last
+    // changed. Show this to the user before calling send_to_contact.
+    #[method(name = "resolve_contact")]
+    async fn resolve_contact(&self, label: String) -> RpcResult<ResolvedContact>;
+
+    // Pay amount sats to label. Fails unless the contact still resolves to confirmed_address, the
+    // address the user confirmed from resolve_contact.
+    #[method(name = "send_to_contact")]
+    async fn send_to_contact(
+        &self,
+        label: String,
+        amount: u64,
+        fee: u64,
+        confirmed_address: Address,
+        wallet: Option<String>,
+    ) -> RpcResult<()>;
+
+    // Pay amount sats to the address identifier resolves to.
+    #[method(name = "send_to_identifier")]
+    async fn send_to_identifier(
+        &self,
+        identifier: String,
+        amount: u64,
+        fee: u64,
+        wallet: Option<String>,
+    ) -> RpcResult<()>;
+
+    // Confirmed transactions of wallet, oldest first, labeled with the name operations they
+    // performed. Built from the address index, so it follows reorgs, but transactions in pruned
+    // blocks are left out. Deposits are listed by list_deposits.
+    #[method(name = "get_wallet_history")]
+    async fn get_wallet_history(&self, wallet: Option<String>)
+        -> RpcResult<Vec<WalletTransaction>>;
+
+    // What wallet spent per name, from get_wallet_history, sorted by key.
+    #[method(name = "get_name_costs")]
+    async fn get_name_costs(&self, wallet: Option<String>) -> RpcResult<Vec<NameCost>>;
+
+    // Write get_name_costs as CSV to dest_path on the node's host, with columns key, spent and
+    // the space separated txids.
+    #[method(name = "export_name_costs")]
+    async fn export_name_costs(&self, wallet: Option<String>, dest_path: PathBuf) -> RpcResult<()>;
+
+    // Unspent deposits from the mainchain credited to wallet.
+    #[method(name = "list_deposits")]
+    async fn list_deposits(&self, wallet: Option<String>) -> RpcResult<Vec<(OutPoint, u64)>>;
+
+    // Withdraw value sats from wallet to main_address on the mainchain. main_fee goes to the
+    // mainchain miner that includes the withdrawal bundle, fee to the sidechain.
+    #[method(name = "create_withdrawal")]
+    async fn create_withdrawal(
+        &self,
+        main_address: ddk::bitcoin::Address,
+        value: u64,
+        main_fee: u64,
+        fee: u64,
+        wallet: Option<String>,
+    ) -> RpcResult<Withdrawal>;
+
+    // Withdrawals created through this node, with their current status.
+    #[method(name = "list_withdrawals")]
+    async fn list_withdrawals(&self) -> RpcResult<Vec<(Withdrawal, WithdrawalStatus)>>;
+
+    // Copy the node and wallet databases to dest_path, which must not exist or be empty, while
+    // the node keeps running.
+    #[method(name = "backup")]
+    async fn backup(&self, dest_path: PathBuf) -> RpcResult<()>;
+
+    // Connect to a sidechain peer and remember it for future restarts.
+    #[method(name = "add_peer")]
+    async fn add_peer(&self, addr: SocketAddr) -> RpcResult<()>;
+
+    #[method(name = "list_peers")]
+    async fn list_peers(&self) -> RpcResult<Vec<(SocketAddr, PeerInfo)>>;
+
+    // Stop dialing addr for the given number of seconds. ddk can't drop an existing connection,
+    // so this takes effect on the next restart or add_peer.
+    #[method(name = "ban_peer")]
+    async fn ban_peer(&self, addr: SocketAddr, seconds: u64) -> RpcResult<()>;
+
+    // Add key to the watchlist, it doesn't have to be owned by the wallet.
+    #[method(name = "watch_name")]
+    async fn watch_name(&self, key: Hash) -> RpcResult<()>;
+
+    #[method(name = "unwatch_name")]
+    async fn unwatch_name(&self, key: Hash) -> RpcResult<()>;
+
+    #[method(name = "list_watched")]
+    async fn list_watched(&self) -> RpcResult<Vec<Hash>>;
+
+    // Watchlist events with a sequence number above since, pass 0 to get all retained events.
+    #[method(name = "get_watch_events")]
+    async fn get_watch_events(&self, since: u64) -> RpcResult<Vec<WatchEvent>>;
+
+    // Push watchlist events over WebSocket as they happen.
+    #[subscription(name = "subscribe_watchlist" => "watchlist_event", unsubscribe = "unsubscribe_watchlist", item = WatchEvent)]
+    async fn subscribe_watchlist(&self) -> SubscriptionResult;
+
+    // Follow the name set: everything registered after cursor, or from genesis without one, in
+    // batches of at most MAX_DIFF_NAMES names, then every block as it is connected or
+    // disconnected. Followers resume with the last cursor they received. The subscription is
+    // refused if cursor isn't on this node's chain, and ends if a reorg happened while the
+    // follower fell behind, the follower has to resync with diff_names then.
+    #[subscription(name = "subscribe_names" => "name_delta", unsubscribe = "unsubscribe_names", item = NameDelta)]
+    async fn subscribe_names(&self, cursor: Option<NameCursor>) -> SubscriptionResult;
+
+    // Push mempool changes over WebSocket as they happen, including registration_conflict events
+    // for keys that several unconfirmed transactions race to register.
+    #[subscription(name = "subscribe_mempool" => "mempool_event", unsubscribe = "unsubscribe_mempool", item = MempoolEvent)]
+    async fn subscribe_mempool(&self) -> SubscriptionResult;
+}
+
+// Value of the Authorization header a client has to send for user and password.
+pub fn basic_authorization(user: &str, password: &str) -> String {
+    let credentials =
+        base64::engine::general_purpose::STANDARD.encode(format!("{user}:{password}"));
+    format!("Basic {credentials}")
+}
+
+// Open a WebSocket connection to the RPC server at url, sending authorization with the handshake.
+// Subscriptions like subscribe_watchlist need a WebSocket connection, so the client is always one.
+pub async fn connect(url: &str, authorization: &str) -> anyhow::Result<WsClient> {
+    let mut headers = HeaderMap::new();
+    headers.insert("authorization", HeaderValue::from_str(authorization)?);
+    let client = WsClientBuilder::default()
+        .set_headers(headers)
+        .build(url)
+        .await?;
+    Ok(client)
+}
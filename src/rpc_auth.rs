@@ -0,0 +1,386 @@
+use crate::metrics;
+use crate::rate_limit::RateLimiter;
+use crate::status::StatusMonitor;
+use hyper::{Body, Request, Response};
+use std::future::Future;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+
+// User name written to the cookie file, so cookie and user/password auth look the same to
+// clients.
+pub const COOKIE_USER: &str = "__cookie__";
+// Methods that can be called without credentials. Everything else can spend from the wallet,
+// mine, or touch the node's files or peers.
+pub const PUBLIC_METHODS: &[&str] = &[
+    "diff_names",
+    "get_attestations",
+    "get_block_filter",
+    "get_disputes",
+    "get_height",
+    "get_identity_keys",
+    "get_main_addresses",
+    "get_name_forms",
+    "get_name_log",
+    "get_name_report",
+    "get_offchain_pointer",
+    "get_onion_binding",
+    "get_record_templates",
+    "get_records",
+    "get_tlsa_records",
+    "get_value",
+    "quote_name",
+    "resolve",
+    "resolve_identifier",
+    "resolve_many",
+    "resolve_signed",
+    "validate_record",
+    "verify_certificate",
+    "verify_offchain_data",
+];
+// Unauthenticated requests have to be buffered to check which methods they call, so they are
+// capped well below the server's own request size limit.
+const MAX_PUBLIC_REQUEST_SIZE: u64 = 64 * 1024;
+
+type BoxError = Box<dyn std::error::Error + Send + Sync + 'static>;
+
+// A random password written to <datadir>/.cookie on startup, readable only by the user running
+// the node. Used when no --rpc-user/--rpc-password is configured.
+pub struct Cookie {
+    path: PathBuf,
+    pub password: String,
+}
+
+impl Cookie {
+    pub fn create(datadir: &Path) -> anyhow::Result<Self> {
+        let path = datadir.join(".cookie");
+        let password = hex::encode(rand::random::<[u8; 32]>());
+        // Left behind by a node that didn't shut down cleanly, and maybe with wider permissions,
+        // which opening an existing file wouldn't change.
+        let _ = std::fs::remove_file(&path);
+        let mut options = std::fs::OpenOptions::new();
+        options.write(true).create_new(true);
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::OpenOptionsExt;
+            options.mode(0o600);
+        }
+        let mut file = options.open(&path)?;
+        file.write_all(format!("{COOKIE_USER}:{password}").as_bytes())?;
+        Ok(Self { path, password })
+    }
+
+    pub fn remove(&self) -> anyhow::Result<()> {
+        std::fs::remove_file(&self.path)?;
+        Ok(())
+    }
+}
+
+// HTTP middleware that lets requests with the right credentials through, and lets anonymous
+// requests through only if every call in them is one of PUBLIC_METHODS and the client is within
+// its rate limit.
+//
+// The server doesn't see client addresses, so all anonymous clients share a single bucket unless
+// the node runs behind trusted_proxies reverse proxies that append the address they got the
+// request from to X-Forwarded-For. Then clients are told apart by the entry trusted_proxies hops
+// from the right of it, the one the outermost trusted proxy appended. Entries further left are
+// whatever the client sent, so they are never used.
+//
+// Load balancers probe without credentials too, so GET /healthz (the process is up) and GET
+// /readyz (NodeStatus::ready, with the NodeStatus as JSON) are answered here as well, and under
+// the older /health and /ready. GET /metrics serves metrics::render to clients with the right
+// credentials. With resolver_only, credentials are ignored and everyone gets the public methods
+// only.
+#[derive(Clone)]
+pub struct AuthLayer {
+    authorization: Arc<String>,
+    rate_limiter: Arc<RateLimiter>,
+    status: Arc<StatusMonitor>,
+    resolver_only: bool,
+    trusted_proxies: usize,
+}
+
+impl AuthLayer {
+    pub fn new(
+        authorization: String,
+        rate_limiter: RateLimiter,
+        status: Arc<StatusMonitor>,
+        resolver_only: bool,
+        trusted_proxies: usize,
+    ) -> Self {
+        Self {
+            authorization: Arc::new(authorization),
+            rate_limiter: Arc::new(rate_limiter),
+            status,
+            resolver_only,
+            trusted_proxies,
+        }
+    }
+}
+
+impl<S> tower::Layer<S> for AuthLayer {
+    type Service = Auth<S>;
+
+    fn layer(&self, inner: S) -> Auth<S> {
+        Auth {
+            inner,
+            authorization: self.authorization.clone(),
+            rate_limiter: self.rate_limiter.clone(),
+            status: self.status.clone(),
+            resolver_only: self.resolver_only,
+            trusted_proxies: self.trusted_proxies,
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct Auth<S> {
+    inner: S,
+    authorization: Arc<String>,
+    rate_limiter: Arc<RateLimiter>,
+    status: Arc<StatusMonitor>,
+    resolver_only: bool,
+    trusted_proxies: usize,
+}
+
+impl<S> tower::Service<Request<Body>> for Auth<S>
+where
+    S: tower::Service<Request<Body>, Response = Response<Body>> + Clone + Send + 'static,
+    S::Error: Into<BoxError>,
+    S::Future: Send,
+{
+    type Response = Response<Body>;
+    type Error = BoxError;
+    type Future = Pin<Box<dyn Future<Output = Result<Response<Body>, BoxError>> + Send>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), BoxError>> {
+        self.inner.poll_ready(cx).map_err(Into::into)
+    }
+
+    fn call(&mut self, request: Request<Body>) -> Self::Future {
+        if request.method() == hyper::Method::GET {
+            match request.uri().path() {
+                "/health" | "/healthz" => return Box::pin(async { Ok(empty_response(200)) }),
+                "/ready" | "/readyz" => {
+                    let response = readiness(&self.status);
+                    return Box::pin(async { Ok(response) });
+                }
+                _ => {}
+            }
+        }
+        let authorized = is_authorized(&request, &self.authorization, self.resolver_only);
+        if request.method() == hyper::Method::GET && request.uri().path() == "/metrics" {
+            let response = if authorized {
+                Response::builder()
+                    .status(200)
+                    .header(hyper::header::CONTENT_TYPE, "text/plain; version=0.0.4")
+                    .body(Body::from(metrics::render()))
+                    .expect("metrics response is valid")
+            } else {
+                unauthorized()
+            };
+            return Box::pin(async { Ok(response) });
+        }
+        let client = forwarded_client(&request, self.trusted_proxies).unwrap_or_default();
+        let rate_limiter = self.rate_limiter.clone();
+        // The service that was polled ready has to be the one that gets called.
+        let clone = self.inner.clone();
+        let mut inner = std::mem::replace(&mut self.inner, clone);
+        Box::pin(async move {
+            if authorized {
+                return inner.call(request).await.map_err(Into::into);
+            }
+            if !rate_limiter.check(&client) {
+                return Ok(empty_response(429));
+            }
+            if let Some(response) = refuse_public_length(&request) {
+                return Ok(response);
+            }
+            let (parts, body) = request.into_parts();
+            let bytes = hyper::body::to_bytes(body).await?;
+            if !only_public_methods(&bytes) {
+                return Ok(unauthorized());
+            }
+            let request = Request::from_parts(parts, Body::from(bytes));
+            inner.call(request).await.map_err(Into::into)
+        })
+    }
+}
+
+// Whether request carries authorization as its Authorization header. Never with resolver_only.
+fn is_authorized(request: &Request<Body>, authorization: &str, resolver_only: bool) -> bool {
+    !resolver_only
+        && request
+            .headers()
+            .get(hyper::header::AUTHORIZATION)
+            .map_or(false, |value| {
+                constant_time_eq(value.as_bytes(), authorization.as_bytes())
+            })
+}
+
+// Response refusing an anonymous request before its body is read, None if the body can be
+// buffered. Chunked requests have no Content-Length and get 411 so the client can retry with one,
+// requests over MAX_PUBLIC_REQUEST_SIZE get 401 since with credentials they would be served.
+fn refuse_public_length(request: &Request<Body>) -> Option<Response<Body>> {
+    let content_length = match request.headers().get(hyper::header::CONTENT_LENGTH) {
+        Some(value) => value
+            .to_str()
+            .ok()
+            .and_then(|value| value.parse::<u64>().ok()),
+        None => return Some(empty_response(411)),
+    };
+    if content_length.map_or(false, |len| len <= MAX_PUBLIC_REQUEST_SIZE) {
+        return None;
+    }
+    Some(unauthorized())
+}
+
+// Address the outermost of trusted_proxies proxies got request from, None without trusted proxies
+// or if the proxies didn't add that many entries. Proxies append to the last X-Forwarded-For
+// header, or add one, so entries are read across all of them in order.
+fn forwarded_client(request: &Request<Body>, trusted_proxies: usize) -> Option<String> {
+    let hops = trusted_proxies.checked_sub(1)?;
+    let mut entries = vec![];
+    for value in request.headers().get_all("X-Forwarded-For") {
+        entries.extend(value.to_str().ok()?.split(',').map(str::trim));
+    }
+    entries
+        .into_iter()
+        .rev()
+        .nth(hops)
+        .map(|client| client.to_string())
+}
+
+// Whether bytes is a JSON-RPC call or batch that only calls PUBLIC_METHODS. Anything that doesn't
+// parse is treated as not public, an authorized client gets the proper parse error.
+fn only_public_methods(bytes: &[u8]) -> bool {
+    fn is_public(call: &serde_json::Value) -> bool {
+        call.get("method")
+            .and_then(|method| method.as_str())
+            .map_or(false, |method| PUBLIC_METHODS.contains(&method))
+    }
+    match serde_json::from_slice::<serde_json::Value>(bytes) {
+        Ok(serde_json::Value::Array(calls)) => !calls.is_empty() && calls.iter().all(is_public),
+        Ok(call) => is_public(&call),
+        Err(_) => false,
+    }
+}
+
+// 200 with the node status if the tip is current, 503 if it is stale and resolvers shouldn't be
+// sent traffic.
+fn readiness(status: &StatusMonitor) -> Response<Body> {
+    let status = match status.status() {
+        Ok(status) => status,
+        Err(err) => {
+            log::error!("failed to get node status: {err}");
+            return empty_response(503);
+        }
+    };
+    Response::builder()
+        .status(if status.ready { 200 } else { 503 })
+        .header(hyper::header::CONTENT_TYPE, "application/json")
+        .body(Body::from(
+            serde_json::to_vec(&status).expect("node status always serializes"),
+        ))
+        .expect("status response is valid")
+}
+
+fn empty_response(status: u16) -> Response<Body> {
+    Response::builder()
+        .status(status)
+        .body(Body::empty())
+        .expect("static response is valid")
+}
+
+fn unauthorized() -> Response<Body> {
+    Response::builder()
+        .status(401)
+        .header("WWW-Authenticate", "Basic realm=\"bitnames\"")
+        .body(Body::empty())
+        .expect("static response is valid")
+}
+
+pub fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn request(headers: &[(&str, &str)]) -> Request<Body> {
+        let mut builder = Request::builder().method("POST").uri("/");
+        for (name, value) in headers {
+            builder = builder.header(*name, *value);
+        }
+        builder.body(Body::empty()).unwrap()
+    }
+
+    #[test]
+    fn compares_in_constant_time() {
+        assert!(constant_time_eq(b"Basic abc", b"Basic abc"));
+        assert!(!constant_time_eq(b"Basic abc", b"Basic abd"));
+        assert!(!constant_time_eq(b"Basic abc", b"Basic ab"));
+        assert!(constant_time_eq(b"", b""));
+    }
+
+    #[test]
+    fn only_public_calls_and_batches_are_public() {
+        let call = |method: &str| format!(r#"{{"jsonrpc":"2.0","id":1,"method":"{method}"}}"#);
+        assert!(only_public_methods(call("get_height").as_bytes()));
+        assert!(!only_public_methods(call("stop").as_bytes()));
+        let batch = format!("[{},{}]", call("get_height"), call("get_value"));
+        assert!(only_public_methods(batch.as_bytes()));
+        let batch = format!("[{},{}]", call("get_height"), call("stop"));
+        assert!(!only_public_methods(batch.as_bytes()));
+        // An empty batch calls nothing public, and the server answers it with an error anyway.
+        assert!(!only_public_methods(b"[]"));
+        assert!(!only_public_methods(br#"{"jsonrpc":"2.0","id":1}"#));
+        assert!(!only_public_methods(b"not json"));
+    }
+
+    #[test]
+    fn anonymous_requests_need_a_small_content_length() {
+        let status = |headers: &[(&str, &str)]| {
+            refuse_public_length(&request(headers)).map(|response| response.status().as_u16())
+        };
+        assert_eq!(status(&[("Content-Length", "100")]), None);
+        let max = MAX_PUBLIC_REQUEST_SIZE.to_string();
+        assert_eq!(status(&[("Content-Length", &max)]), None);
+        let over = (MAX_PUBLIC_REQUEST_SIZE + 1).to_string();
+        assert_eq!(status(&[("Content-Length", &over)]), Some(401));
+        assert_eq!(status(&[("Content-Length", "lots")]), Some(401));
+        assert_eq!(status(&[("Transfer-Encoding", "chunked")]), Some(411));
+    }
+
+    #[test]
+    fn clients_are_told_apart_by_the_outermost_trusted_proxy() {
+        let forwarded = request(&[
+            ("X-Forwarded-For", "10.0.0.9, 192.0.2.1"),
+            ("X-Forwarded-For", "198.51.100.7"),
+        ]);
+        assert_eq!(forwarded_client(&forwarded, 0), None);
+        assert_eq!(
+            forwarded_client(&forwarded, 1).as_deref(),
+            Some("198.51.100.7")
+        );
+        assert_eq!(
+            forwarded_client(&forwarded, 2).as_deref(),
+            Some("192.0.2.1")
+        );
+        // Not as many entries as trusted proxies.
+        assert_eq!(forwarded_client(&forwarded, 4), None);
+        assert_eq!(forwarded_client(&request(&[]), 1), None);
+    }
+
+    #[test]
+    fn resolver_only_ignores_credentials() {
+        let authorized = request(&[("Authorization", "Basic abc")]);
+        assert!(is_authorized(&authorized, "Basic abc", false));
+        assert!(!is_authorized(&authorized, "Basic abc", true));
+        assert!(!is_authorized(&authorized, "Basic abd", false));
+        assert!(!is_authorized(&request(&[]), "Basic abc", false));
+    }
+}
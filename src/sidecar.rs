@@ -0,0 +1,77 @@
+use crate::bitnames::{ChainStats, Error, CHAIN_STATS_KEY};
+use crate::db_env;
+use ddk::heed;
+use ddk::types::Hash;
+use heed::types::{OwnedType, SerdeBincode, Str};
+use heed::Database;
+use std::path::Path;
+
+// Name lookups straight from the databases of a node running on the same machine, for sidecar
+// processes like a DNS mirror or an analytics job that read too much to go through the RPC
+// server. The env is opened read-only with db_env::open_read_only, so the node keeps writing
+// while sidecars read, and a sidecar can't corrupt the node's state whatever it does.
+//
+//     let names = SidecarState::open(&datadir)?;
+//     let value = names.read(|txn, names| names.get_value(txn, &key))?;
+//
+// Only key_to_value and the chain stats are opened, their layout is part of the node's database
+// schema and only changes with SCHEMA_VERSION. Anything else should be read over RPC.
+pub struct SidecarState {
+    env: heed::Env,
+    key_to_value: Database<OwnedType<Hash>, OwnedType<Hash>>,
+    chain_stats: Database<Str, SerdeBincode<ChainStats>>,
+}
+
+impl SidecarState {
+    // Open the databases of the node whose data lives under datadir. The node must have run at
+    // least once, so that they exist.
+    pub fn open(datadir: &Path) -> anyhow::Result<Self> {
+        let env = db_env::open_read_only(datadir)?;
+        let key_to_value = env
+            .open_database(Some("key_to_value"))?
+            .ok_or_else(|| anyhow::anyhow!("the node hasn't created its name database yet"))?;
+        let chain_stats = env
+            .open_database(Some("chain_stats"))?
+            .ok_or_else(|| anyhow::anyhow!("the node hasn't created its name database yet"))?;
+        Ok(Self {
+            env,
+            key_to_value,
+            chain_stats,
+        })
+    }
+
+    // Run f inside a single read transaction, so everything it reads is from the same tip. Like
+    // any LMDB reader, a long running transaction keeps the node from reusing the pages it sees,
+    // so sidecars should read in short transactions.
+    pub fn read<T>(
+        &self,
+        f: impl FnOnce(&heed::RoTxn, &Self) -> Result<T, Error>,
+    ) -> Result<T, Error> {
+        let txn = self.env.read_txn()?;
+        f(&txn, self)
+    }
+
+    pub fn get_value(&self, txn: &heed::RoTxn, key: &Hash) -> Result<Option<Hash>, Error> {
+        Ok(self.key_to_value.get(txn, key)?)
+    }
+
+    pub fn get_tip_height(&self, txn: &heed::RoTxn) -> Result<u32, Error> {
+        Ok(self
+            .chain_stats
+            .get(txn, CHAIN_STATS_KEY)?
+            .unwrap_or_default()
+            .height)
+    }
+
+    // Every registered name as (key, value), in key order, for sidecars mirroring the whole
+    // namespace.
+    pub fn names<'txn>(
+        &self,
+        txn: &'txn heed::RoTxn,
+    ) -> Result<impl Iterator<Item = Result<(Hash, Hash), Error>> + 'txn, Error> {
+        Ok(self
+            .key_to_value
+            .iter(txn)?
+            .map(|entry| entry.map_err(Error::from)))
+    }
+}
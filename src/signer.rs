@@ -0,0 +1,156 @@
+use crate::bitnames::BitName;
+use crate::vault::{self, SigningRules, UnsignedOperation};
+use crate::Wallet;
+use ddk::authorization::Authorization;
+use ddk::types::{AuthorizedTransaction, Hash};
+use serde::{de::DeserializeOwned, Serialize};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+// External signers for the vault.
+//
+// Instead of carrying operation files between the machines, a node started with --vault-signer
+// hands each operation built by sign_vault_registration or sign_vault_send to a signer listening
+// on a local unix socket, and submits the transaction that comes back. The signer holds the keys
+// and checks every operation itself, so the node never sees them.
+//
+// `bitnames vault serve` is such a signer, signing with a wallet on the same machine under the
+// same rules as `bitnames vault sign`. A bridge to a hardware signing device can take its place
+// by speaking the same protocol, one exchange per connection:
+//   request    the operation, as written by build_vault_registration
+//   response   Ok with the signed transaction, as read by submit_vault_transaction, or Err with
+//              the reason the operation was refused
+// Both are bincode encoded and sent as a frame, a 4 byte big endian length followed by that many
+// bytes.
+//
+// The node doesn't trust the signer either, it checks that the response signs the transaction
+// it asked for, with an authorization for every input, and submit_transaction checks the
+// signatures as for any other transaction.
+const MAX_FRAME: u32 = 4 * 1024 * 1024;
+
+pub struct ExternalSigner {
+    socket: PathBuf,
+}
+
+impl ExternalSigner {
+    pub fn new(socket: PathBuf) -> Self {
+        Self { socket }
+    }
+
+    // Have operation signed by the signer.
+    #[cfg(unix)]
+    pub async fn sign(
+        &self,
+        operation: &UnsignedOperation,
+    ) -> anyhow::Result<AuthorizedTransaction<Authorization, BitName>> {
+        let mut stream = tokio::net::UnixStream::connect(&self.socket)
+            .await
+            .map_err(|err| anyhow::anyhow!("signer at {}: {err}", self.socket.display()))?;
+        write_frame(&mut stream, operation).await?;
+        let response: Result<AuthorizedTransaction<Authorization, BitName>, String> =
+            read_frame(&mut stream).await?;
+        let signed = response.map_err(|reason| anyhow::anyhow!("signer refused: {reason}"))?;
+        if Hash::from(signed.transaction.txid()) != Hash::from(operation.transaction.txid()) {
+            anyhow::bail!("signer returned another transaction");
+        }
+        if signed.authorizations.len() != signed.transaction.inputs.len() {
+            anyhow::bail!(
+                "signer returned {} authorizations for {} inputs",
+                signed.authorizations.len(),
+                signed.transaction.inputs.len()
+            );
+        }
+        Ok(signed)
+    }
+
+    #[cfg(not(unix))]
+    pub async fn sign(
+        &self,
+        _operation: &UnsignedOperation,
+    ) -> anyhow::Result<AuthorizedTransaction<Authorization, BitName>> {
+        anyhow::bail!("external signers are only supported on unix")
+    }
+}
+
+// Sign operations sent to socket with wallet, refusing any not within rules, until the process is
+// stopped. Only the owner of the socket can connect to it.
+#[cfg(unix)]
+pub async fn serve(wallet: Arc<Wallet>, socket: &Path, rules: SigningRules) -> anyhow::Result<()> {
+    use std::os::unix::fs::{DirBuilderExt, PermissionsExt};
+    let name = socket
+        .file_name()
+        .ok_or_else(|| anyhow::anyhow!("{} isn't a socket path", socket.display()))?;
+    // The socket is bound inside a directory only the owner can enter, so nobody else can connect
+    // before its own permissions are set, and only then moved into place. Both are left behind by
+    // a signer that didn't shut down cleanly.
+    let staging = socket.with_file_name(format!(".{}.staging", name.to_string_lossy()));
+    let _ = std::fs::remove_file(socket);
+    let _ = std::fs::remove_dir_all(&staging);
+    std::fs::DirBuilder::new().mode(0o700).create(&staging)?;
+    let bound = staging.join("socket");
+    let listener = tokio::net::UnixListener::bind(&bound)?;
+    std::fs::set_permissions(&bound, std::fs::Permissions::from_mode(0o600))?;
+    std::fs::rename(&bound, socket)?;
+    std::fs::remove_dir(&staging)?;
+    println!("signing on {}", socket.display());
+    let rules = Arc::new(rules);
+    loop {
+        let (mut stream, _) = listener.accept().await?;
+        let wallet = wallet.clone();
+        let rules = rules.clone();
+        tokio::spawn(async move {
+            if let Err(err) = answer(&mut stream, &wallet, &rules).await {
+                eprintln!("signer: {err}");
+            }
+        });
+    }
+}
+
+#[cfg(unix)]
+async fn answer(
+    stream: &mut tokio::net::UnixStream,
+    wallet: &Wallet,
+    rules: &SigningRules,
+) -> anyhow::Result<()> {
+    let operation: UnsignedOperation = read_frame(stream).await?;
+    let response = match vault::sign_operation(wallet, operation, rules) {
+        Ok((description, signed)) => {
+            println!("signed: {description}");
+            Ok(signed)
+        }
+        Err(err) => {
+            println!("refused: {err}");
+            Err(err.to_string())
+        }
+    };
+    write_frame(stream, &response).await
+}
+
+async fn write_frame(
+    stream: &mut (impl AsyncWrite + Unpin),
+    value: &impl Serialize,
+) -> anyhow::Result<()> {
+    let bytes = bincode::serialize(value)?;
+    if bytes.len() > MAX_FRAME as usize {
+        anyhow::bail!("frame of {} bytes is too large", bytes.len());
+    }
+    stream
+        .write_all(&(bytes.len() as u32).to_be_bytes())
+        .await?;
+    stream.write_all(&bytes).await?;
+    stream.flush().await?;
+    Ok(())
+}
+
+async fn read_frame<T: DeserializeOwned>(
+    stream: &mut (impl AsyncRead + Unpin),
+) -> anyhow::Result<T> {
+    let len = stream.read_u32().await?;
+    if len > MAX_FRAME {
+        anyhow::bail!("frame of {len} bytes is too large");
+    }
+    let mut bytes = vec![0; len as usize];
+    stream.read_exact(&mut bytes).await?;
+    Ok(bincode::deserialize(&bytes)?)
+}
@@ -0,0 +1,167 @@
+use crate::bitnames::{BitName, BitNamesState, Error};
+use ddk::authorization::Authorization;
+use ddk::heed;
+use ddk::node::State;
+use ddk::types::Body;
+use std::cmp::Reverse;
+use std::collections::{BTreeMap, BinaryHeap, HashSet};
+use std::path::Path;
+
+// Deterministic multi-node simulation of the BitNames state machine.
+//
+// Each simulated node has its own heed env with both the ddk state and BitNamesState, but instead
+// of ddk's real P2P layer, bodies travel over a VirtualNetwork with explicit per-link latency and
+// partitions. There is no wall clock and no randomness: messages are delivered in order of
+// (delivery time, send order), so every run of the same scenario produces the same result.
+//
+// This is meant for reproducing consensus scenarios like two partitions registering the same key,
+// not for testing ddk's networking itself.
+pub struct Simulation {
+    pub nodes: Vec<SimNode>,
+    pub network: VirtualNetwork,
+}
+
+pub struct SimNode {
+    env: heed::Env,
+    ddk_state: ddk::state::State<Authorization, BitName>,
+    pub state: BitNamesState,
+    pub height: u32,
+    // Bodies that were rejected by this node, with the reason.
+    pub rejected: Vec<(Body<Authorization, BitName>, String)>,
+}
+
+impl SimNode {
+    fn new(path: &Path) -> Result<Self, Error> {
+        std::fs::create_dir_all(path).map_err(|err| Error::Sim(err.to_string()))?;
+        let env = heed::EnvOpenOptions::new()
+            .map_size(64 * 1024 * 1024)
+            .max_dbs(ddk::state::State::<Authorization, BitName>::NUM_DBS + BitNamesState::NUM_DBS)
+            .open(path)?;
+        let ddk_state = ddk::state::State::new(&env).map_err(|err| Error::Sim(err.to_string()))?;
+        let state = BitNamesState::new(&env)?;
+        Ok(Self {
+            env,
+            ddk_state,
+            state,
+            height: 0,
+            rejected: vec![],
+        })
+    }
+
+    // Validate and connect a body on top of this node's tip.
+    fn receive(&mut self, body: Body<Authorization, BitName>) -> Result<(), Error> {
+        let height = self.height + 1;
+        let validated = {
+            let txn = self.env.read_txn()?;
+            self.state
+                .validate_body(&txn, height, &self.ddk_state, &body)
+        };
+        if let Err(err) = validated {
+            self.rejected.push((body, err.to_string()));
+            return Ok(());
+        }
+        let mut txn = self.env.write_txn()?;
+        self.state
+            .connect_body(&mut txn, height, &self.ddk_state, &body)?;
+        txn.commit()?;
+        self.height = height;
+        Ok(())
+    }
+
+    pub fn read<T>(
+        &self,
+        f: impl FnOnce(&heed::RoTxn, &BitNamesState) -> Result<T, Error>,
+    ) -> Result<T, Error> {
+        let txn = self.env.read_txn()?;
+        f(&txn, &self.state)
+    }
+}
+
+#[derive(Default)]
+pub struct VirtualNetwork {
+    // Current virtual time, in ticks.
+    pub now: u64,
+    // Latency in ticks for a (from, to) link, links not listed here deliver after one tick.
+    latency: BTreeMap<(usize, usize), u64>,
+    // Links that currently drop every message, stored with the lower index first.
+    partitions: HashSet<(usize, usize)>,
+    // (delivery time, send sequence number, destination, body)
+    queue: BinaryHeap<Reverse<(u64, u64, usize, EncodedBody)>>,
+    next_seq: u64,
+}
+
+// Bodies are kept bincode encoded in the queue, since Body doesn't implement Ord.
+type EncodedBody = Vec<u8>;
+
+impl VirtualNetwork {
+    pub fn set_latency(&mut self, from: usize, to: usize, ticks: u64) {
+        self.latency.insert((from, to), ticks);
+    }
+
+    pub fn partition(&mut self, a: usize, b: usize) {
+        self.partitions.insert((a.min(b), a.max(b)));
+    }
+
+    pub fn heal(&mut self, a: usize, b: usize) {
+        self.partitions.remove(&(a.min(b), a.max(b)));
+    }
+
+    fn send(&mut self, from: usize, to: usize, body: EncodedBody) {
+        if self.partitions.contains(&(from.min(to), from.max(to))) {
+            return;
+        }
+        let latency = self.latency.get(&(from, to)).copied().unwrap_or(1);
+        let seq = self.next_seq;
+        self.next_seq += 1;
+        self.queue
+            .push(Reverse((self.now + latency, seq, to, body)));
+    }
+}
+
+impl Simulation {
+    // Create n nodes with their databases in subdirectories of datadir.
+    pub fn new(n: usize, datadir: &Path) -> Result<Self, Error> {
+        let nodes = (0..n)
+            .map(|i| SimNode::new(&datadir.join(format!("node-{i}"))))
+            .collect::<Result<_, _>>()?;
+        Ok(Self {
+            nodes,
+            network: VirtualNetwork::default(),
+        })
+    }
+
+    // Connect a body on node `from`, as if it had mined it, and gossip it to every other node.
+    pub fn mine(&mut self, from: usize, body: Body<Authorization, BitName>) -> Result<(), Error> {
+        let encoded = bincode::serialize(&body)?;
+        self.nodes[from].receive(body)?;
+        for to in 0..self.nodes.len() {
+            if to != from {
+                self.network.send(from, to, encoded.clone());
+            }
+        }
+        Ok(())
+    }
+
+    // Deliver every message due at or before the given virtual time.
+    pub fn advance_to(&mut self, time: u64) -> Result<(), Error> {
+        while let Some(Reverse((at, _, _, _))) = self.network.queue.peek() {
+            if *at > time {
+                break;
+            }
+            let Reverse((at, _, to, encoded)) = self.network.queue.pop().unwrap();
+            self.network.now = at;
+            self.nodes[to].receive(bincode::deserialize(&encoded)?)?;
+        }
+        self.network.now = time;
+        Ok(())
+    }
+
+    // Deliver messages until the network is idle.
+    pub fn run_until_idle(&mut self) -> Result<(), Error> {
+        while let Some(Reverse((at, _, _, _))) = self.network.queue.peek() {
+            let at = *at;
+            self.advance_to(at)?;
+        }
+        Ok(())
+    }
+}
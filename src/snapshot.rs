@@ -0,0 +1,56 @@
+use crate::bitnames::Error;
+use ddk::types::Hash;
+use ed25519_dalek::{Keypair, PublicKey, Signature, Signer, Verifier};
+use serde::{Deserialize, Serialize};
+
+// The full name map at a given height.
+//
+// key_values is sorted by key, so two nodes at the same tip always produce the same snapshot and
+// the same state root. ddk's utxo set isn't part of it, a fast synced node still connects every
+// block up to the snapshot height through ddk and only skips the BitNames rules for them.
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Eq)]
+pub struct StateSnapshot {
+    pub height: u32,
+    pub key_values: Vec<(Hash, Hash)>,
+}
+
+impl StateSnapshot {
+    // The value a snapshot is checked against before it is imported.
+    pub fn state_root(&self) -> Hash {
+        ddk::types::hash(self)
+    }
+
+    pub fn sign(self, keypair: &Keypair) -> SignedSnapshot {
+        let signature = keypair.sign(&self.state_root());
+        SignedSnapshot {
+            snapshot: self,
+            public_key: keypair.public,
+            signature,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct SignedSnapshot {
+    pub snapshot: StateSnapshot,
+    pub public_key: PublicKey,
+    pub signature: Signature,
+}
+
+impl SignedSnapshot {
+    // Check that the snapshot hashes to the expected state root and is signed by the expected
+    // publisher.
+    pub fn verify(&self, state_root: &Hash, public_key: &PublicKey) -> Result<(), Error> {
+        let actual = self.snapshot.state_root();
+        if actual != *state_root {
+            return Err(Error::SnapshotStateRootMismatch {
+                expected: hex::encode(state_root),
+                actual: hex::encode(actual),
+            });
+        }
+        if self.public_key != *public_key || public_key.verify(&actual, &self.signature).is_err() {
+            return Err(Error::InvalidSnapshotSignature);
+        }
+        Ok(())
+    }
+}
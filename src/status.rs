@@ -0,0 +1,163 @@
+use crate::bitnames::{BitName, StateReader};
+use crate::clock;
+use crate::embed::Node;
+use crate::mainchain::Failover;
+use crate::notify::{self, Event};
+use crate::peer_store::PeerStore;
+use ddk::authorization::Authorization;
+use ddk::types::Hash;
+use serde::{Deserialize, Serialize};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::sync::broadcast;
+
+// How often the mainchain connection and the tip age are checked.
+const CHECK_INTERVAL: Duration = Duration::from_secs(60);
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct NodeStatus {
+    // Tip of the name state.
+    pub height: u32,
+    // Hash of ddk's best header, and the height ddk has headers up to. When that is above
+    // height, the node is still connecting bodies.
+    pub tip_hash: Hash,
+    pub header_height: u32,
+    // Since the last block was connected, or since startup if none was connected yet.
+    pub seconds_since_last_block: u64,
+    pub mainchain_connected: bool,
+    // No block for longer than the configured window, or the mainchain is unreachable, so
+    // answers may be out of date.
+    pub stale: bool,
+    // Not stale, and the name state is at most the configured number of blocks behind the
+    // headers ddk has, which are anchored to the mainchain by BMM. Load balancers use this
+    // through GET /readyz to take lagging resolvers out of rotation.
+    pub ready: bool,
+    // Peers this node knows and hasn't banned. ddk doesn't report which ones are connected.
+    pub known_peers: u64,
+    pub mempool_transactions: u64,
+    // Size of the database file on disk, in bytes.
+    pub db_size: u64,
+    // Registered names, genesis names included, and unspent outputs.
+    pub names: u64,
+    pub utxos: u64,
+}
+
+// Keeps track of when blocks arrive and whether the mainchain node is reachable.
+pub struct StatusMonitor {
+    state: StateReader,
+    node: Arc<Node>,
+    peers: Arc<PeerStore>,
+    ddk_state: ddk::state::State<Authorization, BitName>,
+    stale_after: Duration,
+    max_lag: u32,
+    // By clock::now, in seconds.
+    last_block: Mutex<u64>,
+    mainchain_connected: AtomicBool,
+}
+
+impl StatusMonitor {
+    pub fn new(
+        state: StateReader,
+        node: Arc<Node>,
+        peers: Arc<PeerStore>,
+        stale_after: Duration,
+        max_lag: u32,
+    ) -> anyhow::Result<Self> {
+        let ddk_state = ddk::state::State::new(state.env())?;
+        Ok(Self {
+            state,
+            node,
+            peers,
+            ddk_state,
+            stale_after,
+            max_lag,
+            last_block: Mutex::new(clock::now()),
+            mainchain_connected: AtomicBool::new(true),
+        })
+    }
+
+    pub fn status(&self) -> anyhow::Result<NodeStatus> {
+        let (height, names, utxos) = self.state.read(|txn, state| {
+            Ok((
+                state.get_tip_height(txn)?,
+                state.get_chain_stats(txn)?.registered_names,
+                self.ddk_state.utxos.len(txn)?,
+            ))
+        })?;
+        let since_last_block =
+            Duration::from_secs(clock::now().saturating_sub(*self.last_block.lock().unwrap()));
+        let mainchain_connected = self.mainchain_connected.load(Ordering::Relaxed);
+        let known_peers = self
+            .peers
+            .list()
+            .iter()
+            .filter(|(addr, _)| !self.peers.is_banned(addr))
+            .count();
+        let db_size = std::fs::metadata(self.state.env().path().join("data.mdb"))
+            .map(|metadata| metadata.len())
+            .unwrap_or(0);
+        let header_height = self.node.get_height()?;
+        let stale = since_last_block > self.stale_after || !mainchain_connected;
+        Ok(NodeStatus {
+            height,
+            tip_hash: self.node.get_best_hash()?.into(),
+            header_height,
+            seconds_since_last_block: since_last_block.as_secs(),
+            mainchain_connected,
+            stale,
+            ready: !stale && header_height.saturating_sub(height) <= self.max_lag,
+            known_peers: known_peers as u64,
+            mempool_transactions: self.node.get_all_transactions()?.len() as u64,
+            db_size,
+            names,
+            utxos,
+        })
+    }
+}
+
+// Record connected blocks and check the mainchain every CHECK_INTERVAL, warning on stderr and on
+// the alert notification topic whenever the node becomes stale or recovers.
+pub async fn run(monitor: Arc<StatusMonitor>, mainchain: Arc<Failover>) {
+    let mut events = notify::subscribe();
+    let mut interval = tokio::time::interval(CHECK_INTERVAL);
+    let mut was_stale = false;
+    loop {
+        tokio::select! {
+            event = events.recv() => match event {
+                Ok(Event::Block { .. }) | Err(broadcast::error::RecvError::Lagged(_)) => {
+                    *monitor.last_block.lock().unwrap() = clock::now();
+                }
+                Ok(_) => continue,
+                Err(broadcast::error::RecvError::Closed) => return,
+            },
+            _ = interval.tick() => {
+                let connected = mainchain.active().is_healthy().await;
+                monitor.mainchain_connected.store(connected, Ordering::Relaxed);
+            }
+        }
+        let status = match monitor.status() {
+            Ok(status) => status,
+            Err(err) => {
+                log::error!("failed to get node status: {err}");
+                continue;
+            }
+        };
+        if status.stale == was_stale {
+            continue;
+        }
+        was_stale = status.stale;
+        let message = if !status.stale {
+            format!("tip is current again at height {}", status.height)
+        } else if !status.mainchain_connected {
+            format!("mainchain node {} is unreachable", mainchain.active())
+        } else {
+            format!(
+                "no new block for {} seconds, tip is at height {}",
+                status.seconds_since_last_block, status.height
+            )
+        };
+        log::warn!("{message}");
+        notify::publish(Event::Alert(message));
+    }
+}
@@ -0,0 +1,96 @@
+use ddk::types::{Hash, Transaction};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256, Sha512};
+
+// BitName::Versioned content version of certificate pinning records.
+//
+// A TLSA record pins the TLS certificate of the host a name points at, with the same fields as a
+// DANE TLSA record (RFC 6698), so a client can authenticate the connection against the chain
+// instead of a public CA. Only records in the transaction registering their key are indexed, since
+// names never change and nobody else may speak for one. There are no consensus rules, a record
+// that doesn't count is just ignored.
+pub const TLSA_VERSION: u8 = 3;
+
+// Usages of records that pin the host's own certificate. Usages 0 and 2 pin a CA certificate
+// further up the chain, which verify isn't given.
+pub const USAGE_PKIX_EE: u8 = 1;
+pub const USAGE_DANE_EE: u8 = 3;
+// Selector of a record matching the whole certificate. Selector 1, the SubjectPublicKeyInfo,
+// needs an X.509 parser to check.
+pub const SELECTOR_FULL_CERTIFICATE: u8 = 0;
+
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Eq)]
+pub struct TlsaRecord {
+    pub key: Hash,
+    pub usage: u8,
+    pub selector: u8,
+    // 0 for the exact bytes, 1 for SHA-256, 2 for SHA-512.
+    pub matching_type: u8,
+    pub data: Vec<u8>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum TlsaVerdict {
+    // The name has no TLSA records, the certificate can't be checked against the chain.
+    NoRecords,
+    Match,
+    // No record matches, the connection must not be trusted.
+    Mismatch,
+    // None of the records matched, and some use a selector or matching type this node can't
+    // check, so a client that can should check those itself.
+    Unsupported,
+}
+
+impl TlsaRecord {
+    // Decode a Versioned payload, if it is a record transaction is allowed to carry.
+    pub fn decode(
+        payload: &[u8],
+        transaction: &Transaction<crate::bitnames::BitName>,
+    ) -> Option<Self> {
+        let record: Self = bincode::deserialize(payload).ok()?;
+        if !crate::bitnames::registered_keys(transaction).contains(&record.key) {
+            return None;
+        }
+        Some(record)
+    }
+
+    pub fn encode(&self) -> Vec<u8> {
+        bincode::serialize(self).expect("TLSA records always serialize")
+    }
+
+    // Whether the DER encoded host certificate matches this record, None if the record's usage,
+    // selector or matching type isn't supported.
+    pub fn matches(&self, certificate: &[u8]) -> Option<bool> {
+        let end_entity = self.usage == USAGE_PKIX_EE || self.usage == USAGE_DANE_EE;
+        if !end_entity || self.selector != SELECTOR_FULL_CERTIFICATE {
+            return None;
+        }
+        let matched = match self.matching_type {
+            0 => certificate == self.data.as_slice(),
+            1 => Sha256::digest(certificate).as_slice() == self.data.as_slice(),
+            2 => Sha512::digest(certificate).as_slice() == self.data.as_slice(),
+            _ => return None,
+        };
+        Some(matched)
+    }
+}
+
+// Check the DER encoded certificate presented by the host of a name against the name's records.
+pub fn verify(records: &[TlsaRecord], certificate: &[u8]) -> TlsaVerdict {
+    if records.is_empty() {
+        return TlsaVerdict::NoRecords;
+    }
+    let mut unsupported = false;
+    for record in records {
+        match record.matches(certificate) {
+            Some(true) => return TlsaVerdict::Match,
+            Some(false) => {}
+            None => unsupported = true,
+        }
+    }
+    if unsupported {
+        TlsaVerdict::Unsupported
+    } else {
+        TlsaVerdict::Mismatch
+    }
+}
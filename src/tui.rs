@@ -0,0 +1,188 @@
+use crate::bitnames::{BitName, StateReader};
+use crate::peer_store::{PeerInfo, PeerStore};
+use crate::status::{NodeStatus, StatusMonitor};
+use crate::{wallet, Node, Wallet};
+use crossterm::event::{self, Event, KeyCode, KeyEventKind, KeyModifiers};
+use crossterm::terminal::{
+    disable_raw_mode, enable_raw_mode, EnterAlternateScreen, LeaveAlternateScreen,
+};
+use ddk::types::{Content, GetValue, Hash};
+use ratatui::backend::CrosstermBackend;
+use ratatui::layout::{Constraint, Direction, Layout};
+use ratatui::widgets::{Block, Borders, List, ListItem, Paragraph};
+use ratatui::{Frame, Terminal};
+use std::io::Stdout;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+// How often the dashboard reloads its data from the node.
+const REFRESH_INTERVAL: Duration = Duration::from_secs(1);
+// How long to wait for a key press before redrawing.
+const POLL_INTERVAL: Duration = Duration::from_millis(250);
+
+// Everything the dashboard reads from, the same instances the RPC server uses.
+pub struct Dashboard {
+    pub node: Arc<Node>,
+    pub wallet: Arc<Wallet>,
+    pub peers: Arc<PeerStore>,
+    pub status: Arc<StatusMonitor>,
+    pub state: StateReader,
+}
+
+// What is on screen, reloaded every REFRESH_INTERVAL.
+struct View {
+    status: NodeStatus,
+    peers: Vec<(SocketAddr, PeerInfo)>,
+    mempool: usize,
+    balance: u64,
+    // Key value pairs registered to the wallet. Names don't expire, so there is no countdown to
+    // show next to them.
+    names: Vec<(Hash, Hash)>,
+}
+
+impl Dashboard {
+    fn view(&self) -> anyhow::Result<View> {
+        wallet::sync(&self.node, &self.wallet)?;
+        let mut balance = 0;
+        let mut names = vec![];
+        for output in self.wallet.get_utxos()?.into_values() {
+            match output.content {
+                Content::Custom(BitName::KeyValue { key, value }) => names.push((key, value)),
+                _ => balance += output.get_value(),
+            }
+        }
+        names.sort();
+        Ok(View {
+            status: self.status.status()?,
+            peers: self.peers.list(),
+            mempool: self.node.get_all_transactions()?.len(),
+            balance,
+            names,
+        })
+    }
+
+    // What the search box shows for a hex encoded key.
+    fn search(&self, query: &str) -> String {
+        let key: Hash = match crate::parse_hex_32(query.trim()) {
+            Ok(key) => key,
+            Err(_) => return "keys are 32 bytes of hex".to_string(),
+        };
+        match self.state.read(|txn, state| state.get_value(txn, &key)) {
+            Ok(Some(value)) => format!("registered, value {}", hex::encode(value)),
+            Ok(None) => "available".to_string(),
+            Err(err) => format!("lookup failed: {err}"),
+        }
+    }
+}
+
+// Run the dashboard on the terminal until Esc or Ctrl-C is pressed.
+//
+// Blocks the calling thread, so run it with spawn_blocking. Errors logged by the node's background
+// tasks still go to stderr and can scribble over the screen, the next redraw cleans it up.
+pub fn run(dashboard: Dashboard) -> anyhow::Result<()> {
+    enable_raw_mode()?;
+    let mut stdout = std::io::stdout();
+    crossterm::execute!(stdout, EnterAlternateScreen)?;
+    let mut terminal = Terminal::new(CrosstermBackend::new(stdout))?;
+    let result = event_loop(&mut terminal, &dashboard);
+    disable_raw_mode()?;
+    crossterm::execute!(terminal.backend_mut(), LeaveAlternateScreen)?;
+    terminal.show_cursor()?;
+    result
+}
+
+fn event_loop(
+    terminal: &mut Terminal<CrosstermBackend<Stdout>>,
+    dashboard: &Dashboard,
+) -> anyhow::Result<()> {
+    let mut view = dashboard.view()?;
+    let mut refreshed = Instant::now();
+    let mut query = String::new();
+    let mut result = String::new();
+    loop {
+        if refreshed.elapsed() >= REFRESH_INTERVAL {
+            view = dashboard.view()?;
+            refreshed = Instant::now();
+        }
+        terminal.draw(|frame| draw(frame, &view, &query, &result))?;
+        if !event::poll(POLL_INTERVAL)? {
+            continue;
+        }
+        let key = match event::read()? {
+            Event::Key(key) if key.kind == KeyEventKind::Press => key,
+            _ => continue,
+        };
+        match key.code {
+            KeyCode::Esc => return Ok(()),
+            KeyCode::Char('c') if key.modifiers.contains(KeyModifiers::CONTROL) => return Ok(()),
+            KeyCode::Enter => result = dashboard.search(&query),
+            KeyCode::Backspace => {
+                query.pop();
+            }
+            KeyCode::Char(c) => query.push(c),
+            _ => {}
+        }
+    }
+}
+
+fn draw(frame: &mut Frame, view: &View, query: &str, result: &str) {
+    let rows = Layout::default()
+        .direction(Direction::Vertical)
+        .constraints([
+            Constraint::Length(3),
+            Constraint::Min(0),
+            Constraint::Length(4),
+        ])
+        .split(frame.size());
+    let columns = Layout::default()
+        .direction(Direction::Horizontal)
+        .constraints([Constraint::Percentage(40), Constraint::Percentage(60)])
+        .split(rows[1]);
+
+    let status = &view.status;
+    let status_line = format!(
+        "height {} | last block {}s ago | mainchain {} | mempool {} txs | balance {} sats{}",
+        status.height,
+        status.seconds_since_last_block,
+        if status.mainchain_connected {
+            "connected"
+        } else {
+            "unreachable"
+        },
+        view.mempool,
+        view.balance,
+        if status.stale { " | STALE" } else { "" },
+    );
+    let status_block = Block::default().borders(Borders::ALL).title("Sync");
+    frame.render_widget(Paragraph::new(status_line).block(status_block), rows[0]);
+
+    let peers: Vec<ListItem> = view
+        .peers
+        .iter()
+        .map(|(addr, info)| {
+            let banned = if info.banned_until.is_some() {
+                " banned"
+            } else {
+                ""
+            };
+            ListItem::new(format!("{addr} score {}{banned}", info.score))
+        })
+        .collect();
+    let peers_block = Block::default().borders(Borders::ALL).title("Peers");
+    frame.render_widget(List::new(peers).block(peers_block), columns[0]);
+
+    let names: Vec<ListItem> = view
+        .names
+        .iter()
+        .map(|(key, value)| ListItem::new(format!("{} {}", hex::encode(key), hex::encode(value))))
+        .collect();
+    let names_block = Block::default().borders(Borders::ALL).title("Owned names");
+    frame.render_widget(List::new(names).block(names_block), columns[1]);
+
+    let search_block = Block::default()
+        .borders(Borders::ALL)
+        .title("Search by key (Enter to look up, Esc to quit)");
+    let search = Paragraph::new(format!("> {query}\n{result}")).block(search_block);
+    frame.render_widget(search, rows[2]);
+}
@@ -0,0 +1,161 @@
+use crate::bitnames::BitName;
+use crate::coin_selection::DUST;
+use crate::lock_height::{self, LOCK_HEIGHT_VERSION};
+use ddk::types::{Address, Content, GetValue, Hash, OutPoint, Output, Transaction};
+use std::collections::HashSet;
+
+// Builds BitNames transactions from explicitly given utxos, for integrators like exchanges and
+// custodians that do their own coin selection and key management instead of using a node's
+// wallet.
+//
+// The result only depends on what was added: inputs and outputs are kept in the order they were
+// added, and the change output, if there is one, comes last. Nothing is signed, the transaction
+// has to be authorized with the keys of its input addresses, one Authorization per input in input
+// order, before it is submitted as an AuthorizedTransaction.
+//
+//     let transaction = TxBuilder::new(state.registration_price_at(txn, height)?)
+//         .input(outpoint, utxo)
+//         .register(key, value, owner)
+//         .fee(1000)
+//         .change_to(change_address)
+//         .build()?;
+//
+// Names are immutable once registered, so there are no update or transfer operations to build.
+#[derive(Clone, Debug)]
+pub struct TxBuilder {
+    inputs: Vec<(OutPoint, Output<BitName>)>,
+    outputs: Vec<Output<BitName>>,
+    fee: u64,
+    registration_price: u64,
+    change_address: Option<Address>,
+}
+
+impl TxBuilder {
+    // registration_price is burned for every registered key, on top of the fee. Use
+    // BitNamesState::registration_price_at of the height the transaction is expected to be mined
+    // at, a transaction underpaying it is rejected.
+    pub fn new(registration_price: u64) -> Self {
+        Self {
+            inputs: vec![],
+            outputs: vec![],
+            fee: 0,
+            registration_price,
+            change_address: None,
+        }
+    }
+
+    // Spend the utxo at outpoint, output is what it holds.
+    pub fn input(mut self, outpoint: OutPoint, output: Output<BitName>) -> Self {
+        self.inputs.push((outpoint, output));
+        self
+    }
+
+    // Register key with value, owned by owner.
+    pub fn register(self, key: Hash, value: Hash, owner: Address) -> Self {
+        self.output(Output {
+            address: owner,
+            content: Content::Custom(BitName::KeyValue { key, value }),
+        })
+    }
+
+    // Pay amount sats to address.
+    pub fn pay(self, address: Address, amount: u64) -> Self {
+        self.output(Output {
+            address,
+            content: Content::Value(amount),
+        })
+    }
+
+    // Add any output, like a Versioned record.
+    pub fn output(mut self, output: Output<BitName>) -> Self {
+        self.outputs.push(output);
+        self
+    }
+
+    // Make the transaction invalid below lock_height, with a lock output paying nothing to
+    // address. Only enforced once the lock_height deployment is active.
+    pub fn lock_height(self, lock_height: u32, address: Address) -> Self {
+        self.output(Output {
+            address,
+            content: Content::Custom(BitName::Versioned {
+                version: LOCK_HEIGHT_VERSION,
+                payload: lock_height::encode(lock_height),
+            }),
+        })
+    }
+
+    // Fee paid to the miner, not counting the registration price.
+    pub fn fee(mut self, fee: u64) -> Self {
+        self.fee = fee;
+        self
+    }
+
+    // Where whatever the inputs hold beyond the outputs, fee and registration price goes. Change
+    // below DUST is added to the fee instead.
+    pub fn change_to(mut self, address: Address) -> Self {
+        self.change_address = Some(address);
+        self
+    }
+
+    // Sats burned for the registrations added so far.
+    pub fn burn(&self) -> u64 {
+        let registrations = self
+            .outputs
+            .iter()
+            .filter(|output| matches!(output.content, Content::Custom(BitName::KeyValue { .. })))
+            .count() as u64;
+        registrations * self.registration_price
+    }
+
+    // Check and assemble the unsigned transaction.
+    pub fn build(self) -> anyhow::Result<Transaction<BitName>> {
+        let mut outpoints = HashSet::new();
+        for (outpoint, _) in &self.inputs {
+            if !outpoints.insert(outpoint) {
+                anyhow::bail!("{outpoint:?} is spent twice");
+            }
+        }
+        let mut keys = HashSet::new();
+        for output in &self.outputs {
+            match output.content {
+                Content::Custom(BitName::KeyValue { key, .. }) if !keys.insert(key) => {
+                    anyhow::bail!("key {} is registered twice", hex::encode(key));
+                }
+                Content::Value(value) if value < DUST => {
+                    anyhow::bail!("a payment of {value} sats is below the dust limit of {DUST}");
+                }
+                _ => {}
+            }
+        }
+        let available: u64 = self
+            .inputs
+            .iter()
+            .map(|(_, output)| output.get_value())
+            .sum();
+        let paid: u64 = self.outputs.iter().map(|output| output.get_value()).sum();
+        let required = paid + self.fee + self.burn();
+        let change = available.checked_sub(required).ok_or_else(|| {
+            anyhow::anyhow!("inputs hold {available} sats, but {required} are needed")
+        })?;
+        let mut outputs = self.outputs;
+        if change >= DUST {
+            let address = self.change_address.ok_or_else(|| {
+                anyhow::anyhow!(
+                    "{change} sats of change would go to the miner, set a change address"
+                )
+            })?;
+            outputs.push(Output {
+                address,
+                content: Content::Value(change),
+            });
+        }
+        Ok(Transaction {
+            inputs: self
+                .inputs
+                .into_iter()
+                .map(|(outpoint, _)| outpoint)
+                .collect(),
+            outputs,
+        })
+    }
+}
@@ -0,0 +1,321 @@
+use crate::bitnames::{BitName, StateReader};
+use crate::coin_selection::{self, SpendPolicy, DUST};
+use crate::wallet;
+use crate::{Node, Wallet};
+use ddk::authorization::Authorization;
+use ddk::types::{
+    Address, AuthorizedTransaction, Content, GetValue, Hash, OutPoint, Output, Transaction,
+};
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+// Cold storage for the keys that own names and funds. The vault is a wallet on an offline
+// machine, the online node only knows a list of its addresses:
+//
+// 1. `bitnames vault export-addresses` on the vault writes fresh addresses to a file, which the
+//    online node takes in with import_vault_addresses.
+// 2. build_vault_registration or build_vault_send on the online node write an unsigned
+//    operation, spending the vault's utxos, to a file.
+// 3. `bitnames vault sign` on the vault checks the operation against its allowlist, its maximum
+//    fee and its own addresses, prints what it does, and writes the signed transaction to a file.
+// 4. submit_vault_transaction on the online node submits it.
+//
+// The vault has no copy of the chain, so it takes the values of the outputs an operation spends
+// from the online node. They aren't covered by the signatures, and the fee the vault prints is
+// only right if they are: an online node that lies about them can have a transaction signed that
+// pays more in fees than shown. The vault doesn't keep them either, its wallet only holds them
+// while signing.
+//
+// Files are moved between the machines by the operator, on removable media for example. Instead
+// of steps 2 to 4 the online node can also hand operations to an external signer and submit what
+// comes back, see signer.rs.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize, clap::ValueEnum)]
+pub enum VaultOperation {
+    // Register a name to a vault address, change back to the vault.
+    Register,
+    // Pay a single outside address, change back to the vault.
+    Send,
+}
+
+// What the vault signs, anything else is refused.
+#[derive(Clone, Debug, clap::Args)]
+pub struct SigningRules {
+    /// Operation type this vault signs. Can be given multiple times.
+    #[arg(long, required = true)]
+    pub allow: Vec<VaultOperation>,
+    /// Highest fee in sats this vault signs, including the burn of a registration.
+    #[arg(long)]
+    pub max_fee: u64,
+}
+
+// An operation for the vault to sign, with the outputs it spends so the vault can sign them
+// without a copy of the chain. spent is as claimed by the online node, the vault can only check
+// that it matches the inputs and pays its own addresses.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct UnsignedOperation {
+    pub operation: VaultOperation,
+    pub transaction: Transaction<BitName>,
+    pub spent: Vec<(OutPoint, Output<BitName>)>,
+}
+
+// The vault's addresses as known to the online node, persisted in vault.dat.
+pub struct VaultAddresses {
+    path: PathBuf,
+    addresses: Mutex<Vec<Address>>,
+}
+
+impl VaultAddresses {
+    pub fn open(datadir: &Path) -> anyhow::Result<Self> {
+        let path = datadir.join("vault.dat");
+        let addresses = if path.exists() {
+            bincode::deserialize(&std::fs::read(&path)?)?
+        } else {
+            vec![]
+        };
+        Ok(Self {
+            path,
+            addresses: Mutex::new(addresses),
+        })
+    }
+
+    // Add the addresses in a file written by `bitnames vault export-addresses`.
+    pub fn import(&self, path: &Path) -> anyhow::Result<usize> {
+        let imported: Vec<Address> = bincode::deserialize(&std::fs::read(path)?)?;
+        let mut addresses = self.addresses.lock().unwrap();
+        let mut added = 0;
+        for address in imported {
+            if !addresses.contains(&address) {
+                addresses.push(address);
+                added += 1;
+            }
+        }
+        std::fs::write(&self.path, bincode::serialize(&*addresses)?)?;
+        Ok(added)
+    }
+
+    // A vault address nothing was ever paid to, so names and change don't share addresses while
+    // there are unused ones left.
+    fn fresh(&self, state: &StateReader, skip: &[Address]) -> anyhow::Result<Address> {
+        let addresses = self.addresses.lock().unwrap().clone();
+        let fresh = state.read(|txn, state| {
+            for address in &addresses {
+                if !skip.contains(address) && state.get_address_history(txn, address)?.is_empty() {
+                    return Ok(Some(*address));
+                }
+            }
+            Ok(None)
+        })?;
+        fresh.ok_or_else(|| anyhow::anyhow!("no unused vault addresses left, import more"))
+    }
+
+    // Fund outputs from the vault's utxos, with change to a fresh vault address.
+    fn build(
+        &self,
+        node: &Node,
+        state: &StateReader,
+        operation: VaultOperation,
+        mut outputs: Vec<Output<BitName>>,
+        fee: u64,
+        policy: SpendPolicy,
+    ) -> anyhow::Result<UnsignedOperation> {
+        let addresses = self.addresses.lock().unwrap().clone();
+        if addresses.is_empty() {
+            anyhow::bail!("no vault addresses imported");
+        }
+        let utxos = node.get_utxos_by_addresses(&addresses)?;
+        let amount = fee + outputs.iter().map(GetValue::get_value).sum::<u64>();
+        let (total, coins) = coin_selection::select(utxos, amount, policy)?;
+        let change = total - amount;
+        if change >= DUST {
+            let used: Vec<Address> = outputs.iter().map(|output| output.address).collect();
+            outputs.push(Output {
+                address: self.fresh(state, &used)?,
+                content: Content::Value(change),
+            });
+        }
+        let spent: Vec<(OutPoint, Output<BitName>)> = coins.into_iter().collect();
+        let transaction = Transaction {
+            inputs: spent.iter().map(|(outpoint, _)| outpoint.clone()).collect(),
+            outputs,
+        };
+        Ok(UnsignedOperation {
+            operation,
+            transaction,
+            spent,
+        })
+    }
+
+    pub fn build_registration(
+        &self,
+        node: &Node,
+        state: &StateReader,
+        key: Hash,
+        value: Hash,
+        fee: u64,
+        policy: SpendPolicy,
+    ) -> anyhow::Result<UnsignedOperation> {
+        if state
+            .read(|txn, state| state.get_value(txn, &key))?
+            .is_some()
+        {
+            anyhow::bail!("key is already registered");
+        }
+        let outputs = vec![Output {
+            address: self.fresh(state, &[])?,
+            content: Content::Custom(BitName::KeyValue { key, value }),
+        }];
+        let fee = fee + wallet::registration_burn(node, state, 1)?;
+        self.build(node, state, VaultOperation::Register, outputs, fee, policy)
+    }
+
+    pub fn build_send(
+        &self,
+        node: &Node,
+        state: &StateReader,
+        address: Address,
+        amount: u64,
+        fee: u64,
+        policy: SpendPolicy,
+    ) -> anyhow::Result<UnsignedOperation> {
+        let outputs = vec![Output {
+            address,
+            content: Content::Value(amount),
+        }];
+        self.build(node, state, VaultOperation::Send, outputs, fee, policy)
+    }
+}
+
+// Write count new addresses of the vault wallet to out.
+pub fn export_addresses(wallet: &Wallet, count: usize, out: &Path) -> anyhow::Result<()> {
+    let addresses = (0..count)
+        .map(|_| wallet.get_new_address())
+        .collect::<Result<Vec<Address>, _>>()?;
+    std::fs::write(out, bincode::serialize(&addresses)?)?;
+    Ok(())
+}
+
+// Check that operation does what it says and only that, and is within rules, returning a
+// description of it.
+fn check(
+    operation: &UnsignedOperation,
+    own: &HashSet<Address>,
+    rules: &SigningRules,
+) -> anyhow::Result<String> {
+    if !rules.allow.contains(&operation.operation) {
+        anyhow::bail!("{:?} operations aren't allowed", operation.operation);
+    }
+    let transaction = &operation.transaction;
+    let inputs: HashSet<&OutPoint> = transaction.inputs.iter().collect();
+    if inputs.len() != transaction.inputs.len() {
+        anyhow::bail!("spends an output more than once");
+    }
+    // Every spent output has to be listed exactly once, or its value would count twice below.
+    let spent: HashSet<&OutPoint> = operation
+        .spent
+        .iter()
+        .map(|(outpoint, _)| outpoint)
+        .collect();
+    if spent.len() != operation.spent.len() || spent != inputs {
+        anyhow::bail!("the spent outputs don't match the transaction's inputs");
+    }
+    if let Some((_, output)) = operation
+        .spent
+        .iter()
+        .find(|(_, output)| !own.contains(&output.address))
+    {
+        anyhow::bail!("spends an output to {:?}, which isn't ours", output.address);
+    }
+    let value_in: u64 = operation
+        .spent
+        .iter()
+        .map(|(_, output)| output.get_value())
+        .sum();
+    let value_out: u64 = transaction.outputs.iter().map(GetValue::get_value).sum();
+    let fee = value_in
+        .checked_sub(value_out)
+        .ok_or_else(|| anyhow::anyhow!("outputs exceed inputs"))?;
+    if fee > rules.max_fee {
+        anyhow::bail!("fee of {fee} sats is over the maximum of {}", rules.max_fee);
+    }
+    let foreign: Vec<&Output<BitName>> = transaction
+        .outputs
+        .iter()
+        .filter(|output| !own.contains(&output.address))
+        .collect();
+    let description = match operation.operation {
+        VaultOperation::Register => {
+            let registrations: Vec<Hash> = crate::bitnames::registered_keys(transaction);
+            let other_content = transaction.outputs.iter().any(|output| {
+                !matches!(
+                    output.content,
+                    Content::Value(_) | Content::Custom(BitName::KeyValue { .. })
+                )
+            });
+            if registrations.len() != 1 || other_content || !foreign.is_empty() {
+                anyhow::bail!("a registration has one KeyValue output and pays only us");
+            }
+            format!("register {}", hex::encode(registrations[0]))
+        }
+        VaultOperation::Send => {
+            let other_content = transaction
+                .outputs
+                .iter()
+                .any(|output| !matches!(output.content, Content::Value(_)));
+            if other_content || foreign.len() != 1 {
+                anyhow::bail!("a send has only value outputs and pays one outside address");
+            }
+            format!(
+                "send {} sats to {:?}",
+                foreign[0].get_value(),
+                foreign[0].address
+            )
+        }
+    };
+    Ok(format!(
+        "{description}, fee {fee} sats if the spent output values the online node sent are right, \
+         they can't be checked here"
+    ))
+}
+
+// Sign operation with the vault wallet if it is within rules and checks out, returning a
+// description of it along with the signed transaction.
+pub fn sign_operation(
+    wallet: &Wallet,
+    operation: UnsignedOperation,
+    rules: &SigningRules,
+) -> anyhow::Result<(String, AuthorizedTransaction<Authorization, BitName>)> {
+    let own: HashSet<Address> = wallet.get_addresses()?.into_iter().collect();
+    let description = check(&operation, &own, rules)?;
+    // authorize finds the key for each input from the wallet's utxos, but the vault never sees the
+    // chain and the spent outputs come from the online node. So the ones the wallet doesn't hold
+    // are added only for signing and removed again right after, whether it succeeded or not.
+    let held: HashSet<OutPoint> = wallet.get_utxos()?.into_keys().collect();
+    let added: HashMap<OutPoint, Output<BitName>> = operation
+        .spent
+        .iter()
+        .filter(|(outpoint, _)| !held.contains(outpoint))
+        .cloned()
+        .collect();
+    wallet.put_utxos(&added)?;
+    let signed = wallet.authorize(operation.transaction);
+    let added: Vec<OutPoint> = added.into_keys().collect();
+    wallet.delete_utxos(&added)?;
+    Ok((description, signed?))
+}
+
+// Sign the operation in input with the vault wallet and write the signed transaction to output,
+// see sign_operation.
+pub fn sign(
+    wallet: &Wallet,
+    input: &Path,
+    output: &Path,
+    rules: &SigningRules,
+) -> anyhow::Result<()> {
+    let operation: UnsignedOperation = bincode::deserialize(&std::fs::read(input)?)?;
+    let (description, signed) = sign_operation(wallet, operation, rules)?;
+    std::fs::write(output, bincode::serialize(&signed)?)?;
+    println!("signed: {description}");
+    Ok(())
+}
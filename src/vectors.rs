@@ -0,0 +1,372 @@
+use crate::bitnames::{BitName, BitNamesState};
+use crate::chain_params::{self, ChainParams};
+use crate::rejection::{Rejection, RejectionCode};
+use crate::tlsa::TLSA_VERSION;
+use ddk::authorization::Authorization;
+use ddk::heed;
+use ddk::node::State;
+use ddk::types::{
+    Address, AuthorizedTransaction, Body, Content, Hash, OutPoint, Output, Transaction,
+};
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+
+// Fixed test vectors of the consensus rules, for alternative implementations and auditors, written
+// by `bitnames gen-vectors`.
+//
+// Every vector is a block body to validate at some height, after funding the utxos it lists and
+// connecting the blocks before it, with the outcome BitNamesState gives. Everything is derived
+// from fixed labels and the selected network's chain params, so the same network always gets the
+// same vectors, byte for byte.
+//
+// Only the rules BitNamesState enforces are covered. Authorizations are left empty, ddk checks
+// them before BitNamesState sees a body. The rules of content versions behind a deployment, onion
+// bindings, height locks and main addresses, aren't covered either, since activating them takes
+// several full signalling windows of blocks. For the same reason the content limits, the
+// registration burn and the record sizes are only covered on networks where they are active from
+// genesis, like regtest, elsewhere their vectors record that the rules don't apply yet.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Vector {
+    pub name: String,
+    pub description: String,
+    // Hex of the bincode encoded Vec<(OutPoint, Output)> of utxos the transactions spend, which
+    // have to be in the utxo set before any block is connected.
+    pub utxos: String,
+    // Hex of bincode encoded bodies, connected at heights 1, 2, ... before body.
+    pub prior_blocks: Vec<String>,
+    // Height body is validated at, one above the last prior block.
+    pub height: u32,
+    pub body: String,
+    pub valid: bool,
+    // Why the body is rejected, if it isn't valid.
+    pub rejection: Option<RejectionCode>,
+    pub message: Option<String>,
+}
+
+// Fee every transaction pays on top of the registration price.
+const FEE: u64 = 1000;
+// A content version no rules are assigned to, which is accepted without interpretation.
+const UNASSIGNED_VERSION: u8 = 255;
+
+struct Case {
+    name: &'static str,
+    description: &'static str,
+    prior_blocks: Vec<Body<Authorization, BitName>>,
+    body: Body<Authorization, BitName>,
+}
+
+// Builds the transactions of a case, funding each with a new utxo worth its outputs and a fee.
+struct Builder {
+    params: &'static ChainParams,
+    funded: u64,
+    utxos: Vec<(OutPoint, Output<BitName>)>,
+}
+
+impl Builder {
+    fn address(&self) -> Address {
+        Address::from(ddk::types::hash(&("address", self.funded)))
+    }
+
+    fn transaction(
+        &mut self,
+        outputs: Vec<Content<BitName>>,
+        fee: u64,
+    ) -> AuthorizedTransaction<Authorization, BitName> {
+        let address = self.address();
+        let input = OutPoint::Regular {
+            txid: ddk::types::hash(&("funding", self.funded)).into(),
+            vout: 0,
+        };
+        self.funded += 1;
+        let value: u64 = outputs
+            .iter()
+            .map(|content| match content {
+                Content::Value(value) => *value,
+                _ => 0,
+            })
+            .sum();
+        self.utxos.push((
+            input.clone(),
+            Output {
+                address,
+                content: Content::Value(value + fee),
+            },
+        ));
+        AuthorizedTransaction {
+            transaction: Transaction {
+                inputs: vec![input],
+                outputs: outputs
+                    .into_iter()
+                    .map(|content| Output { address, content })
+                    .collect(),
+            },
+            authorizations: vec![],
+        }
+    }
+
+    // A transaction registering the keys of labels, paying the price at height and FEE.
+    fn registration(
+        &mut self,
+        labels: &[&str],
+        height: u32,
+    ) -> AuthorizedTransaction<Authorization, BitName> {
+        let outputs = labels.iter().map(|label| registration(label)).collect();
+        let fee = self.params.registration_price(height) * labels.len() as u64 + FEE;
+        self.transaction(outputs, fee)
+    }
+}
+
+fn key(label: &str) -> Hash {
+    ddk::types::hash(&("key", label))
+}
+
+fn registration(label: &str) -> Content<BitName> {
+    Content::Custom(BitName::KeyValue {
+        key: key(label),
+        value: ddk::types::hash(&("value", label)),
+    })
+}
+
+fn record(version: u8, payload: Vec<u8>) -> Content<BitName> {
+    Content::Custom(BitName::Versioned { version, payload })
+}
+
+fn cases(builder: &mut Builder) -> Vec<Case> {
+    let params = builder.params;
+    let price = params.registration_price(1);
+    let mut cases = vec![];
+    let mut case = |name, description, prior_blocks, body| {
+        cases.push(Case {
+            name,
+            description,
+            prior_blocks,
+            body,
+        })
+    };
+
+    case(
+        "registration",
+        "a transaction registering one key and paying the registration price",
+        vec![],
+        Body::new(vec![builder.registration(&["a"], 1)], vec![]),
+    );
+    case(
+        "coinbase_claims_fees",
+        "the coinbase claims the fee of a registration, but not its burn",
+        vec![],
+        Body::new(
+            vec![builder.registration(&["a"], 1)],
+            vec![Output {
+                address: builder.address(),
+                content: Content::Value(FEE),
+            }],
+        ),
+    );
+    case(
+        "unassigned_version",
+        "a record of a content version without rules is accepted as is",
+        vec![],
+        Body::new(
+            vec![builder.transaction(vec![record(UNASSIGNED_VERSION, vec![1, 2, 3])], FEE)],
+            vec![],
+        ),
+    );
+    case(
+        "key_already_registered",
+        "the key was registered by the previous block",
+        vec![Body::new(vec![builder.registration(&["a"], 1)], vec![])],
+        Body::new(vec![builder.registration(&["a"], 2)], vec![]),
+    );
+    case(
+        "duplicate_key_in_block",
+        "two transactions in the block register the same key",
+        vec![],
+        Body::new(
+            vec![
+                builder.registration(&["a"], 1),
+                builder.registration(&["a"], 1),
+            ],
+            vec![],
+        ),
+    );
+    case(
+        "duplicate_key_in_transaction",
+        "one transaction registers the same key twice",
+        vec![],
+        Body::new(vec![builder.registration(&["a", "a"], 1)], vec![]),
+    );
+    let labels: Vec<String> = (0..=params.max_registrations_per_block)
+        .map(|i| i.to_string())
+        .collect();
+    let labels: Vec<&str> = labels.iter().map(String::as_str).collect();
+    case(
+        "too_many_registrations",
+        "the block registers one key more than max_registrations_per_block",
+        vec![],
+        Body::new(vec![builder.registration(&labels, 1)], vec![]),
+    );
+    case(
+        "registration_underpaid",
+        "the fee is one sat short of the registration price",
+        vec![],
+        Body::new(
+            vec![builder.transaction(vec![registration("a")], price.saturating_sub(1))],
+            vec![],
+        ),
+    );
+    case(
+        "coinbase_claims_burn",
+        "the coinbase claims one sat of the registration burn",
+        vec![],
+        Body::new(
+            vec![builder.registration(&["a"], 1)],
+            vec![Output {
+                address: builder.address(),
+                content: Content::Value(FEE + 1),
+            }],
+        ),
+    );
+    case(
+        "dust_output",
+        "a value output one sat below the dust limit",
+        vec![],
+        Body::new(
+            vec![builder.transaction(
+                vec![Content::Value(params.dust_limit.saturating_sub(1))],
+                FEE,
+            )],
+            vec![],
+        ),
+    );
+    case(
+        "reserved_content_version",
+        "a versioned output with version 0, which only KeyValue can use",
+        vec![],
+        Body::new(
+            vec![builder.transaction(vec![record(0, vec![])], FEE)],
+            vec![],
+        ),
+    );
+    case(
+        "signal_outside_coinbase",
+        "a version signal in a regular transaction",
+        vec![],
+        Body::new(
+            vec![builder.transaction(vec![Content::Custom(BitName::Signal { bits: 1 })], FEE)],
+            vec![],
+        ),
+    );
+    let records = (0..=params.max_custom_outputs)
+        .map(|_| record(UNASSIGNED_VERSION, vec![]))
+        .collect();
+    case(
+        "too_many_custom_outputs",
+        "one BitName output more than max_custom_outputs",
+        vec![],
+        Body::new(vec![builder.transaction(records, FEE)], vec![]),
+    );
+    case(
+        "record_too_large",
+        "a record one byte larger than max_record_size",
+        vec![],
+        Body::new(
+            vec![builder.transaction(
+                vec![record(
+                    UNASSIGNED_VERSION,
+                    vec![0; params.max_record_size + 1],
+                )],
+                FEE,
+            )],
+            vec![],
+        ),
+    );
+    // Records of one key as large as allowed each, but larger than max_name_records_size
+    // together. A TLSA record starts with the key it is attached to.
+    let mut payload = key("a").to_vec();
+    payload.resize(params.max_record_size, 0);
+    let records = (0..=params.max_name_records_size / params.max_record_size)
+        .map(|_| record(TLSA_VERSION, payload.clone()))
+        .collect();
+    case(
+        "name_records_too_large",
+        "records attached to one key are larger than max_name_records_size together",
+        vec![],
+        Body::new(vec![builder.transaction(records, FEE)], vec![]),
+    );
+    cases
+}
+
+fn encode<T: Serialize>(value: &T) -> anyhow::Result<String> {
+    Ok(hex::encode(bincode::serialize(value)?))
+}
+
+// Generate the vectors of the selected network, using a scratch database in dir.
+pub fn generate(dir: &Path) -> anyhow::Result<Vec<Vector>> {
+    std::fs::create_dir_all(dir)?;
+    let env = heed::EnvOpenOptions::new()
+        .map_size(256 * 1024 * 1024)
+        .max_dbs(ddk::state::State::<Authorization, BitName>::NUM_DBS + BitNamesState::NUM_DBS)
+        .open(dir)?;
+    let ddk_state = ddk::state::State::<Authorization, BitName>::new(&env)?;
+    let state = BitNamesState::new(&env)?;
+    let mut builder = Builder {
+        params: chain_params::selected(),
+        funded: 0,
+        utxos: vec![],
+    };
+    let cases = cases(&mut builder);
+    let mut vectors = vec![];
+    for case in cases {
+        // Every case runs in a write transaction that is never committed, so each starts from an
+        // empty state. All utxos are funded in every case, but each case only spends its own.
+        let mut txn = env.write_txn()?;
+        for (outpoint, output) in &builder.utxos {
+            ddk_state.utxos.put(&mut txn, outpoint, output)?;
+        }
+        for (i, body) in case.prior_blocks.iter().enumerate() {
+            state.validate_body(&txn, i as u32 + 1, &ddk_state, body)?;
+            state.connect_body(&mut txn, i as u32 + 1, &ddk_state, body)?;
+        }
+        let height = case.prior_blocks.len() as u32 + 1;
+        let result = state.validate_body(&txn, height, &ddk_state, &case.body);
+        drop(txn);
+        let spent: Vec<&OutPoint> = case
+            .prior_blocks
+            .iter()
+            .chain(std::iter::once(&case.body))
+            .flat_map(|body| body.transactions.iter())
+            .flat_map(|transaction| transaction.inputs.iter())
+            .collect();
+        let utxos: Vec<&(OutPoint, Output<BitName>)> = builder
+            .utxos
+            .iter()
+            .filter(|(outpoint, _)| spent.contains(&outpoint))
+            .collect();
+        let rejection = result.as_ref().err().map(Rejection::from_state_error);
+        vectors.push(Vector {
+            name: case.name.to_string(),
+            description: case.description.to_string(),
+            utxos: encode(&utxos)?,
+            prior_blocks: case
+                .prior_blocks
+                .iter()
+                .map(encode)
+                .collect::<anyhow::Result<_>>()?,
+            height,
+            body: encode(&case.body)?,
+            valid: result.is_ok(),
+            rejection: rejection.as_ref().map(|rejection| rejection.code),
+            message: rejection.map(|rejection| rejection.message),
+        });
+    }
+    Ok(vectors)
+}
+
+// Write the vectors of the selected network to out as JSON.
+pub fn write(out: &Path) -> anyhow::Result<()> {
+    let dir = std::env::temp_dir().join(format!("bitnames-vectors-{}", std::process::id()));
+    let vectors = generate(&dir);
+    std::fs::remove_dir_all(&dir)?;
+    std::fs::write(out, serde_json::to_string_pretty(&vectors?)?)?;
+    Ok(())
+}
@@ -0,0 +1,856 @@
+use crate::aliases::{AliasRecord, ALIAS_VERSION};
+use crate::attestation::{AttestationRecord, ATTESTATION_VERSION};
+use crate::bitnames::{registered_keys, BitName, StateReader};
+use crate::coin_selection::{self, SpendPolicy, DUST};
+use crate::disputes::{DisputeRecord, DISPUTE_VERSION};
+use crate::identity::{IdentityRecord, IDENTITY_VERSION};
+use crate::main_address::{MainAddressRecord, MAIN_ADDRESS_VERSION};
+use crate::offchain::{SequenceRecord, OFFCHAIN_VERSION};
+use crate::onion::{OnionRecord, ONION_VERSION};
+use crate::rebroadcast;
+use crate::records::{Record, TypedRecord, RECORDS_VERSION};
+use crate::rpc_api::{
+    Availability, NameCost, NameQuote, RegistrationRequest, RegistrationStatus, WalletLabel,
+    WalletTransaction, Withdrawal,
+};
+use crate::tlsa::{TlsaRecord, TLSA_VERSION};
+use crate::{Node, Wallet};
+use ddk::types::{Address, Content, GetValue, Hash, OutPoint, Output, Transaction, Txid};
+use ed25519_dalek::PublicKey;
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+// Wallets loaded into this node, so a registrar can keep customers' funds and names apart inside
+// a single process. The default wallet lives in <datadir>/wallet.mdb, named ones in
+// <datadir>/wallets/<name>.mdb, and are opened on first use.
+pub struct Wallets {
+    datadir: PathBuf,
+    default: Arc<Wallet>,
+    loaded: Mutex<HashMap<String, Arc<Wallet>>>,
+}
+
+impl Wallets {
+    pub fn new(datadir: &Path, default: Arc<Wallet>) -> Self {
+        Self {
+            datadir: datadir.to_path_buf(),
+            default,
+            loaded: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub fn dir(datadir: &Path) -> PathBuf {
+        datadir.join("wallets")
+    }
+
+    fn path(&self, name: &str) -> anyhow::Result<PathBuf> {
+        let valid = !name.is_empty()
+            && name
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_');
+        if !valid {
+            anyhow::bail!("wallet names may only contain letters, digits, - and _");
+        }
+        Ok(Self::dir(&self.datadir).join(format!("{name}.mdb")))
+    }
+
+    pub fn create(&self, name: &str) -> anyhow::Result<()> {
+        let path = self.path(name)?;
+        if path.exists() {
+            anyhow::bail!("wallet {name} already exists");
+        }
+        std::fs::create_dir_all(Self::dir(&self.datadir))?;
+        let wallet = Arc::new(Wallet::new(&path)?);
+        self.loaded.lock().unwrap().insert(name.to_string(), wallet);
+        Ok(())
+    }
+
+    // The named wallet, or the default one for None.
+    pub fn get(&self, name: Option<&str>) -> anyhow::Result<Arc<Wallet>> {
+        let name = match name {
+            Some(name) => name,
+            None => return Ok(self.default.clone()),
+        };
+        let mut loaded = self.loaded.lock().unwrap();
+        if let Some(wallet) = loaded.get(name) {
+            return Ok(wallet.clone());
+        }
+        let path = self.path(name)?;
+        if !path.exists() {
+            anyhow::bail!("wallet {name} doesn't exist");
+        }
+        let wallet = Arc::new(Wallet::new(&path)?);
+        loaded.insert(name.to_string(), wallet.clone());
+        Ok(wallet)
+    }
+
+    // Names of every wallet on disk, not counting the default one.
+    pub fn list(&self) -> anyhow::Result<Vec<String>> {
+        let dir = Self::dir(&self.datadir);
+        if !dir.exists() {
+            return Ok(vec![]);
+        }
+        let mut names = vec![];
+        for entry in std::fs::read_dir(dir)? {
+            let path = entry?.path();
+            if path
+                .extension()
+                .map_or(false, |extension| extension == "mdb")
+            {
+                if let Some(name) = path.file_stem().and_then(|stem| stem.to_str()) {
+                    names.push(name.to_string());
+                }
+            }
+        }
+        names.sort();
+        Ok(names)
+    }
+}
+
+// Rough serialized size of a registration with one input, a KeyValue output and a change output,
+// used to turn fee rates into a registration cost.
+const REGISTRATION_SIZE: u64 = 300;
+
+// Whether key can be registered right now, and roughly what it costs.
+pub fn quote_name(node: &Node, state: &StateReader, key: Hash) -> anyhow::Result<NameQuote> {
+    let (value, fee_rate) =
+        state.read(|txn, state| Ok((state.get_value(txn, &key)?, state.estimate_fee(txn, 1)?)))?;
+    let availability = if value.is_some() {
+        Availability::Registered
+    } else if mempool_keys(node)?.contains(&key) || BUILDING.lock().unwrap().contains(&key) {
+        Availability::Pending
+    } else {
+        Availability::Available
+    };
+    Ok(NameQuote {
+        availability,
+        fee: fee_rate.map(|rate| rate * REGISTRATION_SIZE / 1000),
+        price: registration_burn(node, state, 1)?,
+    })
+}
+
+// Sats a transaction with this many registrations has to burn to get into the next block.
+//
+// Prices only go down with height, so a registration paying this stays valid if it takes a few
+// more blocks to confirm. The burn itself may activate in the meantime, if its deployment is
+// locked in, so this pays the price it would have a deployment window later if that is higher.
+pub fn registration_burn(
+    node: &Node,
+    state: &StateReader,
+    registrations: usize,
+) -> anyhow::Result<u64> {
+    let height = node.get_height()? + 1;
+    let window = state.params().deployment_window;
+    let price = state.read(|txn, state| {
+        let next = state.registration_price_at(txn, height)?;
+        Ok(next.max(state.registration_price_at(txn, height + window)?))
+    })?;
+    Ok(registrations as u64 * price)
+}
+
+// Keys a registration is currently being built for, so two local callers (say two RPC clients)
+// racing for the same key don't both pay for a registration only one of them can get. Once the
+// transaction is submitted the key shows up in the mempool instead, see try_reserve.
+static BUILDING: Mutex<BTreeSet<Hash>> = Mutex::new(BTreeSet::new());
+
+// Keys reserved in BUILDING, released when dropped.
+struct Reservation(Vec<Hash>);
+
+impl Drop for Reservation {
+    fn drop(&mut self) {
+        let mut building = BUILDING.lock().unwrap();
+        for key in &self.0 {
+            building.remove(key);
+        }
+    }
+}
+
+// Keys registered by transactions waiting in the mempool.
+fn mempool_keys(node: &Node) -> anyhow::Result<HashSet<Hash>> {
+    let mut keys = HashSet::new();
+    for transaction in node.get_all_transactions()? {
+        keys.extend(registered_keys(&transaction.transaction));
+    }
+    Ok(keys)
+}
+
+// Add key to reservation unless a registration for it is already being built or pending.
+fn try_reserve(key: Hash, mempool_keys: &HashSet<Hash>, reservation: &mut Reservation) -> bool {
+    if mempool_keys.contains(&key) || !BUILDING.lock().unwrap().insert(key) {
+        return false;
+    }
+    reservation.0.push(key);
+    true
+}
+
+// Bring the wallet's utxo set up to date with the node: add utxos paying to wallet addresses and
+// drop the ones that have been spent.
+pub fn sync(node: &Node, wallet: &Wallet) -> anyhow::Result<()> {
+    let addresses = wallet.get_addresses()?;
+    let utxos = node.get_utxos_by_addresses(&addresses)?;
+    wallet.put_utxos(&utxos)?;
+    let outpoints: Vec<_> = wallet.get_utxos()?.into_keys().collect();
+    let spent = node.get_spent_utxos(&outpoints)?;
+    wallet.delete_utxos(&spent)?;
+    Ok(())
+}
+
+// Every confirmed transaction touching wallet, derived from the address index and the stored
+// bodies rather than recorded as the wallet sends, so it also covers transactions the wallet
+// received and always matches the current chain.
+pub fn history(state: &StateReader, wallet: &Wallet) -> anyhow::Result<Vec<WalletTransaction>> {
+    let addresses: HashSet<Address> = wallet.get_addresses()?.into_iter().collect();
+    let history = state.read(|txn, state| {
+        let mut owned: HashMap<OutPoint, u64> = HashMap::new();
+        let mut heights = BTreeSet::new();
+        for address in &addresses {
+            for entry in state.get_address_history(txn, address)? {
+                owned.insert(entry.outpoint, entry.value);
+                heights.insert(entry.funded_height);
+                heights.extend(entry.spent_height);
+            }
+        }
+        let mut history = vec![];
+        for height in heights {
+            let body = match state.get_block(txn, height)? {
+                Some(body) => body,
+                None => continue,
+            };
+            let mined: u64 = body
+                .coinbase
+                .iter()
+                .filter(|output| addresses.contains(&output.address))
+                .map(GetValue::get_value)
+                .sum();
+            if mined > 0 {
+                history.push(WalletTransaction {
+                    txid: None,
+                    height,
+                    sent: 0,
+                    received: mined,
+                    fee: Some(0),
+                    labels: vec![WalletLabel::Mined { value: mined }],
+                });
+            }
+            for transaction in &body.transactions {
+                let spent: Vec<u64> = transaction
+                    .inputs
+                    .iter()
+                    .filter_map(|input| owned.get(input).copied())
+                    .collect();
+                let funded = !spent.is_empty();
+                let received: u64 = transaction
+                    .outputs
+                    .iter()
+                    .filter(|output| addresses.contains(&output.address))
+                    .map(GetValue::get_value)
+                    .sum();
+                let receives = transaction
+                    .outputs
+                    .iter()
+                    .any(|output| addresses.contains(&output.address));
+                if !funded && !receives {
+                    continue;
+                }
+                let sent: u64 = spent.iter().sum();
+                let fee = (spent.len() == transaction.inputs.len()).then(|| {
+                    let value_out: u64 = transaction.outputs.iter().map(GetValue::get_value).sum();
+                    sent.saturating_sub(value_out)
+                });
+                let mut labels = vec![];
+                for output in &transaction.outputs {
+                    let ours = addresses.contains(&output.address);
+                    let label = match &output.content {
+                        Content::Custom(BitName::KeyValue { key, .. }) if funded => {
+                            Some(WalletLabel::Registered {
+                                key: *key,
+                                owned: ours,
+                            })
+                        }
+                        Content::Custom(BitName::KeyValue { key, .. }) if ours => {
+                            Some(WalletLabel::ReceivedName { key: *key })
+                        }
+                        Content::Custom(BitName::Versioned {
+                            version: DISPUTE_VERSION,
+                            payload,
+                        }) if funded => DisputeRecord::decode(payload)
+                            .map(|record| WalletLabel::Disputed { key: record.key }),
+                        Content::Custom(BitName::Versioned {
+                            version: IDENTITY_VERSION,
+                            payload,
+                        }) if funded => IdentityRecord::decode(payload, transaction)
+                            .filter(|record| record.signature.is_some())
+                            .map(|record| WalletLabel::RotatedIdentity { key: record.key }),
+                        Content::Custom(BitName::Versioned {
+                            version: OFFCHAIN_VERSION,
+                            payload,
+                        }) if funded => SequenceRecord::decode(payload)
+                            .map(|record| WalletLabel::RaisedOffchainSequence { key: record.key }),
+                        Content::Withdrawal {
+                            value,
+                            main_address,
+                            ..
+                        } if funded => Some(WalletLabel::Withdrawal {
+                            main_address: main_address.to_string(),
+                            value: *value,
+                        }),
+                        Content::Value(value) if funded && !ours => Some(WalletLabel::Sent {
+                            address: output.address,
+                            value: *value,
+                        }),
+                        Content::Value(value) if !funded && ours => {
+                            Some(WalletLabel::Received { value: *value })
+                        }
+                        _ => None,
+                    };
+                    labels.extend(label);
+                }
+                history.push(WalletTransaction {
+                    txid: Some(transaction.txid().into()),
+                    height,
+                    sent,
+                    received,
+                    fee,
+                    labels,
+                });
+            }
+        }
+        Ok(history)
+    })?;
+    Ok(history)
+}
+
+// Costs per name from history. Only transactions whose fee is known, the ones the wallet fully
+// funded, are counted.
+pub fn name_costs(state: &StateReader, wallet: &Wallet) -> anyhow::Result<Vec<NameCost>> {
+    let mut costs: BTreeMap<Hash, NameCost> = BTreeMap::new();
+    for transaction in history(state, wallet)? {
+        let (txid, fee) = match (transaction.txid, transaction.fee) {
+            (Some(txid), Some(fee)) => (txid, fee),
+            _ => continue,
+        };
+        let keys: Vec<Hash> = transaction
+            .labels
+            .iter()
+            .filter_map(|label| match label {
+                WalletLabel::Registered { key, .. }
+                | WalletLabel::Disputed { key }
+                | WalletLabel::RotatedIdentity { key }
+                | WalletLabel::RaisedOffchainSequence { key } => Some(*key),
+                _ => None,
+            })
+            .collect();
+        for (i, key) in keys.iter().enumerate() {
+            // The remainder of the split goes to the first name.
+            let share = fee / keys.len() as u64 + if i == 0 { fee % keys.len() as u64 } else { 0 };
+            let cost = costs.entry(*key).or_insert_with(|| NameCost {
+                key: *key,
+                spent: 0,
+                transactions: vec![],
+            });
+            cost.spent += share;
+            if !cost.transactions.contains(&txid) {
+                cost.transactions.push(txid);
+            }
+        }
+    }
+    Ok(costs.into_values().collect())
+}
+
+pub fn name_costs_csv(costs: &[NameCost]) -> String {
+    let mut csv = String::from("key,spent,transactions\n");
+    for cost in costs {
+        let transactions: Vec<String> = cost.transactions.iter().map(hex::encode).collect();
+        csv.push_str(&format!(
+            "{},{},{}\n",
+            hex::encode(cost.key),
+            cost.spent,
+            transactions.join(" ")
+        ));
+    }
+    csv
+}
+
+// Build, sign, and submit a transaction registering key with value, paying fee plus the
+// registration price.
+pub async fn register_name(
+    node: &Node,
+    wallet: &Wallet,
+    state: &StateReader,
+    key: Hash,
+    value: Hash,
+    fee: u64,
+    policy: SpendPolicy,
+) -> anyhow::Result<()> {
+    let owner = wallet.get_new_address()?;
+    register_name_for(node, wallet, state, key, value, owner, fee, policy).await
+}
+
+// Register many names in a single transaction paying fee_per_item plus the registration price for
+// each included registration, and report what happened to every request, in order.
+//
+// Everything goes into one transaction because the wallet only sees confirmed utxos, so separate
+// transactions built back to back would try to spend the same coins. That also caps a batch at
+// max_registrations_per_block, since validate_body would reject a bigger one.
+pub async fn register_names(
+    node: &Node,
+    wallet: &Wallet,
+    state: &StateReader,
+    requests: Vec<RegistrationRequest>,
+    fee_per_item: u64,
+    policy: SpendPolicy,
+) -> anyhow::Result<Vec<RegistrationStatus>> {
+    let mut statuses = vec![];
+    let mut keys = HashSet::new();
+    let mut outputs = vec![];
+    let mempool_keys = mempool_keys(node)?;
+    let mut reservation = Reservation(vec![]);
+    for request in requests {
+        let status = if state
+            .read(|txn, state| state.get_value(txn, &request.key))?
+            .is_some()
+        {
+            RegistrationStatus::AlreadyRegistered
+        } else if !keys.insert(request.key) {
+            RegistrationStatus::Duplicate
+        } else if outputs.len() >= state.params().max_registrations_per_block {
+            RegistrationStatus::Deferred
+        } else if !try_reserve(request.key, &mempool_keys, &mut reservation) {
+            RegistrationStatus::Pending
+        } else {
+            outputs.push(Output {
+                address: request.owner,
+                content: Content::Custom(BitName::KeyValue {
+                    key: request.key,
+                    value: request.value,
+                }),
+            });
+            RegistrationStatus::Submitted
+        };
+        statuses.push(status);
+    }
+    if outputs.is_empty() {
+        return Ok(statuses);
+    }
+    let fee = fee_per_item * outputs.len() as u64 + registration_burn(node, state, outputs.len())?;
+    fund_and_submit(node, wallet, outputs, fee, policy).await?;
+    Ok(statuses)
+}
+
+// Pay amount sats to address.
+pub async fn send(
+    node: &Node,
+    wallet: &Wallet,
+    address: Address,
+    amount: u64,
+    fee: u64,
+    policy: SpendPolicy,
+) -> anyhow::Result<()> {
+    let outputs = vec![Output {
+        address,
+        content: Content::Value(amount),
+    }];
+    fund_and_submit(node, wallet, outputs, fee, policy).await?;
+    Ok(())
+}
+
+// Withdraw value sats to main_address on the mainchain, paying main_fee to the mainchain and fee
+// to the sidechain.
+pub async fn create_withdrawal(
+    node: &Node,
+    wallet: &Wallet,
+    main_address: ddk::bitcoin::Address,
+    value: u64,
+    main_fee: u64,
+    fee: u64,
+    policy: SpendPolicy,
+) -> anyhow::Result<Withdrawal> {
+    let address = wallet.get_new_address()?;
+    let main_address_string = main_address.to_string();
+    let outputs = vec![Output {
+        address,
+        content: Content::Withdrawal {
+            value,
+            main_address,
+            main_fee,
+        },
+    }];
+    let txid = fund_and_submit(node, wallet, outputs, fee, policy).await?;
+    Ok(Withdrawal {
+        outpoint: OutPoint::Regular { txid, vout: 0 },
+        address,
+        main_address: main_address_string,
+        value,
+        main_fee,
+    })
+}
+
+// Put record on chain in an output to a new wallet address, paying fee.
+pub async fn file_dispute(
+    node: &Node,
+    wallet: &Wallet,
+    record: &DisputeRecord,
+    fee: u64,
+    policy: SpendPolicy,
+) -> anyhow::Result<Txid> {
+    let outputs = vec![Output {
+        address: wallet.get_new_address()?,
+        content: Content::Custom(BitName::Versioned {
+            version: DISPUTE_VERSION,
+            payload: record.encode(),
+        }),
+    }];
+    fund_and_submit(node, wallet, outputs, fee, policy).await
+}
+
+// Submit an identity key rotation, see identity.rs. The record is signed already, so any
+// wallet can pay for it.
+pub async fn rotate_identity_key(
+    node: &Node,
+    wallet: &Wallet,
+    record: &IdentityRecord,
+    fee: u64,
+    policy: SpendPolicy,
+) -> anyhow::Result<Txid> {
+    let outputs = vec![Output {
+        address: wallet.get_new_address()?,
+        content: Content::Custom(BitName::Versioned {
+            version: IDENTITY_VERSION,
+            payload: record.encode(),
+        }),
+    }];
+    fund_and_submit(node, wallet, outputs, fee, policy).await
+}
+
+// Submit a raise of an off-chain data pointer's minimum sequence, see offchain.rs. Like identity
+// key rotations the record is signed already, so any wallet can pay for it.
+pub async fn raise_offchain_sequence(
+    node: &Node,
+    wallet: &Wallet,
+    record: &SequenceRecord,
+    fee: u64,
+    policy: SpendPolicy,
+) -> anyhow::Result<Txid> {
+    let outputs = vec![Output {
+        address: wallet.get_new_address()?,
+        content: Content::Custom(BitName::Versioned {
+            version: OFFCHAIN_VERSION,
+            payload: record.encode(),
+        }),
+    }];
+    fund_and_submit(node, wallet, outputs, fee, policy).await
+}
+
+// Deposits from the mainchain that ddk credited to this wallet and that are still unspent.
+pub fn list_deposits(node: &Node, wallet: &Wallet) -> anyhow::Result<Vec<(OutPoint, u64)>> {
+    sync(node, wallet)?;
+    let deposits = wallet
+        .get_utxos()?
+        .into_iter()
+        .filter(|(outpoint, _)| matches!(outpoint, OutPoint::Deposit(_)))
+        .map(|(outpoint, output)| (outpoint, output.get_value()))
+        .collect();
+    Ok(deposits)
+}
+
+// Add inputs paying for outputs and fee plus change to outputs, then sign and submit the
+// transaction. The outputs keep their positions, change goes last.
+async fn fund_and_submit(
+    node: &Node,
+    wallet: &Wallet,
+    mut outputs: Vec<Output<BitName>>,
+    fee: u64,
+    policy: SpendPolicy,
+) -> anyhow::Result<Txid> {
+    sync(node, wallet)?;
+    let amount = fee + outputs.iter().map(GetValue::get_value).sum::<u64>();
+    let (total, coins) = coin_selection::select(wallet.get_utxos()?, amount, policy)?;
+    let change = total - amount;
+    if change >= DUST {
+        outputs.push(Output {
+            address: wallet.get_new_address()?,
+            content: Content::Value(change),
+        });
+    }
+    let transaction = Transaction {
+        inputs: coins.into_keys().collect(),
+        outputs,
+    };
+    let txid = transaction.txid();
+    let transaction = wallet.authorize(transaction)?;
+    node.submit_transaction(&transaction).await?;
+    rebroadcast::track(&transaction);
+    Ok(txid)
+}
+
+// Like register_name, but the registration output goes to owner, while the wallet only pays the
+// fee and price. Since a key can never be registered again, the sponsoring wallet keeps no claim on it.
+pub async fn register_name_for(
+    node: &Node,
+    wallet: &Wallet,
+    state: &StateReader,
+    key: Hash,
+    value: Hash,
+    owner: Address,
+    fee: u64,
+    policy: SpendPolicy,
+) -> anyhow::Result<()> {
+    let outputs = vec![Output {
+        address: owner,
+        content: Content::Custom(BitName::KeyValue { key, value }),
+    }];
+    register(node, wallet, state, key, outputs, fee, policy).await
+}
+
+// Like register_name, but the name is bound to the onion service that signed record, in the same
+// transaction, since a binding is only valid alongside its key's registration.
+pub async fn register_name_with_onion(
+    node: &Node,
+    wallet: &Wallet,
+    state: &StateReader,
+    value: Hash,
+    record: &OnionRecord,
+    fee: u64,
+    policy: SpendPolicy,
+) -> anyhow::Result<()> {
+    let owner = wallet.get_new_address()?;
+    let outputs = vec![
+        Output {
+            address: owner,
+            content: Content::Custom(BitName::KeyValue {
+                key: record.key,
+                value,
+            }),
+        },
+        Output {
+            address: owner,
+            content: Content::Custom(BitName::Versioned {
+                version: ONION_VERSION,
+                payload: record.encode(),
+            }),
+        },
+    ];
+    register(node, wallet, state, record.key, outputs, fee, policy).await
+}
+
+// Like register_name, but with TLSA records pinning the certificates of the name's host. They
+// have to go into the registration, records added later are ignored.
+pub async fn register_name_with_tlsa(
+    node: &Node,
+    wallet: &Wallet,
+    state: &StateReader,
+    key: Hash,
+    value: Hash,
+    records: Vec<TlsaRecord>,
+    fee: u64,
+    policy: SpendPolicy,
+) -> anyhow::Result<()> {
+    if records.iter().any(|record| record.key != key) {
+        anyhow::bail!("every TLSA record must be for the registered key");
+    }
+    let owner = wallet.get_new_address()?;
+    let mut outputs = vec![Output {
+        address: owner,
+        content: Content::Custom(BitName::KeyValue { key, value }),
+    }];
+    for record in records {
+        outputs.push(Output {
+            address: owner,
+            content: Content::Custom(BitName::Versioned {
+                version: TLSA_VERSION,
+                payload: record.encode(),
+            }),
+        });
+    }
+    register(node, wallet, state, key, outputs, fee, policy).await
+}
+
+// Like register_name, but with payment aliases, so user@name pays the user's address. They have
+// to go into the registration, aliases added later are ignored.
+pub async fn register_name_with_aliases(
+    node: &Node,
+    wallet: &Wallet,
+    state: &StateReader,
+    key: Hash,
+    value: Hash,
+    aliases: BTreeMap<String, Address>,
+    fee: u64,
+    policy: SpendPolicy,
+) -> anyhow::Result<()> {
+    let owner = wallet.get_new_address()?;
+    let mut outputs = vec![Output {
+        address: owner,
+        content: Content::Custom(BitName::KeyValue { key, value }),
+    }];
+    for (user, address) in aliases {
+        let record = AliasRecord { key, user, address };
+        outputs.push(Output {
+            address: owner,
+            content: Content::Custom(BitName::Versioned {
+                version: ALIAS_VERSION,
+                payload: record.encode(),
+            }),
+        });
+    }
+    register(node, wallet, state, key, outputs, fee, policy).await
+}
+
+// Like register_name, but with attestations that the registrant controls addresses on other
+// sidechains. They have to go into the registration and verify, others are ignored.
+pub async fn register_name_with_attestations(
+    node: &Node,
+    wallet: &Wallet,
+    state: &StateReader,
+    key: Hash,
+    value: Hash,
+    attestations: Vec<AttestationRecord>,
+    fee: u64,
+    policy: SpendPolicy,
+) -> anyhow::Result<()> {
+    if attestations.iter().any(|record| record.key != key) {
+        anyhow::bail!("every attestation must be for the registered key");
+    }
+    if !attestations.iter().all(AttestationRecord::verify) {
+        anyhow::bail!("an attestation's signature or address doesn't match its public key");
+    }
+    let owner = wallet.get_new_address()?;
+    let mut outputs = vec![Output {
+        address: owner,
+        content: Content::Custom(BitName::KeyValue { key, value }),
+    }];
+    for record in attestations {
+        outputs.push(Output {
+            address: owner,
+            content: Content::Custom(BitName::Versioned {
+                version: ATTESTATION_VERSION,
+                payload: record.encode(),
+            }),
+        });
+    }
+    register(node, wallet, state, key, outputs, fee, policy).await
+}
+
+// Like register_name, but binding mainchain addresses to the name, each with its signmessage
+// proof. Once the main_address deployment is active a registration with a binding that doesn't
+// verify is invalid, so they are checked here first.
+pub async fn register_name_with_main_addresses(
+    node: &Node,
+    wallet: &Wallet,
+    state: &StateReader,
+    key: Hash,
+    value: Hash,
+    records: Vec<MainAddressRecord>,
+    fee: u64,
+    policy: SpendPolicy,
+) -> anyhow::Result<()> {
+    if records.iter().any(|record| record.key != key) {
+        anyhow::bail!("every mainchain address binding must be for the registered key");
+    }
+    if let Some(record) = records.iter().find(|record| !record.verify()) {
+        anyhow::bail!(
+            "the signature for {} isn't a valid signmessage signature of {:?} by it",
+            record.address,
+            crate::main_address::proof_message(&key)
+        );
+    }
+    let owner = wallet.get_new_address()?;
+    let mut outputs = vec![Output {
+        address: owner,
+        content: Content::Custom(BitName::KeyValue { key, value }),
+    }];
+    for record in records {
+        outputs.push(Output {
+            address: owner,
+            content: Content::Custom(BitName::Versioned {
+                version: MAIN_ADDRESS_VERSION,
+                payload: record.encode(),
+            }),
+        });
+    }
+    register(node, wallet, state, key, outputs, fee, policy).await
+}
+
+// Like register_name, but with typed records. Once the records deployment is active a
+// registration with a record that doesn't validate is invalid, so they are checked here first.
+pub async fn register_name_with_records(
+    node: &Node,
+    wallet: &Wallet,
+    state: &StateReader,
+    key: Hash,
+    value: Hash,
+    records: Vec<Record>,
+    fee: u64,
+    policy: SpendPolicy,
+) -> anyhow::Result<()> {
+    for record in &records {
+        record.validate()?;
+    }
+    let owner = wallet.get_new_address()?;
+    let mut outputs = vec![Output {
+        address: owner,
+        content: Content::Custom(BitName::KeyValue { key, value }),
+    }];
+    for record in records {
+        outputs.push(Output {
+            address: owner,
+            content: Content::Custom(BitName::Versioned {
+                version: RECORDS_VERSION,
+                payload: TypedRecord { key, record }.encode(),
+            }),
+        });
+    }
+    register(node, wallet, state, key, outputs, fee, policy).await
+}
+
+// Like register_name, but binding identity key public_key to the name. Later keys have to be
+// rotated to with rotate_identity_key.
+pub async fn register_name_with_identity(
+    node: &Node,
+    wallet: &Wallet,
+    state: &StateReader,
+    key: Hash,
+    value: Hash,
+    public_key: PublicKey,
+    fee: u64,
+    policy: SpendPolicy,
+) -> anyhow::Result<()> {
+    let owner = wallet.get_new_address()?;
+    let record = IdentityRecord {
+        key,
+        public_key,
+        signature: None,
+    };
+    let outputs = vec![
+        Output {
+            address: owner,
+            content: Content::Custom(BitName::KeyValue { key, value }),
+        },
+        Output {
+            address: owner,
+            content: Content::Custom(BitName::Versioned {
+                version: IDENTITY_VERSION,
+                payload: record.encode(),
+            }),
+        },
+    ];
+    register(node, wallet, state, key, outputs, fee, policy).await
+}
+
+// Submit outputs registering key, paying fee plus the registration price.
+async fn register(
+    node: &Node,
+    wallet: &Wallet,
+    state: &StateReader,
+    key: Hash,
+    outputs: Vec<Output<BitName>>,
+    fee: u64,
+    policy: SpendPolicy,
+) -> anyhow::Result<()> {
+    let mut reservation = Reservation(vec![]);
+    if !try_reserve(key, &mempool_keys(node)?, &mut reservation) {
+        anyhow::bail!("a registration for this key is already pending");
+    }
+    let fee = fee + registration_burn(node, state, 1)?;
+    fund_and_submit(node, wallet, outputs, fee, policy).await?;
+    Ok(())
+}
@@ -0,0 +1,146 @@
+use crate::notify::{self, Event, NameChange};
+use ddk::types::Hash;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashSet, VecDeque};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use tokio::sync::broadcast;
+
+// Number of past events kept for get_watch_events polling.
+const MAX_EVENTS: usize = 1000;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum WatchEventKind {
+    Registered,
+    Updated,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct WatchEvent {
+    // Increases by one with every event, so pollers can ask for everything after the last one
+    // they saw.
+    pub sequence: u64,
+    pub kind: WatchEventKind,
+    pub change: NameChange,
+}
+
+// Names someone wants to hear about, whether or not the wallet owns them. The set of names is
+// persisted in watchlist.dat, the events themselves only live in memory.
+pub struct Watchlist {
+    path: PathBuf,
+    keys: Mutex<HashSet<Hash>>,
+    events: Mutex<VecDeque<WatchEvent>>,
+    sender: broadcast::Sender<WatchEvent>,
+}
+
+impl Watchlist {
+    pub fn open(datadir: &Path) -> anyhow::Result<Self> {
+        let path = datadir.join("watchlist.dat");
+        let keys: Vec<Hash> = if path.exists() {
+            bincode::deserialize(&std::fs::read(&path)?)?
+        } else {
+            vec![]
+        };
+        Ok(Self {
+            path,
+            keys: Mutex::new(keys.into_iter().collect()),
+            events: Mutex::new(VecDeque::new()),
+            sender: broadcast::channel(MAX_EVENTS).0,
+        })
+    }
+
+    pub fn watch(&self, key: Hash) -> anyhow::Result<()> {
+        let mut keys = self.keys.lock().unwrap();
+        keys.insert(key);
+        self.save(&keys)
+    }
+
+    pub fn unwatch(&self, key: &Hash) -> anyhow::Result<()> {
+        let mut keys = self.keys.lock().unwrap();
+        keys.remove(key);
+        self.save(&keys)
+    }
+
+    pub fn list(&self) -> Vec<Hash> {
+        self.keys.lock().unwrap().iter().copied().collect()
+    }
+
+    // Events with a sequence number above since, oldest first.
+    pub fn events_since(&self, since: u64) -> Vec<WatchEvent> {
+        let events = self.events.lock().unwrap();
+        events
+            .iter()
+            .filter(|event| event.sequence > since)
+            .cloned()
+            .collect()
+    }
+
+    pub fn subscribe(&self) -> broadcast::Receiver<WatchEvent> {
+        self.sender.subscribe()
+    }
+
+    fn save(&self, keys: &HashSet<Hash>) -> anyhow::Result<()> {
+        let keys: Vec<&Hash> = keys.iter().collect();
+        let tmp_path = self.path.with_extension("dat.tmp");
+        std::fs::write(&tmp_path, bincode::serialize(&keys)?)?;
+        std::fs::rename(&tmp_path, &self.path)?;
+        Ok(())
+    }
+
+    fn record(&self, change: NameChange) -> Option<WatchEvent> {
+        if !self.keys.lock().unwrap().contains(&change.key) {
+            return None;
+        }
+        let kind = match change.previous {
+            None => WatchEventKind::Registered,
+            Some(_) => WatchEventKind::Updated,
+        };
+        let mut events = self.events.lock().unwrap();
+        let sequence = events.back().map_or(1, |event| event.sequence + 1);
+        let event = WatchEvent {
+            sequence,
+            kind,
+            change,
+        };
+        events.push_back(event.clone());
+        if events.len() > MAX_EVENTS {
+            events.pop_front();
+        }
+        // No receivers just means no WebSocket subscribers right now.
+        let _ = self.sender.send(event.clone());
+        Some(event)
+    }
+}
+
+// Record every change to a watched name, and POST it as JSON to webhook if one is configured.
+// Only plain http webhooks are supported.
+pub async fn run(watchlist: std::sync::Arc<Watchlist>, webhook: Option<hyper::Uri>) {
+    let client = hyper::Client::new();
+    let mut events = notify::subscribe();
+    loop {
+        let change = match events.recv().await {
+            Ok(Event::Name(change)) => change,
+            Ok(_) => continue,
+            Err(broadcast::error::RecvError::Lagged(missed)) => {
+                log::warn!("watchlist missed {missed} events");
+                continue;
+            }
+            Err(broadcast::error::RecvError::Closed) => return,
+        };
+        let event = match watchlist.record(change) {
+            Some(event) => event,
+            None => continue,
+        };
+        if let Some(webhook) = &webhook {
+            let request = hyper::Request::post(webhook.clone())
+                .header(hyper::header::CONTENT_TYPE, "application/json")
+                .body(hyper::Body::from(
+                    serde_json::to_vec(&event).expect("watch events serialize to JSON"),
+                ))
+                .expect("webhook request is valid");
+            if let Err(err) = client.request(request).await {
+                log::warn!("failed to call watchlist webhook {webhook}: {err}");
+            }
+        }
+    }
+}
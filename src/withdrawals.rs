@@ -0,0 +1,118 @@
+use crate::notify::{self, Event};
+use crate::rpc_api::{Withdrawal, WithdrawalStatus};
+use crate::Node;
+use ddk::types::{Address, OutPoint, Txid};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use tokio::sync::broadcast;
+
+// Withdrawals created by this node's wallets, persisted as a bincode encoded list in
+// withdrawals.dat. Their status is looked up from the node every time it is asked for, not
+// stored.
+pub struct Withdrawals {
+    path: PathBuf,
+    withdrawals: Mutex<Vec<Withdrawal>>,
+}
+
+impl Withdrawals {
+    pub fn open(datadir: &Path) -> anyhow::Result<Self> {
+        let path = datadir.join("withdrawals.dat");
+        let withdrawals = if path.exists() {
+            bincode::deserialize(&std::fs::read(&path)?)?
+        } else {
+            vec![]
+        };
+        Ok(Self {
+            path,
+            withdrawals: Mutex::new(withdrawals),
+        })
+    }
+
+    pub fn add(&self, withdrawal: Withdrawal) -> anyhow::Result<()> {
+        let mut withdrawals = self.withdrawals.lock().unwrap();
+        withdrawals.push(withdrawal);
+        let tmp_path = self.path.with_extension("dat.tmp");
+        std::fs::write(&tmp_path, bincode::serialize(&*withdrawals)?)?;
+        std::fs::rename(&tmp_path, &self.path)?;
+        Ok(())
+    }
+
+    // Every recorded withdrawal with its current status, oldest first.
+    pub fn list(&self, node: &Node) -> anyhow::Result<Vec<(Withdrawal, WithdrawalStatus)>> {
+        let withdrawals = self.withdrawals.lock().unwrap().clone();
+        let mempool: Vec<Txid> = node
+            .get_all_transactions()?
+            .iter()
+            .map(|transaction| transaction.transaction.txid())
+            .collect();
+        let addresses: Vec<Address> = withdrawals
+            .iter()
+            .map(|withdrawal| withdrawal.address)
+            .collect();
+        let utxos = node.get_utxos_by_addresses(&addresses)?;
+        let mut statuses = vec![];
+        for withdrawal in withdrawals {
+            let unconfirmed = match withdrawal.outpoint {
+                OutPoint::Regular { txid, .. } => mempool.contains(&txid),
+                _ => false,
+            };
+            let status = if utxos.contains_key(&withdrawal.outpoint) {
+                WithdrawalStatus::Pending
+            } else if unconfirmed {
+                WithdrawalStatus::Unconfirmed
+            } else {
+                WithdrawalStatus::Bundled
+            };
+            statuses.push((withdrawal, status));
+        }
+        Ok(statuses)
+    }
+}
+
+// Watch the status of recorded withdrawals after every block, and raise an alert when one that
+// was bundled is back to Pending, which is what a bundle rejected by the mainchain looks like.
+//
+// ddk keeps the pending bundle and its mainchain votes to itself, so a failed bundle can only be
+// noticed through the withdrawals it gives back.
+pub async fn run(withdrawals: Arc<Withdrawals>, node: Arc<Node>) {
+    let mut events = notify::subscribe();
+    let mut previous: HashMap<OutPoint, WithdrawalStatus> = HashMap::new();
+    loop {
+        match events.recv().await {
+            Ok(Event::Block { .. }) | Err(broadcast::error::RecvError::Lagged(_)) => {}
+            Ok(_) => continue,
+            Err(broadcast::error::RecvError::Closed) => return,
+        }
+        let statuses = match withdrawals.list(&node) {
+            Ok(statuses) => statuses,
+            Err(err) => {
+                log::error!("failed to get withdrawal statuses: {err}");
+                continue;
+            }
+        };
+        let mut returned = vec![];
+        for (withdrawal, status) in &statuses {
+            if status == &WithdrawalStatus::Pending
+                && previous.get(&withdrawal.outpoint) == Some(&WithdrawalStatus::Bundled)
+            {
+                returned.push(format!(
+                    "{} sats to {}",
+                    withdrawal.value, withdrawal.main_address
+                ));
+            }
+        }
+        if !returned.is_empty() {
+            let message = format!(
+                "withdrawal bundle failed, back to pending: {}",
+                returned.join(", ")
+            );
+            log::warn!("{message}");
+            notify::publish(Event::Alert(message));
+        }
+        previous = statuses
+            .into_iter()
+            .map(|(withdrawal, status)| (withdrawal.outpoint, status))
+            .collect();
+    }
+}
@@ -0,0 +1,396 @@
+// Differential tests of block validation.
+//
+// Random blocks with every kind of output BitNamesState has rules for, valid or not, are validated
+// by the heed backed BitNamesState and by a reference implementation of the consensus rules over
+// in-memory maps, written from the rules rather than from bitnames.rs. Both have to make the same
+// accept/reject decision on every block and agree on the value of every key afterwards. Where
+// state_machine.rs checks the name databases through reorgs, this checks the rules themselves, so
+// a rule that changes in one place and not the other fails here.
+//
+// The rules of content versions behind a deployment aren't covered, since no deployment becomes
+// active within a sequence.
+
+use bitnames::bitnames::{BitName, BitNamesState};
+use bitnames::chain_params::{self, ChainParams};
+use bitnames::tlsa::TLSA_VERSION;
+use ddk::authorization::Authorization;
+use ddk::heed;
+use ddk::node::State;
+use ddk::types::{
+    Address, AuthorizedTransaction, Body, Content, Hash, OutPoint, Output, Transaction,
+};
+use proptest::prelude::*;
+use std::collections::{BTreeMap, HashMap, HashSet};
+
+const PARAMS: &ChainParams = &chain_params::REGTEST;
+// Keys are drawn from a small pool, so that blocks often try to register a key twice.
+const KEYS: u8 = 16;
+// Fee a transaction that doesn't underpay pays on top of the registration price.
+const FEE: u64 = 1000;
+// A content version no rules are assigned to.
+const UNASSIGNED_VERSION: u8 = 255;
+
+#[derive(Clone, Debug)]
+enum Out {
+    Register { key: u8, value: u8 },
+    Value(u64),
+    // A record of version with a payload of size bytes, starting with the key for TLSA records.
+    Record { version: u8, key: u8, size: usize },
+    Signal,
+}
+
+#[derive(Clone, Debug)]
+struct Tx {
+    outputs: Vec<Out>,
+    // Pay one sat less than the registration price instead of the price and FEE.
+    underpay: bool,
+}
+
+#[derive(Clone, Debug)]
+struct Block {
+    transactions: Vec<Tx>,
+    // The coinbase claims this many sats more than the fees left after the burn, if there is one.
+    coinbase: Option<u64>,
+}
+
+#[derive(Clone, Debug)]
+enum Op {
+    Connect(Block),
+    Disconnect,
+}
+
+fn out() -> impl Strategy<Value = Out> {
+    let dust = PARAMS.dust_limit;
+    let size = prop_oneof![
+        0..64usize,
+        PARAMS.max_record_size - 1..=PARAMS.max_record_size + 1,
+    ];
+    prop_oneof![
+        8 => (0..KEYS, any::<u8>()).prop_map(|(key, value)| Out::Register { key, value }),
+        3 => prop_oneof![4 => dust..dust * 10, 1 => 0..dust].prop_map(Out::Value),
+        3 => (
+            prop_oneof![3 => Just(UNASSIGNED_VERSION), 3 => Just(TLSA_VERSION), 1 => Just(0)],
+            0..KEYS,
+            size,
+        )
+            .prop_map(|(version, key, size)| Out::Record { version, key, size }),
+        1 => Just(Out::Signal),
+    ]
+}
+
+fn tx() -> impl Strategy<Value = Tx> {
+    (
+        prop::collection::vec(out(), 1..6),
+        prop::bool::weighted(0.1),
+    )
+        .prop_map(|(outputs, underpay)| Tx { outputs, underpay })
+}
+
+fn op() -> impl Strategy<Value = Op> {
+    let block = (
+        prop::collection::vec(tx(), 0..4),
+        prop::option::weighted(0.3, 0..2u64),
+    )
+        .prop_map(|(transactions, coinbase)| Block {
+            transactions,
+            coinbase,
+        });
+    prop_oneof![4 => block.prop_map(Op::Connect), 1 => Just(Op::Disconnect)]
+}
+
+fn key(index: u8) -> Hash {
+    ddk::types::hash(&("key", index))
+}
+
+fn value(value: u8) -> Hash {
+    ddk::types::hash(&("value", value))
+}
+
+fn payload(version: u8, key_index: u8, size: usize) -> Vec<u8> {
+    let mut payload = if version == TLSA_VERSION {
+        key(key_index).to_vec()
+    } else {
+        vec![]
+    };
+    payload.resize(size.max(payload.len()), 0);
+    payload
+}
+
+fn registrations(tx: &Tx) -> Vec<u8> {
+    tx.outputs
+        .iter()
+        .filter_map(|out| match out {
+            Out::Register { key, .. } => Some(*key),
+            _ => None,
+        })
+        .collect()
+}
+
+// What tx burns and what it pays as fee in a block at height.
+fn burn_and_fee(tx: &Tx, height: u32) -> (u64, u64) {
+    let burn = registrations(tx).len() as u64 * PARAMS.registration_price(height);
+    let fee = if tx.underpay {
+        burn.saturating_sub(1)
+    } else {
+        burn + FEE
+    };
+    (burn, fee)
+}
+
+fn coinbase_value(block: &Block, height: u32) -> Option<u64> {
+    let (burned, fees) = block
+        .transactions
+        .iter()
+        .map(|tx| burn_and_fee(tx, height))
+        .fold((0, 0), |(burned, fees), (burn, fee)| {
+            (burned + burn, fees + fee)
+        });
+    block
+        .coinbase
+        .map(|extra| fees.saturating_sub(burned) + extra)
+}
+
+// The reference implementation.
+#[derive(Default)]
+struct Reference {
+    names: BTreeMap<Hash, Hash>,
+    // Keys registered by every connected block, the tip last.
+    blocks: Vec<Vec<Hash>>,
+}
+
+impl Reference {
+    fn is_valid_transaction(&self, tx: &Tx, height: u32) -> bool {
+        let custom = tx
+            .outputs
+            .iter()
+            .filter(|out| !matches!(out, Out::Value(_)))
+            .count();
+        if custom > PARAMS.max_custom_outputs {
+            return false;
+        }
+        let mut key_record_sizes: HashMap<u8, usize> = HashMap::new();
+        for out in &tx.outputs {
+            match out {
+                Out::Register { key: index, .. } => {
+                    if self.names.contains_key(&key(*index)) {
+                        return false;
+                    }
+                }
+                Out::Value(value) => {
+                    if *value < PARAMS.dust_limit {
+                        return false;
+                    }
+                }
+                Out::Record { version, key, size } => {
+                    let size = payload(*version, *key, *size).len();
+                    if *version == 0 || size > PARAMS.max_record_size {
+                        return false;
+                    }
+                    if *version == TLSA_VERSION {
+                        let total = key_record_sizes.entry(*key).or_default();
+                        *total += size;
+                        if *total > PARAMS.max_name_records_size {
+                            return false;
+                        }
+                    }
+                }
+                Out::Signal => return false,
+            }
+        }
+        let (burn, fee) = burn_and_fee(tx, height);
+        fee >= burn
+    }
+
+    fn is_valid(&self, block: &Block, height: u32) -> bool {
+        let mut keys = HashSet::new();
+        let mut fees = 0;
+        let mut burned = 0;
+        for tx in &block.transactions {
+            if !self.is_valid_transaction(tx, height) {
+                return false;
+            }
+            let (burn, fee) = burn_and_fee(tx, height);
+            burned += burn;
+            fees += fee;
+            if !registrations(tx)
+                .into_iter()
+                .all(|index| keys.insert(index))
+            {
+                return false;
+            }
+        }
+        if keys.len() > PARAMS.max_registrations_per_block {
+            return false;
+        }
+        coinbase_value(block, height).map_or(true, |coinbase| coinbase <= fees - burned)
+    }
+
+    fn connect(&mut self, block: &Block) {
+        let mut keys = vec![];
+        for tx in &block.transactions {
+            for out in &tx.outputs {
+                if let Out::Register {
+                    key: index,
+                    value: registered,
+                } = out
+                {
+                    self.names.insert(key(*index), value(*registered));
+                    keys.push(key(*index));
+                }
+            }
+        }
+        self.blocks.push(keys);
+    }
+
+    fn disconnect(&mut self) {
+        for key in self.blocks.pop().expect("there is a block to disconnect") {
+            self.names.remove(&key);
+        }
+    }
+}
+
+struct Harness {
+    ddk_state: ddk::state::State<Authorization, BitName>,
+    state: BitNamesState,
+    // Number of funding utxos created so far, so that every one has a distinct outpoint.
+    funded: u64,
+}
+
+impl Harness {
+    // Build the body of block, funding each transaction with a new utxo worth its value outputs
+    // and its fee.
+    fn body(
+        &mut self,
+        txn: &mut heed::RwTxn,
+        height: u32,
+        block: &Block,
+    ) -> Body<Authorization, BitName> {
+        let mut authorized = vec![];
+        for tx in &block.transactions {
+            let address = Address::from(ddk::types::hash(&("address", self.funded)));
+            let input = OutPoint::Regular {
+                txid: ddk::types::hash(&("funding", self.funded)).into(),
+                vout: 0,
+            };
+            self.funded += 1;
+            let mut value_out = 0;
+            let outputs = tx
+                .outputs
+                .iter()
+                .map(|out| {
+                    let content = match out {
+                        Out::Register {
+                            key: index,
+                            value: registered,
+                        } => Content::Custom(BitName::KeyValue {
+                            key: key(*index),
+                            value: value(*registered),
+                        }),
+                        Out::Value(value) => {
+                            value_out += value;
+                            Content::Value(*value)
+                        }
+                        Out::Record { version, key, size } => Content::Custom(BitName::Versioned {
+                            version: *version,
+                            payload: payload(*version, *key, *size),
+                        }),
+                        Out::Signal => Content::Custom(BitName::Signal { bits: 1 }),
+                    };
+                    Output { address, content }
+                })
+                .collect();
+            let (_, fee) = burn_and_fee(tx, height);
+            let funding = Output {
+                address,
+                content: Content::Value(value_out + fee),
+            };
+            self.ddk_state.utxos.put(txn, &input, &funding).unwrap();
+            // BitNamesState doesn't check authorizations, ddk does that before calling it.
+            authorized.push(AuthorizedTransaction {
+                transaction: Transaction {
+                    inputs: vec![input],
+                    outputs,
+                },
+                authorizations: vec![],
+            });
+        }
+        let coinbase = coinbase_value(block, height)
+            .map(|value| Output {
+                address: Address::from(ddk::types::hash(&("coinbase", height))),
+                content: Content::Value(value),
+            })
+            .into_iter()
+            .collect();
+        Body::new(authorized, coinbase)
+    }
+}
+
+fn env() -> &'static heed::Env {
+    static ENV: std::sync::OnceLock<heed::Env> = std::sync::OnceLock::new();
+    ENV.get_or_init(|| {
+        chain_params::select(PARAMS);
+        let path = std::env::temp_dir().join("bitnames-differential");
+        let _ = std::fs::remove_dir_all(&path);
+        std::fs::create_dir_all(&path).unwrap();
+        heed::EnvOpenOptions::new()
+            .map_size(256 * 1024 * 1024)
+            .max_dbs(ddk::state::State::<Authorization, BitName>::NUM_DBS + BitNamesState::NUM_DBS)
+            .open(&path)
+            .unwrap()
+    })
+}
+
+proptest! {
+    #[test]
+    fn state_agrees_with_reference(ops in prop::collection::vec(op(), 1..40)) {
+        let env = env();
+        let mut harness = Harness {
+            ddk_state: ddk::state::State::new(env).unwrap(),
+            state: BitNamesState::new(env).unwrap(),
+            funded: 0,
+        };
+        let mut reference = Reference::default();
+        // Every case runs in one write transaction that is never committed, so each starts from
+        // an empty state.
+        let mut txn = env.write_txn().unwrap();
+        for op in ops {
+            let height = reference.blocks.len() as u32;
+            match op {
+                Op::Connect(block) => {
+                    let body = harness.body(&mut txn, height + 1, &block);
+                    let result = harness
+                        .state
+                        .validate_body(&txn, height + 1, &harness.ddk_state, &body);
+                    prop_assert_eq!(
+                        result.is_ok(),
+                        reference.is_valid(&block, height + 1),
+                        "{:?}: {:?}",
+                        block,
+                        result
+                    );
+                    if result.is_ok() {
+                        harness
+                            .state
+                            .connect_body(&mut txn, height + 1, &harness.ddk_state, &body)
+                            .unwrap();
+                        reference.connect(&block);
+                    }
+                }
+                Op::Disconnect if height == 0 => continue,
+                Op::Disconnect => {
+                    harness.state.disconnect_body(&mut txn, height).unwrap();
+                    reference.disconnect();
+                }
+            }
+            for index in 0..KEYS {
+                let key = key(index);
+                prop_assert_eq!(
+                    harness.state.get_value(&txn, &key).unwrap(),
+                    reference.names.get(&key).copied()
+                );
+            }
+        }
+        let divergences = harness.state.verify_state(&txn).unwrap();
+        prop_assert!(divergences.is_empty(), "{:?}", divergences);
+    }
+}
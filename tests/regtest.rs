@@ -0,0 +1,211 @@
+// Functional tests against a regtest mainchain.
+//
+// These need a drivechain enabled bitcoind running in regtest mode on localhost:18443, with the
+// BitNames sidechain slot activated and the rpc credentials ddk uses, user and password, so they
+// are ignored by default. Run them with
+//
+// cargo test --test regtest -- --ignored
+
+use base64::Engine;
+use jsonrpsee::core::client::ClientT;
+use jsonrpsee::http_client::{HeaderMap, HeaderValue, HttpClient, HttpClientBuilder};
+use jsonrpsee::rpc_params;
+use sha2::{Digest, Sha256};
+use std::process::{Child, Command};
+use std::time::Duration;
+
+const MAINCHAIN_URL: &str = "http://127.0.0.1:18443";
+// Slot of the regtest sidechain, see network.rs.
+const SIDECHAIN_NUMBER: u32 = 0;
+
+// Call method on the mainchain node. bitcoind doesn't answer with a jsonrpc version, which the
+// jsonrpsee client insists on, so this is a plain http request.
+async fn mainchain(method: &str, params: serde_json::Value) -> serde_json::Value {
+    let credentials = base64::engine::general_purpose::STANDARD.encode("user:password");
+    let request = hyper::Request::post(MAINCHAIN_URL)
+        .header("Authorization", format!("Basic {credentials}"))
+        .body(hyper::Body::from(
+            serde_json::json!({"id": 0, "method": method, "params": params}).to_string(),
+        ))
+        .unwrap();
+    let response = hyper::Client::new().request(request).await.unwrap();
+    let bytes = hyper::body::to_bytes(response.into_body()).await.unwrap();
+    let response: serde_json::Value = serde_json::from_slice(&bytes).unwrap();
+    assert!(response["error"].is_null(), "{method} failed: {response}");
+    response["result"].clone()
+}
+
+// Mainchain address depositing to address on the sidechain, with its checksum.
+fn deposit_address(address: &str) -> String {
+    let prefix = format!("s{SIDECHAIN_NUMBER}_{address}_");
+    let checksum = hex::encode(Sha256::digest(prefix.as_bytes()));
+    format!("{prefix}{}", &checksum[..6])
+}
+
+struct TestNode {
+    process: Child,
+    client: HttpClient,
+}
+
+impl TestNode {
+    fn spawn(name: &str, net_port: u16, rpc_port: u16, connect: Option<u16>) -> Self {
+        let datadir = std::env::temp_dir().join(format!("bitnames-regtest-{name}"));
+        let _ = std::fs::remove_dir_all(&datadir);
+        std::fs::create_dir_all(&datadir).unwrap();
+        let mut command = Command::new(env!("CARGO_BIN_EXE_bitnames"));
+        command
+            .arg("--regtest")
+            .arg("--datadir")
+            .arg(&datadir)
+            .arg("--net-port")
+            .arg(net_port.to_string())
+            .arg("--rpc-port")
+            .arg(rpc_port.to_string())
+            .arg("--rpc-user")
+            .arg("test")
+            .arg("--rpc-password")
+            .arg("test");
+        if let Some(peer_port) = connect {
+            command
+                .arg("--connect")
+                .arg(format!("127.0.0.1:{peer_port}"));
+        }
+        let process = command.spawn().unwrap();
+        let credentials = base64::engine::general_purpose::STANDARD.encode("test:test");
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            "Authorization",
+            HeaderValue::from_str(&format!("Basic {credentials}")).unwrap(),
+        );
+        let client = HttpClientBuilder::default()
+            .set_headers(headers)
+            .build(format!("http://127.0.0.1:{rpc_port}"))
+            .unwrap();
+        Self { process, client }
+    }
+
+    async fn wait_for_rpc(&self) {
+        for _ in 0..50 {
+            if self.height().await.is_ok() {
+                return;
+            }
+            tokio::time::sleep(Duration::from_millis(200)).await;
+        }
+        panic!("node rpc never came up");
+    }
+
+    async fn height(&self) -> Result<u32, jsonrpsee::core::Error> {
+        self.client.request("get_height", rpc_params![]).await
+    }
+
+    async fn generate(&self, n: u32) {
+        let () = self
+            .client
+            .request("generate", rpc_params![n])
+            .await
+            .unwrap();
+    }
+
+    async fn wait_for_height(&self, height: u32) {
+        for _ in 0..50 {
+            if self.height().await.unwrap() >= height {
+                return;
+            }
+            tokio::time::sleep(Duration::from_millis(200)).await;
+        }
+        panic!("node never reached height {height}");
+    }
+
+    // Deposit sats from the mainchain wallet to this node's wallet. The deposit is credited once
+    // a block is generated on top of the mainchain block that has it.
+    async fn deposit(&self, sats: u64) {
+        let address: String = self
+            .client
+            .request("get_new_address", rpc_params![])
+            .await
+            .unwrap();
+        let btc = |sats: u64| sats as f64 / 100_000_000.0;
+        mainchain(
+            "createsidechaindeposit",
+            serde_json::json!([
+                SIDECHAIN_NUMBER,
+                deposit_address(&address),
+                btc(sats),
+                btc(10_000)
+            ]),
+        )
+        .await;
+    }
+
+    async fn value(&self, key: [u8; 32]) -> Option<[u8; 32]> {
+        self.client
+            .request("get_value", rpc_params![key])
+            .await
+            .unwrap()
+    }
+}
+
+impl Drop for TestNode {
+    fn drop(&mut self) {
+        let _ = self.process.kill();
+    }
+}
+
+#[tokio::test]
+#[ignore]
+async fn generated_blocks_reach_peer() {
+    let a = TestNode::spawn("a", 24100, 26100, None);
+    a.wait_for_rpc().await;
+    let b = TestNode::spawn("b", 24101, 26101, Some(24100));
+    b.wait_for_rpc().await;
+
+    let start = a.height().await.unwrap();
+    a.generate(5).await;
+    assert_eq!(a.height().await.unwrap(), start + 5);
+    b.wait_for_height(start + 5).await;
+}
+
+#[tokio::test]
+#[ignore]
+async fn conflicting_registrations_settle_the_same_on_both_nodes() {
+    let a = TestNode::spawn("conflict-a", 24102, 26102, None);
+    a.wait_for_rpc().await;
+    let b = TestNode::spawn("conflict-b", 24103, 26103, Some(24102));
+    b.wait_for_rpc().await;
+
+    // Coins for the deposits, mainchain coinbases mature after 100 blocks.
+    let address = mainchain("getnewaddress", serde_json::json!([])).await;
+    mainchain("generatetoaddress", serde_json::json!([101, address])).await;
+    a.deposit(1_000_000).await;
+    b.deposit(1_000_000).await;
+    a.generate(2).await;
+    let height = a.height().await.unwrap();
+    b.wait_for_height(height).await;
+
+    // Both nodes register the same key with a different value before either sees a block with
+    // the other's registration. At most one of them can make it into the chain.
+    let key = [7; 32];
+    let ours = [1; 32];
+    let theirs = [2; 32];
+    let () = a
+        .client
+        .request("register_name", rpc_params![key, ours, 1_000])
+        .await
+        .unwrap();
+    let () = b
+        .client
+        .request("register_name", rpc_params![key, theirs, 1_000])
+        .await
+        .unwrap();
+    a.generate(1).await;
+    b.wait_for_height(height + 1).await;
+    let value = a.value(key).await;
+    assert!(value == Some(ours) || value == Some(theirs));
+    assert_eq!(b.value(key).await, value);
+
+    // The losing registration stays out of blocks built on either node.
+    b.generate(1).await;
+    a.wait_for_height(height + 2).await;
+    assert_eq!(a.value(key).await, value);
+    assert_eq!(b.value(key).await, value);
+}
@@ -0,0 +1,208 @@
+// Property tests of the name state machine.
+//
+// Random sequences of blocks and reorgs are applied to a heed backed BitNamesState and to a
+// reference model of the consensus rules kept in a BTreeMap. After every step both have to agree
+// on which blocks are valid and on the value of every key, and at the end of a sequence
+// verify_state has to find the databases consistent with the stored bodies.
+//
+// Names can only be registered, they are never updated, transferred or expire, so registering and
+// disconnecting blocks are the only operations there are.
+
+use bitnames::bitnames::{BitName, BitNamesState};
+use bitnames::chain_params;
+use ddk::authorization::Authorization;
+use ddk::heed;
+use ddk::node::State;
+use ddk::types::{
+    Address, AuthorizedTransaction, Body, Content, Hash, OutPoint, Output, Transaction,
+};
+use proptest::prelude::*;
+use std::collections::{BTreeMap, HashSet};
+
+// Keys are drawn from a small pool, so that sequences often try to register a key twice.
+const KEYS: u8 = 16;
+// Fee every transaction pays on top of the registration price.
+const FEE: u64 = 1000;
+
+// A transaction registering (key index, value) pairs.
+type Registrations = Vec<(u8, u8)>;
+
+#[derive(Clone, Debug)]
+enum Op {
+    // Try to connect a block with these transactions on top of the tip.
+    Connect(Vec<Registrations>),
+    // Disconnect the tip, as in a reorg.
+    Disconnect,
+}
+
+fn op() -> impl Strategy<Value = Op> {
+    let registrations = prop::collection::vec((0..KEYS, any::<u8>()), 1..3);
+    prop_oneof![
+        3 => prop::collection::vec(registrations, 0..4).prop_map(Op::Connect),
+        1 => Just(Op::Disconnect),
+    ]
+}
+
+fn key(index: u8) -> Hash {
+    ddk::types::hash(&("key", index))
+}
+
+fn value(value: u8) -> Hash {
+    ddk::types::hash(&("value", value))
+}
+
+// The consensus rules the state is checked against.
+#[derive(Default)]
+struct Model {
+    names: BTreeMap<Hash, Hash>,
+    // Keys registered by every connected block, the tip last.
+    blocks: Vec<Vec<Hash>>,
+}
+
+impl Model {
+    // A block is valid if no key it registers is registered already or twice within it.
+    fn is_valid(&self, transactions: &[Registrations]) -> bool {
+        let mut keys = HashSet::new();
+        transactions
+            .iter()
+            .flatten()
+            .all(|(index, _)| !self.names.contains_key(&key(*index)) && keys.insert(*index))
+    }
+
+    fn connect(&mut self, transactions: &[Registrations]) {
+        let mut keys = vec![];
+        for (index, registered) in transactions.iter().flatten() {
+            self.names.insert(key(*index), value(*registered));
+            keys.push(key(*index));
+        }
+        self.blocks.push(keys);
+    }
+
+    fn disconnect(&mut self) {
+        for key in self.blocks.pop().expect("there is a block to disconnect") {
+            self.names.remove(&key);
+        }
+    }
+}
+
+struct Harness {
+    ddk_state: ddk::state::State<Authorization, BitName>,
+    state: BitNamesState,
+    // Number of funding utxos created so far, so that every one has a distinct outpoint.
+    funded: u64,
+}
+
+impl Harness {
+    // Build a body for transactions, funding each of them with a new utxo that pays the
+    // registration price and FEE.
+    fn body(
+        &mut self,
+        txn: &mut heed::RwTxn,
+        height: u32,
+        transactions: &[Registrations],
+    ) -> Body<Authorization, BitName> {
+        let price = chain_params::selected().registration_price(height);
+        let mut authorized = vec![];
+        for registrations in transactions {
+            let address = Address::from(ddk::types::hash(&("address", self.funded)));
+            let input = OutPoint::Regular {
+                txid: ddk::types::hash(&("funding", self.funded)).into(),
+                vout: 0,
+            };
+            self.funded += 1;
+            let funding = Output {
+                address,
+                content: Content::Value(price * registrations.len() as u64 + FEE),
+            };
+            self.ddk_state.utxos.put(txn, &input, &funding).unwrap();
+            let outputs = registrations
+                .iter()
+                .map(|(index, registered)| Output {
+                    address,
+                    content: Content::Custom(BitName::KeyValue {
+                        key: key(*index),
+                        value: value(*registered),
+                    }),
+                })
+                .collect();
+            // BitNamesState doesn't check authorizations, ddk does that before calling it.
+            authorized.push(AuthorizedTransaction {
+                transaction: Transaction {
+                    inputs: vec![input],
+                    outputs,
+                },
+                authorizations: vec![],
+            });
+        }
+        Body::new(authorized, vec![])
+    }
+}
+
+fn env() -> &'static heed::Env {
+    static ENV: std::sync::OnceLock<heed::Env> = std::sync::OnceLock::new();
+    ENV.get_or_init(|| {
+        chain_params::select(&chain_params::REGTEST);
+        let path = std::env::temp_dir().join("bitnames-state-machine");
+        let _ = std::fs::remove_dir_all(&path);
+        std::fs::create_dir_all(&path).unwrap();
+        heed::EnvOpenOptions::new()
+            .map_size(256 * 1024 * 1024)
+            .max_dbs(ddk::state::State::<Authorization, BitName>::NUM_DBS + BitNamesState::NUM_DBS)
+            .open(&path)
+            .unwrap()
+    })
+}
+
+proptest! {
+    #[test]
+    fn state_matches_model(ops in prop::collection::vec(op(), 1..40)) {
+        let env = env();
+        let mut harness = Harness {
+            ddk_state: ddk::state::State::new(env).unwrap(),
+            state: BitNamesState::new(env).unwrap(),
+            funded: 0,
+        };
+        let mut model = Model::default();
+        // Every case runs in one write transaction that is never committed, so each starts from
+        // an empty state.
+        let mut txn = env.write_txn().unwrap();
+        for op in ops {
+            let height = model.blocks.len() as u32;
+            match op {
+                Op::Connect(transactions) => {
+                    let body = harness.body(&mut txn, height + 1, &transactions);
+                    let valid = harness
+                        .state
+                        .validate_body(&txn, height + 1, &harness.ddk_state, &body)
+                        .is_ok();
+                    prop_assert_eq!(valid, model.is_valid(&transactions));
+                    if valid {
+                        harness
+                            .state
+                            .connect_body(&mut txn, height + 1, &harness.ddk_state, &body)
+                            .unwrap();
+                        model.connect(&transactions);
+                    }
+                }
+                Op::Disconnect if height == 0 => continue,
+                Op::Disconnect => {
+                    harness.state.disconnect_body(&mut txn, height).unwrap();
+                    model.disconnect();
+                }
+            }
+            prop_assert_eq!(
+                harness.state.get_chain_stats(&txn).unwrap().height,
+                model.blocks.len() as u32
+            );
+            for index in 0..KEYS {
+                let key = key(index);
+                prop_assert_eq!(
+                    harness.state.get_value(&txn, &key).unwrap(),
+                    model.names.get(&key).copied()
+                );
+            }
+        }
+        let divergences = harness.state.verify_state(&txn).unwrap();
+        prop_assert!(divergences.is_empty(), "{:?}", divergences);
+    }
+}